@@ -8,3 +8,5 @@ pub(crate) mod fmt;
 pub mod executor;
 pub mod interrupt;
 pub mod peripheral;
+#[cfg(feature = "systick-time-driver")]
+pub mod systick;
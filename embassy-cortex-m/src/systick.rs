@@ -0,0 +1,440 @@
+//! Tickless `embassy-time` driver on the SysTick timer.
+//!
+//! SysTick is a 24-bit down counter running at the core clock. Instead of interrupting at a fixed
+//! rate, the driver programs the reload value so that the counter reaches zero at the next alarm,
+//! or after the longest possible period if no alarm is set. The cycles of each elapsed period are
+//! accumulated at the SysTick exception, and [`now`](embassy_time::Instant::now) adds the cycles
+//! elapsed in the current period.
+//!
+//! A later alarm only changes the reload value, which takes effect when the current period ends.
+//! The counter is only restarted for an alarm that is due before that, and the cycles elapsed in
+//! the interrupted period are added to the accumulated ones.
+//!
+//! The HAL has to call [`init`] with the core clock frequency, [`set_clock_freq`] whenever that
+//! frequency changes, and [`on_interrupt`] from the `SysTick` exception handler.
+
+use core::cell::Cell;
+use core::{mem, ptr};
+
+use atomic_polyfill::{AtomicU8, Ordering};
+use cortex_m::peripheral::{SCB, SYST};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::driver::{AlarmHandle, Driver};
+use embassy_time::TICK_HZ;
+
+const ALARM_COUNT: usize = 3;
+
+/// Longest period, limited by the 24-bit reload value.
+const MAX_PERIOD: u32 = 1 << 24;
+/// Shortest period, so the exception doesn't starve everything else.
+const MIN_PERIOD: u32 = 256;
+/// Cycles left in the current period below which the reload value isn't changed anymore, so it
+/// can't race with the reload. The exception at the end of the period reprograms it instead.
+const RELOAD_MARGIN: u32 = 64;
+
+// SYST_CSR
+const CSR_ENABLE: u32 = 1 << 0;
+const CSR_TICKINT: u32 = 1 << 1;
+const CSR_CLKSOURCE: u32 = 1 << 2;
+const CSR_COUNTFLAG: u32 = 1 << 16;
+
+// SCB_ICSR
+const ICSR_PENDSTSET: u32 = 1 << 26;
+
+struct AlarmState {
+    timestamp: Cell<u64>,
+
+    // This is really a Option<(fn(*mut ()), *mut ())>
+    // but fn pointers aren't allowed in const yet
+    callback: Cell<*const ()>,
+    ctx: Cell<*mut ()>,
+}
+
+unsafe impl Send for AlarmState {}
+
+impl AlarmState {
+    const fn new() -> Self {
+        Self {
+            timestamp: Cell::new(u64::MAX),
+            callback: Cell::new(ptr::null()),
+            ctx: Cell::new(ptr::null_mut()),
+        }
+    }
+}
+
+struct Counter {
+    /// SysTick clock frequency
+    clock_hz: Cell<u32>,
+    /// Ticks elapsed before the last change of `clock_hz`.
+    tick_offset: Cell<u64>,
+    /// Cycles elapsed since the last change of `clock_hz`, up to the start of the current period.
+    base: Cell<u64>,
+    /// Length of the current period in cycles.
+    period: Cell<u32>,
+    /// Length of the period after the current one, i.e. the reload value plus one.
+    next_period: Cell<u32>,
+}
+
+struct SystickDriver {
+    alarm_count: AtomicU8,
+    counter: Mutex<CriticalSectionRawMutex, Counter>,
+    alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+}
+
+const ALARM_STATE_NEW: AlarmState = AlarmState::new();
+
+embassy_time::time_driver_impl!(static DRIVER: SystickDriver = SystickDriver {
+    alarm_count: AtomicU8::new(0),
+    counter: Mutex::const_new(CriticalSectionRawMutex::new(), Counter {
+        clock_hz: Cell::new(0),
+        tick_offset: Cell::new(0),
+        base: Cell::new(0),
+        period: Cell::new(MAX_PERIOD),
+        next_period: Cell::new(MAX_PERIOD),
+    }),
+    alarms: Mutex::const_new(CriticalSectionRawMutex::new(), [ALARM_STATE_NEW; ALARM_COUNT]),
+});
+
+fn syst() -> &'static cortex_m::peripheral::syst::RegisterBlock {
+    unsafe { &*SYST::PTR }
+}
+
+fn scb() -> &'static cortex_m::peripheral::scb::RegisterBlock {
+    unsafe { &*SCB::PTR }
+}
+
+/// Convert `cycles` at `clock_hz` to ticks, rounding down.
+fn cycles_to_ticks(cycles: u64, clock_hz: u32) -> u64 {
+    let clock_hz = clock_hz as u64;
+    cycles / clock_hz * TICK_HZ + cycles % clock_hz * TICK_HZ / clock_hz
+}
+
+/// Convert `ticks` to cycles at `clock_hz`, rounding up, so alarms never fire early.
+fn ticks_to_cycles(ticks: u64, clock_hz: u32) -> u64 {
+    let clock_hz = clock_hz as u64;
+    ticks / TICK_HZ * clock_hz + (ticks % TICK_HZ * clock_hz + TICK_HZ - 1) / TICK_HZ
+}
+
+/// Cycles elapsed at counter value `val`, in a period of `period` cycles starting at `base`.
+fn cycles_at(base: u64, period: u32, val: u32) -> u64 {
+    // The counter reads zero for one cycle after a restart, before it's reloaded.
+    match val {
+        0 => base,
+        val => base + (period - val) as u64,
+    }
+}
+
+/// Length of the period starting at `now` and ending at the next alarm, `at`, both in cycles.
+fn period_until(now: u64, at: Option<u64>) -> u32 {
+    match at {
+        Some(at) => at.saturating_sub(now).clamp(MIN_PERIOD as u64, MAX_PERIOD as u64) as u32,
+        None => MAX_PERIOD,
+    }
+}
+
+/// Index of the alarm with the earliest `timestamp` that is due at `now`.
+fn next_due(timestamps: impl Iterator<Item = u64>, now: u64) -> Option<usize> {
+    timestamps
+        .enumerate()
+        .filter(|&(_, timestamp)| timestamp <= now)
+        .min_by_key(|&(_, timestamp)| timestamp)
+        .map(|(n, _)| n)
+}
+
+impl SystickDriver {
+    fn init(&self, clock_hz: u32) {
+        critical_section::with(|cs| {
+            let counter = self.counter.borrow(cs);
+            counter.clock_hz.set(clock_hz);
+
+            let syst = syst();
+            unsafe {
+                syst.csr.write(0);
+                syst.rvr.write(MAX_PERIOD - 1);
+                syst.cvr.write(0);
+                syst.csr.write(CSR_CLKSOURCE | CSR_TICKINT | CSR_ENABLE);
+            }
+        })
+    }
+
+    /// Cycles elapsed since the last clock change.
+    ///
+    /// A period that ended is added to `base` right away, whether the exception ran already or
+    /// not, so time never goes backwards and every period is counted exactly once.
+    fn cycles(&self, counter: &Counter) -> u64 {
+        let syst = syst();
+
+        let val = syst.cvr.read();
+        // Reading clears COUNTFLAG, which is set when the counter reaches zero. Writing the
+        // counter clears it as well, so it's only set if a period ended since it was added.
+        if syst.csr.read() & CSR_COUNTFLAG == 0 {
+            return cycles_at(counter.base.get(), counter.period.get(), val);
+        }
+
+        counter.base.set(counter.base.get() + counter.period.get() as u64);
+        counter.period.set(counter.next_period.get());
+        // Read again, the counter may have been reloaded after the first read.
+        cycles_at(counter.base.get(), counter.period.get(), syst.cvr.read())
+    }
+
+    fn ticks(&self, counter: &Counter) -> u64 {
+        counter.tick_offset.get() + cycles_to_ticks(self.cycles(counter), counter.clock_hz.get())
+    }
+
+    /// Make the counter reach zero at the next alarm.
+    ///
+    /// If the alarm is due before the current period ends, the counter is restarted. Otherwise
+    /// only the reload value is changed, so the current period runs to its end and no cycles are
+    /// lost.
+    fn reprogram(&self, cs: critical_section::CriticalSection) {
+        let counter = self.counter.borrow(cs);
+        let now = self.cycles(counter);
+        let end = counter.base.get() + counter.period.get() as u64;
+
+        let next = self
+            .alarms
+            .borrow(cs)
+            .iter()
+            .map(|alarm| alarm.timestamp.get())
+            .filter(|&timestamp| timestamp != u64::MAX)
+            .min();
+        let at =
+            next.map(|next| ticks_to_cycles(next.saturating_sub(counter.tick_offset.get()), counter.clock_hz.get()));
+
+        let period = period_until(now, at);
+        if now + (period as u64) < end {
+            self.restart(counter, period);
+        } else {
+            self.set_next_period(counter, period_until(end, at));
+        }
+    }
+
+    /// Restart the counter with a period of `period` cycles, adding the cycles elapsed in the
+    /// current one to `base`.
+    fn restart(&self, counter: &Counter, period: u32) {
+        let syst = syst();
+        // Read the counter right before restarting it, to lose as few cycles as possible.
+        let now = self.cycles(counter);
+        unsafe {
+            syst.rvr.write(period - 1);
+            // Restarts the counter, which reloads at the next cycle.
+            syst.cvr.write(0);
+        }
+        counter.base.set(now);
+        counter.period.set(period);
+        counter.next_period.set(period);
+    }
+
+    /// Set the length of the period after the current one.
+    fn set_next_period(&self, counter: &Counter, period: u32) {
+        if counter.next_period.get() == period {
+            return;
+        }
+        let syst = syst();
+        // Zero means the counter is about to reload, too.
+        if syst.cvr.read() <= RELOAD_MARGIN {
+            // Too close to the reload to tell which value it loads. The exception at the end of
+            // this period runs `reprogram` again.
+            return;
+        }
+        unsafe { syst.rvr.write(period - 1) };
+        counter.next_period.set(period);
+    }
+
+    fn on_interrupt(&self) {
+        critical_section::with(|cs| {
+            let counter = self.counter.borrow(cs);
+            let now = self.ticks(counter);
+
+            // Fire the due alarms earliest first. Bounded, because a callback may set an alarm
+            // that is due already; `set_alarm` pends the exception again for it.
+            for _ in 0..ALARM_COUNT {
+                let timestamps = self.alarms.borrow(cs).iter().map(|alarm| alarm.timestamp.get());
+                match next_due(timestamps, now) {
+                    Some(n) => self.trigger_alarm(n, cs),
+                    None => break,
+                }
+            }
+
+            self.reprogram(cs);
+        })
+    }
+
+    fn set_clock_freq(&self, clock_hz: u32) {
+        critical_section::with(|cs| {
+            let counter = self.counter.borrow(cs);
+            if counter.clock_hz.get() == 0 {
+                // Not started yet, `init` sets the frequency.
+                return;
+            }
+            // Restart the counter, so the cycles of the new period are counted at the new frequency.
+            self.restart(counter, MAX_PERIOD);
+            let elapsed = cycles_to_ticks(counter.base.get(), counter.clock_hz.get());
+            counter.tick_offset.set(counter.tick_offset.get() + elapsed);
+            counter.base.set(0);
+            counter.clock_hz.set(clock_hz);
+            self.reprogram(cs);
+        })
+    }
+
+    fn get_alarm<'a>(&'a self, cs: critical_section::CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
+        // safety: we're allowed to assume the AlarmState is created by us, and
+        // we never create one that's out of bounds.
+        unsafe { self.alarms.borrow(cs).get_unchecked(alarm.id() as usize) }
+    }
+
+    fn trigger_alarm(&self, n: usize, cs: critical_section::CriticalSection) {
+        let alarm = &self.alarms.borrow(cs)[n];
+        alarm.timestamp.set(u64::MAX);
+
+        // Call after clearing alarm, so the callback can set another alarm.
+
+        // safety:
+        // - we can ignore the possiblity of `f` being unset (null) because of the safety contract of `allocate_alarm`.
+        // - other than that we only store valid function pointers into alarm.callback
+        let f: fn(*mut ()) = unsafe { mem::transmute(alarm.callback.get()) };
+        f(alarm.ctx.get());
+    }
+}
+
+impl Driver for SystickDriver {
+    fn now(&self) -> u64 {
+        critical_section::with(|cs| self.ticks(self.counter.borrow(cs)))
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+        let id = self.alarm_count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+            if x < ALARM_COUNT as u8 {
+                Some(x + 1)
+            } else {
+                None
+            }
+        });
+
+        match id {
+            Ok(id) => Some(AlarmHandle::new(id)),
+            Err(_) => None,
+        }
+    }
+
+    fn set_alarm_callback(&self, alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            alarm.callback.set(callback as *const ());
+            alarm.ctx.set(ctx);
+        })
+    }
+
+    fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            alarm.timestamp.set(timestamp);
+            if timestamp <= self.ticks(self.counter.borrow(cs)) {
+                // Already due, fire it from the exception as soon as the critical section ends,
+                // instead of waiting for the shortest period.
+                unsafe { scb().icsr.write(ICSR_PENDSTSET) };
+                return true;
+            }
+
+            // Restarts the counter only if the alarm is due before the current period ends.
+            self.reprogram(cs);
+            true
+        })
+    }
+}
+
+/// Start the SysTick time driver.
+///
+/// `clock_hz` is the core clock frequency, which clocks SysTick.
+///
+/// # Safety
+///
+/// SysTick must not be used by anything else.
+pub unsafe fn init(clock_hz: u32) {
+    DRIVER.init(clock_hz)
+}
+
+/// Update the SysTick clock frequency after the core clock changed.
+///
+/// Time keeps running from the current value, at the new frequency. Does nothing before [`init`].
+pub fn set_clock_freq(clock_hz: u32) {
+    DRIVER.set_clock_freq(clock_hz)
+}
+
+/// Handle the SysTick exception. Must be called from the `SysTick` exception handler.
+pub fn on_interrupt() {
+    DRIVER.on_interrupt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_HZ: u32 = 72_000_000;
+
+    #[test]
+    fn alarms_never_fire_early() {
+        for ticks in [0, 1, TICK_HZ - 1, TICK_HZ, TICK_HZ + 1, 12_345_678_901, u32::MAX as u64] {
+            let cycles = ticks_to_cycles(ticks, CLOCK_HZ);
+            assert_eq!(cycles_to_ticks(cycles, CLOCK_HZ), ticks);
+            if cycles > 0 {
+                assert!(cycles_to_ticks(cycles - 1, CLOCK_HZ) < ticks);
+            }
+        }
+    }
+
+    #[test]
+    fn ticks_are_monotonic() {
+        let mut last = 0;
+        for cycles in (0..10 * CLOCK_HZ as u64).step_by(CLOCK_HZ as usize / 1000 + 7) {
+            let ticks = cycles_to_ticks(cycles, CLOCK_HZ);
+            assert!(ticks >= last);
+            last = ticks;
+        }
+    }
+
+    #[test]
+    fn cycles_are_monotonic_across_periods() {
+        let period = 1000;
+        let mut base = 0;
+        let mut last = 0;
+        for _ in 0..3 {
+            // The counter restarts at zero, then counts down from the reload value.
+            for val in [0].into_iter().chain((1..period).rev()) {
+                let cycles = cycles_at(base, period, val);
+                assert!(cycles >= last);
+                last = cycles;
+            }
+            // The end of the period is added to `base` when the counter reaches zero.
+            base += period as u64;
+            assert_eq!(cycles_at(base, period, 0), last + 1);
+        }
+    }
+
+    #[test]
+    fn period_ends_at_alarm() {
+        assert_eq!(period_until(1000, None), MAX_PERIOD);
+        assert_eq!(period_until(1000, Some(1000 + 5000)), 5000);
+        assert_eq!(period_until(1000, Some(u64::MAX)), MAX_PERIOD);
+        // Due or passed alarms end the period as soon as possible.
+        assert_eq!(period_until(1000, Some(1000)), MIN_PERIOD);
+        assert_eq!(period_until(1000, Some(0)), MIN_PERIOD);
+    }
+
+    #[test]
+    fn due_alarms_fire_earliest_first() {
+        let mut timestamps = [30, u64::MAX, 10, 20];
+        let mut fired = [0; 4];
+        let mut count = 0;
+        while let Some(n) = next_due(timestamps.iter().copied(), 25) {
+            timestamps[n] = u64::MAX;
+            fired[count] = n;
+            count += 1;
+        }
+        assert_eq!(&fired[..count], &[2, 3]);
+        assert_eq!(next_due([u64::MAX; 3].into_iter(), u64::MAX - 1), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Continuous conversions into a DMA ring buffer
+//!
+//! [`ContinuousAdc`] scans a [`Sequence`] over and over, each scan started by a
+//! [`Trigger`]. The DMA writes the results into a circular buffer, from which
+//! [`ContinuousAdc::read`] reads them in whole scans, so sampling goes on at a steady rate while
+//! the task processes the previous results, e.g. for audio or power monitoring.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::continuous::{ContinuousAdc, Trigger};
+//! use embassy_gd32::adc::{Adc, RegularTrigger, SampleTime, Sequence};
+//!
+//! let adc = Adc::new(p.ADC0);
+//! let (mut voltage, mut current) = (p.PA0, p.PA1);
+//! let mut sequence = Sequence::new();
+//! sequence.push(&mut voltage, SampleTime::Cycles55_5);
+//! sequence.push(&mut current, SampleTime::Cycles55_5);
+//! let mut buf = [0; 256];
+//! // TIMER2 sets the sample rate, with its update events as trigger output.
+//! let trigger = Trigger::Triggered(RegularTrigger::Timer2Trgo);
+//! let mut samples = ContinuousAdc::new(&adc, p.DMA0_CH0, &sequence, trigger, &mut buf);
+//! let mut scans = [0; 32];
+//! loop {
+//!     match samples.read(&mut scans).await {
+//!         // `scans[..n]` holds voltage and current, alternating.
+//!         Ok(n) => {}
+//!         // Some results were lost, reading goes on from the oldest scan left.
+//!         Err(_) => {}
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! The reader is woken each time the DMA has filled half of the buffer, so the buffer should hold
+//! at least two scans and leave the task time to read one half while the other fills. If the task
+//! falls behind by a whole buffer, the oldest results are overwritten and
+//! [`ContinuousAdc::read`] returns [`OverrunError`].
+
+use core::marker::PhantomData;
+
+use super::*;
+use crate::dma::ReadableRingBuffer;
+
+/// What starts the scans of a [`ContinuousAdc`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    /// Each scan starts as soon as the previous one ends, at the rate given by the sample times
+    FreeRunning,
+    /// Each trigger starts a scan, with [`RegularTrigger::Software`] each call of
+    /// [`ContinuousAdc::trigger`]
+    Triggered(RegularTrigger),
+}
+
+/// Conversion results were overwritten by the DMA before they were read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OverrunError;
+
+/// Continuous conversions of a sequence of channels
+pub struct ContinuousAdc<'a, T: Instance> {
+    ring: ReadableRingBuffer<'a, u16>,
+    scan_len: usize,
+    _adc: PhantomData<&'a T>,
+}
+
+impl<'a, T: Instance> ContinuousAdc<'a, T> {
+    /// Scan `sequence` at each `trigger`, and stream the results into `buf` through `dma`. The
+    /// conversions start right away.
+    ///
+    /// The ADC is only borrowed, so its injected group can be used meanwhile, while owning the
+    /// DMA channel keeps other scans of the regular sequence out.
+    ///
+    /// Panics if the sequence is empty, if `buf` holds less than a scan, or if the ADC doesn't
+    /// have the trigger.
+    pub fn new<'d: 'a>(
+        _adc: &'a Adc<'d, T>,
+        dma: impl Peripheral<P = impl RxDma<T>> + 'a,
+        sequence: &Sequence<'_, T>,
+        trigger: Trigger,
+        buf: &'a mut [u16],
+    ) -> Self {
+        assert!(!sequence.is_empty(), "sequence is empty");
+        assert!(buf.len() >= sequence.len(), "buffer must hold a scan");
+        sequence.configure();
+
+        let r = T::regs();
+        // The result register holds the result in its low half-word.
+        let ring = unsafe { ReadableRingBuffer::new(dma, r.rdata.as_ptr() as *const u16, buf) };
+        modify_ctl0::<T>(CTL0_SM, 0);
+        start::<T>(trigger);
+
+        Self {
+            ring,
+            scan_len: sequence.len(),
+            _adc: PhantomData,
+        }
+    }
+
+    /// Start a scan, with [`RegularTrigger::Software`] as trigger.
+    pub fn trigger(&mut self) {
+        modify_ctl1::<T>(CTL1_SWRCST, 0);
+    }
+
+    /// Wait for at least one scan, and read as many whole scans into `buf` as are available and
+    /// fit. Returns the number of results read, a multiple of the number of channels.
+    ///
+    /// If results were overwritten before they were read, nothing is read and an error is
+    /// returned. The next read goes on from the oldest whole scan left. Panics if `buf` can't
+    /// hold a scan.
+    pub async fn read(&mut self, buf: &mut [u16]) -> Result<usize, OverrunError> {
+        assert!(buf.len() >= self.scan_len, "buffer must hold a scan");
+        let res = match self.ring.wait(self.scan_len).await {
+            Ok(()) => self.ring.available().and_then(|available| {
+                let n = available.min(buf.len());
+                self.ring.read(&mut buf[..n - n % self.scan_len])
+            }),
+            Err(e) => Err(e),
+        };
+        res.map_err(|_| {
+            self.ring.resync(self.scan_len);
+            OverrunError
+        })
+    }
+}
+
+/// Start the conversions of ADC `T` into its DMA at `trigger`, the regular sequence being set.
+pub(super) fn start<T: Instance>(trigger: Trigger) {
+    match trigger {
+        Trigger::FreeRunning => {
+            modify_ctl1::<T>(CTL1_CTN | CTL1_DMA, 0);
+            modify_ctl1::<T>(CTL1_SWRCST, 0);
+        }
+        Trigger::Triggered(trigger) => {
+            trigger::select_regular::<T>(trigger);
+            modify_ctl1::<T>(CTL1_DMA, 0);
+        }
+    }
+}
+
+impl<'a, T: Instance> Drop for ContinuousAdc<'a, T> {
+    fn drop(&mut self) {
+        // Let the scan in progress end, so it doesn't end the next read of the ADC.
+        stop_scan::<T>(self.scan_len as u32);
+    }
+}
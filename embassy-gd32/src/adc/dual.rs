@@ -0,0 +1,289 @@
+//! Dual ADC modes
+//!
+//! ADC0 and ADC1 can convert together, both started by the trigger of ADC0. In the regular
+//! simultaneous mode both ADCs scan their regular sequence at the same time, e.g. to sample the
+//! voltage and the current of a power measurement at exactly the same instants. In the fast
+//! interleaved mode both ADCs convert the same channel, ADC0 7 ADC clocks after ADC1, which
+//! nearly doubles the sample rate of the channel.
+//!
+//! Each result of the dual modes is a word of 32 bits moved by the DMA of ADC0, with the result
+//! of ADC0 in its low half-word and the result of ADC1 in its high half-word, see [`split`].
+//!
+//! The inputs of the ADCs are single-ended. The regular simultaneous mode samples the two legs of
+//! a differential signal at the same instant, one on each ADC, so [`difference`] gives a
+//! pseudo-differential result. It rejects common-mode changes slower than the sample time, but
+//! each leg must stay within VSSA and VREF+, and small signals like those of strain gauges or
+//! thermocouples still need an amplifier in front.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::continuous::Trigger;
+//! use embassy_gd32::adc::dual::{split, ContinuousDualAdc, DualAdc};
+//! use embassy_gd32::adc::{Adc, RegularTrigger, SampleTime, Sequence};
+//!
+//! let mut dual = DualAdc::new(Adc::new(p.ADC0), Adc::new(p.ADC1));
+//! let (mut voltage, mut current) = (p.PA0, p.PA1);
+//! let mut sequence0 = Sequence::new();
+//! sequence0.push(&mut voltage, SampleTime::Cycles55_5);
+//! let mut sequence1 = Sequence::new();
+//! sequence1.push(&mut current, SampleTime::Cycles55_5);
+//! let mut buf = [0; 256];
+//! // TIMER2 sets the sample rate, with its update events as trigger output.
+//! let mut samples = ContinuousDualAdc::new_simultaneous(
+//!     &mut dual,
+//!     p.DMA0_CH0,
+//!     &sequence0,
+//!     &sequence1,
+//!     Trigger::Triggered(RegularTrigger::Timer2Trgo),
+//!     &mut buf,
+//! );
+//! let mut scans = [0; 32];
+//! loop {
+//!     if let Ok(n) = samples.read(&mut scans).await {
+//!         let power: u32 = scans[..n].iter().map(|&r| split(r)).map(|(v, i)| v as u32 * i as u32).sum();
+//!     }
+//! }
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use super::continuous::{self, OverrunError, Trigger};
+use super::*;
+use crate::dma::ReadableRingBuffer;
+use crate::peripherals::{ADC0, ADC1};
+
+/// Split a result of the dual modes into the results of ADC0 and ADC1.
+///
+/// In the fast interleaved mode, the result of ADC1 is the earlier sample.
+pub const fn split(result: u32) -> (u16, u16) {
+    (result as u16, (result >> 16) as u16)
+}
+
+/// The result of ADC0 minus the result of ADC1 of a result of the dual modes, from -4095 to 4095.
+pub const fn difference(result: u32) -> i16 {
+    let (adc0, adc1) = split(result);
+    adc0 as i16 - adc1 as i16
+}
+
+/// ADC0 and ADC1, converting together
+pub struct DualAdc<'d> {
+    adc0: Adc<'d, ADC0>,
+    adc1: Adc<'d, ADC1>,
+}
+
+impl<'d> DualAdc<'d> {
+    /// Use `adc0` and `adc1` together. They convert on their own outside of the dual modes.
+    pub fn new(adc0: Adc<'d, ADC0>, adc1: Adc<'d, ADC1>) -> Self {
+        Self { adc0, adc1 }
+    }
+
+    /// ADC0, to convert on its own.
+    pub fn adc0(&mut self) -> &mut Adc<'d, ADC0> {
+        &mut self.adc0
+    }
+
+    /// ADC1, to convert on its own.
+    pub fn adc1(&mut self) -> &mut Adc<'d, ADC1> {
+        &mut self.adc1
+    }
+
+    /// Get back the ADCs.
+    pub fn into_inner(self) -> (Adc<'d, ADC0>, Adc<'d, ADC1>) {
+        (self.adc0, self.adc1)
+    }
+
+    /// Start a simultaneous scan of `sequence0` and `sequence1` that moves the results into
+    /// `results` through `dma`.
+    fn start_simultaneous<'a>(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<ADC0>> + 'a,
+        sequence0: &Sequence<'_, ADC0>,
+        sequence1: &Sequence<'_, ADC1>,
+        results: &'a mut [u32],
+    ) -> Transfer<'a> {
+        assert!(results.len() >= sequence0.len(), "results must hold a scan");
+        configure_simultaneous(sequence0, sequence1);
+
+        let transfer = unsafe {
+            Transfer::new_read(
+                dma,
+                ADC0::regs().rdata.as_ptr() as *const u32,
+                &mut results[..sequence0.len()],
+            )
+        };
+        modify_ctl1::<ADC0>(CTL1_SWRCST, 0);
+        transfer
+    }
+
+    /// Scan `sequence0` on ADC0 and `sequence1` on ADC1 at the same time, once, blocking. The
+    /// results of the conversions at index `i` of the sequences are moved into `results[i]` by
+    /// `dma`.
+    ///
+    /// The conversions at the same index should have the same sample time, so they end together,
+    /// and must not convert the same channel. Panics if the sequences are empty, don't have the
+    /// same length, or `results` is shorter.
+    pub fn blocking_read_simultaneous(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<ADC0>>,
+        sequence0: &Sequence<'_, ADC0>,
+        sequence1: &Sequence<'_, ADC1>,
+        results: &mut [u32],
+    ) {
+        let transfer = self.start_simultaneous(dma, sequence0, sequence1, results);
+        while transfer.is_running() {}
+        drop(transfer);
+        stop_dual(0);
+    }
+
+    /// Scan `sequence0` on ADC0 and `sequence1` on ADC1 at the same time, once, like
+    /// [`DualAdc::blocking_read_simultaneous`].
+    pub async fn read_simultaneous(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<ADC0>>,
+        sequence0: &Sequence<'_, ADC0>,
+        sequence1: &Sequence<'_, ADC1>,
+        results: &mut [u32],
+    ) {
+        // A scan can't be stopped. If the read is dropped, wait for the conversions to end, so
+        // they don't end the next read.
+        let len = sequence0.len() as u32;
+        let on_drop = OnDrop::new(|| stop_dual(len));
+        self.start_simultaneous(dma, sequence0, sequence1, results).await;
+        on_drop.defuse();
+        stop_dual(0);
+    }
+}
+
+/// Continuous conversions of both ADCs in a dual mode
+///
+/// Like [`ContinuousAdc`](super::continuous::ContinuousAdc), the results stream into a DMA ring
+/// buffer and are read in whole scans.
+pub struct ContinuousDualAdc<'a> {
+    ring: ReadableRingBuffer<'a, u32>,
+    scan_len: usize,
+    _adc: PhantomData<&'a mut ()>,
+}
+
+impl<'a> ContinuousDualAdc<'a> {
+    /// Scan `sequence0` on ADC0 and `sequence1` on ADC1 at the same time at each `trigger`, and
+    /// stream the results into `buf` through `dma`. The conversions start right away.
+    ///
+    /// The sequences are paired up like in [`DualAdc::blocking_read_simultaneous`]. Panics if the
+    /// sequences are empty, don't have the same length, or if `buf` holds less than a scan.
+    pub fn new_simultaneous<'d: 'a>(
+        _dual: &'a mut DualAdc<'d>,
+        dma: impl Peripheral<P = impl RxDma<ADC0>> + 'a,
+        sequence0: &Sequence<'_, ADC0>,
+        sequence1: &Sequence<'_, ADC1>,
+        trigger: Trigger,
+        buf: &'a mut [u32],
+    ) -> Self {
+        assert!(buf.len() >= sequence0.len(), "buffer must hold a scan");
+        configure_simultaneous(sequence0, sequence1);
+        Self::start(dma, sequence0.len(), trigger, buf)
+    }
+
+    /// Convert `pin` on both ADCs in the fast interleaved mode at each `trigger`, and stream the
+    /// results into `buf` through `dma`. The conversions start right away.
+    ///
+    /// Each trigger converts the pin on ADC1, and 7 ADC clocks later on ADC0, which takes the
+    /// shortest sample time [`SampleTime::Cycles2_5`]. With [`Trigger::FreeRunning`], both ADCs
+    /// convert over and over, a sample each 7 or 8 ADC clocks. Panics if `buf` is empty.
+    pub fn new_interleaved<'d: 'a, P>(
+        _dual: &'a mut DualAdc<'d>,
+        dma: impl Peripheral<P = impl RxDma<ADC0>> + 'a,
+        pin: &'a mut P,
+        trigger: Trigger,
+        buf: &'a mut [u32],
+    ) -> Self
+    where
+        P: AdcPin<ADC0> + AdcPin<ADC1>,
+    {
+        assert!(!buf.is_empty(), "buffer is empty");
+        // The sample time must be shorter than the 7 ADC clocks between the conversions.
+        sealed::AdcPin::<ADC0>::set_as_analog(pin);
+        let channel = sealed::AdcPin::<ADC0>::channel(pin);
+        set_channel_sample_time::<ADC0>(channel, SampleTime::Cycles2_5);
+        set_sequence::<ADC0>(core::iter::once(channel));
+        let channel = sealed::AdcPin::<ADC1>::channel(pin);
+        set_channel_sample_time::<ADC1>(channel, SampleTime::Cycles2_5);
+        set_sequence::<ADC1>(core::iter::once(channel));
+        modify_ctl0::<ADC0>(CTL0_SYNCM_REGULAR_FOLLOW_UP_FAST, CTL0_SYNCM);
+        Self::start(dma, 1, trigger, buf)
+    }
+
+    /// Start the DMA of ADC0 and the conversions, the dual mode being set.
+    fn start(
+        dma: impl Peripheral<P = impl RxDma<ADC0>> + 'a,
+        scan_len: usize,
+        trigger: Trigger,
+        buf: &'a mut [u32],
+    ) -> Self {
+        let ring = unsafe { ReadableRingBuffer::new(dma, ADC0::regs().rdata.as_ptr() as *const u32, buf) };
+        if trigger == Trigger::FreeRunning {
+            modify_ctl1::<ADC1>(CTL1_CTN, 0);
+        }
+        // ADC1 follows the triggers of ADC0.
+        continuous::start::<ADC0>(trigger);
+
+        Self {
+            ring,
+            scan_len,
+            _adc: PhantomData,
+        }
+    }
+
+    /// Start a scan, with [`RegularTrigger::Software`] as trigger.
+    pub fn trigger(&mut self) {
+        modify_ctl1::<ADC0>(CTL1_SWRCST, 0);
+    }
+
+    /// Wait for at least one scan, and read as many whole scans into `buf` as are available and
+    /// fit, like [`ContinuousAdc::read`](super::continuous::ContinuousAdc::read). Returns the
+    /// number of results read.
+    pub async fn read(&mut self, buf: &mut [u32]) -> Result<usize, OverrunError> {
+        assert!(buf.len() >= self.scan_len, "buffer must hold a scan");
+        let res = match self.ring.wait(self.scan_len).await {
+            Ok(()) => self.ring.available().and_then(|available| {
+                let n = available.min(buf.len());
+                self.ring.read(&mut buf[..n - n % self.scan_len])
+            }),
+            Err(e) => Err(e),
+        };
+        res.map_err(|_| {
+            self.ring.resync(self.scan_len);
+            OverrunError
+        })
+    }
+}
+
+impl<'a> Drop for ContinuousDualAdc<'a> {
+    fn drop(&mut self) {
+        // Let the scan in progress end, so it doesn't end the next read of the ADCs.
+        stop_dual(self.scan_len as u32);
+    }
+}
+
+/// Load the sequences of the regular simultaneous mode, and set the mode.
+fn configure_simultaneous(sequence0: &Sequence<'_, ADC0>, sequence1: &Sequence<'_, ADC1>) {
+    assert!(!sequence0.is_empty(), "sequence is empty");
+    assert!(
+        sequence0.len() == sequence1.len(),
+        "the sequences must have the same length"
+    );
+    sequence0.configure();
+    sequence1.configure();
+    modify_ctl0::<ADC1>(CTL0_SM, 0);
+    modify_ctl0::<ADC0>(CTL0_SM | CTL0_SYNCM_REGULAR_PARALLEL, CTL0_SYNCM);
+}
+
+/// Stop the conversions of both ADCs and leave the dual mode, like [`stop_scan`].
+fn stop_dual(conversions: u32) {
+    stop_scan::<ADC0>(conversions);
+    stop_scan::<ADC1>(0);
+    // The results of ADC1 were read through ADC0, discard them.
+    ADC1::regs().rdata.read();
+    modify_ctl0::<ADC0>(0, CTL0_SYNCM);
+}
@@ -0,0 +1,167 @@
+//! Injected conversions
+//!
+//! Besides the regular sequence, each ADC has an injected group of up to 4 conversions with a
+//! trigger of its own. A trigger of the injected group interrupts the regular conversions, which
+//! go on afterwards, so e.g. motor control samples the phase currents at the PWM trigger point
+//! while [`ContinuousAdc`](super::continuous::ContinuousAdc) keeps monitoring the supply.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::{Adc, InjectedTrigger, SampleTime, Sequence};
+//!
+//! let adc = Adc::new(p.ADC0);
+//! let (mut phase_a, mut phase_b) = (p.PA0, p.PA1);
+//! let mut sequence = Sequence::new();
+//! sequence.push(&mut phase_a, SampleTime::Cycles14_5);
+//! sequence.push(&mut phase_b, SampleTime::Cycles14_5);
+//! // TIMER0 channel 3 compares at the middle of the PWM period.
+//! let mut injected = adc.injected(&sequence, InjectedTrigger::Timer0Ch3);
+//! // Results relative to the output at zero current.
+//! injected.set_offset(0, 2048);
+//! injected.set_offset(1, 2048);
+//! let mut currents = [0; 2];
+//! loop {
+//!     injected.read(&mut currents).await;
+//! }
+//! # }
+//! ```
+//!
+//! The sample times are set per channel, so a channel in both the injected group and the regular
+//! sequence is sampled with the sample time set last.
+
+use core::marker::PhantomData;
+
+use atomic_polyfill::Ordering;
+
+use super::*;
+
+/// Injected group of an ADC, see [`Adc::injected`]
+pub struct InjectedGroup<'a, T: Instance> {
+    len: usize,
+    trigger: InjectedTrigger,
+    _adc: PhantomData<&'a T>,
+}
+
+impl<'d, T: Instance> Adc<'d, T> {
+    /// Convert `sequence`, up to 4 conversions, as the injected group at each `trigger`.
+    ///
+    /// The group only borrows the ADC, so the regular conversions go on meanwhile. Panics if the
+    /// sequence is empty or longer than 4, if the ADC doesn't have the trigger, or if the injected
+    /// group is already in use.
+    pub fn injected<'a>(&'a self, sequence: &Sequence<'_, T>, trigger: InjectedTrigger) -> InjectedGroup<'a, T> {
+        assert!(
+            (1..=4).contains(&sequence.len()),
+            "the injected group has 1 to 4 conversions"
+        );
+        assert!(
+            !T::state().injected.swap(true, Ordering::Acquire),
+            "injected group already in use"
+        );
+
+        // With fewer than 4 conversions, the group starts after the first ones in ADC_ISQ.
+        let first = 4 - sequence.len();
+        let mut isq = (sequence.len() as u32 - 1) << ISQ_IL_OFFSET;
+        for i in 0..sequence.len() {
+            set_channel_sample_time::<T>(sequence.channels[i], sequence.sample_times[i]);
+            isq |= (sequence.channels[i] as u32) << (5 * (first + i));
+        }
+        let r = T::regs();
+        unsafe {
+            r.isq.write(|w| w.bits(isq));
+            for i in 0..4 {
+                ioff::<T>(i).write_volatile(0);
+            }
+            r.stat.write(|w| w.bits(!STAT_EOIC));
+        }
+        trigger::select_injected::<T>(trigger);
+
+        InjectedGroup {
+            len: sequence.len(),
+            trigger,
+            _adc: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: Instance> InjectedGroup<'a, T> {
+    /// Subtract `offset` from the result of conversion `index` of the group, so the results
+    /// are signed, e.g. relative to the output of a current sensor at zero current.
+    ///
+    /// Panics if there is no such conversion, or the offset is above 4095.
+    pub fn set_offset(&mut self, index: usize, offset: u16) {
+        assert!(index < self.len, "no such injected conversion");
+        assert!(offset <= 0xFFF, "offset out of range");
+        unsafe { ioff::<T>(index).write_volatile(offset as u32) }
+    }
+
+    /// Read the latest results of the group into `results`, in the order of the sequence.
+    ///
+    /// Panics if `results` is shorter than the group.
+    pub fn results(&self, results: &mut [i16]) {
+        assert!(results.len() >= self.len, "results must hold the group");
+        for (i, result) in results[..self.len].iter_mut().enumerate() {
+            *result = unsafe { idata::<T>(i).read_volatile() } as i16;
+        }
+    }
+
+    /// Whether the group was converted since the last read.
+    pub fn is_done(&self) -> bool {
+        T::regs().stat.read().bits() & STAT_EOIC != 0
+    }
+
+    /// Start the conversions with [`InjectedTrigger::Software`], or wait for the next trigger
+    /// otherwise.
+    fn start(&mut self) {
+        unsafe { T::regs().stat.write(|w| w.bits(!STAT_EOIC)) };
+        if self.trigger == InjectedTrigger::Software {
+            modify_ctl1::<T>(CTL1_SWICST, 0);
+        }
+    }
+
+    /// Convert the group, blocking, and read the results into `results`, like
+    /// [`InjectedGroup::read`].
+    pub fn blocking_read(&mut self, results: &mut [i16]) {
+        self.start();
+        while !self.is_done() {}
+        self.results(results);
+    }
+
+    /// Convert the group, and read the results into `results`, in the order of the sequence.
+    ///
+    /// With [`InjectedTrigger::Software`] this starts the conversions, otherwise it waits for the
+    /// conversions of the next trigger. Panics if `results` is shorter than the group.
+    pub async fn read(&mut self, results: &mut [i16]) {
+        assert!(results.len() >= self.len, "results must hold the group");
+        self.start();
+        poll_fn(|cx| {
+            T::state().injected_waker.register(cx.waker());
+            if self.is_done() {
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again.
+            modify_ctl0::<T>(CTL0_EOICIE, 0);
+            Poll::Pending
+        })
+        .await;
+        self.results(results);
+    }
+}
+
+impl<'a, T: Instance> Drop for InjectedGroup<'a, T> {
+    fn drop(&mut self) {
+        modify_ctl0::<T>(0, CTL0_EOICIE);
+        modify_ctl1::<T>(CTL1_ETSIC_SWICST, CTL1_ETEIC | CTL1_ETSIC);
+        T::state().injected.store(false, Ordering::Release);
+    }
+}
+
+/// Address of `ADC_IOFFx` of conversion `index` of the injected group.
+fn ioff<T: Instance>(index: usize) -> *mut u32 {
+    unsafe { T::regs().ioff0.as_ptr().add(index) }
+}
+
+/// Address of `ADC_IDATAx` of conversion `index` of the injected group.
+fn idata<T: Instance>(index: usize) -> *mut u32 {
+    unsafe { T::regs().idata0.as_ptr().add(index) }
+}
@@ -0,0 +1,889 @@
+//! Analog to digital converter (ADC)
+//!
+//! [`Adc`] converts the voltage of one channel at a time to a 12-bit value, from 0 at VSSA to
+//! 4095 at VREF+. The ADC is calibrated when the driver is created.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::{Adc, SampleTime};
+//!
+//! let mut adc = Adc::new(p.ADC0);
+//! adc.set_sample_time(SampleTime::Cycles55_5);
+//! let mut pin = p.PA1;
+//! let value = adc.blocking_read(&mut pin);
+//! ```
+//!
+//! The channels are selected by the [`AdcPin`]s of an ADC, the GPIO pins in analog mode and the
+//! internal channels. All inputs are single-ended, see [`dual`] for differential signals. ADC0
+//! measures the internal temperature sensor and the internal voltage reference, see
+//! [`Adc::enable_temperature`] and [`Adc::enable_vrefint`], and the die temperature in degrees
+//! Celsius:
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! let mut adc = embassy_gd32::adc::Adc::new(p.ADC0);
+//! let celsius = adc.blocking_read_temperature();
+//! ```
+//!
+//! Conversions are available as blocking functions, and as async functions that wait for the
+//! end of conversion interrupt. Each ADC converts for one driver at a time, tasks that share an
+//! ADC share its driver, e.g. through a mutex. The driver implements the embedded-hal 0.2
+//! `OneShot` trait, and with the `nightly` feature [`AsyncOneShot`].
+//!
+//! A [`Sequence`] lists up to 16 conversions in the order they run, each channel with its own
+//! sample time. [`Adc::read_sequence`] scans it once, with all results moved by DMA, and
+//! [`continuous::ContinuousAdc`] scans it over and over into a DMA ring buffer. The injected group
+//! converts up to 4 channels at a trigger of its own in between, see [`injected`]. The
+//! [`watchdog`] notices results out of range of any of these conversions. ADC0 and ADC1 also
+//! convert together, simultaneously or interleaved, see [`dual`].
+//!
+//! The regular conversions can be oversampled in hardware for up to 16-bit results of slow
+//! signals, see [`Adc::set_oversampling`], or single channels in software, see
+//! [`Adc::blocking_read_oversampled`].
+//!
+//! A conversion takes the sample time plus 12.5 cycles of the ADC clock, see
+//! [`crate::cctl::Config::adc_pre`]. The sample time should let the input settle through the
+//! source impedance, longer sample times suit sources of higher impedance.
+#![macro_use]
+
+pub mod continuous;
+pub mod dual;
+pub mod injected;
+mod trigger;
+pub mod watchdog;
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use atomic_polyfill::AtomicBool;
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+pub use self::trigger::{InjectedTrigger, RegularTrigger};
+use crate::dma::Transfer;
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::{cctl, interrupt, pac, peripherals, Peripheral};
+
+/// Sampling time of a channel, in cycles of the ADC clock, see `SPTn` in `ADC_SAMPTx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleTime {
+    /// 2.5 cycles
+    Cycles2_5 = 0,
+    /// 14.5 cycles
+    Cycles14_5 = 1,
+    /// 27.5 cycles
+    Cycles27_5 = 2,
+    /// 55.5 cycles
+    Cycles55_5 = 3,
+    /// 83.5 cycles
+    Cycles83_5 = 4,
+    /// 111.5 cycles
+    Cycles111_5 = 5,
+    /// 143.5 cycles
+    Cycles143_5 = 6,
+    /// 479.5 cycles
+    Cycles479_5 = 7,
+}
+
+impl Default for SampleTime {
+    fn default() -> Self {
+        Self::Cycles55_5
+    }
+}
+
+/// Number of conversions summed up into one result when oversampling, see `OVSR` in
+/// `ADC_OVSAMPCTL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OversamplingRatio {
+    /// 2 conversions
+    X2 = 0,
+    /// 4 conversions
+    X4 = 1,
+    /// 8 conversions
+    X8 = 2,
+    /// 16 conversions
+    X16 = 3,
+    /// 32 conversions
+    X32 = 4,
+    /// 64 conversions
+    X64 = 5,
+    /// 128 conversions
+    X128 = 6,
+    /// 256 conversions
+    X256 = 7,
+}
+
+impl OversamplingRatio {
+    /// The number of conversions.
+    pub fn conversions(self) -> u32 {
+        2 << self as u32
+    }
+}
+
+/// Sequence of up to 16 conversions, scanned in order
+///
+/// The pins stay borrowed by the sequence, in analog mode. A channel may appear several times,
+/// it's sampled with the sample time of its last entry then.
+///
+/// ```no_run
+/// # async fn example() {
+/// # let p = embassy_gd32::init(Default::default()).unwrap();
+/// use embassy_gd32::adc::{Adc, SampleTime, Sequence};
+///
+/// let mut adc = Adc::new(p.ADC0);
+/// let (mut pa0, mut pa1, mut dma) = (p.PA0, p.PA1, p.DMA0_CH0);
+/// let mut sequence = Sequence::new();
+/// let voltage = sequence.push(&mut pa0, SampleTime::Cycles14_5);
+/// let current = sequence.push(&mut pa1, SampleTime::Cycles143_5);
+/// let mut results = [0; 2];
+/// adc.read_sequence(&mut dma, &sequence, &mut results).await;
+/// let (voltage, current) = (results[voltage], results[current]);
+/// # }
+/// ```
+pub struct Sequence<'p, T: Instance> {
+    channels: [u8; 16],
+    sample_times: [SampleTime; 16],
+    len: usize,
+    _pins: PhantomData<(&'p mut (), T)>,
+}
+
+impl<'p, T: Instance> Sequence<'p, T> {
+    /// Create an empty sequence.
+    pub const fn new() -> Self {
+        Self {
+            channels: [0; 16],
+            sample_times: [SampleTime::Cycles2_5; 16],
+            len: 0,
+            _pins: PhantomData,
+        }
+    }
+
+    /// Append a conversion of `pin`, sampled for `sample_time`. Returns the index of its result
+    /// in the results of a scan.
+    ///
+    /// Panics if the sequence already has 16 conversions.
+    pub fn push(&mut self, pin: &'p mut impl AdcPin<T>, sample_time: SampleTime) -> usize {
+        assert!(self.len < 16, "a sequence has at most 16 conversions");
+        pin.set_as_analog();
+        self.channels[self.len] = pin.channel();
+        self.sample_times[self.len] = sample_time;
+        self.len += 1;
+        self.len - 1
+    }
+
+    /// Number of conversions of the sequence.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the sequence has no conversions.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Load the sequence and its sample times into the ADC.
+    fn configure(&self) {
+        for i in 0..self.len {
+            set_channel_sample_time::<T>(self.channels[i], self.sample_times[i]);
+        }
+        set_sequence::<T>(self.channels[..self.len].iter().copied());
+    }
+}
+
+impl<'p, T: Instance> Default for Sequence<'p, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Internal temperature sensor, channel 16 of ADC0, see [`Adc::enable_temperature`]
+pub struct Temperature;
+
+impl Temperature {
+    /// Convert a result `raw` of the temperature sensor to degrees Celsius, with `vref_mv` the
+    /// voltage of VREF+ in millivolts, e.g. from [`VrefInt::to_vref_mv`].
+    ///
+    /// The sensor has no factory calibration, so this uses the typical values of the datasheet,
+    /// 1.45 V at 25 °C falling by 4.1 mV/°C. Devices differ from them by several degrees, which is
+    /// fine to follow changes of the temperature, but an absolute temperature needs a calibration
+    /// of its own.
+    pub fn to_celsius(raw: u16, vref_mv: u32) -> f32 {
+        let mv = raw as f32 * vref_mv as f32 / 4095.0;
+        (TS_V25_MV - mv) / TS_AVG_SLOPE_MV_PER_C + 25.0
+    }
+}
+
+/// Internal voltage reference, channel 17 of ADC0, see [`Adc::enable_vrefint`]
+pub struct VrefInt;
+
+impl VrefInt {
+    /// Typical voltage of the internal reference in millivolts
+    pub const MILLIVOLTS: u32 = 1200;
+
+    /// Compute the voltage of VREF+ in millivolts from a result `raw` of the internal reference,
+    /// e.g. to tell the supply voltage where VREF+ is tied to VDDA.
+    pub fn to_vref_mv(raw: u16) -> u32 {
+        Self::MILLIVOLTS * 4095 / (raw as u32).max(1)
+    }
+}
+
+/// State shared with the interrupt handler
+pub struct State {
+    waker: AtomicWaker,
+    injected_waker: AtomicWaker,
+    /// The injected group is in use.
+    injected: AtomicBool,
+    watchdog: watchdog::WatchdogState,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            injected_waker: AtomicWaker::new(),
+            injected: AtomicBool::new(false),
+            watchdog: watchdog::WatchdogState::new(),
+        }
+    }
+}
+
+/// ADC driver
+pub struct Adc<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    sample_time: SampleTime,
+}
+
+impl<'d, T: Instance> Adc<'d, T> {
+    /// Enable and calibrate the ADC. The sample time is [`SampleTime::default`].
+    pub fn new(adc: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(adc);
+
+        T::enable();
+        T::reset();
+        // Regular conversions are started by software.
+        modify_ctl1::<T>(CTL1_ADCON | CTL1_ETERC | CTL1_ETSRC_SWRCST, 0);
+
+        // The interrupt may be shared with another ADC, so it stays enabled.
+        let irq = unsafe { T::Interrupt::steal() };
+        irq.unpend();
+        irq.enable();
+
+        let mut this = Self {
+            _peri: adc,
+            sample_time: SampleTime::default(),
+        };
+        this.calibrate();
+        this
+    }
+
+    /// Calibrate the ADC, which cancels its offset error. Done by [`Adc::new`], and worth doing
+    /// again after large changes of the supply voltage or temperature.
+    ///
+    /// There is no gain calibration, the gain error is within the limits of the datasheet.
+    /// Measuring a known voltage, e.g. through [`Adc::enable_vrefint`], corrects the gain of
+    /// VREF+ in software.
+    pub fn calibrate(&mut self) {
+        // The ADC must be on for at least 14 ADC clocks before the calibration.
+        delay_adc_clocks::<T>(14);
+        modify_ctl1::<T>(CTL1_RSTCLB, 0);
+        while T::regs().ctl1.read().bits() & CTL1_RSTCLB != 0 {}
+        modify_ctl1::<T>(CTL1_CLB, 0);
+        while T::regs().ctl1.read().bits() & CTL1_CLB != 0 {}
+    }
+
+    /// Set the sample time of the following conversions.
+    pub fn set_sample_time(&mut self, sample_time: SampleTime) {
+        self.sample_time = sample_time;
+    }
+
+    /// The sample time of the conversions.
+    pub fn sample_time(&self) -> SampleTime {
+        self.sample_time
+    }
+
+    /// Oversample the regular conversions in hardware: each result is the sum of `ratio`
+    /// conversions, shifted right by `shift` bits.
+    ///
+    /// Each doubling of the ratio adds half a bit of resolution for noisy inputs, e.g.
+    /// [`OversamplingRatio::X256`] with a shift of 4 gives 16-bit results, at 1/256 of the sample
+    /// rate. This applies to all regular conversions, single reads and sequences alike, but not
+    /// to the injected group. The ADC is switched off while the oversampler is configured.
+    ///
+    /// Panics if the shift is above 8.
+    pub fn set_oversampling(&mut self, ratio: OversamplingRatio, shift: u8) {
+        assert!(shift <= 8, "shift out of range");
+        self.configure_oversampler(
+            OVSAMPCTL_OVSEN | ((ratio as u32) << OVSAMPCTL_OVSR_OFFSET) | ((shift as u32) << OVSAMPCTL_OVSS_OFFSET),
+        );
+    }
+
+    /// Stop oversampling the regular conversions in hardware.
+    pub fn disable_oversampling(&mut self) {
+        self.configure_oversampler(0);
+    }
+
+    /// Write the oversampler settings `ovsampctl`, with the ADC off.
+    fn configure_oversampler(&mut self, ovsampctl: u32) {
+        let r = T::regs();
+        modify_ctl1::<T>(0, CTL1_ADCON);
+        unsafe {
+            r.ovsampctl
+                .modify(|r, w| w.bits((r.bits() & !(OVSAMPCTL_OVSEN | OVSAMPCTL_OVSR | OVSAMPCTL_OVSS)) | ovsampctl))
+        };
+        modify_ctl1::<T>(CTL1_ADCON, 0);
+        // Let the ADC stabilize after switching it on.
+        delay_adc_clocks::<T>(14);
+    }
+
+    /// Enable the temperature sensor and the internal voltage reference, which share an enable
+    /// bit, and wait for them to start up.
+    fn enable_internal_channels(&mut self) {
+        if T::regs().ctl1.read().bits() & CTL1_TSVREN == 0 {
+            modify_ctl1::<T>(CTL1_TSVREN, 0);
+            // Start-up time of the temperature sensor, the longer of the two.
+            cortex_m::asm::delay(cctl::clocks().sys.0 / 1_000_000 * TS_START_US);
+        }
+    }
+
+    /// Enable the internal temperature sensor, which is converted through the returned channel.
+    ///
+    /// The sensor needs a sample time of at least 17.1 µs, e.g. [`SampleTime::Cycles479_5`] at
+    /// ADC clocks up to 28 MHz.
+    pub fn enable_temperature(&mut self) -> Temperature
+    where
+        Temperature: AdcPin<T>,
+    {
+        self.enable_internal_channels();
+        Temperature
+    }
+
+    /// Enable the internal voltage reference, which is converted through the returned channel.
+    pub fn enable_vrefint(&mut self) -> VrefInt
+    where
+        VrefInt: AdcPin<T>,
+    {
+        self.enable_internal_channels();
+        VrefInt
+    }
+
+    /// Oversample `pin` in software, blocking: sum up `ratio` conversions and shift the sum
+    /// right by `shift` bits, like [`Adc::set_oversampling`].
+    ///
+    /// This oversamples one channel while the other conversions stay as they are, and works on
+    /// top of the sample time. Panics if the shift is above 8.
+    pub fn blocking_read_oversampled(&mut self, pin: &mut impl AdcPin<T>, ratio: OversamplingRatio, shift: u8) -> u16 {
+        assert!(shift <= 8, "shift out of range");
+        let sum: u32 = (0..ratio.conversions()).map(|_| self.blocking_read(pin) as u32).sum();
+        (sum >> shift) as u16
+    }
+
+    /// Oversample `pin` in software, like [`Adc::blocking_read_oversampled`].
+    pub async fn read_oversampled(&mut self, pin: &mut impl AdcPin<T>, ratio: OversamplingRatio, shift: u8) -> u16 {
+        assert!(shift <= 8, "shift out of range");
+        let mut sum = 0;
+        for _ in 0..ratio.conversions() {
+            sum += self.read(pin).await as u32;
+        }
+        (sum >> shift) as u16
+    }
+
+    /// Measure the voltage of VREF+ in millivolts through the internal reference, blocking, see
+    /// [`VrefInt::to_vref_mv`]. Where VREF+ is tied to VDDA, that's the analog supply voltage,
+    /// e.g. for [`crate::dac::Dac::set_reference`].
+    ///
+    /// The reference is sampled for [`SampleTime::Cycles479_5`], the sample time of the other
+    /// conversions stays.
+    pub fn blocking_read_vref_mv(&mut self) -> u32
+    where
+        VrefInt: AdcPin<T>,
+    {
+        let mut vrefint = self.enable_vrefint();
+        VrefInt::to_vref_mv(self.blocking_read_sampled(&mut vrefint, SampleTime::Cycles479_5))
+    }
+
+    /// Measure the voltage of VREF+ in millivolts, like [`Adc::blocking_read_vref_mv`].
+    pub async fn read_vref_mv(&mut self) -> u32
+    where
+        VrefInt: AdcPin<T>,
+    {
+        let mut vrefint = self.enable_vrefint();
+        VrefInt::to_vref_mv(self.read_sampled(&mut vrefint, SampleTime::Cycles479_5).await)
+    }
+
+    /// Measure the die temperature in degrees Celsius, blocking, see [`Temperature::to_celsius`].
+    ///
+    /// The voltage of VREF+ is measured through the internal reference too. Both are sampled for
+    /// [`SampleTime::Cycles479_5`], the sample time of the other conversions stays.
+    pub fn blocking_read_temperature(&mut self) -> f32
+    where
+        Temperature: AdcPin<T>,
+        VrefInt: AdcPin<T>,
+    {
+        let (mut temperature, mut vrefint) = (self.enable_temperature(), self.enable_vrefint());
+        let vref = self.blocking_read_sampled(&mut vrefint, SampleTime::Cycles479_5);
+        let raw = self.blocking_read_sampled(&mut temperature, SampleTime::Cycles479_5);
+        Temperature::to_celsius(raw, VrefInt::to_vref_mv(vref))
+    }
+
+    /// Measure the die temperature in degrees Celsius, like [`Adc::blocking_read_temperature`].
+    pub async fn read_temperature(&mut self) -> f32
+    where
+        Temperature: AdcPin<T>,
+        VrefInt: AdcPin<T>,
+    {
+        let (mut temperature, mut vrefint) = (self.enable_temperature(), self.enable_vrefint());
+        let vref = self.read_sampled(&mut vrefint, SampleTime::Cycles479_5).await;
+        let raw = self.read_sampled(&mut temperature, SampleTime::Cycles479_5).await;
+        Temperature::to_celsius(raw, VrefInt::to_vref_mv(vref))
+    }
+
+    /// Select `channel` as the only conversion of the regular sequence, with `sample_time`.
+    fn select_channel(&mut self, channel: u8, sample_time: SampleTime) {
+        set_channel_sample_time::<T>(channel, sample_time);
+        set_sequence::<T>(core::iter::once(channel));
+    }
+
+    /// Convert the voltage of `pin`, blocking.
+    pub fn blocking_read(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+        self.blocking_read_sampled(pin, self.sample_time)
+    }
+
+    /// Convert the voltage of `pin`, sampled for `sample_time`, blocking.
+    fn blocking_read_sampled(&mut self, pin: &mut impl AdcPin<T>, sample_time: SampleTime) -> u16 {
+        pin.set_as_analog();
+        self.select_channel(pin.channel(), sample_time);
+
+        let r = T::regs();
+        modify_ctl1::<T>(CTL1_SWRCST, 0);
+        while r.stat.read().bits() & STAT_EOC == 0 {}
+        // Reading the data clears EOC.
+        r.rdata.read().bits() as u16
+    }
+
+    /// Convert the voltage of `pin`, waiting for the end of the conversion.
+    pub async fn read(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+        self.read_sampled(pin, self.sample_time).await
+    }
+
+    /// Convert the voltage of `pin`, sampled for `sample_time`, waiting for the end of the
+    /// conversion.
+    async fn read_sampled(&mut self, pin: &mut impl AdcPin<T>, sample_time: SampleTime) -> u16 {
+        pin.set_as_analog();
+        self.select_channel(pin.channel(), sample_time);
+
+        let r = T::regs();
+        // A conversion can't be stopped. If the read is dropped, wait for the conversion and
+        // discard its result, so it doesn't end the next read.
+        let on_drop = OnDrop::new(|| {
+            modify_ctl0::<T>(0, CTL0_EOCIE);
+            while r.stat.read().bits() & STAT_EOC == 0 {}
+            r.rdata.read();
+        });
+        modify_ctl1::<T>(CTL1_SWRCST, 0);
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if r.stat.read().bits() & STAT_EOC != 0 {
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again.
+            modify_ctl0::<T>(CTL0_EOCIE, 0);
+            Poll::Pending
+        })
+        .await;
+        on_drop.defuse();
+        r.rdata.read().bits() as u16
+    }
+
+    /// Start a scan of `sequence` that moves its results into `results` through `dma`.
+    fn start_scan<'a>(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<T>> + 'a,
+        sequence: &Sequence<'_, T>,
+        results: &'a mut [u16],
+    ) -> Transfer<'a> {
+        assert!(!sequence.is_empty(), "sequence is empty");
+        assert!(results.len() >= sequence.len(), "results must hold a scan");
+        sequence.configure();
+
+        let r = T::regs();
+        modify_ctl0::<T>(CTL0_SM, 0);
+        modify_ctl1::<T>(CTL1_DMA, 0);
+        // The result register holds the result in its low half-word.
+        let transfer =
+            unsafe { Transfer::new_read(dma, r.rdata.as_ptr() as *const u16, &mut results[..sequence.len()]) };
+        modify_ctl1::<T>(CTL1_SWRCST, 0);
+        transfer
+    }
+
+    /// Scan `sequence` once, blocking, with the results moved into `results` by `dma`. The
+    /// result of each conversion is at the index [`Sequence::push`] returned.
+    ///
+    /// Panics if the sequence is empty, or `results` is shorter.
+    pub fn blocking_read_sequence(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<T>>,
+        sequence: &Sequence<'_, T>,
+        results: &mut [u16],
+    ) {
+        let transfer = self.start_scan(dma, sequence, results);
+        while transfer.is_running() {}
+        drop(transfer);
+        stop_scan::<T>(0);
+    }
+
+    /// Scan `sequence` once, with the results moved into `results` by `dma`, like
+    /// [`Adc::blocking_read_sequence`].
+    pub async fn read_sequence(
+        &mut self,
+        dma: impl Peripheral<P = impl RxDma<T>>,
+        sequence: &Sequence<'_, T>,
+        results: &mut [u16],
+    ) {
+        // A scan can't be stopped. If the read is dropped, wait for the conversions to end, so
+        // they don't end the next read.
+        let len = sequence.len() as u32;
+        let on_drop = OnDrop::new(|| stop_scan::<T>(len));
+        self.start_scan(dma, sequence, results).await;
+        on_drop.defuse();
+        stop_scan::<T>(0);
+    }
+}
+
+impl<'d, T: Instance> Drop for Adc<'d, T> {
+    fn drop(&mut self) {
+        modify_ctl1::<T>(0, CTL1_ADCON | CTL1_TSVREN);
+        T::disable();
+    }
+}
+
+impl<'d, T: Instance, P> embedded_hal_02::adc::OneShot<T, u16, P> for Adc<'d, T>
+where
+    P: AdcPin<T> + embedded_hal_02::adc::Channel<T, ID = u8>,
+{
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self, pin: &mut P) -> nb::Result<u16, Self::Error> {
+        Ok(self.blocking_read(pin))
+    }
+}
+
+/// Single conversions of a channel `P`, awaited
+///
+/// embedded-hal-async has no ADC trait yet. This is the async counterpart of the embedded-hal 0.2
+/// `OneShot` trait, so drivers of sensors can be generic over the ADC and its channels.
+#[cfg(feature = "nightly")]
+pub trait AsyncOneShot<P> {
+    /// Error of a conversion
+    type Error;
+
+    /// Convert the voltage of `pin`.
+    async fn read(&mut self, pin: &mut P) -> Result<u16, Self::Error>;
+}
+
+#[cfg(feature = "nightly")]
+impl<'d, T: Instance, P: AdcPin<T>> AsyncOneShot<P> for Adc<'d, T> {
+    type Error = core::convert::Infallible;
+
+    async fn read(&mut self, pin: &mut P) -> Result<u16, Self::Error> {
+        Ok(self.read(pin).await)
+    }
+}
+
+/// Set the `set` bits and clear the `clear` bits of `ADC_CTL0`, which the interrupt handler
+/// modifies too.
+fn modify_ctl0<T: Instance>(set: u32, clear: u32) {
+    critical_section::with(|_| unsafe { T::regs().ctl0.modify(|r, w| w.bits((r.bits() & !clear) | set)) })
+}
+
+/// Set the `set` bits and clear the `clear` bits of `ADC_CTL1`.
+fn modify_ctl1<T: Instance>(set: u32, clear: u32) {
+    unsafe { T::regs().ctl1.modify(|r, w| w.bits((r.bits() & !clear) | set)) }
+}
+
+/// Set the sample time of `channel` in `ADC_SAMPT0` or `ADC_SAMPT1`.
+fn set_channel_sample_time<T: Instance>(channel: u8, sample_time: SampleTime) {
+    let r = T::regs();
+    let (reg, shift) = match channel {
+        0..=9 => (&r.sampt1, 3 * channel as u32),
+        _ => (&r.sampt0, 3 * (channel as u32 - 10)),
+    };
+    unsafe { reg.modify(|r, w| w.bits((r.bits() & !(0b111 << shift)) | ((sample_time as u32) << shift))) }
+}
+
+/// Stop the scans of the regular sequence and its DMA requests, to convert single channels
+/// again. Waits for up to `conversions` conversions in progress to end, and discards their
+/// results.
+fn stop_scan<T: Instance>(conversions: u32) {
+    modify_ctl1::<T>(CTL1_ETSRC_SWRCST, CTL1_CTN | CTL1_DMA | CTL1_ETSRC);
+    modify_ctl0::<T>(0, CTL0_SM);
+    if conversions != 0 {
+        delay_adc_clocks::<T>(MAX_CONVERSION_CLOCKS * conversions);
+        T::regs().rdata.read();
+    }
+}
+
+/// Set the channels of the regular sequence, in this order, 1 to 16 of them.
+fn set_sequence<T: Instance>(channels: impl Iterator<Item = u8>) {
+    // The conversions 0 to 5 are in ADC_RSQ2, 6 to 11 in ADC_RSQ1 and 12 to 15 in ADC_RSQ0.
+    let mut rsq = [0; 3];
+    let mut len = 0;
+    for (i, channel) in channels.enumerate() {
+        rsq[2 - i / 6] |= (channel as u32) << (5 * (i % 6));
+        len += 1;
+    }
+    rsq[0] |= (len - 1) << RSQ0_RL_OFFSET;
+    let r = T::regs();
+    unsafe {
+        r.rsq0.write(|w| w.bits(rsq[0]));
+        r.rsq1.write(|w| w.bits(rsq[1]));
+        r.rsq2.write(|w| w.bits(rsq[2]));
+    }
+}
+
+/// Busy wait for at least `clocks` cycles of the ADC clock.
+fn delay_adc_clocks<T: Instance>(clocks: u32) {
+    let per_clock = cctl::clocks().sys.0 / T::frequency().0 + 1;
+    cortex_m::asm::delay(clocks * per_clock)
+}
+
+/// Wake the conversion waiting for the interrupt.
+unsafe fn on_interrupt<T: Instance>() {
+    // The flags are cleared by the woken task, so disable the interrupts that are pending.
+    let r = T::regs();
+    let stat = r.stat.read().bits();
+    let ctl0 = r.ctl0.read().bits();
+    if stat & STAT_EOC != 0 && ctl0 & CTL0_EOCIE != 0 {
+        r.ctl0.modify(|r, w| w.bits(r.bits() & !CTL0_EOCIE));
+        T::state().waker.wake();
+    }
+    if stat & STAT_EOIC != 0 && ctl0 & CTL0_EOICIE != 0 {
+        r.ctl0.modify(|r, w| w.bits(r.bits() & !CTL0_EOICIE));
+        T::state().injected_waker.wake();
+    }
+    watchdog::on_interrupt::<T>(stat, ctl0);
+}
+
+#[interrupt]
+unsafe fn ADC0_1() {
+    on_interrupt::<peripherals::ADC0>();
+    on_interrupt::<peripherals::ADC1>();
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        fn regs() -> &'static pac::adc0::RegisterBlock;
+        fn state() -> &'static State;
+
+        /// The `ETSRC` value of `trigger`, with the AFIO remap it needs selected, or `None` if
+        /// the ADC doesn't have it.
+        fn regular_trigger(trigger: RegularTrigger) -> Option<u32>;
+
+        /// The `ETSIC` value of `trigger`, like [`Instance::regular_trigger`].
+        fn injected_trigger(trigger: InjectedTrigger) -> Option<u32>;
+    }
+
+    pub trait AdcPin<T: Instance> {
+        /// Put the pin into analog mode, nothing for internal channels.
+        fn set_as_analog(&mut self) {}
+
+        /// The channel number of the pin.
+        fn channel(&self) -> u8;
+    }
+}
+
+/// ADC peripheral instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {
+    type Interrupt: Interrupt;
+}
+
+/// A channel of ADC `T`, a GPIO pin or an internal channel
+pub trait AdcPin<T: Instance>: sealed::AdcPin<T> {}
+
+dma_trait!(RxDma, Instance);
+
+/// Implement [`Instance`] for ADC `$inst`, with its triggers and the AFIO remaps that tell apart
+/// the triggers sharing a value, see `adc_trigger_fn!`.
+macro_rules! impl_adc {
+    ($inst:ident, $irq:ident,
+        regular: $regular_remap:ident $regular:tt,
+        injected: $injected_remap:ident $injected:tt $(,)?
+    ) => {
+        impl crate::adc::sealed::Instance for peripherals::$inst {
+            fn regs() -> &'static crate::pac::adc0::RegisterBlock {
+                unsafe { &*crate::pac::$inst::ptr() }
+            }
+
+            fn state() -> &'static crate::adc::State {
+                static STATE: crate::adc::State = crate::adc::State::new();
+                &STATE
+            }
+
+            adc_trigger_fn!(regular_trigger, RegularTrigger, $regular_remap, $regular);
+            adc_trigger_fn!(injected_trigger, InjectedTrigger, $injected_remap, $injected);
+        }
+
+        impl crate::adc::Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::$irq;
+        }
+    };
+}
+
+/// Implement the trigger lookup of [`sealed::Instance`] for `$trigger_type`, from the `ETSRC` or
+/// `ETSIC` values of the triggers, with the layout of AFIO remap `$remap` the trigger needs.
+macro_rules! adc_trigger_fn {
+    ($fn:ident, $trigger_type:ident, $remap:ident, {
+        $($trigger:ident => $bits:literal $(remap $layout:ident)?),* $(,)?
+    }) => {
+        fn $fn(trigger: crate::adc::$trigger_type) -> Option<u32> {
+            match trigger {
+                $(
+                    crate::adc::$trigger_type::$trigger => {
+                        $(crate::afio::remap::<crate::afio::$remap>(crate::afio::Remap::$layout);)?
+                        Some($bits)
+                    }
+                )*
+                #[allow(unreachable_patterns)]
+                _ => None,
+            }
+        }
+    };
+}
+
+/// Implement [`AdcPin`] for the GPIO pins of ADC `$inst`, with their channel numbers.
+macro_rules! impl_adc_pin {
+    ($inst:ident, { $($pin:ident => $channel:expr),* $(,)? }) => {
+        $(
+            impl crate::adc::sealed::AdcPin<peripherals::$inst> for peripherals::$pin {
+                fn set_as_analog(&mut self) {
+                    unsafe { crate::gpio::sealed::Pin::set_as_analog(self) }
+                }
+
+                fn channel(&self) -> u8 {
+                    $channel
+                }
+            }
+
+            impl crate::adc::AdcPin<peripherals::$inst> for peripherals::$pin {}
+
+            impl embedded_hal_02::adc::Channel<peripherals::$inst> for peripherals::$pin {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $channel
+                }
+            }
+        )*
+    };
+}
+
+/// Implement [`AdcPin`] for an internal channel of ADC `$inst`.
+macro_rules! impl_adc_internal {
+    ($inst:ident, $channel_type:ident, $channel:expr) => {
+        impl crate::adc::sealed::AdcPin<peripherals::$inst> for crate::adc::$channel_type {
+            fn channel(&self) -> u8 {
+                $channel
+            }
+        }
+
+        impl crate::adc::AdcPin<peripherals::$inst> for crate::adc::$channel_type {}
+
+        impl embedded_hal_02::adc::Channel<peripherals::$inst> for crate::adc::$channel_type {
+            type ID = u8;
+
+            fn channel() -> u8 {
+                $channel
+            }
+        }
+    };
+}
+
+/// Start-up time of the temperature sensor, in microseconds
+const TS_START_US: u32 = 10;
+
+/// Voltage of the temperature sensor at 25 °C in millivolts, typical
+const TS_V25_MV: f32 = 1450.0;
+
+/// Slope of the voltage of the temperature sensor in millivolts per °C, typical
+const TS_AVG_SLOPE_MV_PER_C: f32 = 4.1;
+
+/// Longest conversion, at the longest sample time, in ADC clocks
+const MAX_CONVERSION_CLOCKS: u32 = 492;
+
+// ADC_STAT
+const STAT_WDE: u32 = 1 << 0;
+const STAT_EOC: u32 = 1 << 1;
+const STAT_EOIC: u32 = 1 << 2;
+
+// ADC_CTL0
+const CTL0_WDCHSEL_OFFSET: u32 = 0;
+const CTL0_WDCHSEL: u32 = 0b11111 << 0;
+const CTL0_EOCIE: u32 = 1 << 5;
+const CTL0_WDEIE: u32 = 1 << 6;
+const CTL0_EOICIE: u32 = 1 << 7;
+const CTL0_SM: u32 = 1 << 8;
+const CTL0_WDSC: u32 = 1 << 9;
+const CTL0_SYNCM: u32 = 0b1111 << 16;
+const CTL0_SYNCM_REGULAR_PARALLEL: u32 = 0b0110 << 16;
+const CTL0_SYNCM_REGULAR_FOLLOW_UP_FAST: u32 = 0b0111 << 16;
+const CTL0_IWDEN: u32 = 1 << 22;
+const CTL0_RWDEN: u32 = 1 << 23;
+
+// ADC_CTL1
+const CTL1_ADCON: u32 = 1 << 0;
+const CTL1_CTN: u32 = 1 << 1;
+const CTL1_CLB: u32 = 1 << 2;
+const CTL1_RSTCLB: u32 = 1 << 3;
+const CTL1_DMA: u32 = 1 << 8;
+const CTL1_ETSIC_OFFSET: u32 = 12;
+const CTL1_ETSIC: u32 = 0b111 << 12;
+const CTL1_ETSIC_SWICST: u32 = 0b111 << 12;
+const CTL1_ETEIC: u32 = 1 << 15;
+const CTL1_ETSRC_OFFSET: u32 = 17;
+const CTL1_ETSRC: u32 = 0b111 << 17;
+const CTL1_ETSRC_SWRCST: u32 = 0b111 << 17;
+const CTL1_ETERC: u32 = 1 << 20;
+const CTL1_SWICST: u32 = 1 << 21;
+const CTL1_SWRCST: u32 = 1 << 22;
+const CTL1_TSVREN: u32 = 1 << 23;
+
+// ADC_RSQ0
+const RSQ0_RL_OFFSET: u32 = 20;
+
+// ADC_ISQ
+const ISQ_IL_OFFSET: u32 = 20;
+
+// ADC_OVSAMPCTL
+const OVSAMPCTL_OVSEN: u32 = 1 << 0;
+const OVSAMPCTL_OVSR_OFFSET: u32 = 2;
+const OVSAMPCTL_OVSR: u32 = 0b111 << 2;
+const OVSAMPCTL_OVSS_OFFSET: u32 = 5;
+const OVSAMPCTL_OVSS: u32 = 0b1111 << 5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vref_from_vrefint() {
+        // 1.2 V reads 1489 with VREF+ at 3.3 V.
+        assert_eq!(VrefInt::to_vref_mv(1489), 3300);
+        assert_eq!(VrefInt::to_vref_mv(4095), VrefInt::MILLIVOLTS);
+        // A reading of zero doesn't divide by zero.
+        assert_eq!(VrefInt::to_vref_mv(0), VrefInt::MILLIVOLTS * 4095);
+    }
+
+    #[test]
+    fn temperature_at_typical_values() {
+        // 1.45 V with VREF+ at 3.3 V is 25 °C, and every 4.1 mV less one degree more.
+        let at_25 = Temperature::to_celsius(1799, 3300);
+        assert!((at_25 - 25.0).abs() < 0.1);
+        let at_35 = Temperature::to_celsius(1799 - 51, 3300);
+        assert!((at_35 - 35.0).abs() < 0.5);
+    }
+}
@@ -0,0 +1,70 @@
+//! Trigger sources of the conversions
+
+use super::*;
+
+/// What starts the conversions of the regular sequence, see `ETSRC` in `ADC_CTL1`
+///
+/// Not every ADC has every trigger, see [`Instance`]. Selecting a trigger the ADC doesn't have
+/// panics. The triggers sharing a value of `ETSRC` are told apart by an AFIO remap, which is
+/// selected along with the trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RegularTrigger {
+    /// Software starts the conversions
+    Software,
+    /// The compare events of TIMER0 channel 0
+    Timer0Ch0,
+    /// The compare events of TIMER0 channel 1
+    Timer0Ch1,
+    /// The compare events of TIMER0 channel 2
+    Timer0Ch2,
+    /// The compare events of TIMER1 channel 1
+    Timer1Ch1,
+    /// The trigger output `TRGO` of TIMER2, e.g. at its update events, see
+    /// [`crate::timer::low_level::MasterMode`]
+    Timer2Trgo,
+    /// The compare events of TIMER3 channel 3
+    Timer3Ch3,
+    /// The trigger output `TRGO` of TIMER7
+    Timer7Trgo,
+    /// The EXTI line 11
+    Exti11,
+}
+
+/// What starts the conversions of the injected group, see `ETSIC` in `ADC_CTL1`
+///
+/// Like [`RegularTrigger`], not every ADC has every trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InjectedTrigger {
+    /// Software starts the conversions
+    Software,
+    /// The trigger output `TRGO` of TIMER0
+    Timer0Trgo,
+    /// The compare events of TIMER0 channel 3
+    Timer0Ch3,
+    /// The trigger output `TRGO` of TIMER1
+    Timer1Trgo,
+    /// The compare events of TIMER1 channel 0
+    Timer1Ch0,
+    /// The compare events of TIMER2 channel 3
+    Timer2Ch3,
+    /// The trigger output `TRGO` of TIMER3
+    Timer3Trgo,
+    /// The compare events of TIMER7 channel 3
+    Timer7Ch3,
+    /// The EXTI line 15
+    Exti15,
+}
+
+/// Select `trigger` for the regular sequence of ADC `T`, with external triggers enabled.
+pub(super) fn select_regular<T: Instance>(trigger: RegularTrigger) {
+    let etsrc = unwrap!(T::regular_trigger(trigger), "trigger not available for this ADC");
+    modify_ctl1::<T>(CTL1_ETERC | (etsrc << CTL1_ETSRC_OFFSET), CTL1_ETSRC);
+}
+
+/// Select `trigger` for the injected group of ADC `T`, with external triggers enabled.
+pub(super) fn select_injected<T: Instance>(trigger: InjectedTrigger) {
+    let etsic = unwrap!(T::injected_trigger(trigger), "trigger not available for this ADC");
+    modify_ctl1::<T>(CTL1_ETEIC | (etsic << CTL1_ETSIC_OFFSET), CTL1_ETSIC);
+}
@@ -0,0 +1,172 @@
+//! Analog watchdog
+//!
+//! The analog watchdog compares the results of one channel, or of all channels, against a low
+//! and a high threshold as the ADC converts them, in the regular sequence and in the injected
+//! group. A result out of range is an event of the watchdog, which wakes
+//! [`AnalogWatchdog::wait_out_of_range`] or calls a callback from the interrupt, so e.g. a
+//! battery undervoltage is noticed without a task looking at every conversion.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::continuous::{ContinuousAdc, Trigger};
+//! use embassy_gd32::adc::{Adc, RegularTrigger, SampleTime, Sequence};
+//!
+//! let adc = Adc::new(p.ADC0);
+//! let mut battery = p.PA2;
+//! // Below 3000 is an undervoltage, there is no upper limit.
+//! let mut watchdog = adc.watchdog(&battery, 3000, 4095);
+//! let mut sequence = Sequence::new();
+//! sequence.push(&mut battery, SampleTime::Cycles479_5);
+//! // TIMER2 sets the sample rate, the task doesn't need to read the results.
+//! let mut buf = [0; 2];
+//! let trigger = Trigger::Triggered(RegularTrigger::Timer2Trgo);
+//! let _samples = ContinuousAdc::new(&adc, p.DMA0_CH0, &sequence, trigger, &mut buf);
+//! watchdog.wait_out_of_range().await;
+//! # }
+//! ```
+//!
+//! The watchdog watches the conversions whichever driver starts them, it needs no DMA of its own.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use atomic_polyfill::Ordering;
+use critical_section::Mutex;
+
+use super::*;
+
+/// State of the analog watchdog shared with the interrupt handler
+pub(crate) struct WatchdogState {
+    waker: AtomicWaker,
+    /// The watchdog is in use.
+    taken: AtomicBool,
+    /// A result was out of range since the last wait.
+    out_of_range: AtomicBool,
+    callback: Mutex<Cell<Option<fn()>>>,
+}
+
+impl WatchdogState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            taken: AtomicBool::new(false),
+            out_of_range: AtomicBool::new(false),
+            callback: Mutex::new(Cell::new(None)),
+        }
+    }
+}
+
+/// Analog watchdog of an ADC, see [`Adc::watchdog`] and [`Adc::watchdog_all`]
+pub struct AnalogWatchdog<'a, T: Instance> {
+    _adc: PhantomData<&'a T>,
+}
+
+impl<'d, T: Instance> Adc<'d, T> {
+    /// Watch the results of `pin` against `low` and `high`. A result below `low` or above `high`
+    /// is out of range.
+    ///
+    /// The watchdog only borrows the ADC, so it watches the conversions of the other drivers
+    /// meanwhile. Panics if a threshold is above 4095, or if the watchdog is already in use.
+    pub fn watchdog<'a>(&'a self, pin: &impl AdcPin<T>, low: u16, high: u16) -> AnalogWatchdog<'a, T> {
+        AnalogWatchdog::new(CTL0_WDSC | ((pin.channel() as u32) << CTL0_WDCHSEL_OFFSET), low, high)
+    }
+
+    /// Watch the results of all channels against `low` and `high`, like [`Adc::watchdog`].
+    pub fn watchdog_all<'a>(&'a self, low: u16, high: u16) -> AnalogWatchdog<'a, T> {
+        AnalogWatchdog::new(0, low, high)
+    }
+}
+
+impl<'a, T: Instance> AnalogWatchdog<'a, T> {
+    fn new(channels: u32, low: u16, high: u16) -> Self {
+        assert!(
+            !T::state().watchdog.taken.swap(true, Ordering::Acquire),
+            "analog watchdog already in use"
+        );
+        let mut this = Self { _adc: PhantomData };
+        this.set_thresholds(low, high);
+        T::state().watchdog.out_of_range.store(false, Ordering::Relaxed);
+        unsafe { T::regs().stat.write(|w| w.bits(!STAT_WDE)) };
+        modify_ctl0::<T>(
+            CTL0_RWDEN | CTL0_IWDEN | channels,
+            CTL0_WDSC | CTL0_WDCHSEL | CTL0_WDEIE,
+        );
+        this
+    }
+
+    /// Set the thresholds. A result below `low` or above `high` is out of range.
+    ///
+    /// Panics if a threshold is above 4095.
+    pub fn set_thresholds(&mut self, low: u16, high: u16) {
+        assert!(low <= 0xFFF && high <= 0xFFF, "threshold out of range");
+        let r = T::regs();
+        unsafe {
+            r.wdlt.write(|w| w.bits(low as u32));
+            r.wdht.write(|w| w.bits(high as u32));
+        }
+    }
+
+    /// Whether a result was out of range since the last wait, without waiting.
+    pub fn is_out_of_range(&self) -> bool {
+        T::state().watchdog.out_of_range.load(Ordering::Acquire) || T::regs().stat.read().bits() & STAT_WDE != 0
+    }
+
+    /// Wait for a result out of range.
+    ///
+    /// Returns right away if a result was out of range since the last call.
+    pub async fn wait_out_of_range(&mut self) {
+        let r = T::regs();
+        poll_fn(|cx| {
+            T::state().watchdog.waker.register(cx.waker());
+            let event = T::state().watchdog.out_of_range.swap(false, Ordering::AcqRel);
+            if event || r.stat.read().bits() & STAT_WDE != 0 {
+                unsafe { r.stat.write(|w| w.bits(!STAT_WDE)) };
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again, unless there is a callback.
+            modify_ctl0::<T>(CTL0_WDEIE, 0);
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Call `callback` from the ADC interrupt for each result out of range, e.g. to switch off a
+    /// load right away. `None` removes the callback.
+    ///
+    /// The interrupt stays enabled while there is a callback, so the conversions out of range
+    /// should be rare enough for the interrupt to keep up, e.g. paced by a timer trigger.
+    pub fn set_callback(&mut self, callback: Option<fn()>) {
+        critical_section::with(|cs| T::state().watchdog.callback.borrow(cs).set(callback));
+        if callback.is_some() {
+            modify_ctl0::<T>(CTL0_WDEIE, 0);
+        } else {
+            modify_ctl0::<T>(0, CTL0_WDEIE);
+        }
+    }
+}
+
+impl<'a, T: Instance> Drop for AnalogWatchdog<'a, T> {
+    fn drop(&mut self) {
+        modify_ctl0::<T>(0, CTL0_RWDEN | CTL0_IWDEN | CTL0_WDSC | CTL0_WDEIE);
+        critical_section::with(|cs| T::state().watchdog.callback.borrow(cs).set(None));
+        T::state().watchdog.taken.store(false, Ordering::Release);
+    }
+}
+
+/// Handle an event of the analog watchdog, if its interrupt is enabled.
+pub(super) unsafe fn on_interrupt<T: Instance>(stat: u32, ctl0: u32) {
+    if stat & STAT_WDE == 0 || ctl0 & CTL0_WDEIE == 0 {
+        return;
+    }
+    let r = T::regs();
+    let state = &T::state().watchdog;
+    r.stat.write(|w| w.bits(!STAT_WDE));
+    let callback = critical_section::with(|cs| state.callback.borrow(cs).get());
+    match callback {
+        Some(callback) => callback(),
+        None => r.ctl0.modify(|r, w| w.bits(r.bits() & !CTL0_WDEIE)),
+    }
+    state.out_of_range.store(true, Ordering::Release);
+    state.waker.wake();
+}
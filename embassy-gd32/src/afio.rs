@@ -0,0 +1,252 @@
+//! Alternate function IO (AFIO) pin remapping.
+//!
+//! On GD32 chips a peripheral's pins are not selected per pin, but per peripheral: each remappable
+//! peripheral has a small field in `AFIO_PCF0`/`AFIO_PCF1` choosing between a fixed set of pin
+//! layouts. The available layouts for each peripheral are declared in the chip file.
+//!
+//! Drivers select the layout from the pins they're given, and panic if the pins belong to different
+//! layouts, e.g. [`crate::usart::Pins`] for the USART pins. For a peripheral without driver,
+//! [`remap`] selects a layout by hand, before the peripheral is used:
+//!
+//! ```no_run
+//! use embassy_gd32::afio::{self, Remap, Usart0Remap};
+//!
+//! // USART0 TX/RX on PB6/PB7 instead of PA9/PA10.
+//! afio::remap::<Usart0Remap>(Remap::Full);
+//! ```
+#![macro_use]
+
+use atomic_polyfill::{AtomicU32, Ordering};
+
+use crate::pac;
+
+/// Pin layout selection for a remappable peripheral.
+///
+/// Not every peripheral supports every layout; see the documentation of the remap target for the
+/// pins used by each one. Selecting a layout the peripheral doesn't have panics.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Remap {
+    /// Default pin layout (reset state).
+    None,
+    /// Partial remap. For TIMER1 this is "partial remap 1".
+    Partial,
+    /// Second partial remap, only available for TIMER1.
+    Partial2,
+    /// Full remap.
+    Full,
+}
+
+/// Set of [`Remap`] layouts.
+///
+/// Pin traits report the layouts in which a pin carries a signal as a `RemapSet`, drivers then
+/// pick the layout that all their pins agree on, see [`remap_for_pins`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RemapSet(u8);
+
+impl RemapSet {
+    /// No layout.
+    pub const EMPTY: Self = Self(0);
+    /// All layouts.
+    pub const ALL: Self = Self(0b1111);
+
+    /// This set with `remap` added.
+    pub const fn with(self, remap: Remap) -> Self {
+        Self(self.0 | 1 << remap as u8)
+    }
+
+    /// Whether `remap` is in the set.
+    pub const fn contains(self, remap: Remap) -> bool {
+        self.0 & (1 << remap as u8) != 0
+    }
+
+    /// Layouts contained in both sets.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Whether the set contains no layout.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The first layout of the set, in declaration order of [`Remap`].
+    pub fn first(self) -> Option<Remap> {
+        [Remap::None, Remap::Partial, Remap::Partial2, Remap::Full]
+            .into_iter()
+            .find(|r| self.contains(*r))
+    }
+}
+
+/// Serial wire / JTAG debug port configuration.
+///
+/// Disabling (part of) the debug port releases the pins for general purpose use.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SwjConfig {
+    /// Full JTAG-DP and SW-DP (reset state).
+    Full,
+    /// JTAG-DP and SW-DP, but NJTRST (PB4) released.
+    NoNjtrst,
+    /// JTAG-DP disabled, SW-DP enabled. Releases PA15, PB3 and PB4.
+    SwdOnly,
+    /// JTAG-DP and SW-DP disabled. Releases all debug pins, including PA13/PA14.
+    ///
+    /// Note that this makes it impossible to attach a debugger without holding the chip in reset.
+    Disabled,
+}
+
+/// SPI0: `None` NSS/PA4 SCK/PA5 MISO/PA6 MOSI/PA7, `Full` NSS/PA15 SCK/PB3 MISO/PB4 MOSI/PB5.
+pub struct Spi0Remap;
+/// I2C0: `None` SCL/PB6 SDA/PB7, `Full` SCL/PB8 SDA/PB9.
+pub struct I2c0Remap;
+/// USART0: `None` TX/PA9 RX/PA10, `Full` TX/PB6 RX/PB7.
+pub struct Usart0Remap;
+/// USART1: `None` CTS/PA0 RTS/PA1 TX/PA2 RX/PA3 CK/PA4, `Full` CTS/PD3 RTS/PD4 TX/PD5 RX/PD6 CK/PD7.
+pub struct Usart1Remap;
+/// USART2: `None` TX/PB10 RX/PB11 CK/PB12 CTS/PB13 RTS/PB14, `Partial` TX/PC10 RX/PC11 CK/PC12
+/// CTS/PB13 RTS/PB14, `Full` TX/PD8 RX/PD9 CK/PD10 CTS/PD11 RTS/PD12.
+pub struct Usart2Remap;
+/// TIMER0: `None` ETI/PA12 CH0/PA8 CH1/PA9 CH2/PA10 CH3/PA11 BRKIN/PB12 CH0N/PB13 CH1N/PB14 CH2N/PB15,
+/// `Partial` like `None` but BRKIN/PA6 CH0N/PA7 CH1N/PB0 CH2N/PB1, `Full` ETI/PE7 CH0/PE9 CH1/PE11
+/// CH2/PE13 CH3/PE14 BRKIN/PE15 CH0N/PE8 CH1N/PE10 CH2N/PE12.
+pub struct Timer0Remap;
+/// TIMER1: `None` CH0/PA0 CH1/PA1 CH2/PA2 CH3/PA3, `Partial` CH0/PA15 CH1/PB3 CH2/PA2 CH3/PA3,
+/// `Partial2` CH0/PA0 CH1/PA1 CH2/PB10 CH3/PB11, `Full` CH0/PA15 CH1/PB3 CH2/PB10 CH3/PB11.
+pub struct Timer1Remap;
+/// TIMER2: `None` CH0/PA6 CH1/PA7 CH2/PB0 CH3/PB1, `Partial` CH0/PB4 CH1/PB5 CH2/PB0 CH3/PB1,
+/// `Full` CH0/PC6 CH1/PC7 CH2/PC8 CH3/PC9.
+pub struct Timer2Remap;
+/// TIMER3: `None` CH0/PB6 CH1/PB7 CH2/PB8 CH3/PB9, `Full` CH0/PD12 CH1/PD13 CH2/PD14 CH3/PD15.
+pub struct Timer3Remap;
+/// CAN0: `None` RX/PA11 TX/PA12, `Partial` RX/PB8 TX/PB9, `Full` RX/PD0 TX/PD1.
+pub struct Can0Remap;
+/// CAN1: `None` RX/PB12 TX/PB13, `Full` RX/PB5 TX/PB6.
+pub struct Can1Remap;
+/// ADC0 injected trigger `0b110`: `None` EXTI15, `Full` TIMER7 CH3, see
+/// [`crate::adc::InjectedTrigger`].
+pub struct Adc0InjectedTriggerRemap;
+/// ADC0 regular trigger `0b110`: `None` EXTI11, `Full` TIMER7 TRGO, see
+/// [`crate::adc::RegularTrigger`].
+pub struct Adc0RegularTriggerRemap;
+/// ADC1 injected trigger `0b110`, like [`Adc0InjectedTriggerRemap`].
+pub struct Adc1InjectedTriggerRemap;
+/// ADC1 regular trigger `0b110`, like [`Adc0RegularTriggerRemap`].
+pub struct Adc1RegularTriggerRemap;
+/// Serial wire / JTAG debug port, see [`SwjConfig`].
+pub struct SwjRemap;
+
+/// Select the pin layout of a peripheral.
+///
+/// This enables the AFIO clock if needed. It should be called before the peripheral's driver is
+/// created, since the pins are configured by the driver according to the selected layout.
+pub fn remap<T: RemapTarget>(setting: T::Setting) {
+    let bits = unwrap!(T::bits(setting), "remap setting not available for this peripheral");
+    let mask = ((1 << T::WIDTH) - 1) << T::OFFSET;
+
+    critical_section::with(|_| unsafe {
+        let rcu = &*pac::RCU::ptr();
+        rcu.apb2en.modify(|_, w| w.afen().set_bit());
+
+        let afio = &*pac::AFIO::ptr();
+        match T::REG {
+            sealed::Register::PCF0 => {
+                // SWJ_CFG is write-only and reads back as zero, so every read-modify-write of PCF0
+                // would silently re-enable the full debug port. Keep a shadow copy and write it back.
+                let mut swj = SWJ_CFG.load(Ordering::Relaxed);
+                if T::OFFSET == SWJ_CFG_OFFSET {
+                    swj = bits << SWJ_CFG_OFFSET;
+                    SWJ_CFG.store(swj, Ordering::Relaxed);
+                }
+                afio.pcf0
+                    .modify(|r, w| w.bits((r.bits() & !mask & !SWJ_CFG_MASK) | (bits << T::OFFSET) | swj));
+            }
+            sealed::Register::PCF1 => {
+                afio.pcf1.modify(|r, w| w.bits((r.bits() & !mask) | (bits << T::OFFSET)));
+            }
+        }
+    });
+}
+
+/// Select the layout of `T` that routes the signals to all the given pins.
+///
+/// `pins` holds the layouts of each pin passed to a driver, as returned by its pin trait. This is
+/// what lets drivers accept degraded `AnyPin`s: the layout is derived from the pins at runtime.
+/// Panics if the pins don't belong to a common layout.
+pub(crate) fn remap_for_pins<T: RemapTarget<Setting = Remap>>(pins: &[RemapSet]) {
+    let common = pins.iter().fold(RemapSet::ALL, |a, b| a.intersection(*b));
+    let remap = unwrap!(common.first(), "pins don't share an AFIO remap layout");
+    remap::<T>(remap);
+}
+
+/// Check that the given pins are all in the default layout, for peripherals without a remap.
+///
+/// Panics otherwise, like [`remap_for_pins`].
+pub(crate) fn check_default_layout(pins: &[RemapSet]) {
+    let common = pins.iter().fold(RemapSet::ALL, |a, b| a.intersection(*b));
+    assert!(common.contains(Remap::None), "pins can't carry the peripheral's signals");
+}
+
+const SWJ_CFG_OFFSET: u8 = 24;
+const SWJ_CFG_MASK: u32 = 0b111 << SWJ_CFG_OFFSET;
+
+static SWJ_CFG: AtomicU32 = AtomicU32::new(0);
+
+impl sealed::RemapTarget for SwjRemap {
+    type Setting = SwjConfig;
+    const REG: sealed::Register = sealed::Register::PCF0;
+    const OFFSET: u8 = SWJ_CFG_OFFSET;
+    const WIDTH: u8 = 3;
+
+    fn bits(setting: SwjConfig) -> Option<u32> {
+        Some(match setting {
+            SwjConfig::Full => 0b000,
+            SwjConfig::NoNjtrst => 0b001,
+            SwjConfig::SwdOnly => 0b010,
+            SwjConfig::Disabled => 0b100,
+        })
+    }
+}
+impl RemapTarget for SwjRemap {}
+
+pub(crate) mod sealed {
+    #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+    pub enum Register {
+        PCF0,
+        PCF1,
+    }
+
+    pub trait RemapTarget {
+        type Setting: Copy;
+
+        const REG: Register;
+        const OFFSET: u8;
+        const WIDTH: u8;
+
+        fn bits(setting: Self::Setting) -> Option<u32>;
+    }
+}
+
+/// A peripheral whose pins can be remapped through AFIO.
+pub trait RemapTarget: sealed::RemapTarget + 'static {}
+
+macro_rules! impl_remap {
+    ($type:ident, $reg:ident, $offset:expr, $width:expr, { $($setting:ident => $bits:expr),* $(,)? }) => {
+        impl crate::afio::sealed::RemapTarget for crate::afio::$type {
+            type Setting = crate::afio::Remap;
+            const REG: crate::afio::sealed::Register = crate::afio::sealed::Register::$reg;
+            const OFFSET: u8 = $offset;
+            const WIDTH: u8 = $width;
+
+            fn bits(setting: crate::afio::Remap) -> Option<u32> {
+                match setting {
+                    $(crate::afio::Remap::$setting => Some($bits),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+        }
+        impl crate::afio::RemapTarget for crate::afio::$type {}
+    };
+}
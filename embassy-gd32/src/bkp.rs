@@ -0,0 +1,280 @@
+//! Backup registers and tamper detection
+//!
+//! The backup domain holds [`DATA_COUNT`] 16-bit data registers. Like the RTC, they keep their
+//! values across resets, and on `VBAT` while the rest of the chip is powered down, which makes
+//! them useful for small state such as boot counters or bootloader flags. They are only cleared
+//! by a backup domain reset, or by a tamper event.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::bkp::Backup;
+//!
+//! let mut bkp = Backup::new(p.BKP);
+//! let boots = bkp.read_value::<u32>(0).unwrap_or(0);
+//! bkp.write_value(0, &(boots + 1));
+//! ```
+//!
+//! A tamper event is an edge on the tamper pin, PC13, to the configured level. It clears all data
+//! registers, so they can hold secrets that must not survive opening the enclosure.
+//!
+//! Some registers are reserved by other parts of the HAL, so application data must not use them:
+//! - [`crate::fwdgt::supervisor::STARVED_REGISTER`] (35), for the task that starved the
+//!   watchdog;
+//! - [`crate::bootloader::MAGIC_REGISTER`] (36), for the jump to the bootloader;
+//! - [`crate::rtc::BKP_DATA_FIRST`] to [`crate::rtc::BKP_DATA_LAST`] (37 to 41), for the
+//!   [`crate::rtc::Rtc`] offset.
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::fmc::eeprom::Value;
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::peripherals::{BKP, PC13};
+use crate::{interrupt, pac};
+
+/// Number of data registers
+pub const DATA_COUNT: usize = 42;
+
+static TAMPER_WAKER: AtomicWaker = AtomicWaker::new();
+static TAMPERED: AtomicBool = AtomicBool::new(false);
+static TAMPER_CALLBACK: Mutex<Cell<Option<fn()>>> = Mutex::new(Cell::new(None));
+
+/// Tamper pin level that triggers a tamper event
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TamperLevel {
+    /// A rising edge to high
+    High,
+    /// A falling edge to low
+    Low,
+}
+
+/// Backup data registers and tamper detection
+pub struct Backup<'d> {
+    _inner: PeripheralRef<'d, BKP>,
+    tamper_pin: Option<PeripheralRef<'d, PC13>>,
+}
+
+impl<'d> Backup<'d> {
+    /// Create a driver for the backup data registers.
+    pub fn new(inner: impl Peripheral<P = BKP> + 'd) -> Self {
+        into_ref!(inner);
+        critical_section::with(|_| unsafe { enable() });
+
+        Self {
+            _inner: inner,
+            tamper_pin: None,
+        }
+    }
+
+    /// Create a driver for the backup data registers with tamper detection on PC13.
+    ///
+    /// The pin is used by the backup domain, no GPIO configuration is needed.
+    pub fn new_with_tamper(
+        inner: impl Peripheral<P = BKP> + 'd,
+        tamper_pin: impl Peripheral<P = PC13> + 'd,
+        level: TamperLevel,
+    ) -> Self {
+        into_ref!(inner, tamper_pin);
+
+        let bkp = regs();
+        critical_section::with(|_| unsafe {
+            enable();
+            write_enable(true);
+
+            // Clear a stale event, so the interrupt doesn't fire right away.
+            bkp.tpcs.modify(|r, w| w.bits(r.bits() | TPCS_TER | TPCS_TIR));
+            let tpal = match level {
+                TamperLevel::High => 0,
+                TamperLevel::Low => TPCTL_TPAL,
+            };
+            // TPAL must be set before TPEN, changing it while enabled triggers a tamper event.
+            bkp.tpctl.write(|w| w.bits(tpal));
+            bkp.tpctl.write(|w| w.bits(tpal | TPCTL_TPEN));
+            bkp.tpcs.modify(|r, w| w.bits(r.bits() | TPCS_TPIE));
+            write_enable(false);
+
+            let irq = interrupt::TAMPER::steal();
+            irq.unpend();
+            irq.enable();
+        });
+
+        Self {
+            _inner: inner,
+            tamper_pin: Some(tamper_pin),
+        }
+    }
+
+    /// Read data register `n`.
+    ///
+    /// Panics if `n` is not below [`DATA_COUNT`].
+    pub fn read(&self, n: usize) -> u16 {
+        assert!(n < DATA_COUNT, "no such backup data register");
+        data_read(n)
+    }
+
+    /// Write data register `n`.
+    ///
+    /// Panics if `n` is not below [`DATA_COUNT`].
+    pub fn write(&mut self, n: usize, value: u16) {
+        assert!(n < DATA_COUNT, "no such backup data register");
+        critical_section::with(|_| unsafe { data_write(n, value) });
+    }
+
+    /// Read a value stored from data register `first` on, or `None` if the registers don't hold a
+    /// valid value.
+    ///
+    /// Each register holds two bytes of the value. Panics if the value doesn't fit.
+    pub fn read_value<T: Value>(&self, first: usize) -> Option<T> {
+        let mut buf = [0; 2 * DATA_COUNT];
+        let buf = &mut buf[..value_len::<T>(first)];
+        for (i, chunk) in buf.chunks_mut(2).enumerate() {
+            chunk.copy_from_slice(&data_read(first + i).to_le_bytes()[..chunk.len()]);
+        }
+        T::load(buf)
+    }
+
+    /// Store `value` in the data registers from `first` on.
+    ///
+    /// Each register holds two bytes of the value. Panics if the value doesn't fit.
+    pub fn write_value<T: Value>(&mut self, first: usize, value: &T) {
+        let mut buf = [0; 2 * DATA_COUNT];
+        let buf = &mut buf[..value_len::<T>(first)];
+        value.store(buf);
+        critical_section::with(|_| {
+            for (i, chunk) in buf.chunks(2).enumerate() {
+                let mut bytes = [0; 2];
+                bytes[..chunk.len()].copy_from_slice(chunk);
+                unsafe { data_write(first + i, u16::from_le_bytes(bytes)) };
+            }
+        })
+    }
+
+    /// Wait for a tamper event.
+    ///
+    /// Returns right away if a tamper event occurred since the last call. The data registers are
+    /// cleared by then.
+    pub async fn wait_for_tamper(&mut self) {
+        poll_fn(|cx| {
+            TAMPER_WAKER.register(cx.waker());
+            match TAMPERED.swap(false, Ordering::AcqRel) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Call `callback` from the tamper interrupt when a tamper event occurs, e.g. to clear other
+    /// secrets right away. `None` removes the callback.
+    pub fn set_tamper_callback(&mut self, callback: Option<fn()>) {
+        critical_section::with(|cs| TAMPER_CALLBACK.borrow(cs).set(callback));
+    }
+}
+
+impl<'d> Drop for Backup<'d> {
+    fn drop(&mut self) {
+        if self.tamper_pin.is_some() {
+            let bkp = regs();
+            critical_section::with(|_| unsafe {
+                write_enable(true);
+                bkp.tpcs.modify(|r, w| w.bits(r.bits() & !TPCS_TPIE));
+                bkp.tpctl.modify(|r, w| w.bits(r.bits() & !TPCTL_TPEN));
+                write_enable(false);
+            });
+        }
+    }
+}
+
+/// Number of bytes of a `T` stored from data register `first` on.
+fn value_len<T: Value>(first: usize) -> usize {
+    assert!(
+        first * 2 + T::SIZE <= 2 * DATA_COUNT,
+        "value doesn't fit in the backup data registers"
+    );
+    T::SIZE
+}
+
+#[interrupt]
+unsafe fn TAMPER() {
+    let bkp = regs();
+    let callback = critical_section::with(|cs| {
+        // Clear the event, which also re-arms tamper detection, and the interrupt flag.
+        write_enable(true);
+        bkp.tpcs.modify(|r, w| w.bits(r.bits() | TPCS_TER | TPCS_TIR));
+        write_enable(false);
+        TAMPER_CALLBACK.borrow(cs).get()
+    });
+
+    TAMPERED.store(true, Ordering::Release);
+    if let Some(callback) = callback {
+        callback();
+    }
+    TAMPER_WAKER.wake();
+}
+
+fn regs() -> &'static pac::bkp::RegisterBlock {
+    unsafe { &*pac::BKP::ptr() }
+}
+
+/// Enable the backup domain interface clocks.
+///
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn enable() {
+    let rcu = &*pac::RCU::ptr();
+    rcu.apb1en
+        .modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN | APB1EN_BKPIEN));
+}
+
+/// Allow or forbid writes to the backup domain.
+///
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn write_enable(enable: bool) {
+    let pmu = &*pac::PMU::ptr();
+    pmu.ctl.modify(|r, w| match enable {
+        true => w.bits(r.bits() | PMU_CTL_BKPWEN),
+        false => w.bits(r.bits() & !PMU_CTL_BKPWEN),
+    });
+}
+
+/// Address of data register `n`. Registers 0 to 9 and 10 to 41 are in two blocks.
+fn data_ptr(n: usize) -> *mut u32 {
+    let offset = match n {
+        0..=9 => 0x04 + 4 * n,
+        _ => 0x40 + 4 * (n - 10),
+    };
+    (pac::BKP::ptr() as usize + offset) as *mut u32
+}
+
+pub(crate) fn data_read(n: usize) -> u16 {
+    unsafe { data_ptr(n).read_volatile() as u16 }
+}
+
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn data_write(n: usize, value: u16) {
+    write_enable(true);
+    data_ptr(n).write_volatile(value as u32);
+    write_enable(false);
+}
+
+// BKP_TPCTL
+const TPCTL_TPEN: u32 = 1 << 0;
+const TPCTL_TPAL: u32 = 1 << 1;
+
+// BKP_TPCS
+const TPCS_TER: u32 = 1 << 0;
+const TPCS_TIR: u32 = 1 << 1;
+const TPCS_TPIE: u32 = 1 << 2;
+
+// RCU_APB1EN
+const APB1EN_BKPIEN: u32 = 1 << 27;
+const APB1EN_PMUEN: u32 = 1 << 28;
+
+// PMU_CTL
+const PMU_CTL_BKPWEN: u32 = 1 << 8;
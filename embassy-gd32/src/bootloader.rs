@@ -0,0 +1,107 @@
+//! Bootloader handoff for firmware updates
+//!
+//! A firmware update, e.g. over USB DFU, is written by a bootloader rather than by the
+//! application itself: the host asks the application to detach, and the application resets
+//! into the bootloader. The request crosses the reset as a magic value in backup data register
+//! [`MAGIC_REGISTER`], which keeps its value across resets, see [`crate::bkp`].
+//!
+//! The factory bootloader in the system memory is entered with [`jump_to_system_bootloader`].
+//! It can't be jumped to from a running application, whose peripherals and interrupts are set
+//! up, so the application resets, and [`check_system_bootloader`] jumps to it first thing after
+//! the reset:
+//!
+//! ```no_run
+//! use embassy_gd32::bootloader;
+//!
+//! // First thing in `main`, before `embassy_gd32::init`.
+//! unsafe { bootloader::check_system_bootloader() };
+//! let p = embassy_gd32::init(Default::default()).unwrap();
+//!
+//! // Later, e.g. when the host detaches the DFU runtime interface:
+//! bootloader::jump_to_system_bootloader();
+//! ```
+//!
+//! A bootloader of its own, e.g. built on `embassy-boot`, is requested the same way with
+//! [`reset_with_magic`] and a value of its choice, which the bootloader reads with
+//! [`take_magic`]. It writes the update with [`crate::fmc::Flash`], which implements the
+//! `embedded-storage` traits, created with [`crate::fmc::Flash::new_with_reserved`] to protect the
+//! bootloader, and starts the application with [`jump`].
+
+use cortex_m::peripheral::SCB;
+
+use crate::bkp;
+use crate::chip::SYSTEM_MEMORY_BASE;
+
+/// Backup data register that holds the magic value across the reset. It isn't used by the
+/// RTC, see [`crate::rtc::BKP_DATA_FIRST`].
+pub const MAGIC_REGISTER: usize = 36;
+
+/// Magic value of [`jump_to_system_bootloader`]
+pub const SYSTEM_BOOTLOADER_MAGIC: u16 = 0xB007;
+
+/// Store `magic` in [`MAGIC_REGISTER`] and reset the chip.
+///
+/// Zero means no request, so it shouldn't be used as a magic value.
+pub fn reset_with_magic(magic: u16) -> ! {
+    critical_section::with(|_| unsafe {
+        bkp::enable();
+        bkp::data_write(MAGIC_REGISTER, magic);
+    });
+    SCB::sys_reset()
+}
+
+/// Take the magic value stored by [`reset_with_magic`], clearing it so the next reset boots
+/// normally. Returns `None` if there is none.
+///
+/// This doesn't need [`crate::init`], so a bootloader can call it first thing.
+pub fn take_magic() -> Option<u16> {
+    critical_section::with(|_| unsafe {
+        bkp::enable();
+        match bkp::data_read(MAGIC_REGISTER) {
+            0 => None,
+            magic => {
+                bkp::data_write(MAGIC_REGISTER, 0);
+                Some(magic)
+            }
+        }
+    })
+}
+
+/// Reset into the factory bootloader in the system memory, see [`check_system_bootloader`].
+pub fn jump_to_system_bootloader() -> ! {
+    reset_with_magic(SYSTEM_BOOTLOADER_MAGIC)
+}
+
+/// Jump to the factory bootloader if [`jump_to_system_bootloader`] requested it before the
+/// reset, otherwise return. Other magic values are left for [`take_magic`].
+///
+/// # Safety
+///
+/// Must be called first thing after the reset, before [`crate::init`], so the bootloader
+/// starts with the chip in its reset state.
+pub unsafe fn check_system_bootloader() {
+    let requested = critical_section::with(|_| {
+        bkp::enable();
+        let requested = bkp::data_read(MAGIC_REGISTER) == SYSTEM_BOOTLOADER_MAGIC;
+        if requested {
+            bkp::data_write(MAGIC_REGISTER, 0);
+        }
+        requested
+    });
+    if requested {
+        jump(SYSTEM_MEMORY_BASE)
+    }
+}
+
+/// Start the program at `address`, whose vector table holds the initial stack pointer and the
+/// reset handler, e.g. the application from a bootloader.
+///
+/// # Safety
+///
+/// `address` must hold a valid vector table, and the program must cope with the peripherals as
+/// they are left, so this is best called before setting any up.
+pub unsafe fn jump(address: usize) -> ! {
+    trace!("jumping to 0x{:x}", address);
+    (*SCB::PTR).vtor.write(address as u32);
+    cortex_m::asm::bootload(address as *const u32)
+}
@@ -0,0 +1,428 @@
+//! Interrupt driven CAN with software queues
+//!
+//! [`BufferedCan`] keeps the frames to send and the received ones in buffers passed to
+//! [`BufferedCan::new`], so frames aren't lost while the application is busy, as long as the
+//! buffers are deep enough. The interrupts move the frames between the buffers and the hardware.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::can::buffered::BufferedCan;
+//! use embassy_gd32::can::{Can, Config, Envelope, Frame, StandardId};
+//!
+//! let can = Can::new(p.CAN0, p.PA11, p.PA12, Config::default());
+//! let mut tx_buf = [Frame::new(StandardId::ZERO, &[]).unwrap(); 16];
+//! let mut rx_buf = [None::<Envelope>; 16];
+//! let mut can = BufferedCan::new(can, &mut tx_buf, &mut rx_buf);
+//!
+//! let envelope = can.read().await.unwrap();
+//! can.write(&envelope.frame).await;
+//! # }
+//! ```
+//!
+//! The queued frame with the highest priority, i.e. the lowest identifier, goes to the next empty
+//! mailbox. Frames with the same identifier are sent in the order they were written. The
+//! interrupts of CAN0 are shared with the USB device.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::ptr;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::*;
+use crate::interrupt;
+use crate::interrupt::InterruptExt;
+
+/// Frames lost on reception
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Overruns {
+    /// Number of times a frame was lost because a hardware FIFO was full, which happens if the
+    /// interrupt is delayed for longer than three frames take
+    pub fifo: u32,
+    /// Frames dropped because the receive buffer was full
+    pub buffer: u32,
+}
+
+/// Queue in a buffer lent by [`BufferedCan`]
+struct Queue<E> {
+    buf: *mut E,
+    cap: usize,
+    len: usize,
+}
+
+unsafe impl<E> Send for Queue<E> {}
+
+impl<E: Copy> Queue<E> {
+    const fn new() -> Self {
+        Self {
+            buf: ptr::null_mut(),
+            cap: 0,
+            len: 0,
+        }
+    }
+
+    fn slot(&self, i: usize) -> *mut E {
+        unsafe { self.buf.add(i) }
+    }
+
+    fn get(&self, i: usize) -> E {
+        unsafe { self.slot(i).read() }
+    }
+
+    fn push(&mut self, e: E) -> Result<(), E> {
+        if self.len == self.cap {
+            return Err(e);
+        }
+        unsafe { self.slot(self.len).write(e) };
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<E> {
+        match self.len {
+            0 => None,
+            _ => Some(self.remove(0)),
+        }
+    }
+
+    /// Remove the element at `i`, moving the ones after it forward.
+    fn remove(&mut self, i: usize) -> E {
+        let e = self.get(i);
+        for j in i..self.len - 1 {
+            unsafe { self.slot(j).write(self.get(j + 1)) };
+        }
+        self.len -= 1;
+        e
+    }
+}
+
+struct Queues {
+    tx: Queue<Frame>,
+    rx: Queue<Option<Envelope>>,
+    #[cfg(feature = "time")]
+    sent_at: Option<embassy_time::Instant>,
+}
+
+pub struct State {
+    tx_waker: AtomicWaker,
+    rx_waker: AtomicWaker,
+    queues: Mutex<CriticalSectionRawMutex, RefCell<Queues>>,
+    fifo_overruns: AtomicU32,
+    buffer_overruns: AtomicU32,
+    /// [`TimeTriggered::send_time`] of the current configuration, for all drivers
+    pub(super) send_time: AtomicBool,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            tx_waker: AtomicWaker::new(),
+            rx_waker: AtomicWaker::new(),
+            queues: Mutex::const_new(
+                CriticalSectionRawMutex::new(),
+                RefCell::new(Queues {
+                    tx: Queue::new(),
+                    rx: Queue::new(),
+                    #[cfg(feature = "time")]
+                    sent_at: None,
+                }),
+            ),
+            fifo_overruns: AtomicU32::new(0),
+            buffer_overruns: AtomicU32::new(0),
+            send_time: AtomicBool::new(false),
+        }
+    }
+}
+
+/// CAN driver with software queues
+pub struct BufferedCan<'d, T: Instance> {
+    can: Can<'d, T>,
+    _bufs: PhantomData<(&'d mut [Frame], &'d mut [Option<Envelope>])>,
+    /// Overruns already returned by `try_read`
+    reported: Overruns,
+}
+
+impl<'d, T: Instance> BufferedCan<'d, T> {
+    /// Queue the frames to send in `tx_buf` and the received ones in `rx_buf`. The contents of the
+    /// buffers don't matter, and neither may be empty.
+    pub fn new(can: Can<'d, T>, tx_buf: &'d mut [Frame], rx_buf: &'d mut [Option<Envelope>]) -> Self {
+        assert!(
+            !tx_buf.is_empty() && !rx_buf.is_empty(),
+            "CAN buffers must not be empty"
+        );
+
+        let state = T::state();
+        critical_section::with(|cs| {
+            let mut queues = state.queues.borrow(cs).borrow_mut();
+            queues.tx = Queue {
+                buf: tx_buf.as_mut_ptr(),
+                cap: tx_buf.len(),
+                len: 0,
+            };
+            queues.rx = Queue {
+                buf: rx_buf.as_mut_ptr(),
+                cap: rx_buf.len(),
+                len: 0,
+            };
+            #[cfg(feature = "time")]
+            {
+                queues.sent_at = None;
+            }
+        });
+        state.fifo_overruns.store(0, Ordering::Relaxed);
+        state.buffer_overruns.store(0, Ordering::Relaxed);
+
+        regs::<T>().write(
+            INTEN,
+            INTEN_TMEIE | INTEN_RFNEIE0 | INTEN_RFOIE0 | INTEN_RFNEIE1 | INTEN_RFOIE1,
+        );
+        unsafe {
+            let irq = T::TxInterrupt::steal();
+            irq.unpend();
+            irq.enable();
+            let irq = T::Rx0Interrupt::steal();
+            irq.unpend();
+            irq.enable();
+            let irq = T::Rx1Interrupt::steal();
+            irq.unpend();
+            irq.enable();
+        }
+
+        Self {
+            can,
+            _bufs: PhantomData,
+            reported: Overruns::default(),
+        }
+    }
+
+    /// Queue `frame`, returning `false` if the transmit buffer is full.
+    pub fn try_write(&mut self, frame: &Frame) -> bool {
+        critical_section::with(|cs| {
+            let mut queues = T::state().queues.borrow(cs).borrow_mut();
+            if queues.tx.push(*frame).is_err() {
+                return false;
+            }
+            load_mailboxes::<T>(&mut queues.tx);
+            true
+        })
+    }
+
+    /// Queue `frame`, waiting for space in the transmit buffer.
+    pub async fn write(&mut self, frame: &Frame) {
+        poll_fn(|cx| {
+            T::state().tx_waker.register(cx.waker());
+            match self.try_write(frame) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait until all queued frames are sent.
+    pub async fn flush(&mut self) {
+        poll_fn(|cx| {
+            T::state().tx_waker.register(cx.waker());
+            let empty = critical_section::with(|cs| T::state().queues.borrow(cs).borrow().tx.len == 0);
+            match empty && regs::<T>().read(TSTAT) & TSTAT_TME == TSTAT_TME {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Take the next received frame.
+    ///
+    /// Overruns since the last call are returned first, once for each kind, see
+    /// [`BufferedCan::overruns`] for their number.
+    pub fn try_read(&mut self) -> Result<Envelope, TryReceiveError> {
+        let overruns = self.overruns();
+        if overruns.fifo != self.reported.fifo {
+            self.reported.fifo = overruns.fifo;
+            return Err(TryReceiveError::Overrun(Overrun::Fifo));
+        }
+        if overruns.buffer != self.reported.buffer {
+            self.reported.buffer = overruns.buffer;
+            return Err(TryReceiveError::Overrun(Overrun::Buffer));
+        }
+        critical_section::with(|cs| T::state().queues.borrow(cs).borrow_mut().rx.pop())
+            .flatten()
+            .ok_or(TryReceiveError::Empty)
+    }
+
+    /// Take the next received frame, waiting for one, see [`BufferedCan::try_read`].
+    pub async fn read(&mut self) -> Result<Envelope, Overrun> {
+        poll_fn(|cx| {
+            T::state().rx_waker.register(cx.waker());
+            match self.try_read() {
+                Ok(envelope) => Poll::Ready(Ok(envelope)),
+                Err(TryReceiveError::Overrun(e)) => Poll::Ready(Err(e)),
+                Err(TryReceiveError::Empty) => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Whether the controller is bus-off, see [`Can::is_bus_off`].
+    pub fn is_bus_off(&self) -> bool {
+        self.can.is_bus_off()
+    }
+
+    /// Wait until the controller has rejoined the bus, see [`Can::wait_bus_off_recovered`]. The
+    /// queued frames are sent afterwards.
+    pub async fn wait_bus_off_recovered(&mut self) {
+        self.can.wait_bus_off_recovered().await
+    }
+
+    /// The error counters and state, see [`Can::error_counters`].
+    pub fn error_counters(&self) -> ErrorCounters {
+        self.can.error_counters()
+    }
+
+    /// The error of the last frame with one, see [`Can::last_error`].
+    pub fn last_error(&mut self) -> Option<BusError> {
+        self.can.last_error()
+    }
+
+    /// When the last successful transmission ended, as seen by the interrupt handler, or `None`
+    /// if no frame was sent yet.
+    #[cfg(feature = "time")]
+    pub fn last_sent_at(&self) -> Option<embassy_time::Instant> {
+        critical_section::with(|cs| T::state().queues.borrow(cs).borrow().sent_at)
+    }
+
+    /// Frames lost on reception since the driver was created.
+    pub fn overruns(&self) -> Overruns {
+        let state = T::state();
+        Overruns {
+            fifo: state.fifo_overruns.load(Ordering::Relaxed),
+            buffer: state.buffer_overruns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for BufferedCan<'d, T> {
+    fn drop(&mut self) {
+        // The interrupts stay enabled in the NVIC, as CAN0 shares them with the USB device.
+        regs::<T>().write(INTEN, 0);
+        critical_section::with(|cs| {
+            let mut queues = T::state().queues.borrow(cs).borrow_mut();
+            queues.tx = Queue::new();
+            queues.rx = Queue::new();
+        });
+    }
+}
+
+/// Move the queued frames with the highest priority to the empty mailboxes.
+///
+/// The controller sends the mailboxes by identifier, and those with the same identifier in
+/// mailbox order, so a frame waits while one with the same identifier is in a mailbox.
+fn load_mailboxes<T: Instance>(tx: &mut Queue<Frame>) {
+    let r = regs::<T>();
+    loop {
+        let tstat = r.read(TSTAT);
+        if tstat & TSTAT_TME == 0 {
+            return;
+        }
+        let mut pending = [None; 3];
+        for (index, id) in pending.iter_mut().enumerate() {
+            if tstat & (TSTAT_TME0 << index) == 0 {
+                *id = Some(r.read(TMI + 0x10 * index) & !(TMI_TEN | MI_FT));
+            }
+        }
+
+        let next = (0..tx.len)
+            .map(|i| (i, tx.get(i)))
+            .filter(|(_, frame)| !pending.contains(&Some(frame.id.to_bits())))
+            .min_by_key(|(_, frame)| frame.priority());
+        match next {
+            Some((i, _)) => {
+                let frame = tx.remove(i);
+                write_mailbox::<T>((tstat >> TSTAT_NUM_OFFSET) & 0b11, &frame);
+            }
+            None => return,
+        }
+    }
+}
+
+unsafe fn on_tx_interrupt<T: Instance>() {
+    // The interrupts of CAN0 also fire for the USB device, and do nothing without a `BufferedCan`.
+    if regs::<T>().read(INTEN) & INTEN_TMEIE == 0 {
+        return;
+    }
+    // Writing `MTFx` clears the other status flags of the mailbox too. A mailbox finishing after
+    // the read fires the interrupt again.
+    let r = regs::<T>();
+    let tstat = r.read(TSTAT);
+    r.write(TSTAT, tstat & TSTAT_MTF);
+    critical_section::with(|cs| {
+        let mut queues = T::state().queues.borrow(cs).borrow_mut();
+        #[cfg(feature = "time")]
+        if tstat & TSTAT_MTFNERR != 0 {
+            queues.sent_at = Some(embassy_time::Instant::now());
+        }
+        load_mailboxes::<T>(&mut queues.tx);
+    });
+    T::state().tx_waker.wake();
+}
+
+unsafe fn on_rx_interrupt<T: Instance>(fifo: Fifo) {
+    let r = regs::<T>();
+    let state = T::state();
+    let rfifo = RFIFO0 + 4 * fifo as usize;
+    if r.read(INTEN) & INTEN_TMEIE == 0 {
+        return;
+    }
+
+    // The flag is cleared by writing one, writing zero has no effect.
+    if r.read(rfifo) & RFIFO_RFO != 0 {
+        r.write(rfifo, RFIFO_RFO);
+        state.fifo_overruns.fetch_add(1, Ordering::Relaxed);
+    }
+    // Empty the FIFO even if the buffer is full, or the interrupt would fire again right away.
+    while let Some(envelope) = receive_from::<T>(fifo) {
+        let res = critical_section::with(|cs| state.queues.borrow(cs).borrow_mut().rx.push(Some(envelope)));
+        if res.is_err() {
+            state.buffer_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    state.rx_waker.wake();
+}
+
+macro_rules! impl_irq {
+    ($tx:ident, $rx0:ident, $rx1:ident, $inst:ident $(, shared with $usbd:ident)?) => {
+        #[interrupt]
+        unsafe fn $tx() {
+            on_tx_interrupt::<peripherals::$inst>();
+            $(
+                #[cfg(feature = "nightly")]
+                crate::usbd::on_interrupt::<peripherals::$usbd>();
+            )?
+        }
+
+        #[interrupt]
+        unsafe fn $rx0() {
+            on_rx_interrupt::<peripherals::$inst>(Fifo::Fifo0);
+            $(
+                #[cfg(feature = "nightly")]
+                crate::usbd::on_interrupt::<peripherals::$usbd>();
+            )?
+        }
+
+        #[interrupt]
+        unsafe fn $rx1() {
+            on_rx_interrupt::<peripherals::$inst>(Fifo::Fifo1)
+        }
+    };
+}
+
+impl_irq!(USBD_HP_CAN0_TX, USBD_LP_CAN0_RX0, CAN0_RX1, CAN0, shared with USBD);
+impl_irq!(CAN1_TX, CAN1_RX0, CAN1_RX1, CAN1);
@@ -0,0 +1,1139 @@
+//! Controller area network (CAN)
+//!
+//! CAN0 and CAN1 each have three transmit mailboxes and two receive FIFOs of three frames. The
+//! controller picks the FIFO of a received frame with filters, of which each instance has
+//! [`FILTER_COUNT`]. The filter registers of both instances are in CAN0, so CAN1 keeps the CAN0
+//! clock running.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::can::{Can, Config, Frame, StandardId};
+//!
+//! let mut can = Can::new(p.CAN0, p.PA11, p.PA12, Config::default());
+//! let id = StandardId::new(0x123).unwrap();
+//! can.blocking_transmit(&Frame::new(id, &[1, 2, 3]).unwrap());
+//! let envelope = can.blocking_receive().unwrap();
+//! ```
+//!
+//! All frames are received into FIFO 0 until [`Can::set_filter`] changes the filters. A frame is
+//! only sent once another node acknowledges it, so a lone node retransmits it forever, unless
+//! [`Config::auto_retransmit`] is disabled.
+//! [`Mode::SilentLoopback`] receives the sent frames without a bus, e.g. for a self-test.
+//!
+//! [`buffered::BufferedCan`] queues frames in software and moves them with interrupts, for async
+//! use.
+#![macro_use]
+
+use atomic_polyfill::Ordering;
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Pull};
+use crate::interrupt::Interrupt;
+use crate::time::Hertz;
+use crate::{pac, peripherals, Peripheral};
+
+pub mod buffered;
+
+/// Number of filters of each instance
+pub const FILTER_COUNT: usize = 14;
+
+/// Configuration error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The bit rate is zero or above 1 Mbit/s.
+    InvalidBitrate,
+    /// The sample point is not between 50% and 100% of the bit time.
+    InvalidSamplePoint,
+    /// No prescaler gives the bit rate exactly with the APB1 clock.
+    NoExactTiming,
+    /// A field of [`BitTiming`] is out of range.
+    InvalidTiming,
+}
+
+/// Bit timing, see `CAN_BT` in the user manual
+///
+/// A bit is `1 + seg1 + seg2` time quanta of `prescaler` APB1 clock cycles. The bus is sampled
+/// after `1 + seg1` quanta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BitTiming {
+    /// APB1 clock cycles per time quantum, 1 to 1024
+    pub prescaler: u16,
+    /// Time quanta between the synchronization segment and the sample point, 1 to 16
+    pub seg1: u8,
+    /// Time quanta after the sample point, 1 to 8
+    pub seg2: u8,
+    /// Synchronization jump width in time quanta, 1 to 4, and at most `seg2`
+    pub sjw: u8,
+}
+
+impl BitTiming {
+    /// Compute the timing for `config`, or check `config.timing`, for an APB1 clock of `pclk`.
+    fn new(pclk: Hertz, config: &Config) -> Result<Self, ConfigError> {
+        let timing = match config.timing {
+            Some(timing) => timing,
+            None => Self::compute(pclk.0, config)?,
+        };
+        timing.check()?;
+        Ok(timing)
+    }
+
+    /// Pick the most time quanta per bit, which gives the finest resynchronization, for which a
+    /// prescaler gives the bit rate exactly, then the sample point closest to the requested one
+    /// with that many quanta.
+    fn compute(pclk: u32, config: &Config) -> Result<Self, ConfigError> {
+        if config.bitrate == 0 || config.bitrate > 1_000_000 {
+            return Err(ConfigError::InvalidBitrate);
+        }
+        if !(500..1000).contains(&config.sample_point) {
+            return Err(ConfigError::InvalidSamplePoint);
+        }
+
+        for quanta in (MIN_QUANTA..=MAX_QUANTA).rev() {
+            let cycles = config.bitrate * quanta;
+            if pclk % cycles != 0 || pclk / cycles > MAX_PRESCALER {
+                continue;
+            }
+            let seg2 = ((quanta * (1000 - config.sample_point as u32) + 500) / 1000).clamp(1, 8);
+            let seg1 = quanta - 1 - seg2;
+            if seg1 > 16 {
+                continue;
+            }
+            return Ok(Self {
+                prescaler: (pclk / cycles) as u16,
+                seg1: seg1 as u8,
+                seg2: seg2 as u8,
+                sjw: config.sjw.min(seg2 as u8),
+            });
+        }
+        Err(ConfigError::NoExactTiming)
+    }
+
+    fn check(&self) -> Result<(), ConfigError> {
+        let valid = (1..=MAX_PRESCALER as u16).contains(&self.prescaler)
+            && (1..=16).contains(&self.seg1)
+            && (1..=8).contains(&self.seg2)
+            && (1..=4).contains(&self.sjw)
+            && self.sjw <= self.seg2;
+        match valid {
+            true => Ok(()),
+            false => Err(ConfigError::InvalidTiming),
+        }
+    }
+
+    /// `CAN_BT` value, without the test mode bits
+    fn bt(&self) -> u32 {
+        (self.prescaler as u32 - 1)
+            | ((self.seg1 as u32 - 1) << BT_BS1_OFFSET)
+            | ((self.seg2 as u32 - 1) << BT_BS2_OFFSET)
+            | ((self.sjw as u32 - 1) << BT_SJW_OFFSET)
+    }
+}
+
+/// Operating mode, see `CAN_BT` in the user manual
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Send and receive on the bus
+    Normal,
+    /// Receive frames and acknowledge nothing, the TX pin stays recessive. Monitors a bus without
+    /// disturbing it.
+    Silent,
+    /// Receive the sent frames, which are also sent on the bus. Frames from the bus aren't
+    /// received.
+    Loopback,
+    /// Receive the sent frames without using the bus, for a self-test
+    SilentLoopback,
+}
+
+/// What to do once the controller is bus-off, i.e. has left the bus after too many errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusOffRecovery {
+    /// Rejoin the bus after 128 times 11 recessive bits
+    Automatic,
+    /// Stay bus-off until [`Can::recover_bus_off`] starts the automatic recovery
+    Manual,
+}
+
+/// Time-triggered communication, see `TTC` in `CAN_CTL` in the user manual
+///
+/// The controller counts bit times in a 16-bit timer and captures it at the start of each sent
+/// and received frame, see [`Envelope::bus_time`] and [`Can::sent_bus_time`]. Schedules
+/// usually disable [`Config::auto_retransmit`] too, so a frame never misses its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeTriggered {
+    /// Send the captured time in the last two bytes of frames with 8 data bytes, little-endian,
+    /// instead of their data
+    pub send_time: bool,
+}
+
+/// CAN configuration
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Bit rate in bit/s, at most 1 Mbit/s
+    pub bitrate: u32,
+    /// Sample point in per mille of the bit time, 875 as recommended by CiA by default
+    pub sample_point: u16,
+    /// Largest synchronization jump width in time quanta, from 1 to 4
+    pub sjw: u8,
+    /// Bit timing to use instead of the one computed from the fields above
+    pub timing: Option<BitTiming>,
+    /// Retransmit a frame until it's sent when it loses arbitration or fails. If disabled, each
+    /// frame is only sent once.
+    pub auto_retransmit: bool,
+    /// Operating mode, [`Mode::Normal`] by default
+    pub mode: Mode,
+    /// Bus-off recovery, [`BusOffRecovery::Manual`] by default, which lets the application find
+    /// out why the node went bus-off first
+    pub bus_off_recovery: BusOffRecovery,
+    /// Time-triggered communication, disabled by default
+    pub time_triggered: Option<TimeTriggered>,
+    /// Keep the frames in a full receive FIFO and drop the arriving one. By default the arriving
+    /// frame replaces the last one in the FIFO.
+    pub lock_fifos: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bitrate: 500_000,
+            sample_point: 875,
+            sjw: 1,
+            timing: None,
+            auto_retransmit: true,
+            mode: Mode::Normal,
+            bus_off_recovery: BusOffRecovery::Manual,
+            time_triggered: None,
+            lock_fifos: false,
+        }
+    }
+}
+
+/// Standard 11-bit identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StandardId(u16);
+
+impl StandardId {
+    /// Identifier with the highest priority
+    pub const ZERO: Self = Self(0);
+    /// Identifier with the lowest priority
+    pub const MAX: Self = Self(0x7FF);
+
+    /// Create an identifier, if `raw` fits in 11 bits.
+    pub const fn new(raw: u16) -> Option<Self> {
+        match raw <= Self::MAX.0 {
+            true => Some(Self(raw)),
+            false => None,
+        }
+    }
+
+    /// The identifier as an integer.
+    pub const fn as_raw(self) -> u16 {
+        self.0
+    }
+}
+
+/// Extended 29-bit identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExtendedId(u32);
+
+impl ExtendedId {
+    /// Identifier with the highest priority
+    pub const ZERO: Self = Self(0);
+    /// Identifier with the lowest priority
+    pub const MAX: Self = Self(0x1FFF_FFFF);
+
+    /// Create an identifier, if `raw` fits in 29 bits.
+    pub const fn new(raw: u32) -> Option<Self> {
+        match raw <= Self::MAX.0 {
+            true => Some(Self(raw)),
+            false => None,
+        }
+    }
+
+    /// The identifier as an integer.
+    pub const fn as_raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Frame identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Id {
+    /// Standard 11-bit identifier
+    Standard(StandardId),
+    /// Extended 29-bit identifier
+    Extended(ExtendedId),
+}
+
+impl From<StandardId> for Id {
+    fn from(id: StandardId) -> Self {
+        Self::Standard(id)
+    }
+}
+
+impl From<ExtendedId> for Id {
+    fn from(id: ExtendedId) -> Self {
+        Self::Extended(id)
+    }
+}
+
+impl Id {
+    /// The identifier in the layout of `CAN_TMIx`, `CAN_RFIFOMIx` and the 32-bit filters
+    fn to_bits(self) -> u32 {
+        match self {
+            Id::Standard(id) => (id.0 as u32) << MI_SFID_OFFSET,
+            Id::Extended(id) => (id.0 << MI_EFID_OFFSET) | MI_FF,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        match bits & MI_FF {
+            0 => Id::Standard(StandardId((bits >> MI_SFID_OFFSET) as u16)),
+            _ => Id::Extended(ExtendedId(bits >> MI_EFID_OFFSET)),
+        }
+    }
+}
+
+/// Data or remote frame
+///
+/// A remote frame has no data, and asks the node sending frames with its identifier to send one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    id: Id,
+    len: u8,
+    data: [u8; 8],
+    remote: bool,
+}
+
+impl Frame {
+    /// Create a data frame, if `data` is at most 8 bytes.
+    pub fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+        let mut frame = Self {
+            id: id.into(),
+            len: data.len() as u8,
+            data: [0; 8],
+            remote: false,
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// Create a remote frame requesting `len` bytes, if `len` is at most 8.
+    pub fn new_remote(id: impl Into<Id>, len: u8) -> Option<Self> {
+        if len > 8 {
+            return None;
+        }
+        Some(Self {
+            id: id.into(),
+            len,
+            data: [0; 8],
+            remote: true,
+        })
+    }
+
+    /// The frame's identifier.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Whether this is a remote frame.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// The data length code, i.e. the length of the data, or the requested one of a remote frame.
+    pub fn dlc(&self) -> u8 {
+        self.len
+    }
+
+    /// The frame's data, empty for a remote frame.
+    pub fn data(&self) -> &[u8] {
+        match self.remote {
+            true => &[],
+            false => &self.data[..self.len as usize],
+        }
+    }
+
+    /// Arbitration order, the frame with the lowest value wins. A standard frame wins over an
+    /// extended one with the same 11 high bits, and a data frame over a remote one with the same
+    /// identifier.
+    fn priority(&self) -> u32 {
+        let rtr = self.remote as u32;
+        match self.id {
+            Id::Standard(id) => ((id.0 as u32) << 21) | (rtr << 20),
+            Id::Extended(id) => ((id.0 >> 18) << 21) | (0b11 << 19) | ((id.0 & 0x3FFFF) << 1) | rtr,
+        }
+    }
+}
+
+/// Receive FIFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fifo {
+    /// FIFO 0
+    Fifo0 = 0,
+    /// FIFO 1
+    Fifo1 = 1,
+}
+
+/// Transmit mailbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mailbox {
+    /// Mailbox 0
+    Mailbox0 = 0,
+    /// Mailbox 1
+    Mailbox1 = 1,
+    /// Mailbox 2
+    Mailbox2 = 2,
+}
+
+impl Mailbox {
+    fn from_index(index: u32) -> Self {
+        match index {
+            0 => Mailbox::Mailbox0,
+            1 => Mailbox::Mailbox1,
+            _ => Mailbox::Mailbox2,
+        }
+    }
+}
+
+/// A received frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Envelope {
+    /// The frame
+    pub frame: Frame,
+    /// FIFO the frame was received into
+    pub fifo: Fifo,
+    /// Index of the filter that accepted the frame, counted over the active filters of `fifo`
+    pub filter_index: u8,
+    /// The bus time captured at the start of the frame, with [`Config::time_triggered`]
+    pub bus_time: Option<u16>,
+    /// When the frame was taken from the FIFO. [`buffered::BufferedCan`] does so in the interrupt
+    /// handler, right after the frame was received.
+    #[cfg(feature = "time")]
+    pub timestamp: embassy_time::Instant,
+}
+
+/// Frames were lost on reception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Overrun {
+    /// A frame arrived while its receive FIFO was full, see [`Config::lock_fifos`]
+    Fifo,
+    /// A frame was received while the receive buffer of [`buffered::BufferedCan`] was full
+    Buffer,
+}
+
+/// Error of [`Can::try_receive`] and [`buffered::BufferedCan::try_read`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TryReceiveError {
+    /// No frame was received
+    Empty,
+    /// Frames were lost
+    Overrun(Overrun),
+}
+
+/// Error counters and state, see `CAN_ERR` in the user manual
+///
+/// The counters rise by 8 for most errors and drop by 1 for each successful frame. A node with a
+/// counter above 127 is error passive, and one with a transmit error counter above 255 is bus-off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorCounters {
+    /// Transmit error counter
+    pub transmit: u8,
+    /// Receive error counter
+    pub receive: u8,
+    /// A counter reached the warning limit of 96
+    pub warning: bool,
+    /// A counter exceeded 127, the node may only send passive error flags
+    pub passive: bool,
+    /// The node left the bus, see [`Can::is_bus_off`]
+    pub bus_off: bool,
+}
+
+/// Error in the last frame on the bus, see `ERRN` in `CAN_ERR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusError {
+    /// More than five equal bits in a row
+    Stuff,
+    /// A fixed-form field had a wrong bit
+    Form,
+    /// No node acknowledged a sent frame
+    Acknowledgement,
+    /// A sent recessive bit was read back dominant, outside of arbitration
+    BitRecessive,
+    /// A sent dominant bit was read back recessive
+    BitDominant,
+    /// The CRC of a received frame didn't match
+    Crc,
+}
+
+/// Receive filter, matching an identifier under a mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Filter {
+    id: u32,
+    mask: u32,
+    fifo: Fifo,
+}
+
+impl Filter {
+    /// Accept all frames into `fifo`.
+    pub const fn accept_all(fifo: Fifo) -> Self {
+        Self { id: 0, mask: 0, fifo }
+    }
+
+    /// Accept the standard frames whose identifier matches `id` in the bits set in `mask` into
+    /// `fifo`.
+    pub const fn standard(id: StandardId, mask: u16, fifo: Fifo) -> Self {
+        Self {
+            id: (id.0 as u32) << MI_SFID_OFFSET,
+            mask: (((mask & StandardId::MAX.0) as u32) << MI_SFID_OFFSET) | MI_FF,
+            fifo,
+        }
+    }
+
+    /// Accept the extended frames whose identifier matches `id` in the bits set in `mask` into
+    /// `fifo`.
+    pub const fn extended(id: ExtendedId, mask: u32, fifo: Fifo) -> Self {
+        Self {
+            id: (id.0 << MI_EFID_OFFSET) | MI_FF,
+            mask: ((mask & ExtendedId::MAX.0) << MI_EFID_OFFSET) | MI_FF,
+            fifo,
+        }
+    }
+}
+
+/// CAN driver
+pub struct Can<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    rx: PeripheralRef<'d, AnyPin>,
+    tx: PeripheralRef<'d, AnyPin>,
+}
+
+impl<'d, T: Instance> Can<'d, T> {
+    /// Create a CAN driver and join the bus.
+    ///
+    /// Returns once the controller has seen 11 recessive bits, i.e. an idle bus.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, rx, tx);
+
+        let timing = unwrap!(BitTiming::new(T::frequency(), &config), "invalid CAN configuration");
+        T::remap(&[RxPin::<T>::remaps(&*rx), TxPin::<T>::remaps(&*tx)]);
+        T::enable();
+        <peripherals::CAN0 as crate::cctl::sealed::CCTLPeripherial>::enable();
+        unsafe {
+            // A floating RX would keep the controller from joining the bus.
+            rx.set_as_af_pull(AFType::Input, Pull::Up);
+            tx.set_as_af(AFType::OutputPushPull);
+        }
+
+        // The RCU reset would also reset the filters of the other instance, which live in CAN0.
+        let r = regs::<T>();
+        r.write(CTL, CTL_SWRST);
+        while r.read(CTL) & CTL_SWRST != 0 {}
+
+        let mut this = Self {
+            _peri: peri,
+            rx: rx.map_into(),
+            tx: tx.map_into(),
+        };
+        this.set_filter(0, Some(Filter::accept_all(Fifo::Fifo0)));
+        for index in 1..FILTER_COUNT {
+            this.set_filter(index, None);
+        }
+        Self::configure(&config, &timing);
+        this
+    }
+
+    /// Change the configuration, leaving and joining the bus again.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        let timing = BitTiming::new(T::frequency(), config)?;
+        Self::configure(config, &timing);
+        Ok(())
+    }
+
+    fn configure(config: &Config, timing: &BitTiming) {
+        let r = regs::<T>();
+
+        Self::enter_init_mode();
+        let mode = match config.mode {
+            Mode::Normal => 0,
+            Mode::Silent => BT_SCMOD,
+            Mode::Loopback => BT_LCMOD,
+            Mode::SilentLoopback => BT_SCMOD | BT_LCMOD,
+        };
+        r.write(BT, timing.bt() | mode);
+        let ard = match config.auto_retransmit {
+            true => 0,
+            false => CTL_ARD,
+        };
+        let abor = match config.bus_off_recovery {
+            BusOffRecovery::Automatic => CTL_ABOR,
+            BusOffRecovery::Manual => 0,
+        };
+        let ttc = match config.time_triggered {
+            Some(_) => CTL_TTC,
+            None => 0,
+        };
+        let rfod = match config.lock_fifos {
+            true => CTL_RFOD,
+            false => 0,
+        };
+        r.modify(CTL, ard | abor | ttc | rfod, CTL_ARD | CTL_ABOR | CTL_TTC | CTL_RFOD);
+        let send_time = config.time_triggered.map_or(false, |ttc| ttc.send_time);
+        T::state().send_time.store(send_time, Ordering::Relaxed);
+        Self::leave_init_mode();
+    }
+
+    /// Leave the bus and enter initialization mode, in which the configuration can be changed.
+    ///
+    /// A frame that is being sent or received is finished first.
+    fn enter_init_mode() {
+        let r = regs::<T>();
+        r.modify(CTL, CTL_IWMOD, CTL_SLPWMOD);
+        while r.read(STAT) & (STAT_IWS | STAT_SLPWS) != STAT_IWS {}
+    }
+
+    /// Enter normal mode, which joins the bus once it's idle.
+    fn leave_init_mode() {
+        let r = regs::<T>();
+        r.modify(CTL, 0, CTL_IWMOD);
+        while r.read(STAT) & STAT_IWS != 0 {}
+    }
+
+    /// Set the filter at `index`, below [`FILTER_COUNT`], or disable it with `None`.
+    ///
+    /// A frame is received if any enabled filter accepts it, into the FIFO of the first one.
+    pub fn set_filter(&mut self, index: usize, filter: Option<Filter>) {
+        assert!(index < FILTER_COUNT, "CAN filter index out of range");
+        let bank = T::FIRST_FILTER + index;
+        let bit = 1 << bank;
+        let f = filter_regs();
+
+        // The filter registers are shared with the other instance.
+        critical_section::with(|_| {
+            f.modify(
+                FCTL,
+                FCTL_FLD | ((FILTER_COUNT as u32) << FCTL_HBC1F_OFFSET),
+                FCTL_HBC1F,
+            );
+            f.modify(FW, 0, bit);
+            if let Some(filter) = filter {
+                f.modify(FSCFG, bit, 0);
+                f.modify(FMCFG, 0, bit);
+                match filter.fifo {
+                    Fifo::Fifo0 => f.modify(FAFIFO, 0, bit),
+                    Fifo::Fifo1 => f.modify(FAFIFO, bit, 0),
+                }
+                f.write(FDATA0 + 8 * bank, filter.id);
+                f.write(FDATA1 + 8 * bank, filter.mask);
+                f.modify(FW, bit, 0);
+            }
+            f.modify(FCTL, 0, FCTL_FLD);
+        });
+    }
+
+    /// Queue `frame` in an empty mailbox, returning the mailbox, or `None` if all are full.
+    ///
+    /// The controller sends the queued frame with the lowest identifier first.
+    pub fn try_transmit(&mut self, frame: &Frame) -> Option<Mailbox> {
+        let r = regs::<T>();
+        let tstat = r.read(TSTAT);
+        if tstat & TSTAT_TME == 0 {
+            return None;
+        }
+        // `NUM` is the number of an empty mailbox.
+        let index = (tstat >> TSTAT_NUM_OFFSET) & 0b11;
+        write_mailbox::<T>(index, frame);
+        Some(Mailbox::from_index(index))
+    }
+
+    /// Queue `frame`, waiting for a mailbox to be empty, see [`Can::try_transmit`].
+    pub fn blocking_transmit(&mut self, frame: &Frame) -> Mailbox {
+        loop {
+            if let Some(mailbox) = self.try_transmit(frame) {
+                return mailbox;
+            }
+        }
+    }
+
+    /// The bus time captured at the start of the last frame sent from `mailbox`, or `None` if
+    /// [`Config::time_triggered`] is disabled or the frame wasn't sent, e.g. because it's still
+    /// pending, failed or was [aborted](Can::abort).
+    pub fn sent_bus_time(&self, mailbox: Mailbox) -> Option<u16> {
+        let r = regs::<T>();
+        // The mailbox is empty after an abort too, only MTF and MTFNERR tell a sent frame.
+        let sent = (TSTAT_MTF0 | TSTAT_MTFNERR0) << (8 * mailbox as u32);
+        if r.read(CTL) & CTL_TTC == 0 || !self.is_mailbox_empty(mailbox) || r.read(TSTAT) & sent != sent {
+            return None;
+        }
+        Some((r.read(TMI + 0x10 * mailbox as usize + TMP) >> MP_TS_OFFSET) as u16)
+    }
+
+    /// Whether `mailbox` is empty, i.e. its frame was sent or aborted.
+    pub fn is_mailbox_empty(&self, mailbox: Mailbox) -> bool {
+        regs::<T>().read(TSTAT) & (TSTAT_TME0 << mailbox as u32) != 0
+    }
+
+    /// Abort the frame in `mailbox`, returning whether it was aborted before it was sent.
+    ///
+    /// A frame that is being sent is finished first.
+    pub fn abort(&mut self, mailbox: Mailbox) -> bool {
+        let r = regs::<T>();
+        let shift = 8 * mailbox as u32;
+        if self.is_mailbox_empty(mailbox) {
+            return false;
+        }
+        // The flags are cleared by writing one, writing zero has no effect.
+        r.write(TSTAT, TSTAT_MST0 << shift);
+        while !self.is_mailbox_empty(mailbox) {}
+        let tstat = r.read(TSTAT);
+        r.write(TSTAT, TSTAT_MTF0 << shift);
+        tstat & (TSTAT_MTFNERR0 << shift) == 0
+    }
+
+    /// Whether the controller is bus-off.
+    pub fn is_bus_off(&self) -> bool {
+        regs::<T>().read(ERR) & ERR_BOERR != 0
+    }
+
+    /// The error counters and state.
+    pub fn error_counters(&self) -> ErrorCounters {
+        let err = regs::<T>().read(ERR);
+        ErrorCounters {
+            transmit: (err >> ERR_TECNT_OFFSET) as u8,
+            receive: (err >> ERR_RECNT_OFFSET) as u8,
+            warning: err & ERR_WERR != 0,
+            passive: err & ERR_PERR != 0,
+            bus_off: err & ERR_BOERR != 0,
+        }
+    }
+
+    /// The error of the last frame with one, or `None` if there was none since the last call.
+    pub fn last_error(&mut self) -> Option<BusError> {
+        let r = regs::<T>();
+        let errn = (r.read(ERR) & ERR_ERRN) >> ERR_ERRN_OFFSET;
+        // The controller never sets the "set by software" code, which marks the error as seen.
+        r.modify(ERR, ERR_ERRN_SOFTWARE << ERR_ERRN_OFFSET, ERR_ERRN);
+        match errn {
+            1 => Some(BusError::Stuff),
+            2 => Some(BusError::Form),
+            3 => Some(BusError::Acknowledgement),
+            4 => Some(BusError::BitRecessive),
+            5 => Some(BusError::BitDominant),
+            6 => Some(BusError::Crc),
+            _ => None,
+        }
+    }
+
+    /// Start the recovery from bus-off with [`BusOffRecovery::Manual`]. The controller rejoins the
+    /// bus after 128 times 11 recessive bits.
+    ///
+    /// Does nothing if the controller isn't bus-off.
+    pub fn recover_bus_off(&mut self) {
+        if self.is_bus_off() {
+            // Leaving initialization mode starts the recovery, without waiting for it.
+            Self::enter_init_mode();
+            regs::<T>().modify(CTL, 0, CTL_IWMOD);
+        }
+    }
+
+    /// Wait until the controller has rejoined the bus, starting the recovery with
+    /// [`BusOffRecovery::Manual`]. Returns right away if the controller isn't bus-off.
+    ///
+    /// The recovery needs an idle bus, so this waits as long as the bus fault lasts. There is no
+    /// interrupt for the end of the recovery: the state is checked once per recovery time with
+    /// the `time` feature, and on each poll otherwise.
+    pub async fn wait_bus_off_recovered(&mut self) {
+        let r = regs::<T>();
+        if r.read(CTL) & CTL_ABOR == 0 {
+            self.recover_bus_off();
+        }
+        while self.is_bus_off() || r.read(STAT) & STAT_IWS != 0 {
+            #[cfg(feature = "time")]
+            embassy_time::Timer::after(Self::recovery_time()).await;
+            #[cfg(not(feature = "time"))]
+            embassy_futures::yield_now().await;
+        }
+    }
+
+    /// Time of 128 times 11 bits.
+    #[cfg(feature = "time")]
+    fn recovery_time() -> embassy_time::Duration {
+        let bt = regs::<T>().read(BT);
+        let quanta = 3 + ((bt >> BT_BS1_OFFSET) & 0xF) + ((bt >> BT_BS2_OFFSET) & 0x7);
+        let cycles = ((bt & BT_BAUDPSC) + 1) as u64 * quanta as u64 * 128 * 11;
+        embassy_time::Duration::from_micros(cycles * 1_000_000 / T::frequency().0 as u64 + 1)
+    }
+
+    /// Take the next received frame, from FIFO 0 first.
+    ///
+    /// A FIFO overrun is returned once, before the frames still in the FIFO.
+    pub fn try_receive(&mut self) -> Result<Envelope, TryReceiveError> {
+        let r = regs::<T>();
+        for fifo in [Fifo::Fifo0, Fifo::Fifo1] {
+            let rfifo = RFIFO0 + 4 * fifo as usize;
+            if r.read(rfifo) & RFIFO_RFO != 0 {
+                // The flag is cleared by writing one, writing zero has no effect.
+                r.write(rfifo, RFIFO_RFO);
+                return Err(TryReceiveError::Overrun(Overrun::Fifo));
+            }
+        }
+        receive_from::<T>(Fifo::Fifo0)
+            .or_else(|| receive_from::<T>(Fifo::Fifo1))
+            .ok_or(TryReceiveError::Empty)
+    }
+
+    /// Take the next received frame, waiting for one, see [`Can::try_receive`].
+    pub fn blocking_receive(&mut self) -> Result<Envelope, Overrun> {
+        loop {
+            match self.try_receive() {
+                Ok(envelope) => return Ok(envelope),
+                Err(TryReceiveError::Overrun(e)) => return Err(e),
+                Err(TryReceiveError::Empty) => {}
+            }
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Can<'d, T> {
+    fn drop(&mut self) {
+        // Sleep mode leaves the bus.
+        regs::<T>().write(CTL, CTL_SLPWMOD);
+        // CAN0 holds the filters of CAN1.
+        if T::FIRST_FILTER != 0 || !can1_enabled() {
+            T::disable();
+        }
+        unsafe {
+            self.rx.set_as_disconnected();
+            self.tx.set_as_disconnected();
+        }
+    }
+}
+
+/// Queue `frame` in the empty mailbox `index`.
+fn write_mailbox<T: Instance>(index: u32, frame: &Frame) {
+    let r = regs::<T>();
+    let mi = TMI + 0x10 * index as usize;
+    let tsen = match T::state().send_time.load(Ordering::Relaxed) && frame.len == 8 && !frame.remote {
+        true => TMP_TSEN,
+        false => 0,
+    };
+    r.write(mi + TMP, frame.len as u32 | tsen);
+    r.write(mi + TMDATA0, u32::from_le_bytes(frame.data[..4].try_into().unwrap()));
+    r.write(mi + TMDATA1, u32::from_le_bytes(frame.data[4..].try_into().unwrap()));
+    let ft = match frame.remote {
+        true => MI_FT,
+        false => 0,
+    };
+    r.write(mi, frame.id.to_bits() | ft | TMI_TEN);
+}
+
+/// Take the next frame from `fifo`.
+fn receive_from<T: Instance>(fifo: Fifo) -> Option<Envelope> {
+    let r = regs::<T>();
+    let rfifo = RFIFO0 + 4 * fifo as usize;
+    if r.read(rfifo) & RFIFO_RFL == 0 {
+        return None;
+    }
+
+    let mi = RFIFOMI + 0x10 * fifo as usize;
+    let id = r.read(mi);
+    let mp = r.read(mi + RFIFOMP);
+    let remote = id & MI_FT != 0;
+    let mut data = [0; 8];
+    if !remote {
+        data[..4].copy_from_slice(&r.read(mi + RFIFOMDATA0).to_le_bytes());
+        data[4..].copy_from_slice(&r.read(mi + RFIFOMDATA1).to_le_bytes());
+    }
+    // Release the frame. `RFD` is cleared by hardware once the next one is readable.
+    r.write(rfifo, RFIFO_RFD);
+    while r.read(rfifo) & RFIFO_RFD != 0 {}
+
+    Some(Envelope {
+        frame: Frame {
+            id: Id::from_bits(id),
+            len: (mp & MP_DLENC).min(8) as u8,
+            data,
+            remote,
+        },
+        fifo,
+        filter_index: (mp >> MP_FI_OFFSET) as u8,
+        bus_time: match r.read(CTL) & CTL_TTC {
+            0 => None,
+            _ => Some((mp >> MP_TS_OFFSET) as u16),
+        },
+        #[cfg(feature = "time")]
+        timestamp: embassy_time::Instant::now(),
+    })
+}
+
+/// Whether the CAN1 clock is enabled.
+fn can1_enabled() -> bool {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    rcu.apb1en.read().bits() & APB1EN_CAN1EN != 0
+}
+
+/// Registers of an instance, accessed by offset, as the mailboxes, FIFOs and filters are arrays
+#[derive(Clone, Copy)]
+struct Regs(usize);
+
+impl Regs {
+    fn read(self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the `set` bits and clear the `clear` bits of the register at `offset`.
+    fn modify(self, offset: usize, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set)
+    }
+}
+
+fn regs<T: Instance>() -> Regs {
+    Regs(T::base())
+}
+
+/// The filter registers of both instances, which are in CAN0
+fn filter_regs() -> Regs {
+    Regs(pac::CAN0::ptr() as usize)
+}
+
+pub(crate) mod sealed {
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        /// First filter bank of the instance
+        const FIRST_FILTER: usize;
+
+        fn base() -> usize;
+        fn state() -> &'static super::buffered::State;
+
+        /// Select the AFIO layout of the pins, see [`crate::afio::remap_for_pins`].
+        fn remap(pins: &[crate::afio::RemapSet]);
+    }
+}
+
+/// CAN peripheral instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {
+    type TxInterrupt: Interrupt;
+    type Rx0Interrupt: Interrupt;
+    type Rx1Interrupt: Interrupt;
+}
+
+pin_trait!(RxPin, Instance);
+pin_trait!(TxPin, Instance);
+
+macro_rules! impl_can {
+    ($inst:ident, $first_filter:expr, $remap:ident, $tx:ident, $rx0:ident, $rx1:ident) => {
+        impl crate::can::sealed::Instance for peripherals::$inst {
+            const FIRST_FILTER: usize = $first_filter;
+
+            fn base() -> usize {
+                crate::pac::$inst::ptr() as usize
+            }
+
+            fn state() -> &'static crate::can::buffered::State {
+                static STATE: crate::can::buffered::State = crate::can::buffered::State::new();
+                &STATE
+            }
+
+            fn remap(pins: &[crate::afio::RemapSet]) {
+                crate::afio::remap_for_pins::<crate::afio::$remap>(pins)
+            }
+        }
+
+        impl crate::can::Instance for peripherals::$inst {
+            type TxInterrupt = crate::interrupt::$tx;
+            type Rx0Interrupt = crate::interrupt::$rx0;
+            type Rx1Interrupt = crate::interrupt::$rx1;
+        }
+    };
+}
+
+/// Bit timing limits
+const MIN_QUANTA: u32 = 8;
+const MAX_QUANTA: u32 = 25;
+const MAX_PRESCALER: u32 = 1024;
+
+// Register offsets
+const CTL: usize = 0x00;
+const STAT: usize = 0x04;
+const TSTAT: usize = 0x08;
+const RFIFO0: usize = 0x0C;
+const INTEN: usize = 0x14;
+const ERR: usize = 0x18;
+const BT: usize = 0x1C;
+// Mailbox registers, at `0x10 * n` from the first register of mailbox `n`
+const TMI: usize = 0x180;
+const TMP: usize = 0x04;
+const TMDATA0: usize = 0x08;
+const TMDATA1: usize = 0x0C;
+const RFIFOMI: usize = 0x1B0;
+const RFIFOMP: usize = 0x04;
+const RFIFOMDATA0: usize = 0x08;
+const RFIFOMDATA1: usize = 0x0C;
+const FCTL: usize = 0x200;
+const FMCFG: usize = 0x204;
+const FSCFG: usize = 0x20C;
+const FAFIFO: usize = 0x214;
+const FW: usize = 0x21C;
+// Filter data registers, at `8 * n` for filter bank `n`
+const FDATA0: usize = 0x240;
+const FDATA1: usize = 0x244;
+
+// CAN_CTL
+const CTL_IWMOD: u32 = 1 << 0;
+const CTL_SLPWMOD: u32 = 1 << 1;
+const CTL_RFOD: u32 = 1 << 3;
+const CTL_ARD: u32 = 1 << 4;
+const CTL_ABOR: u32 = 1 << 6;
+const CTL_TTC: u32 = 1 << 7;
+const CTL_SWRST: u32 = 1 << 15;
+
+// CAN_STAT
+const STAT_IWS: u32 = 1 << 0;
+const STAT_SLPWS: u32 = 1 << 1;
+
+// CAN_TSTAT, the mailbox 1 and 2 flags are 8 and 16 bits above the mailbox 0 ones
+const TSTAT_MTF0: u32 = 1 << 0;
+const TSTAT_MTF: u32 = TSTAT_MTF0 | (TSTAT_MTF0 << 8) | (TSTAT_MTF0 << 16);
+const TSTAT_MTFNERR0: u32 = 1 << 1;
+#[cfg(feature = "time")]
+const TSTAT_MTFNERR: u32 = TSTAT_MTFNERR0 | (TSTAT_MTFNERR0 << 8) | (TSTAT_MTFNERR0 << 16);
+const TSTAT_MST0: u32 = 1 << 7;
+const TSTAT_NUM_OFFSET: u32 = 24;
+const TSTAT_TME0: u32 = 1 << 26;
+const TSTAT_TME: u32 = 0b111 << 26;
+
+// CAN_RFIFOx
+const RFIFO_RFL: u32 = 0b11;
+const RFIFO_RFO: u32 = 1 << 4;
+const RFIFO_RFD: u32 = 1 << 5;
+
+// CAN_INTEN
+const INTEN_TMEIE: u32 = 1 << 0;
+const INTEN_RFNEIE0: u32 = 1 << 1;
+const INTEN_RFOIE0: u32 = 1 << 3;
+const INTEN_RFNEIE1: u32 = 1 << 4;
+const INTEN_RFOIE1: u32 = 1 << 6;
+
+// CAN_ERR
+const ERR_WERR: u32 = 1 << 0;
+const ERR_PERR: u32 = 1 << 1;
+const ERR_BOERR: u32 = 1 << 2;
+const ERR_ERRN_OFFSET: u32 = 4;
+const ERR_ERRN: u32 = 0b111 << 4;
+const ERR_ERRN_SOFTWARE: u32 = 0b111;
+const ERR_TECNT_OFFSET: u32 = 16;
+const ERR_RECNT_OFFSET: u32 = 24;
+
+// CAN_BT
+const BT_BAUDPSC: u32 = 0x3FF;
+const BT_BS1_OFFSET: u32 = 16;
+const BT_BS2_OFFSET: u32 = 20;
+const BT_SJW_OFFSET: u32 = 24;
+const BT_LCMOD: u32 = 1 << 30;
+const BT_SCMOD: u32 = 1 << 31;
+
+// CAN_TMIx and CAN_RFIFOMIx
+const TMI_TEN: u32 = 1 << 0;
+const MI_FT: u32 = 1 << 1;
+const MI_FF: u32 = 1 << 2;
+const MI_EFID_OFFSET: u32 = 3;
+const MI_SFID_OFFSET: u32 = 21;
+
+// CAN_TMPx and CAN_RFIFOMPx
+const MP_DLENC: u32 = 0xF;
+const MP_FI_OFFSET: u32 = 8;
+const MP_TS_OFFSET: u32 = 16;
+const TMP_TSEN: u32 = 1 << 8;
+
+// CAN_FCTL
+const FCTL_FLD: u32 = 1 << 0;
+const FCTL_HBC1F_OFFSET: u32 = 8;
+const FCTL_HBC1F: u32 = 0x3F << 8;
+
+// RCU_APB1EN
+const APB1EN_CAN1EN: u32 = 1 << 26;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bitrate: u32, sample_point: u16) -> Config {
+        Config {
+            bitrate,
+            sample_point,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exact_timing() {
+        let timing = BitTiming::compute(45_000_000, &config(500_000, 875)).unwrap();
+        let quanta = 1 + timing.seg1 as u32 + timing.seg2 as u32;
+        assert_eq!(45_000_000 / (timing.prescaler as u32 * quanta), 500_000);
+        assert_eq!(timing.check(), Ok(()));
+    }
+
+    #[test]
+    fn unreachable_timing() {
+        // 1 Mbit/s needs at least 8 quanta per bit, i.e. an APB1 clock of 8 MHz.
+        assert_eq!(
+            BitTiming::compute(7_000_000, &config(1_000_000, 875)),
+            Err(ConfigError::NoExactTiming)
+        );
+        // No number of quanta from 8 to 25 divides 37 MHz / 1 Mbit/s.
+        assert_eq!(
+            BitTiming::compute(37_000_000, &config(1_000_000, 875)),
+            Err(ConfigError::NoExactTiming)
+        );
+        // 1 kbit/s at 90 MHz needs a prescaler of at least 3600.
+        assert_eq!(
+            BitTiming::compute(90_000_000, &config(1_000, 875)),
+            Err(ConfigError::NoExactTiming)
+        );
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert_eq!(
+            BitTiming::compute(45_000_000, &config(0, 875)),
+            Err(ConfigError::InvalidBitrate)
+        );
+        assert_eq!(
+            BitTiming::compute(45_000_000, &config(2_000_000, 875)),
+            Err(ConfigError::InvalidBitrate)
+        );
+        assert_eq!(
+            BitTiming::compute(45_000_000, &config(500_000, 400)),
+            Err(ConfigError::InvalidSamplePoint)
+        );
+    }
+}
@@ -0,0 +1,1278 @@
+//! Clock control (RCU clock tree configuration).
+//!
+//! The clocks are set up by [`crate::init`] from [`Config`], and can be changed later with
+//! [`reconfigure`], e.g. to run from IRC8M while idle and from the PLL while busy.
+//!
+//! # HXTAL clock monitor
+//!
+//! With [`Config::hxtal_monitor`] set, the clock monitor (CKM) watches HXTAL. If the crystal stops,
+//! the hardware switches the system clock to IRC8M and turns off the PLL if it runs from HXTAL,
+//! then raises an NMI. The NMI has to call [`on_nmi`], either through the handler installed by
+//! the `ckm-nmi` feature or from the application's own `NonMaskableInt` handler. Afterwards
+//! [`hxtal_failed`] returns `true`, [`wait_for_hxtal_failure`] completes and the clock frequencies
+//! reflect the fallback to IRC8M.
+//!
+//! # USB and I2S clocks
+//!
+//! USB needs exactly 48 MHz, taken either from the PLL through the USB prescaler or from IRC48M,
+//! see [`Config::usb`]. The GD32E503 has neither PLL1/PLL2 nor `PREDV1`, which only exist on the
+//! connectivity line devices; its I2S peripherals run from the system clock, reported as
+//! [`Clocks::i2s`].
+#![macro_use]
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::time::Hertz;
+use crate::{interrupt, pac};
+
+/// IRC8M speed
+pub const IRC8M_FREQ: Hertz = Hertz(8_000_000);
+
+/// IRC48M speed
+pub const IRC48M_FREQ: Hertz = Hertz(48_000_000);
+
+/// IRC40K speed
+pub const IRC40K_FREQ: Hertz = Hertz(40_000);
+
+/// LXTAL speed
+pub const LXTAL_FREQ: Hertz = Hertz(32_768);
+
+/// USB clock, see [`UsbClockSource`]
+pub const USB_FREQ: Hertz = Hertz(48_000_000);
+
+/// Maximum ADC clock, see [`ADCPrescaler`]
+pub const ADC_MAX_FREQ: Hertz = Hertz(40_000_000);
+
+/// Maximum system clock
+const SYS_MAX: u32 = 180_000_000;
+/// Maximum APB1 clock
+const APB1_MAX: u32 = 90_000_000;
+/// Maximum APB2 clock
+const APB2_MAX: u32 = 180_000_000;
+
+/// System clock source
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SysClockSource {
+    /// Internal 8 MHz RC oscillator
+    Irc8m,
+    /// External crystal, see [`Config::hxtal`]
+    Hxtal,
+    /// PLL, see [`Config::pll`]
+    Pll,
+}
+
+/// PLL input clock
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PllSource {
+    /// IRC8M divided by 2
+    Irc8mDiv2,
+    /// HXTAL divided by [`Pll::prediv`]
+    Hxtal,
+    /// IRC48M divided by [`Pll::prediv`]
+    Irc48m,
+}
+
+/// PLL multiplication factor, ×2 to ×64.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PLLMul(u8);
+
+impl PLLMul {
+    /// Multiply the PLL input clock by `factor`.
+    ///
+    /// `factor` must be in `2..=64`, otherwise configuring the clocks fails with
+    /// [`ClockError::InvalidPllMul`].
+    pub const fn factor(factor: u8) -> Self {
+        Self(factor)
+    }
+
+    /// The multiplication factor.
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+
+    /// `PLLMF` value, split by the caller into its `RCU_CFG0` bits.
+    fn bits(self) -> u32 {
+        match self.0 {
+            // ×2..×15, and ×16 has two encodings
+            2..=16 => self.0 as u32 - 2,
+            _ => self.0 as u32 - 1,
+        }
+    }
+}
+
+/// PLL configuration
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pll {
+    /// PLL input clock
+    pub source: PllSource,
+    /// Predivider (`PREDV0`), 1 to 16. Ignored for [`PllSource::Irc8mDiv2`].
+    pub prediv: u8,
+    /// PLL multiplication factor
+    pub mul: PLLMul,
+}
+
+/// AHB prescaler
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AHBPrescaler {
+    NotDivided,
+    Div2,
+    Div4,
+    Div8,
+    Div16,
+    Div64,
+    Div128,
+    Div256,
+    Div512,
+}
+
+impl AHBPrescaler {
+    fn bits(self) -> u32 {
+        match self {
+            AHBPrescaler::NotDivided => 0b0000,
+            AHBPrescaler::Div2 => 0b1000,
+            AHBPrescaler::Div4 => 0b1001,
+            AHBPrescaler::Div8 => 0b1010,
+            AHBPrescaler::Div16 => 0b1011,
+            AHBPrescaler::Div64 => 0b1100,
+            AHBPrescaler::Div128 => 0b1101,
+            AHBPrescaler::Div256 => 0b1110,
+            AHBPrescaler::Div512 => 0b1111,
+        }
+    }
+
+    fn divisor(self) -> u32 {
+        match self {
+            AHBPrescaler::NotDivided => 1,
+            AHBPrescaler::Div2 => 2,
+            AHBPrescaler::Div4 => 4,
+            AHBPrescaler::Div8 => 8,
+            AHBPrescaler::Div16 => 16,
+            AHBPrescaler::Div64 => 64,
+            AHBPrescaler::Div128 => 128,
+            AHBPrescaler::Div256 => 256,
+            AHBPrescaler::Div512 => 512,
+        }
+    }
+}
+
+/// APB1/APB2 prescaler
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum APBPrescaler {
+    NotDivided,
+    Div2,
+    Div4,
+    Div8,
+    Div16,
+}
+
+impl APBPrescaler {
+    fn bits(self) -> u32 {
+        match self {
+            APBPrescaler::NotDivided => 0b000,
+            APBPrescaler::Div2 => 0b100,
+            APBPrescaler::Div4 => 0b101,
+            APBPrescaler::Div8 => 0b110,
+            APBPrescaler::Div16 => 0b111,
+        }
+    }
+
+    fn divisor(self) -> u32 {
+        match self {
+            APBPrescaler::NotDivided => 1,
+            APBPrescaler::Div2 => 2,
+            APBPrescaler::Div4 => 4,
+            APBPrescaler::Div8 => 8,
+            APBPrescaler::Div16 => 16,
+        }
+    }
+}
+
+/// ADC clock prescaler (`ADCPSC`)
+///
+/// The ADC clock is derived either from APB2 or from AHB. It must not exceed [`ADC_MAX_FREQ`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ADCPrescaler {
+    Apb2Div2,
+    Apb2Div4,
+    Apb2Div6,
+    Apb2Div8,
+    Apb2Div12,
+    Apb2Div16,
+    AhbDiv5,
+    AhbDiv6,
+    AhbDiv10,
+    AhbDiv20,
+}
+
+impl ADCPrescaler {
+    /// The 4-bit `ADCPSC` value.
+    fn bits(self) -> u32 {
+        match self {
+            ADCPrescaler::Apb2Div2 => 0b0000,
+            ADCPrescaler::Apb2Div4 => 0b0001,
+            ADCPrescaler::Apb2Div6 => 0b0010,
+            ADCPrescaler::Apb2Div8 => 0b0011,
+            ADCPrescaler::Apb2Div12 => 0b0101,
+            ADCPrescaler::Apb2Div16 => 0b0111,
+            ADCPrescaler::AhbDiv5 => 0b1000,
+            ADCPrescaler::AhbDiv6 => 0b1001,
+            ADCPrescaler::AhbDiv10 => 0b1010,
+            ADCPrescaler::AhbDiv20 => 0b1011,
+        }
+    }
+
+    fn freq(self, ahb: u32, apb2: u32) -> u32 {
+        match self {
+            ADCPrescaler::Apb2Div2 => apb2 / 2,
+            ADCPrescaler::Apb2Div4 => apb2 / 4,
+            ADCPrescaler::Apb2Div6 => apb2 / 6,
+            ADCPrescaler::Apb2Div8 => apb2 / 8,
+            ADCPrescaler::Apb2Div12 => apb2 / 12,
+            ADCPrescaler::Apb2Div16 => apb2 / 16,
+            ADCPrescaler::AhbDiv5 => ahb / 5,
+            ADCPrescaler::AhbDiv6 => ahb / 6,
+            ADCPrescaler::AhbDiv10 => ahb / 10,
+            ADCPrescaler::AhbDiv20 => ahb / 20,
+        }
+    }
+}
+
+/// USB clock prescaler (`USBDPSC`), dividing the PLL output down to 48 MHz.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbPrescaler {
+    NotDivided,
+    Div1_5,
+    Div2,
+    Div2_5,
+    Div3,
+    Div3_5,
+    Div4,
+}
+
+impl UsbPrescaler {
+    /// The 3-bit `USBDPSC` value.
+    fn bits(self) -> u32 {
+        match self {
+            UsbPrescaler::Div1_5 => 0b000,
+            UsbPrescaler::NotDivided => 0b001,
+            UsbPrescaler::Div2_5 => 0b010,
+            UsbPrescaler::Div2 => 0b011,
+            UsbPrescaler::Div3 => 0b100,
+            UsbPrescaler::Div3_5 => 0b101,
+            UsbPrescaler::Div4 => 0b110,
+        }
+    }
+
+    fn freq(self, pll: u32) -> u32 {
+        match self {
+            UsbPrescaler::NotDivided => pll,
+            UsbPrescaler::Div1_5 => pll * 2 / 3,
+            UsbPrescaler::Div2 => pll / 2,
+            UsbPrescaler::Div2_5 => pll * 2 / 5,
+            UsbPrescaler::Div3 => pll / 3,
+            UsbPrescaler::Div3_5 => pll * 2 / 7,
+            UsbPrescaler::Div4 => pll / 4,
+        }
+    }
+}
+
+/// USB 48 MHz clock source (`CK48MSEL`)
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbClockSource {
+    /// PLL output divided by the prescaler. The result must be exactly 48 MHz.
+    Pll(UsbPrescaler),
+    /// Internal 48 MHz RC oscillator. Accurate enough for USB device mode only when trimmed by
+    /// the CTC from the USB start-of-frame packets.
+    Irc48m,
+}
+
+/// RTC clock source (`RTCSRC`)
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RtcClockSource {
+    /// External 32.768 kHz crystal
+    Lxtal,
+    /// Internal 40 kHz RC oscillator. Inaccurate, but needs no external parts.
+    Irc40k,
+    /// HXTAL divided by 128. Stops in deep-sleep and standby.
+    HxtalDiv128,
+}
+
+/// Clocks kept running in sleep mode.
+///
+/// In sleep mode (`WFI`/`WFE` without `SLEEPDEEP`) only the core clock stops. The SRAM and flash
+/// interface clocks keep running by default, and can be gated to save power if nothing, e.g. a DMA
+/// transfer, accesses them while the core sleeps. Peripheral clocks are controlled by their
+/// drivers. In deep-sleep all clocks but LXTAL and IRC40K stop regardless.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SleepClocks {
+    /// SRAM interface clock (`SRAMSPEN`)
+    pub sram: bool,
+    /// Flash memory controller clock (`FMCSPEN`)
+    pub fmc: bool,
+}
+
+impl Default for SleepClocks {
+    /// Keep all clocks running, which is the reset state.
+    fn default() -> Self {
+        Self { sram: true, fmc: true }
+    }
+}
+
+/// Configuration of the clocks
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Frequency of the external crystal, `None` if HXTAL is not used.
+    pub hxtal: Option<Hertz>,
+    /// PLL configuration, `None` if the PLL is not used.
+    pub pll: Option<Pll>,
+    /// System clock source
+    pub sys: SysClockSource,
+    pub ahb_pre: AHBPrescaler,
+    pub apb1_pre: APBPrescaler,
+    pub apb2_pre: APBPrescaler,
+    pub adc_pre: ADCPrescaler,
+    /// RTC clock source, `None` to leave the RTC clock as it is.
+    ///
+    /// The RTC clock lives in the backup domain and keeps its configuration across resets. It can
+    /// only be changed by resetting the backup domain, which also clears the RTC counter and the
+    /// backup registers. This is done only if the configured source differs from the current one.
+    pub rtc: Option<RtcClockSource>,
+    /// USB clock source, `None` if USB is not used.
+    pub usb: Option<UsbClockSource>,
+    /// Enable the HXTAL clock monitor, see the [module documentation](self).
+    pub hxtal_monitor: bool,
+    /// Clocks kept running in sleep mode
+    pub sleep: SleepClocks,
+}
+
+impl Default for Config {
+    /// Run everything from IRC8M, which is the reset state.
+    fn default() -> Self {
+        Self {
+            hxtal: None,
+            pll: None,
+            sys: SysClockSource::Irc8m,
+            ahb_pre: AHBPrescaler::NotDivided,
+            apb1_pre: APBPrescaler::NotDivided,
+            apb2_pre: APBPrescaler::NotDivided,
+            adc_pre: ADCPrescaler::Apb2Div2,
+            rtc: None,
+            usb: None,
+            hxtal_monitor: false,
+            sleep: SleepClocks::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Run from the PLL fed by HXTAL, at `target_sys` or the closest frequency below it.
+    ///
+    /// The PLL predivider and multiplier are picked to get as close as possible to `target_sys`,
+    /// capped at the 180 MHz maximum. AHB and APB2 are undivided, APB1 is divided by two if needed
+    /// to stay within 90 MHz, and the ADC gets the fastest clock up to [`ADC_MAX_FREQ`].
+    pub fn fast(hxtal: Hertz, target_sys: Hertz) -> Self {
+        let target = target_sys.0.min(SYS_MAX);
+
+        let mut config = Self {
+            hxtal: Some(hxtal),
+            ..Default::default()
+        };
+
+        let sys = if target == hxtal.0 {
+            config.sys = SysClockSource::Hxtal;
+            hxtal.0
+        } else {
+            // (frequency, prediv, mul) closest to the target from below, or the slowest setting if
+            // even that is above the target.
+            let mut best: Option<(u32, u8, u8)> = None;
+            for prediv in 1..=16u8 {
+                let input = hxtal.0 / prediv as u32;
+                for mul in 2..=64u8 {
+                    let freq = input * mul as u32;
+                    let better = match best {
+                        None => true,
+                        Some((best_freq, _, _)) if best_freq > target => freq < best_freq,
+                        Some((best_freq, _, _)) => freq <= target && freq > best_freq,
+                    };
+                    if better {
+                        best = Some((freq, prediv, mul));
+                    }
+                }
+            }
+            let (freq, prediv, mul) = unwrap!(best);
+            config.pll = Some(Pll {
+                source: PllSource::Hxtal,
+                prediv,
+                mul: PLLMul::factor(mul),
+            });
+            config.sys = SysClockSource::Pll;
+            freq
+        };
+
+        config.set_bus_prescalers(sys);
+        config
+    }
+
+    /// Run from the PLL fed by IRC8M, at `target_sys` or the closest frequency below it.
+    ///
+    /// The PLL runs from IRC8M/2, so the system clock is a multiple of 4 MHz. Targets up to 8 MHz
+    /// use IRC8M directly. The bus prescalers are chosen like in [`Config::fast`].
+    pub fn fast_irc8m(target_sys: Hertz) -> Self {
+        let target = target_sys.0.min(SYS_MAX);
+
+        let mut config = Self::default();
+        let sys = if target > IRC8M_FREQ.0 {
+            let mul = (target / (IRC8M_FREQ.0 / 2)).clamp(2, 64) as u8;
+            config.pll = Some(Pll {
+                source: PllSource::Irc8mDiv2,
+                prediv: 1,
+                mul: PLLMul::factor(mul),
+            });
+            config.sys = SysClockSource::Pll;
+            IRC8M_FREQ.0 / 2 * mul as u32
+        } else {
+            IRC8M_FREQ.0
+        };
+
+        config.set_bus_prescalers(sys);
+        config
+    }
+
+    /// Fastest AHB, APB and ADC clocks allowed for a system clock of `sys`.
+    fn set_bus_prescalers(&mut self, sys: u32) {
+        self.ahb_pre = AHBPrescaler::NotDivided;
+        self.apb2_pre = APBPrescaler::NotDivided;
+        self.apb1_pre = if sys > APB1_MAX {
+            APBPrescaler::Div2
+        } else {
+            APBPrescaler::NotDivided
+        };
+        self.adc_pre = [
+            ADCPrescaler::Apb2Div2,
+            ADCPrescaler::Apb2Div4,
+            ADCPrescaler::Apb2Div6,
+            ADCPrescaler::Apb2Div8,
+            ADCPrescaler::Apb2Div12,
+            ADCPrescaler::Apb2Div16,
+        ]
+        .into_iter()
+        .find(|pre| pre.freq(sys, sys) <= ADC_MAX_FREQ.0)
+        .unwrap_or(ADCPrescaler::Apb2Div16);
+    }
+}
+
+/// Clock configuration error
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    /// HXTAL is used by the system clock, the PLL or the RTC, but [`Config::hxtal`] is `None`.
+    NoHxtal,
+    /// The system clock is the PLL, but [`Config::pll`] is `None`.
+    NoPll,
+    /// PLL multiplication factor outside `2..=64`.
+    InvalidPllMul(u8),
+    /// HXTAL predivider outside `1..=16`.
+    InvalidPrediv(u8),
+    /// System clock above 180 MHz.
+    SysTooHigh(Hertz),
+    /// APB1 clock above 90 MHz.
+    Apb1TooHigh(Hertz),
+    /// APB2 clock above 180 MHz.
+    Apb2TooHigh(Hertz),
+    /// ADC clock above [`ADC_MAX_FREQ`], see [`Config::adc_pre`].
+    AdcTooHigh(Hertz),
+    /// With a `timedriver-timer*` feature, an APB1 timer clock that isn't a multiple of
+    /// `embassy_time::TICK_HZ`, or is above 65536 times it.
+    InvalidTimeDriverClock(Hertz),
+    /// HXTAL didn't stabilize, e.g. because no crystal is fitted.
+    HxtalTimeout,
+    /// The PLL didn't lock.
+    PllTimeout,
+    /// LXTAL didn't stabilize.
+    LxtalTimeout,
+    /// IRC40K didn't stabilize.
+    Irc40kTimeout,
+    /// IRC48M didn't stabilize.
+    Irc48mTimeout,
+    /// USB clock other than 48 MHz.
+    InvalidUsbClock(Hertz),
+}
+
+/// Clock frequencies
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Clocks {
+    pub sys: Hertz,
+    pub ahb: Hertz,
+    pub apb1: Hertz,
+    pub apb2: Hertz,
+    /// Clock of the timers on APB1. Twice the APB1 clock if APB1 is divided.
+    pub apb1_timer: Hertz,
+    /// Clock of the timers on APB2. Twice the APB2 clock if APB2 is divided.
+    pub apb2_timer: Hertz,
+    /// ADC clock, see [`Config::adc_pre`]
+    pub adc: Hertz,
+    /// RTC clock, `None` if the RTC clock is not enabled
+    pub rtc: Option<Hertz>,
+    /// USB clock, `None` if USB is not configured, see [`Config::usb`]
+    pub usb: Option<Hertz>,
+    /// I2S1/I2S2 clock. The GD32E503 has no PLL2, the I2S peripherals run from the system clock.
+    pub i2s: Hertz,
+}
+
+/// Current clock frequencies
+///
+/// Written by `init`, `reconfigure` and after an HXTAL failure.
+static CLOCK_FREQS: Mutex<Cell<Option<Clocks>>> = Mutex::new(Cell::new(None));
+
+fn set_freqs(freqs: Clocks) {
+    critical_section::with(|cs| CLOCK_FREQS.borrow(cs).set(Some(freqs)));
+    #[cfg(feature = "timedriver-systick")]
+    embassy_cortex_m::systick::set_clock_freq(freqs.ahb.0);
+    #[cfg(feature = "_timedriver-timer")]
+    crate::time_driver::set_clock_freq(freqs.apb1_timer);
+}
+
+/// The current clock frequencies.
+///
+/// These are the frequencies actually achieved, which may differ from the requested ones, e.g.
+/// after [`Config::fast`] rounded the system clock down, or after a fallback to IRC8M.
+///
+/// # Panics
+///
+/// Panics if called before [`crate::init`], the frequencies aren't known until then. Drivers
+/// call it when created, which needs the peripherals `init` returns, so they can't get here early.
+pub fn clocks() -> Clocks {
+    critical_section::with(|cs| unwrap!(CLOCK_FREQS.borrow(cs).get(), "clocks not initialized"))
+}
+
+/// Compute the frequencies `config` results in, checking them against the chip limits.
+fn compute(config: &Config) -> Result<Clocks, ClockError> {
+    let hxtal = || config.hxtal.map(|f| f.0).ok_or(ClockError::NoHxtal);
+
+    let pll = match config.pll {
+        Some(pll) => {
+            let input = match pll.source {
+                PllSource::Irc8mDiv2 => IRC8M_FREQ.0 / 2,
+                PllSource::Hxtal | PllSource::Irc48m => {
+                    if !(1..=16).contains(&pll.prediv) {
+                        return Err(ClockError::InvalidPrediv(pll.prediv));
+                    }
+                    let input = match pll.source {
+                        PllSource::Hxtal => hxtal()?,
+                        _ => IRC48M_FREQ.0,
+                    };
+                    input / pll.prediv as u32
+                }
+            };
+            if !(2..=64).contains(&pll.mul.get()) {
+                return Err(ClockError::InvalidPllMul(pll.mul.get()));
+            }
+            Some(input * pll.mul.get() as u32)
+        }
+        None => None,
+    };
+
+    let sys = match config.sys {
+        SysClockSource::Irc8m => IRC8M_FREQ.0,
+        SysClockSource::Hxtal => hxtal()?,
+        SysClockSource::Pll => pll.ok_or(ClockError::NoPll)?,
+    };
+    if sys > SYS_MAX {
+        return Err(ClockError::SysTooHigh(Hertz(sys)));
+    }
+
+    let ahb = sys / config.ahb_pre.divisor();
+    let apb1 = ahb / config.apb1_pre.divisor();
+    let apb2 = ahb / config.apb2_pre.divisor();
+    if apb1 > APB1_MAX {
+        return Err(ClockError::Apb1TooHigh(Hertz(apb1)));
+    }
+    if apb2 > APB2_MAX {
+        return Err(ClockError::Apb2TooHigh(Hertz(apb2)));
+    }
+    let adc = config.adc_pre.freq(ahb, apb2);
+    if adc > ADC_MAX_FREQ.0 {
+        return Err(ClockError::AdcTooHigh(Hertz(adc)));
+    }
+
+    let rtc = match config.rtc {
+        Some(RtcClockSource::Lxtal) => Some(LXTAL_FREQ),
+        Some(RtcClockSource::Irc40k) => Some(IRC40K_FREQ),
+        Some(RtcClockSource::HxtalDiv128) => Some(Hertz(hxtal()? / 128)),
+        // Left as configured before the last reset.
+        None => current_rtc_freq(config.hxtal),
+    };
+
+    let usb = match config.usb {
+        Some(UsbClockSource::Pll(pre)) => {
+            let usb = pre.freq(pll.ok_or(ClockError::NoPll)?);
+            if usb != USB_FREQ.0 {
+                return Err(ClockError::InvalidUsbClock(Hertz(usb)));
+            }
+            Some(USB_FREQ)
+        }
+        Some(UsbClockSource::Irc48m) => Some(IRC48M_FREQ),
+        None => None,
+    };
+
+    let timer = |pre: APBPrescaler, apb: u32| match pre {
+        APBPrescaler::NotDivided => apb,
+        _ => apb * 2,
+    };
+
+    Ok(Clocks {
+        sys: Hertz(sys),
+        ahb: Hertz(ahb),
+        apb1: Hertz(apb1),
+        apb2: Hertz(apb2),
+        apb1_timer: Hertz(timer(config.apb1_pre, apb1)),
+        apb2_timer: Hertz(timer(config.apb2_pre, apb2)),
+        adc: Hertz(adc),
+        rtc,
+        usb,
+        i2s: Hertz(sys),
+    })
+}
+
+/// RTC clock source as currently configured in the backup domain.
+fn current_rtc_source() -> Option<RtcClockSource> {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let bdctl = rcu.bdctl.read().bits();
+    if bdctl & BDCTL_RTCEN == 0 {
+        return None;
+    }
+    match (bdctl >> BDCTL_RTCSRC_OFFSET) & 0b11 {
+        0b01 => Some(RtcClockSource::Lxtal),
+        0b10 => Some(RtcClockSource::Irc40k),
+        0b11 => Some(RtcClockSource::HxtalDiv128),
+        _ => None,
+    }
+}
+
+/// RTC clock frequency as currently configured in the backup domain.
+fn current_rtc_freq(hxtal: Option<Hertz>) -> Option<Hertz> {
+    match current_rtc_source()? {
+        RtcClockSource::Lxtal => Some(LXTAL_FREQ),
+        RtcClockSource::Irc40k => Some(IRC40K_FREQ),
+        RtcClockSource::HxtalDiv128 => hxtal.map(|f| Hertz(f.0 / 128)),
+    }
+}
+
+/// Start the RTC clock source and select it for the RTC.
+///
+/// The start-up poll runs with interrupts enabled, LXTAL can take seconds.
+unsafe fn apply_rtc(source: RtcClockSource) -> Result<(), ClockError> {
+    let rcu = &*pac::RCU::ptr();
+    let pmu = &*pac::PMU::ptr();
+
+    let (src, ready, timeout): (u32, fn(&pac::rcu::RegisterBlock) -> bool, _) = match source {
+        RtcClockSource::Lxtal => (
+            0b01,
+            |rcu| rcu.bdctl.read().bits() & BDCTL_LXTALSTB != 0,
+            (LXTAL_STARTUP_TIMEOUT, ClockError::LxtalTimeout),
+        ),
+        RtcClockSource::Irc40k => {
+            critical_section::with(|_| rcu.rstsck.modify(|r, w| w.bits(r.bits() | RSTSCK_IRC40KEN)));
+            (
+                0b10,
+                |rcu| rcu.rstsck.read().bits() & RSTSCK_IRC40KSTB != 0,
+                (STARTUP_TIMEOUT, ClockError::Irc40kTimeout),
+            )
+        }
+        // HXTAL was started by `start_oscillators`.
+        RtcClockSource::HxtalDiv128 => (0b11, |_| true, (STARTUP_TIMEOUT, ClockError::HxtalTimeout)),
+    };
+
+    // The backup domain is write protected.
+    critical_section::with(|_| {
+        rcu.apb1en
+            .modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN | APB1EN_BKPIEN));
+        pmu.ctl.modify(|r, w| w.bits(r.bits() | PMU_CTL_BKPWEN));
+    });
+
+    let bdctl = rcu.bdctl.read().bits();
+    let current = (bdctl >> BDCTL_RTCSRC_OFFSET) & 0b11;
+    if current != src || bdctl & BDCTL_RTCEN == 0 {
+        if current != 0 && current != src {
+            // RTCSRC can't be changed without a backup domain reset.
+            rcu.bdctl.modify(|r, w| w.bits(r.bits() | BDCTL_BKPRST));
+            rcu.bdctl.modify(|r, w| w.bits(r.bits() & !BDCTL_BKPRST));
+        }
+
+        if source == RtcClockSource::Lxtal {
+            rcu.bdctl.modify(|r, w| w.bits(r.bits() | BDCTL_LXTALEN));
+        }
+        let res = wait_for(|| ready(rcu), timeout.0, timeout.1);
+        if res.is_ok() {
+            rcu.bdctl.modify(|r, w| {
+                w.bits((r.bits() & !(0b11 << BDCTL_RTCSRC_OFFSET)) | (src << BDCTL_RTCSRC_OFFSET) | BDCTL_RTCEN)
+            });
+        }
+        critical_section::with(|_| pmu.ctl.modify(|r, w| w.bits(r.bits() & !PMU_CTL_BKPWEN)));
+        return res;
+    }
+
+    critical_section::with(|_| pmu.ctl.modify(|r, w| w.bits(r.bits() & !PMU_CTL_BKPWEN)));
+    Ok(())
+}
+
+/// Oscillator start-up and PLL lock timeout, in polling loop iterations.
+const STARTUP_TIMEOUT: u32 = 0xFFFF;
+/// LXTAL start-up timeout. A 32 kHz crystal can take seconds to start.
+const LXTAL_STARTUP_TIMEOUT: u32 = 0x3FF_FFFF;
+
+/// Poll `ready` up to `timeout` times.
+fn wait_for(mut ready: impl FnMut() -> bool, timeout: u32, err: ClockError) -> Result<(), ClockError> {
+    for _ in 0..timeout {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(err)
+}
+
+/// Flash wait states needed at a system clock of `sys`.
+fn wait_states(sys: Hertz) -> u32 {
+    // One wait state for every started 36 MHz above the first.
+    (sys.0.saturating_sub(1) / 36_000_000).min(4)
+}
+
+fn set_wait_states(ws: u32) {
+    let fmc = unsafe { &*pac::FMC::ptr() };
+    fmc.ws.modify(|r, w| unsafe { w.bits((r.bits() & !WS_WSCNT) | ws) });
+}
+
+// RCU_CTL
+const CTL_IRC8MEN: u32 = 1 << 0;
+const CTL_IRC8MSTB: u32 = 1 << 1;
+const CTL_HXTALEN: u32 = 1 << 16;
+const CTL_HXTALSTB: u32 = 1 << 17;
+const CTL_CKMEN: u32 = 1 << 19;
+const CTL_PLLEN: u32 = 1 << 24;
+const CTL_PLLSTB: u32 = 1 << 25;
+
+// RCU_CFG0
+const CFG0_SCS: u32 = 0b11;
+const CFG0_SCSS_OFFSET: u32 = 2;
+const CFG0_AHBPSC_OFFSET: u32 = 4;
+const CFG0_APB1PSC_OFFSET: u32 = 8;
+const CFG0_APB2PSC_OFFSET: u32 = 11;
+const CFG0_PSC_MASK: u32 =
+    (0b1111 << CFG0_AHBPSC_OFFSET) | (0b111 << CFG0_APB1PSC_OFFSET) | (0b111 << CFG0_APB2PSC_OFFSET);
+const CFG0_ADCPSC_MASK: u32 = (0b11 << 14) | (1 << 28);
+const CFG0_PLLSEL: u32 = 1 << 16;
+const CFG0_PLLMF_MASK: u32 = (0b1111 << 18) | (1 << 27) | (1 << 30);
+const CFG0_USBDPSC_MASK: u32 = (0b11 << 22) | (1 << 31);
+
+// RCU_INT
+const INT_CKMIF: u32 = 1 << 7;
+const INT_CKMIC: u32 = 1 << 23;
+
+// RCU_AHBEN
+const AHBEN_SRAMSPEN: u32 = 1 << 2;
+const AHBEN_FMCSPEN: u32 = 1 << 4;
+
+// RCU_APB1EN
+const APB1EN_BKPIEN: u32 = 1 << 27;
+const APB1EN_PMUEN: u32 = 1 << 28;
+
+// RCU_BDCTL
+const BDCTL_LXTALEN: u32 = 1 << 0;
+const BDCTL_LXTALSTB: u32 = 1 << 1;
+const BDCTL_RTCSRC_OFFSET: u32 = 8;
+const BDCTL_RTCEN: u32 = 1 << 15;
+const BDCTL_BKPRST: u32 = 1 << 16;
+
+// RCU_RSTSCK
+const RSTSCK_IRC40KEN: u32 = 1 << 0;
+const RSTSCK_IRC40KSTB: u32 = 1 << 1;
+
+// PMU_CTL
+const PMU_CTL_BKPWEN: u32 = 1 << 8;
+
+// RCU_CFG1
+const CFG1_PREDV0: u32 = 0b1111;
+const CFG1_ADCPSC_3: u32 = 1 << 29;
+const CFG1_PLLPRESEL: u32 = 1 << 30;
+
+// RCU_ADDCTL
+const ADDCTL_CK48MSEL: u32 = 1 << 0;
+const ADDCTL_IRC48MEN: u32 = 1 << 16;
+const ADDCTL_IRC48MSTB: u32 = 1 << 17;
+
+// FMC_WS
+const WS_WSCNT: u32 = 0b111;
+
+/// Switch the system clock to `source` (`SCS` encoding) and wait for the switch to complete.
+fn switch_sys(rcu: &pac::rcu::RegisterBlock, scs: u32) {
+    rcu.cfg0.modify(|r, w| unsafe { w.bits((r.bits() & !CFG0_SCS) | scs) });
+    while (rcu.cfg0.read().bits() >> CFG0_SCSS_OFFSET) & 0b11 != scs {}
+}
+
+/// Whether `config` uses IRC48M, for the USB clock or the PLL.
+fn uses_irc48m(config: &Config) -> bool {
+    config.usb == Some(UsbClockSource::Irc48m) || config.pll.map(|pll| pll.source) == Some(PllSource::Irc48m)
+}
+
+/// Start the oscillators and the RTC clock `config` needs, without running anything else from them.
+///
+/// This runs with interrupts enabled, only the register updates take a critical section each, so
+/// the start-up polls don't block interrupts. The PLL is started too, unless the system clock runs
+/// from it: it can only be reprogrammed while nothing uses it. Returns whether the PLL was started.
+///
+/// If an oscillator doesn't start or the PLL doesn't lock, the system clock is left unchanged.
+unsafe fn start_oscillators(config: &Config) -> Result<bool, ClockError> {
+    let rcu = &*pac::RCU::ptr();
+
+    if config.hxtal.is_some() {
+        critical_section::with(|_| rcu.ctl.modify(|r, w| w.bits(r.bits() | CTL_HXTALEN)));
+        wait_for(
+            || rcu.ctl.read().bits() & CTL_HXTALSTB != 0,
+            STARTUP_TIMEOUT,
+            ClockError::HxtalTimeout,
+        )?;
+        HXTAL_FAILED.store(false, Ordering::Relaxed);
+    }
+
+    if uses_irc48m(config) {
+        critical_section::with(|_| rcu.addctl.modify(|r, w| w.bits(r.bits() | ADDCTL_IRC48MEN)));
+        wait_for(
+            || rcu.addctl.read().bits() & ADDCTL_IRC48MSTB != 0,
+            STARTUP_TIMEOUT,
+            ClockError::Irc48mTimeout,
+        )?;
+    }
+
+    let pll_is_sys = (rcu.cfg0.read().bits() >> CFG0_SCSS_OFFSET) & 0b11 == 0b10;
+    let pll_started = config.pll.is_some() && !pll_is_sys;
+    if pll_started {
+        start_pll(config)?;
+    }
+
+    if let Some(rtc) = config.rtc {
+        apply_rtc(rtc)?;
+    }
+
+    Ok(pll_started)
+}
+
+/// Reprogram the PLL for `config` and wait for it to lock, the system clock must not run from it.
+unsafe fn start_pll(config: &Config) -> Result<(), ClockError> {
+    let rcu = &*pac::RCU::ptr();
+
+    // The PLL can only be configured while it is off.
+    critical_section::with(|_| rcu.ctl.modify(|r, w| w.bits(r.bits() & !CTL_PLLEN)));
+    while rcu.ctl.read().bits() & CTL_PLLSTB != 0 {}
+
+    let Some(pll) = config.pll else {
+        return Ok(());
+    };
+    let mf = pll.mul.bits();
+    let mf = ((mf & 0b1111) << 18) | (((mf >> 4) & 1) << 27) | (((mf >> 5) & 1) << 30);
+    critical_section::with(|_| {
+        let sel = match pll.source {
+            PllSource::Irc8mDiv2 => 0,
+            PllSource::Hxtal | PllSource::Irc48m => {
+                let presel = match pll.source {
+                    PllSource::Irc48m => CFG1_PLLPRESEL,
+                    _ => 0,
+                };
+                rcu.cfg1.modify(|r, w| {
+                    w.bits((r.bits() & !(CFG1_PREDV0 | CFG1_PLLPRESEL)) | presel | (pll.prediv as u32 - 1))
+                });
+                CFG0_PLLSEL
+            }
+        };
+        rcu.cfg0
+            .modify(|r, w| w.bits((r.bits() & !(CFG0_PLLSEL | CFG0_PLLMF_MASK)) | sel | mf));
+        rcu.ctl.modify(|r, w| w.bits(r.bits() | CTL_PLLEN));
+    });
+    wait_for(
+        || rcu.ctl.read().bits() & CTL_PLLSTB != 0,
+        STARTUP_TIMEOUT,
+        ClockError::PllTimeout,
+    )
+}
+
+/// Switch the clock tree to the state described by `config`, once [`start_oscillators`] started
+/// what it needs. `pll_started` is what `start_oscillators` returned.
+///
+/// The switch goes through IRC8M, so the PLL and prescalers can be changed while nothing runs from
+/// them, and the flash wait states are set for the faster of the old and new system clock while
+/// the switch is in progress.
+///
+/// Must run in a critical section. If the PLL has to be reprogrammed here and doesn't lock, this
+/// returns early with the system clock still running from IRC8M.
+unsafe fn apply(config: &Config, old: Clocks, new: &Clocks, pll_started: bool) -> Result<(), ClockError> {
+    let rcu = &*pac::RCU::ptr();
+
+    // Run from IRC8M while the PLL and HXTAL are reconfigured.
+    rcu.ctl.modify(|r, w| w.bits(r.bits() | CTL_IRC8MEN));
+    while rcu.ctl.read().bits() & CTL_IRC8MSTB == 0 {}
+
+    set_wait_states(wait_states(old.sys).max(wait_states(new.sys)));
+
+    switch_sys(rcu, 0b00);
+
+    rcu.ctl.modify(|r, w| w.bits(r.bits() & !CTL_CKMEN));
+    if !pll_started {
+        start_pll(config)?;
+    }
+
+    if config.hxtal.is_some() {
+        if config.hxtal_monitor {
+            rcu.ctl.modify(|r, w| w.bits(r.bits() | CTL_CKMEN));
+        }
+    } else {
+        rcu.ctl.modify(|r, w| w.bits(r.bits() & !CTL_HXTALEN));
+    }
+
+    let psc = (config.ahb_pre.bits() << CFG0_AHBPSC_OFFSET)
+        | (config.apb1_pre.bits() << CFG0_APB1PSC_OFFSET)
+        | (config.apb2_pre.bits() << CFG0_APB2PSC_OFFSET);
+    rcu.cfg0.modify(|r, w| w.bits((r.bits() & !CFG0_PSC_MASK) | psc));
+
+    // ADCPSC is split over CFG0 bits 15:14 and 28, and CFG1 bit 29.
+    let adcpsc = config.adc_pre.bits();
+    rcu.cfg0
+        .modify(|r, w| w.bits((r.bits() & !CFG0_ADCPSC_MASK) | ((adcpsc & 0b11) << 14) | (((adcpsc >> 2) & 1) << 28)));
+    rcu.cfg1
+        .modify(|r, w| w.bits((r.bits() & !CFG1_ADCPSC_3) | (((adcpsc >> 3) & 1) << 29)));
+
+    // USBDPSC is split over CFG0 bits 23:22 and 31.
+    if let Some(usb) = config.usb {
+        let (usbdpsc, ck48msel) = match usb {
+            UsbClockSource::Pll(pre) => (pre.bits(), 0),
+            UsbClockSource::Irc48m => (UsbPrescaler::NotDivided.bits(), ADDCTL_CK48MSEL),
+        };
+        rcu.cfg0.modify(|r, w| {
+            w.bits((r.bits() & !CFG0_USBDPSC_MASK) | ((usbdpsc & 0b11) << 22) | (((usbdpsc >> 2) & 1) << 31))
+        });
+        rcu.addctl
+            .modify(|r, w| w.bits((r.bits() & !ADDCTL_CK48MSEL) | ck48msel));
+    }
+
+    switch_sys(
+        rcu,
+        match config.sys {
+            SysClockSource::Irc8m => 0b00,
+            SysClockSource::Hxtal => 0b01,
+            SysClockSource::Pll => 0b10,
+        },
+    );
+
+    set_wait_states(wait_states(new.sys));
+
+    if !uses_irc48m(config) {
+        rcu.addctl.modify(|r, w| w.bits(r.bits() & !ADDCTL_IRC48MEN));
+    }
+
+    set_sleep_clocks(config.sleep);
+
+    Ok(())
+}
+
+/// Select the clocks kept running in sleep mode, see [`SleepClocks`].
+///
+/// This can also be set as part of the clock configuration, see [`Config::sleep`].
+pub fn set_sleep_clocks(clocks: SleepClocks) {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let set = (clocks.sram as u32 * AHBEN_SRAMSPEN) | (clocks.fmc as u32 * AHBEN_FMCSPEN);
+    critical_section::with(|_| {
+        rcu.ahben
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(AHBEN_SRAMSPEN | AHBEN_FMCSPEN)) | set) })
+    });
+}
+
+/// The clocks currently kept running in sleep mode.
+pub fn sleep_clocks() -> SleepClocks {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let ahben = rcu.ahben.read().bits();
+    SleepClocks {
+        sram: ahben & AHBEN_SRAMSPEN != 0,
+        fmc: ahben & AHBEN_FMCSPEN != 0,
+    }
+}
+
+static HXTAL_FAILED: AtomicBool = AtomicBool::new(false);
+static HXTAL_FAILURE_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Whether the clock monitor detected an HXTAL failure.
+///
+/// Cleared when [`reconfigure`] successfully starts HXTAL again.
+pub fn hxtal_failed() -> bool {
+    HXTAL_FAILED.load(Ordering::Relaxed)
+}
+
+/// Wait until the clock monitor detects an HXTAL failure.
+///
+/// Returns immediately if it already has, see [`hxtal_failed`].
+pub async fn wait_for_hxtal_failure() {
+    poll_fn(|cx| {
+        HXTAL_FAILURE_WAKER.register(cx.waker());
+        match hxtal_failed() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Handle a clock monitor NMI.
+///
+/// Must be called from the `NonMaskableInt` handler if the `ckm-nmi` feature is disabled and the
+/// clock monitor is used. Returns `false` if the NMI was not raised by the clock monitor.
+pub fn on_nmi() -> bool {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    if rcu.int.read().bits() & INT_CKMIF == 0 {
+        return false;
+    }
+    rcu.int.modify(|r, w| unsafe { w.bits(r.bits() | INT_CKMIC) });
+    HXTAL_FAILED.store(true, Ordering::Relaxed);
+
+    // Critical sections don't mask the NMI, so the clock frequencies and the waker are updated
+    // from the RCU interrupt instead.
+    unsafe { interrupt::RCU_CTC::steal() }.pend();
+    true
+}
+
+#[cfg(feature = "ckm-nmi")]
+#[cortex_m_rt::exception]
+fn NonMaskableInt() {
+    on_nmi();
+}
+
+#[interrupt]
+unsafe fn RCU_CTC() {
+    if !HXTAL_FAILED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    critical_section::with(|_| {
+        // The hardware switched the system clock to IRC8M, the prescalers are unchanged. Every
+        // bus clock is the system clock divided by an integer, so scale them by the same divisor.
+        let old = clocks();
+        let sys = IRC8M_FREQ.0;
+        let scale = |f: Hertz| Hertz(sys / (old.sys.0 / f.0));
+        set_freqs(Clocks {
+            sys: Hertz(sys),
+            ahb: scale(old.ahb),
+            apb1: scale(old.apb1),
+            apb2: scale(old.apb2),
+            apb1_timer: scale(old.apb1_timer),
+            apb2_timer: scale(old.apb2_timer),
+            adc: scale(old.adc),
+            // Stops if it runs from HXTAL, keeps running otherwise.
+            rtc: old
+                .rtc
+                .filter(|_| current_rtc_source() != Some(RtcClockSource::HxtalDiv128)),
+            // Stops if it runs from a PLL fed by HXTAL, which the hardware turned off.
+            usb: old.usb.filter(|_| {
+                let rcu = &*pac::RCU::ptr();
+                rcu.addctl.read().bits() & ADDCTL_CK48MSEL != 0 || rcu.ctl.read().bits() & CTL_PLLSTB != 0
+            }),
+            i2s: Hertz(sys),
+        });
+    });
+
+    HXTAL_FAILURE_WAKER.wake();
+}
+
+/// Validate and apply `config`.
+///
+/// The oscillators are started with interrupts enabled, only the switch of the clock source, the
+/// prescalers and the stored frequencies runs in a critical section. If the PLL the system clock
+/// ran from doesn't lock again, this falls back to IRC8M.
+fn configure(config: &Config) -> Result<Clocks, ClockError> {
+    let clocks = compute(config)?;
+    // Only checked for the requested configuration. The reset state and the fallback to IRC8M
+    // are applied anyway, the time driver picks the nearest prescaler for them.
+    #[cfg(feature = "_timedriver-timer")]
+    if crate::time_driver::prescaler(clocks.apb1_timer).is_none() {
+        return Err(ClockError::InvalidTimeDriverClock(clocks.apb1_timer));
+    }
+    let pll_started = unsafe { start_oscillators(config)? };
+    critical_section::with(|_| unsafe {
+        let old = self::clocks();
+        match apply(config, old, &clocks, pll_started) {
+            Ok(()) => {
+                set_freqs(clocks);
+                Ok(clocks)
+            }
+            Err(e) => {
+                // Run undivided from IRC8M, which is always available and within all limits.
+                let fallback = Config {
+                    adc_pre: config.adc_pre,
+                    ..Default::default()
+                };
+                // `apply` failed while running from IRC8M.
+                let fallback_clocks = unwrap!(compute(&fallback));
+                unwrap!(apply(&fallback, old, &fallback_clocks, false));
+                set_freqs(fallback_clocks);
+                Err(e)
+            }
+        }
+    })
+}
+
+pub(crate) unsafe fn init(config: Config) -> Result<Clocks, ClockError> {
+    let irq = interrupt::RCU_CTC::steal();
+    irq.unpend();
+    irq.enable();
+
+    // The reset state, in case `config` is invalid and is not applied.
+    set_freqs(unwrap!(compute(&Config::default())));
+
+    configure(&config)
+}
+
+/// Change the clock configuration at runtime.
+///
+/// The system clock is switched to IRC8M, the PLL and prescalers are reprogrammed, and the system
+/// clock is switched to the new source. HXTAL and the PLL are turned off if the new configuration
+/// doesn't use them.
+///
+/// Drivers compute their bit rates and timings from the clocks when they are created or
+/// configured, so they have to be reconfigured or recreated after the clocks changed.
+///
+/// Oscillators are started with interrupts enabled, LXTAL can take seconds. Interrupts are only
+/// masked while the system clock, the prescalers and the frequencies returned by [`clocks`] are
+/// switched.
+///
+/// If the configuration is invalid or an oscillator fails to start, the clocks are left unchanged.
+/// If the PLL the system clock ran from doesn't lock again, the system keeps running from IRC8M,
+/// undivided. Either way the error is returned.
+pub fn reconfigure(config: Config) -> Result<Clocks, ClockError> {
+    configure(&config)
+}
+
+/// The RTC clock, starting IRC40K for it if no RTC clock is enabled.
+///
+/// Lets drivers that need the RTC running work on boards without LXTAL.
+#[cfg(feature = "timedriver-rtc")]
+pub(crate) fn rtc_clock_or_irc40k() -> Result<Hertz, ClockError> {
+    if let Some(rtc) = clocks().rtc {
+        return Ok(rtc);
+    }
+    unsafe { apply_rtc(RtcClockSource::Irc40k)? };
+    critical_section::with(|_| {
+        set_freqs(Clocks {
+            rtc: Some(IRC40K_FREQ),
+            ..clocks()
+        })
+    });
+    Ok(IRC40K_FREQ)
+}
+
+pub(crate) mod sealed {
+    use crate::time::Hertz;
+
+    pub trait CCTLPeripherial {
+        /// Frequency of the bus clock the peripheral runs from.
+        fn frequency() -> Hertz;
+        fn enable();
+        fn disable();
+        fn reset();
+    }
+}
+
+/// A peripheral with its own clock enable and reset bits in the RCU.
+pub trait CCTLPeripherial: sealed::CCTLPeripherial + 'static {}
+
+/// Implement [`CCTLPeripherial`] for a peripheral clocked from the `Clocks` field `$clk`, with
+/// enable bit `$bit` in `$en` and reset bit `$bit` in `$rst`.
+macro_rules! impl_cctl_periph {
+    ($type:ident, $clk:ident, $en:ident, $rst:ident, $bit:expr) => {
+        impl crate::cctl::sealed::CCTLPeripherial for peripherals::$type {
+            fn frequency() -> crate::time::Hertz {
+                crate::cctl::clocks().$clk
+            }
+
+            fn enable() {
+                critical_section::with(|_| unsafe {
+                    let rcu = &*crate::pac::RCU::ptr();
+                    rcu.$en.modify(|r, w| w.bits(r.bits() | (1 << $bit)));
+                })
+            }
+
+            fn disable() {
+                critical_section::with(|_| unsafe {
+                    let rcu = &*crate::pac::RCU::ptr();
+                    rcu.$en.modify(|r, w| w.bits(r.bits() & !(1 << $bit)));
+                })
+            }
+
+            fn reset() {
+                critical_section::with(|_| unsafe {
+                    let rcu = &*crate::pac::RCU::ptr();
+                    rcu.$rst.modify(|r, w| w.bits(r.bits() | (1 << $bit)));
+                    rcu.$rst.modify(|r, w| w.bits(r.bits() & !(1 << $bit)));
+                })
+            }
+        }
+
+        impl crate::cctl::CCTLPeripherial for peripherals::$type {}
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The PLL at `mul` times 4 MHz, with APB2 at half of it and the ADC at a quarter.
+    fn pll_config(mul: u8) -> Config {
+        let mut config = Config::default();
+        config.pll = Some(Pll {
+            source: PllSource::Irc8mDiv2,
+            prediv: 1,
+            mul: PLLMul::factor(mul),
+        });
+        config.sys = SysClockSource::Pll;
+        config.apb1_pre = APBPrescaler::Div2;
+        config.apb2_pre = APBPrescaler::Div2;
+        config.adc_pre = ADCPrescaler::Apb2Div2;
+        // `None` reads the current RTC clock from the registers.
+        config.rtc = Some(RtcClockSource::Irc40k);
+        config
+    }
+
+    #[test]
+    fn adc_clock_at_limit() {
+        let clocks = compute(&pll_config(40)).unwrap();
+        assert_eq!(clocks.sys, Hertz(160_000_000));
+        assert_eq!(clocks.adc, ADC_MAX_FREQ);
+    }
+
+    #[test]
+    fn adc_clock_above_limit() {
+        assert_eq!(
+            compute(&pll_config(41)).err(),
+            Some(ClockError::AdcTooHigh(Hertz(41_000_000)))
+        );
+
+        let mut config = pll_config(41);
+        config.adc_pre = ADCPrescaler::Apb2Div4;
+        assert_eq!(compute(&config).unwrap().adc, Hertz(20_500_000));
+    }
+}
@@ -0,0 +1,806 @@
+pub use gd32e5::gd32e503 as pac;
+
+/// Flash memory start address
+pub const FLASH_BASE: usize = 0x0800_0000;
+/// Flash memory size of the largest GD32E503 variant (GD32E503xE)
+pub const FLASH_SIZE: usize = 512 * 1024;
+/// Address of the 96-bit unique device ID
+pub const UID_BASE: usize = 0x1FFF_F7E8;
+/// Address of the memory density register: flash size in KiB in the low half-word, SRAM size in
+/// KiB in the high half-word
+pub const MEMORY_DENSITY: usize = 0x1FFF_F7E0;
+/// Start of the system memory, which holds the factory bootloader
+pub const SYSTEM_MEMORY_BASE: usize = 0x1FFF_B000;
+/// Flash layout: a single bank of 8 KiB pages
+pub const FLASH_SECTORS: &[crate::fmc::FlashSector] = &[crate::fmc::FlashSector {
+    offset: 0,
+    size: FLASH_SIZE as u32,
+    page_size: 8 * 1024,
+}];
+
+embassy_hal_common::peripherals! {
+    // GPIO port A
+    PA0,
+    PA1,
+    PA2,
+    PA3,
+    PA4,
+    PA5,
+    PA6,
+    PA7,
+    PA8,
+    PA9,
+    PA10,
+    PA11,
+    PA12,
+    PA13,
+    PA14,
+    PA15,
+
+    // GPIO port B
+    PB0,
+    PB1,
+    PB2,
+    PB3,
+    PB4,
+    PB5,
+    PB6,
+    PB7,
+    PB8,
+    PB9,
+    PB10,
+    PB11,
+    PB12,
+    PB13,
+    PB14,
+    PB15,
+
+    // GPIO port C
+    PC0,
+    PC1,
+    PC2,
+    PC3,
+    PC4,
+    PC5,
+    PC6,
+    PC7,
+    PC8,
+    PC9,
+    PC10,
+    PC11,
+    PC12,
+    PC13,
+    PC14,
+    PC15,
+
+    // GPIO port D
+    PD0,
+    PD1,
+    PD2,
+    PD3,
+    PD4,
+    PD5,
+    PD6,
+    PD7,
+    PD8,
+    PD9,
+    PD10,
+    PD11,
+    PD12,
+    PD13,
+    PD14,
+    PD15,
+
+    // GPIO port E
+    PE0,
+    PE1,
+    PE2,
+    PE3,
+    PE4,
+    PE5,
+    PE6,
+    PE7,
+    PE8,
+    PE9,
+    PE10,
+    PE11,
+    PE12,
+    PE13,
+    PE14,
+    PE15,
+
+    // GPIO port F
+    PF0,
+    PF1,
+    PF2,
+    PF3,
+    PF4,
+    PF5,
+    PF6,
+    PF7,
+    PF8,
+    PF9,
+    PF10,
+    PF11,
+    PF12,
+    PF13,
+    PF14,
+    PF15,
+
+    // GPIO port G
+    PG0,
+    PG1,
+    PG2,
+    PG3,
+    PG4,
+    PG5,
+    PG6,
+    PG7,
+    PG8,
+    PG9,
+    PG10,
+    PG11,
+    PG12,
+    PG13,
+    PG14,
+    PG15,
+
+    // EXTI
+    EXTI0,
+    EXTI1,
+    EXTI2,
+    EXTI3,
+    EXTI4,
+    EXTI5,
+    EXTI6,
+    EXTI7,
+    EXTI8,
+    EXTI9,
+    EXTI10,
+    EXTI11,
+    EXTI12,
+    EXTI13,
+    EXTI14,
+    EXTI15,
+    // EXTI lines of the LVD, RTC alarm and USB wakeup
+    EXTI16,
+    EXTI17,
+    EXTI18,
+
+    // Flash memory controller
+    FMC,
+
+    // Real time clock
+    RTC,
+
+    // Backup registers
+    BKP,
+
+    // CRC calculation unit
+    CRC,
+
+    // Trigonometric math unit
+    TMU,
+
+    // DMA channels
+    DMA0_CH0,
+    DMA0_CH1,
+    DMA0_CH2,
+    DMA0_CH3,
+    DMA0_CH4,
+    DMA0_CH5,
+    DMA0_CH6,
+    DMA1_CH0,
+    DMA1_CH1,
+    DMA1_CH2,
+    DMA1_CH3,
+    DMA1_CH4,
+
+    // ADC
+    ADC0,
+    ADC1,
+
+    // DAC
+    DAC,
+
+    // Comparators
+    CMP1,
+    CMP3,
+    CMP5,
+
+    // USART
+    USART0,
+    USART1,
+    USART2,
+
+    // I2C
+    I2C0,
+    I2C1,
+
+    // CAN
+    CAN0,
+    CAN1,
+
+    // Timers
+    TIMER0,
+    TIMER1,
+    TIMER2,
+    TIMER3,
+    TIMER4,
+    TIMER5,
+    TIMER6,
+    TIMER7,
+    TIMER8,
+    TIMER9,
+    TIMER10,
+    TIMER11,
+    TIMER12,
+    TIMER13,
+
+    // Super high-resolution timer
+    SHRTIMER,
+    SHRTIMER_ST0,
+    SHRTIMER_ST1,
+    SHRTIMER_ST2,
+    SHRTIMER_ST3,
+    SHRTIMER_ST4,
+
+    // USB device
+    USBD,
+
+    // Watchdog timers
+    FWDGT,
+    WWDGT,
+}
+
+impl_pin!(PA0, 0, 0, EXTI0);
+impl_pin!(PA1, 0, 1, EXTI1);
+impl_pin!(PA2, 0, 2, EXTI2);
+impl_pin!(PA3, 0, 3, EXTI3);
+impl_pin!(PA4, 0, 4, EXTI4);
+impl_pin!(PA5, 0, 5, EXTI5);
+impl_pin!(PA6, 0, 6, EXTI6);
+impl_pin!(PA7, 0, 7, EXTI7);
+impl_pin!(PA8, 0, 8, EXTI8);
+impl_pin!(PA9, 0, 9, EXTI9);
+impl_pin!(PA10, 0, 10, EXTI10);
+impl_pin!(PA11, 0, 11, EXTI11);
+impl_pin!(PA12, 0, 12, EXTI12);
+impl_pin!(PA13, 0, 13, EXTI13);
+impl_pin!(PA14, 0, 14, EXTI14);
+impl_pin!(PA15, 0, 15, EXTI15);
+
+impl_pin!(PB0, 1, 0, EXTI0);
+impl_pin!(PB1, 1, 1, EXTI1);
+impl_pin!(PB2, 1, 2, EXTI2);
+impl_pin!(PB3, 1, 3, EXTI3);
+impl_pin!(PB4, 1, 4, EXTI4);
+impl_pin!(PB5, 1, 5, EXTI5);
+impl_pin!(PB6, 1, 6, EXTI6);
+impl_pin!(PB7, 1, 7, EXTI7);
+impl_pin!(PB8, 1, 8, EXTI8);
+impl_pin!(PB9, 1, 9, EXTI9);
+impl_pin!(PB10, 1, 10, EXTI10);
+impl_pin!(PB11, 1, 11, EXTI11);
+impl_pin!(PB12, 1, 12, EXTI12);
+impl_pin!(PB13, 1, 13, EXTI13);
+impl_pin!(PB14, 1, 14, EXTI14);
+impl_pin!(PB15, 1, 15, EXTI15);
+
+impl_pin!(PC0, 2, 0, EXTI0);
+impl_pin!(PC1, 2, 1, EXTI1);
+impl_pin!(PC2, 2, 2, EXTI2);
+impl_pin!(PC3, 2, 3, EXTI3);
+impl_pin!(PC4, 2, 4, EXTI4);
+impl_pin!(PC5, 2, 5, EXTI5);
+impl_pin!(PC6, 2, 6, EXTI6);
+impl_pin!(PC7, 2, 7, EXTI7);
+impl_pin!(PC8, 2, 8, EXTI8);
+impl_pin!(PC9, 2, 9, EXTI9);
+impl_pin!(PC10, 2, 10, EXTI10);
+impl_pin!(PC11, 2, 11, EXTI11);
+impl_pin!(PC12, 2, 12, EXTI12);
+impl_pin!(PC13, 2, 13, EXTI13);
+impl_pin!(PC14, 2, 14, EXTI14);
+impl_pin!(PC15, 2, 15, EXTI15);
+
+impl_pin!(PD0, 3, 0, EXTI0);
+impl_pin!(PD1, 3, 1, EXTI1);
+impl_pin!(PD2, 3, 2, EXTI2);
+impl_pin!(PD3, 3, 3, EXTI3);
+impl_pin!(PD4, 3, 4, EXTI4);
+impl_pin!(PD5, 3, 5, EXTI5);
+impl_pin!(PD6, 3, 6, EXTI6);
+impl_pin!(PD7, 3, 7, EXTI7);
+impl_pin!(PD8, 3, 8, EXTI8);
+impl_pin!(PD9, 3, 9, EXTI9);
+impl_pin!(PD10, 3, 10, EXTI10);
+impl_pin!(PD11, 3, 11, EXTI11);
+impl_pin!(PD12, 3, 12, EXTI12);
+impl_pin!(PD13, 3, 13, EXTI13);
+impl_pin!(PD14, 3, 14, EXTI14);
+impl_pin!(PD15, 3, 15, EXTI15);
+
+impl_pin!(PE0, 4, 0, EXTI0);
+impl_pin!(PE1, 4, 1, EXTI1);
+impl_pin!(PE2, 4, 2, EXTI2);
+impl_pin!(PE3, 4, 3, EXTI3);
+impl_pin!(PE4, 4, 4, EXTI4);
+impl_pin!(PE5, 4, 5, EXTI5);
+impl_pin!(PE6, 4, 6, EXTI6);
+impl_pin!(PE7, 4, 7, EXTI7);
+impl_pin!(PE8, 4, 8, EXTI8);
+impl_pin!(PE9, 4, 9, EXTI9);
+impl_pin!(PE10, 4, 10, EXTI10);
+impl_pin!(PE11, 4, 11, EXTI11);
+impl_pin!(PE12, 4, 12, EXTI12);
+impl_pin!(PE13, 4, 13, EXTI13);
+impl_pin!(PE14, 4, 14, EXTI14);
+impl_pin!(PE15, 4, 15, EXTI15);
+
+impl_pin!(PF0, 5, 0, EXTI0);
+impl_pin!(PF1, 5, 1, EXTI1);
+impl_pin!(PF2, 5, 2, EXTI2);
+impl_pin!(PF3, 5, 3, EXTI3);
+impl_pin!(PF4, 5, 4, EXTI4);
+impl_pin!(PF5, 5, 5, EXTI5);
+impl_pin!(PF6, 5, 6, EXTI6);
+impl_pin!(PF7, 5, 7, EXTI7);
+impl_pin!(PF8, 5, 8, EXTI8);
+impl_pin!(PF9, 5, 9, EXTI9);
+impl_pin!(PF10, 5, 10, EXTI10);
+impl_pin!(PF11, 5, 11, EXTI11);
+impl_pin!(PF12, 5, 12, EXTI12);
+impl_pin!(PF13, 5, 13, EXTI13);
+impl_pin!(PF14, 5, 14, EXTI14);
+impl_pin!(PF15, 5, 15, EXTI15);
+
+impl_pin!(PG0, 6, 0, EXTI0);
+impl_pin!(PG1, 6, 1, EXTI1);
+impl_pin!(PG2, 6, 2, EXTI2);
+impl_pin!(PG3, 6, 3, EXTI3);
+impl_pin!(PG4, 6, 4, EXTI4);
+impl_pin!(PG5, 6, 5, EXTI5);
+impl_pin!(PG6, 6, 6, EXTI6);
+impl_pin!(PG7, 6, 7, EXTI7);
+impl_pin!(PG8, 6, 8, EXTI8);
+impl_pin!(PG9, 6, 9, EXTI9);
+impl_pin!(PG10, 6, 10, EXTI10);
+impl_pin!(PG11, 6, 11, EXTI11);
+impl_pin!(PG12, 6, 12, EXTI12);
+impl_pin!(PG13, 6, 13, EXTI13);
+impl_pin!(PG14, 6, 14, EXTI14);
+impl_pin!(PG15, 6, 15, EXTI15);
+
+impl_remap!(Spi0Remap, PCF0, 0, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(I2c0Remap, PCF0, 1, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Usart0Remap, PCF0, 2, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Usart1Remap, PCF0, 3, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Usart2Remap, PCF0, 4, 2, { None => 0b00, Partial => 0b01, Full => 0b11 });
+impl_remap!(Timer0Remap, PCF0, 6, 2, { None => 0b00, Partial => 0b01, Full => 0b11 });
+impl_remap!(Timer1Remap, PCF0, 8, 2, { None => 0b00, Partial => 0b01, Partial2 => 0b10, Full => 0b11 });
+impl_remap!(Timer2Remap, PCF0, 10, 2, { None => 0b00, Partial => 0b10, Full => 0b11 });
+impl_remap!(Timer3Remap, PCF0, 12, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Can0Remap, PCF0, 13, 2, { None => 0b00, Partial => 0b10, Full => 0b11 });
+impl_remap!(Adc0InjectedTriggerRemap, PCF0, 17, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Adc0RegularTriggerRemap, PCF0, 18, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Adc1InjectedTriggerRemap, PCF0, 19, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Adc1RegularTriggerRemap, PCF0, 20, 1, { None => 0b0, Full => 0b1 });
+impl_remap!(Can1Remap, PCF0, 22, 1, { None => 0b0, Full => 0b1 });
+
+impl_dma_channel!(DMA0_CH0, 0, 0);
+impl_dma_channel!(DMA0_CH1, 0, 1);
+impl_dma_channel!(DMA0_CH2, 0, 2);
+impl_dma_channel!(DMA0_CH3, 0, 3);
+impl_dma_channel!(DMA0_CH4, 0, 4);
+impl_dma_channel!(DMA0_CH5, 0, 5);
+impl_dma_channel!(DMA0_CH6, 0, 6);
+impl_dma_channel!(DMA1_CH0, 1, 0);
+impl_dma_channel!(DMA1_CH1, 1, 1);
+impl_dma_channel!(DMA1_CH2, 1, 2);
+impl_dma_channel!(DMA1_CH3, 1, 3);
+impl_dma_channel!(DMA1_CH4, 1, 4);
+
+impl_cctl_periph!(ADC0, adc, apb2en, apb2rst, 9);
+impl_cctl_periph!(ADC1, adc, apb2en, apb2rst, 10);
+
+impl_adc!(ADC0, ADC0_1,
+    regular: Adc0RegularTriggerRemap {
+        Timer0Ch0 => 0b000,
+        Timer0Ch1 => 0b001,
+        Timer0Ch2 => 0b010,
+        Timer1Ch1 => 0b011,
+        Timer2Trgo => 0b100,
+        Timer3Ch3 => 0b101,
+        Exti11 => 0b110 remap None,
+        Timer7Trgo => 0b110 remap Full,
+        Software => 0b111,
+    },
+    injected: Adc0InjectedTriggerRemap {
+        Timer0Trgo => 0b000,
+        Timer0Ch3 => 0b001,
+        Timer1Trgo => 0b010,
+        Timer1Ch0 => 0b011,
+        Timer2Ch3 => 0b100,
+        Timer3Trgo => 0b101,
+        Exti15 => 0b110 remap None,
+        Timer7Ch3 => 0b110 remap Full,
+        Software => 0b111,
+    },
+);
+impl_adc!(ADC1, ADC0_1,
+    regular: Adc1RegularTriggerRemap {
+        Timer0Ch0 => 0b000,
+        Timer0Ch1 => 0b001,
+        Timer0Ch2 => 0b010,
+        Timer1Ch1 => 0b011,
+        Timer2Trgo => 0b100,
+        Timer3Ch3 => 0b101,
+        Exti11 => 0b110 remap None,
+        Timer7Trgo => 0b110 remap Full,
+        Software => 0b111,
+    },
+    injected: Adc1InjectedTriggerRemap {
+        Timer0Trgo => 0b000,
+        Timer0Ch3 => 0b001,
+        Timer1Trgo => 0b010,
+        Timer1Ch0 => 0b011,
+        Timer2Ch3 => 0b100,
+        Timer3Trgo => 0b101,
+        Exti15 => 0b110 remap None,
+        Timer7Ch3 => 0b110 remap Full,
+        Software => 0b111,
+    },
+);
+impl_adc_pin!(ADC0, {
+    PA0 => 0, PA1 => 1, PA2 => 2, PA3 => 3, PA4 => 4, PA5 => 5, PA6 => 6, PA7 => 7,
+    PB0 => 8, PB1 => 9, PC0 => 10, PC1 => 11, PC2 => 12, PC3 => 13, PC4 => 14, PC5 => 15,
+});
+impl_adc_pin!(ADC1, {
+    PA0 => 0, PA1 => 1, PA2 => 2, PA3 => 3, PA4 => 4, PA5 => 5, PA6 => 6, PA7 => 7,
+    PB0 => 8, PB1 => 9, PC0 => 10, PC1 => 11, PC2 => 12, PC3 => 13, PC4 => 14, PC5 => 15,
+});
+impl_adc_internal!(ADC0, Temperature, 16);
+impl_adc_internal!(ADC0, VrefInt, 17);
+dma_trait_impl!(crate::adc::RxDma, ADC0, DMA0_CH0);
+
+impl_cctl_periph!(WWDGT, apb1, apb1en, apb1rst, 11);
+
+impl_cctl_periph!(DAC, apb1, apb1en, apb1rst, 29);
+
+impl_dac!(DAC);
+pin_trait_impl!(crate::dac::Out0Pin, DAC, { PA4 => [None] });
+pin_trait_impl!(crate::dac::Out1Pin, DAC, { PA5 => [None] });
+dma_trait_impl!(crate::dac::TxDma, DAC, crate::dac::waveform::Ch0, DMA1_CH2);
+dma_trait_impl!(crate::dac::TxDma, DAC, crate::dac::waveform::Ch1, DMA1_CH3);
+impl_dac_trigger_timer!(DAC, {
+    TIMER5 => Timer5Trgo,
+    TIMER7 => Timer7Trgo,
+    TIMER6 => Timer6Trgo,
+    TIMER4 => Timer4Trgo,
+    TIMER1 => Timer1Trgo,
+    TIMER3 => Timer3Trgo,
+});
+
+impl_cmp!(CMP1, 0x20, 19);
+impl_cmp!(CMP3, 0x28, 20);
+impl_cmp!(CMP5, 0x30, 21);
+pin_trait_impl!(crate::cmp::NonInvertingPin, CMP1, { PA7 => [None] });
+pin_trait_impl!(crate::cmp::InvertingPin, CMP1, { PA2 => [None] });
+pin_trait_impl!(crate::cmp::NonInvertingPin, CMP3, { PB0 => [None] });
+pin_trait_impl!(crate::cmp::InvertingPin, CMP3, { PB2 => [None] });
+pin_trait_impl!(crate::cmp::NonInvertingPin, CMP5, { PB11 => [None] });
+pin_trait_impl!(crate::cmp::InvertingPin, CMP5, { PB15 => [None] });
+
+impl_cctl_periph!(USART0, apb2, apb2en, apb2rst, 14);
+impl_cctl_periph!(USART1, apb1, apb1en, apb1rst, 17);
+impl_cctl_periph!(USART2, apb1, apb1en, apb1rst, 18);
+
+impl_usart!(USART0, Usart0Remap);
+impl_usart!(USART1, Usart1Remap);
+impl_usart!(USART2, Usart2Remap);
+pin_trait_impl!(crate::usart::TxPin, USART0, { PA9 => [None], PB6 => [Full] });
+pin_trait_impl!(crate::usart::RxPin, USART0, { PA10 => [None], PB7 => [Full] });
+pin_trait_impl!(crate::usart::CtsPin, USART0, { PA11 => [None, Full] });
+pin_trait_impl!(crate::usart::RtsPin, USART0, { PA12 => [None, Full] });
+pin_trait_impl!(crate::usart::TxPin, USART1, { PA2 => [None], PD5 => [Full] });
+pin_trait_impl!(crate::usart::RxPin, USART1, { PA3 => [None], PD6 => [Full] });
+pin_trait_impl!(crate::usart::CtsPin, USART1, { PA0 => [None], PD3 => [Full] });
+pin_trait_impl!(crate::usart::RtsPin, USART1, { PA1 => [None], PD4 => [Full] });
+pin_trait_impl!(crate::usart::TxPin, USART2, { PB10 => [None], PC10 => [Partial], PD8 => [Full] });
+pin_trait_impl!(crate::usart::RxPin, USART2, { PB11 => [None], PC11 => [Partial], PD9 => [Full] });
+pin_trait_impl!(crate::usart::CtsPin, USART2, { PB13 => [None, Partial], PD11 => [Full] });
+pin_trait_impl!(crate::usart::RtsPin, USART2, { PB14 => [None, Partial], PD12 => [Full] });
+
+impl_cctl_periph!(I2C0, apb1, apb1en, apb1rst, 21);
+impl_cctl_periph!(I2C1, apb1, apb1en, apb1rst, 22);
+
+impl_i2c!(I2C0, I2C0_EV, I2C0_ER, I2c0Remap);
+impl_i2c!(I2C1, I2C1_EV, I2C1_ER);
+pin_trait_impl!(crate::i2c::SclPin, I2C0, { PB6 => [None], PB8 => [Full] });
+pin_trait_impl!(crate::i2c::SdaPin, I2C0, { PB7 => [None], PB9 => [Full] });
+pin_trait_impl!(crate::i2c::SclPin, I2C1, { PB10 => [None] });
+pin_trait_impl!(crate::i2c::SdaPin, I2C1, { PB11 => [None] });
+pin_trait_impl!(crate::i2c::SmbaPin, I2C0, { PB5 => [None, Full] });
+pin_trait_impl!(crate::i2c::SmbaPin, I2C1, { PB12 => [None] });
+dma_trait_impl!(crate::i2c::TxDma, I2C0, DMA0_CH5);
+dma_trait_impl!(crate::i2c::RxDma, I2C0, DMA0_CH6);
+dma_trait_impl!(crate::i2c::TxDma, I2C1, DMA0_CH3);
+dma_trait_impl!(crate::i2c::RxDma, I2C1, DMA0_CH4);
+
+impl_cctl_periph!(CAN0, apb1, apb1en, apb1rst, 25);
+impl_cctl_periph!(CAN1, apb1, apb1en, apb1rst, 26);
+
+impl_can!(CAN0, 0, Can0Remap, USBD_HP_CAN0_TX, USBD_LP_CAN0_RX0, CAN0_RX1);
+impl_can!(CAN1, 14, Can1Remap, CAN1_TX, CAN1_RX0, CAN1_RX1);
+pin_trait_impl!(crate::can::RxPin, CAN0, { PA11 => [None], PB8 => [Partial], PD0 => [Full] });
+pin_trait_impl!(crate::can::TxPin, CAN0, { PA12 => [None], PB9 => [Partial], PD1 => [Full] });
+pin_trait_impl!(crate::can::RxPin, CAN1, { PB12 => [None], PB5 => [Full] });
+pin_trait_impl!(crate::can::TxPin, CAN1, { PB13 => [None], PB6 => [Full] });
+
+impl_cctl_periph!(TIMER0, apb2_timer, apb2en, apb2rst, 11);
+impl_cctl_periph!(TIMER1, apb1_timer, apb1en, apb1rst, 0);
+impl_cctl_periph!(TIMER2, apb1_timer, apb1en, apb1rst, 1);
+impl_cctl_periph!(TIMER3, apb1_timer, apb1en, apb1rst, 2);
+impl_cctl_periph!(TIMER4, apb1_timer, apb1en, apb1rst, 3);
+impl_cctl_periph!(TIMER5, apb1_timer, apb1en, apb1rst, 4);
+impl_cctl_periph!(TIMER6, apb1_timer, apb1en, apb1rst, 5);
+impl_cctl_periph!(TIMER7, apb2_timer, apb2en, apb2rst, 13);
+impl_cctl_periph!(TIMER8, apb2_timer, apb2en, apb2rst, 19);
+impl_cctl_periph!(TIMER9, apb2_timer, apb2en, apb2rst, 20);
+impl_cctl_periph!(TIMER10, apb2_timer, apb2en, apb2rst, 21);
+impl_cctl_periph!(TIMER11, apb1_timer, apb1en, apb1rst, 6);
+impl_cctl_periph!(TIMER12, apb1_timer, apb1en, apb1rst, 7);
+impl_cctl_periph!(TIMER13, apb1_timer, apb1en, apb1rst, 8);
+
+impl_timer!(
+    TIMER0,
+    TIMER0_UP_TIMER9 + TIMER0_BRK_TIMER8 + TIMER0_TRG_CMT_TIMER10 + TIMER0_CHANNEL,
+    Timer0Remap,
+    [
+        MasterInstance,
+        SlaveInstance,
+        CaptureCompareInstance,
+        EncoderInstance,
+        ExternalTriggerInstance,
+        AdvancedInstance
+    ]
+);
+impl_timer!(
+    TIMER1,
+    TIMER1,
+    Timer1Remap,
+    [
+        MasterInstance,
+        SlaveInstance,
+        CaptureCompareInstance,
+        EncoderInstance,
+        ExternalTriggerInstance
+    ]
+);
+impl_timer!(
+    TIMER2,
+    TIMER2,
+    Timer2Remap,
+    [
+        MasterInstance,
+        SlaveInstance,
+        CaptureCompareInstance,
+        EncoderInstance,
+        ExternalTriggerInstance
+    ]
+);
+impl_timer!(
+    TIMER3,
+    TIMER3,
+    Timer3Remap,
+    [
+        MasterInstance,
+        SlaveInstance,
+        CaptureCompareInstance,
+        EncoderInstance,
+        ExternalTriggerInstance
+    ]
+);
+impl_timer!(
+    TIMER4,
+    TIMER4,
+    [MasterInstance, SlaveInstance, CaptureCompareInstance, EncoderInstance]
+);
+impl_timer!(TIMER5, TIMER5, [MasterInstance]);
+impl_timer!(TIMER6, TIMER6, [MasterInstance]);
+impl_timer!(
+    TIMER7,
+    TIMER7_UP_TIMER12 + TIMER7_BRK_TIMER11 + TIMER7_TRG_CMT_TIMER13 + TIMER7_CHANNEL,
+    [
+        MasterInstance,
+        SlaveInstance,
+        CaptureCompareInstance,
+        EncoderInstance,
+        ExternalTriggerInstance,
+        AdvancedInstance
+    ]
+);
+impl_timer!(TIMER8, TIMER0_BRK_TIMER8, [SlaveInstance, CaptureCompareInstance]);
+impl_timer!(TIMER9, TIMER0_UP_TIMER9, [CaptureCompareInstance]);
+impl_timer!(TIMER10, TIMER0_TRG_CMT_TIMER10, [CaptureCompareInstance]);
+impl_timer!(TIMER11, TIMER7_BRK_TIMER11, [SlaveInstance, CaptureCompareInstance]);
+impl_timer!(TIMER12, TIMER7_UP_TIMER12, [CaptureCompareInstance]);
+impl_timer!(TIMER13, TIMER7_TRG_CMT_TIMER13, [CaptureCompareInstance]);
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER0, { PA8 => [None, Partial], PE9 => [Full] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER0, { PA9 => [None, Partial], PE11 => [Full] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER0, { PA10 => [None, Partial], PE13 => [Full] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER0, { PA11 => [None, Partial], PE14 => [Full] });
+pin_trait_impl!(crate::timer::Channel0ComplementaryPin, TIMER0, { PB13 => [None], PA7 => [Partial], PE8 => [Full] });
+pin_trait_impl!(crate::timer::Channel1ComplementaryPin, TIMER0, { PB14 => [None], PB0 => [Partial], PE10 => [Full] });
+pin_trait_impl!(crate::timer::Channel2ComplementaryPin, TIMER0, { PB15 => [None], PB1 => [Partial], PE12 => [Full] });
+pin_trait_impl!(crate::timer::BreakInputPin, TIMER0, { PB12 => [None], PA6 => [Partial], PE15 => [Full] });
+pin_trait_impl!(crate::timer::ExternalTriggerPin, TIMER0, { PA12 => [None, Partial], PE7 => [Full] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER1, { PA0 => [None, Partial2], PA15 => [Partial, Full] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER1, { PA1 => [None, Partial2], PB3 => [Partial, Full] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER1, { PA2 => [None, Partial], PB10 => [Partial2, Full] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER1, { PA3 => [None, Partial], PB11 => [Partial2, Full] });
+pin_trait_impl!(crate::timer::ExternalTriggerPin, TIMER1, { PA0 => [None, Partial2], PA15 => [Partial, Full] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER2, { PA6 => [None], PB4 => [Partial], PC6 => [Full] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER2, { PA7 => [None], PB5 => [Partial], PC7 => [Full] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER2, { PB0 => [None, Partial], PC8 => [Full] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER2, { PB1 => [None, Partial], PC9 => [Full] });
+pin_trait_impl!(crate::timer::ExternalTriggerPin, TIMER2, { PD2 => [None, Partial, Full] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER3, { PB6 => [None], PD12 => [Full] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER3, { PB7 => [None], PD13 => [Full] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER3, { PB8 => [None], PD14 => [Full] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER3, { PB9 => [None], PD15 => [Full] });
+pin_trait_impl!(crate::timer::ExternalTriggerPin, TIMER3, { PE0 => [None, Full] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER4, { PA0 => [None] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER4, { PA1 => [None] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER4, { PA2 => [None] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER4, { PA3 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER7, { PC6 => [None] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER7, { PC7 => [None] });
+pin_trait_impl!(crate::timer::Channel2Pin, TIMER7, { PC8 => [None] });
+pin_trait_impl!(crate::timer::Channel3Pin, TIMER7, { PC9 => [None] });
+pin_trait_impl!(crate::timer::Channel0ComplementaryPin, TIMER7, { PA7 => [None] });
+pin_trait_impl!(crate::timer::Channel1ComplementaryPin, TIMER7, { PB0 => [None] });
+pin_trait_impl!(crate::timer::Channel2ComplementaryPin, TIMER7, { PB1 => [None] });
+pin_trait_impl!(crate::timer::BreakInputPin, TIMER7, { PA6 => [None] });
+pin_trait_impl!(crate::timer::ExternalTriggerPin, TIMER7, { PA0 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER8, { PA2 => [None] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER8, { PA3 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER9, { PB8 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER10, { PB9 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER11, { PB14 => [None] });
+pin_trait_impl!(crate::timer::Channel1Pin, TIMER11, { PB15 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER12, { PA6 => [None] });
+pin_trait_impl!(crate::timer::Channel0Pin, TIMER13, { PA7 => [None] });
+impl_internal_trigger!(TIMER0, { TIMER4 => Internal0, TIMER1 => Internal1, TIMER2 => Internal2, TIMER3 => Internal3 });
+impl_internal_trigger!(TIMER1, { TIMER0 => Internal0, TIMER7 => Internal1, TIMER2 => Internal2, TIMER3 => Internal3 });
+impl_internal_trigger!(TIMER2, { TIMER0 => Internal0, TIMER1 => Internal1, TIMER4 => Internal2, TIMER3 => Internal3 });
+impl_internal_trigger!(TIMER3, { TIMER0 => Internal0, TIMER1 => Internal1, TIMER2 => Internal2, TIMER7 => Internal3 });
+impl_internal_trigger!(TIMER4, { TIMER1 => Internal0, TIMER2 => Internal1, TIMER3 => Internal2, TIMER7 => Internal3 });
+impl_internal_trigger!(TIMER7, { TIMER0 => Internal0, TIMER1 => Internal1, TIMER3 => Internal2, TIMER4 => Internal3 });
+impl_internal_trigger!(TIMER8, { TIMER1 => Internal0, TIMER2 => Internal1 });
+impl_internal_trigger!(TIMER11, { TIMER3 => Internal0, TIMER4 => Internal1 });
+dma_trait_impl!(crate::timer::UpDma, TIMER0, DMA0_CH4);
+dma_trait_impl!(crate::timer::UpDma, TIMER1, DMA0_CH1);
+dma_trait_impl!(crate::timer::UpDma, TIMER2, DMA0_CH2);
+dma_trait_impl!(crate::timer::UpDma, TIMER3, DMA0_CH6);
+dma_trait_impl!(crate::timer::UpDma, TIMER4, DMA1_CH1);
+dma_trait_impl!(crate::timer::UpDma, TIMER5, DMA1_CH2);
+dma_trait_impl!(crate::timer::UpDma, TIMER6, DMA1_CH3);
+dma_trait_impl!(crate::timer::UpDma, TIMER7, DMA1_CH0);
+dma_trait_impl!(crate::timer::CcDma, TIMER0, crate::timer::simple_pwm::Ch0, DMA0_CH1);
+dma_trait_impl!(crate::timer::CcDma, TIMER0, crate::timer::simple_pwm::Ch1, DMA0_CH2);
+dma_trait_impl!(crate::timer::CcDma, TIMER0, crate::timer::simple_pwm::Ch2, DMA0_CH5);
+dma_trait_impl!(crate::timer::CcDma, TIMER0, crate::timer::simple_pwm::Ch3, DMA0_CH3);
+dma_trait_impl!(crate::timer::CcDma, TIMER1, crate::timer::simple_pwm::Ch0, DMA0_CH4);
+dma_trait_impl!(crate::timer::CcDma, TIMER1, crate::timer::simple_pwm::Ch1, DMA0_CH6);
+dma_trait_impl!(crate::timer::CcDma, TIMER1, crate::timer::simple_pwm::Ch2, DMA0_CH0);
+dma_trait_impl!(crate::timer::CcDma, TIMER1, crate::timer::simple_pwm::Ch3, DMA0_CH6);
+dma_trait_impl!(crate::timer::CcDma, TIMER2, crate::timer::simple_pwm::Ch0, DMA0_CH5);
+dma_trait_impl!(crate::timer::CcDma, TIMER2, crate::timer::simple_pwm::Ch2, DMA0_CH1);
+dma_trait_impl!(crate::timer::CcDma, TIMER2, crate::timer::simple_pwm::Ch3, DMA0_CH2);
+dma_trait_impl!(crate::timer::CcDma, TIMER3, crate::timer::simple_pwm::Ch0, DMA0_CH0);
+dma_trait_impl!(crate::timer::CcDma, TIMER3, crate::timer::simple_pwm::Ch1, DMA0_CH3);
+dma_trait_impl!(crate::timer::CcDma, TIMER3, crate::timer::simple_pwm::Ch2, DMA0_CH4);
+dma_trait_impl!(crate::timer::CcDma, TIMER4, crate::timer::simple_pwm::Ch0, DMA1_CH4);
+dma_trait_impl!(crate::timer::CcDma, TIMER4, crate::timer::simple_pwm::Ch1, DMA1_CH3);
+dma_trait_impl!(crate::timer::CcDma, TIMER4, crate::timer::simple_pwm::Ch2, DMA1_CH1);
+dma_trait_impl!(crate::timer::CcDma, TIMER4, crate::timer::simple_pwm::Ch3, DMA1_CH0);
+dma_trait_impl!(crate::timer::CcDma, TIMER7, crate::timer::simple_pwm::Ch0, DMA1_CH2);
+dma_trait_impl!(crate::timer::CcDma, TIMER7, crate::timer::simple_pwm::Ch1, DMA1_CH4);
+dma_trait_impl!(crate::timer::CcDma, TIMER7, crate::timer::simple_pwm::Ch2, DMA1_CH0);
+dma_trait_impl!(crate::timer::CcDma, TIMER7, crate::timer::simple_pwm::Ch3, DMA1_CH1);
+
+impl_cctl_periph!(SHRTIMER, apb2, apb2en, apb2rst, 29);
+
+impl_shrtimer!(SHRTIMER);
+impl_shrtimer_slave!(SHRTIMER_ST0, 0);
+impl_shrtimer_slave!(SHRTIMER_ST1, 1);
+impl_shrtimer_slave!(SHRTIMER_ST2, 2);
+impl_shrtimer_slave!(SHRTIMER_ST3, 3);
+impl_shrtimer_slave!(SHRTIMER_ST4, 4);
+pin_trait_impl!(crate::shrtimer::Channel0Pin, SHRTIMER_ST0, { PA8 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel1Pin, SHRTIMER_ST0, { PA9 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel0Pin, SHRTIMER_ST1, { PA10 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel1Pin, SHRTIMER_ST1, { PA11 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel0Pin, SHRTIMER_ST2, { PB12 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel1Pin, SHRTIMER_ST2, { PB13 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel0Pin, SHRTIMER_ST3, { PB14 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel1Pin, SHRTIMER_ST3, { PB15 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel0Pin, SHRTIMER_ST4, { PC8 => [None] });
+pin_trait_impl!(crate::shrtimer::Channel1Pin, SHRTIMER_ST4, { PC9 => [None] });
+pin_trait_impl!(crate::shrtimer::Fault0Pin, SHRTIMER, { PA12 => [None] });
+pin_trait_impl!(crate::shrtimer::Fault1Pin, SHRTIMER, { PA15 => [None] });
+pin_trait_impl!(crate::shrtimer::Fault2Pin, SHRTIMER, { PB10 => [None] });
+pin_trait_impl!(crate::shrtimer::Fault3Pin, SHRTIMER, { PB11 => [None] });
+pin_trait_impl!(crate::shrtimer::Fault4Pin, SHRTIMER, { PC7 => [None] });
+
+impl_cctl_periph!(USBD, apb1, apb1en, apb1rst, 23);
+
+#[cfg(feature = "nightly")]
+impl_usbd!(USBD, USBD_HP_CAN0_TX, USBD_LP_CAN0_RX0);
+#[cfg(feature = "nightly")]
+pin_trait_impl!(crate::usbd::DpPin, USBD, { PA12 => [None] });
+#[cfg(feature = "nightly")]
+pin_trait_impl!(crate::usbd::DmPin, USBD, { PA11 => [None] });
+
+pub mod irqs {
+    use embassy_cortex_m::interrupt::_export::declare;
+
+    use crate::pac::Interrupt as InterruptEnum;
+
+    declare!(WWDGT);
+    declare!(LVD);
+    declare!(TAMPER);
+    declare!(RTC);
+    declare!(FMC);
+    declare!(RCU_CTC);
+    declare!(EXTI_LINE0);
+    declare!(EXTI_LINE1);
+    declare!(EXTI_LINE2);
+    declare!(EXTI_LINE3);
+    declare!(EXTI_LINE4);
+    declare!(DMA0_CHANNEL0);
+    declare!(DMA0_CHANNEL1);
+    declare!(DMA0_CHANNEL2);
+    declare!(DMA0_CHANNEL3);
+    declare!(DMA0_CHANNEL4);
+    declare!(DMA0_CHANNEL5);
+    declare!(DMA0_CHANNEL6);
+    declare!(ADC0_1);
+    declare!(USBD_HP_CAN0_TX);
+    declare!(USBD_LP_CAN0_RX0);
+    declare!(CAN0_RX1);
+    declare!(EXTI_LINE9_5);
+    declare!(TIMER0_BRK_TIMER8);
+    declare!(TIMER0_UP_TIMER9);
+    declare!(TIMER0_TRG_CMT_TIMER10);
+    declare!(TIMER0_CHANNEL);
+    declare!(TIMER1);
+    declare!(TIMER2);
+    declare!(TIMER3);
+    declare!(I2C0_EV);
+    declare!(I2C0_ER);
+    declare!(I2C1_EV);
+    declare!(I2C1_ER);
+    declare!(EXTI_LINE15_10);
+    declare!(RTC_ALARM);
+    declare!(USBD_WKUP);
+    declare!(TIMER7_BRK_TIMER11);
+    declare!(TIMER7_UP_TIMER12);
+    declare!(TIMER7_TRG_CMT_TIMER13);
+    declare!(TIMER7_CHANNEL);
+    declare!(TIMER4);
+    declare!(TIMER5);
+    declare!(TIMER6);
+    declare!(DMA1_CHANNEL0);
+    declare!(DMA1_CHANNEL1);
+    declare!(DMA1_CHANNEL2);
+    declare!(DMA1_CHANNEL3_4);
+    declare!(CAN1_TX);
+    declare!(CAN1_RX0);
+    declare!(CAN1_RX1);
+    declare!(SHRTIMER_IRQ6);
+    declare!(TMU);
+    declare!(CMP);
+}
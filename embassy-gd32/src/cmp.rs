@@ -0,0 +1,316 @@
+//! Analog comparators (CMP)
+//!
+//! A [`Comparator`] compares the voltage of its non-inverting input pin with a reference: a
+//! fraction of the internal voltage reference, a DAC output or a second pin. Its output is high
+//! while the input is above the reference, within nanoseconds, which makes it the fast path of
+//! an overcurrent protection: routed to the break input of TIMER0 or TIMER7, it turns the PWM
+//! outputs off without the CPU.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::cmp::{Comparator, Config, Hysteresis, Output, Reference};
+//! use embassy_gd32::gpio::Level;
+//! use embassy_gd32::timer::complementary_pwm::BreakInput;
+//!
+//! // Trip above 0.9 V across the shunt, with VREFINT at 1.2 V.
+//! let mut config = Config::default();
+//! config.hysteresis = Hysteresis::Medium;
+//! config.output = Output::Timer0Break;
+//! let mut cmp = Comparator::new(p.CMP1, p.PA7, Reference::VrefintThreeQuarters, config);
+//! // `brk` goes to `ComplementaryPwm::new` of TIMER0.
+//! let brk = BreakInput::new_comparator();
+//!
+//! cmp.wait_for_output(Level::High).await;
+//! // Overcurrent, the PWM outputs are off already.
+//! # }
+//! ```
+//!
+//! The output also drives an EXTI line of its own, which wakes [`Comparator::wait_for_output`]
+//! and the `_edge` functions, and can wake the core from deep-sleep. A DAC reference needs the
+//! DAC channel enabled, see [`crate::dac::Dac`], and sets the trip point in finer steps than
+//! the fractions of VREFINT.
+//!
+//! The comparators are clocked with AFIO, which [`crate::init`] enables.
+#![macro_use]
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::exti::{self, Edge};
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::{AnyPin, Level};
+use crate::{pac, Peripheral};
+
+/// Reference of the inverting input, see `CMPx_MSEL` in `CMPx_CS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reference {
+    /// A quarter of the internal voltage reference, about 0.3 V
+    VrefintQuarter = 0,
+    /// Half of the internal voltage reference, about 0.6 V
+    VrefintHalf = 1,
+    /// Three quarters of the internal voltage reference, about 0.9 V
+    VrefintThreeQuarters = 2,
+    /// The internal voltage reference, about 1.2 V
+    Vrefint = 3,
+    /// Channel 0 of the DAC
+    DacOut0 = 4,
+    /// Channel 1 of the DAC
+    DacOut1 = 5,
+}
+
+/// Hysteresis of the comparator, see `CMPx_HST` in `CMPx_CS`
+///
+/// Hysteresis keeps a noisy input from toggling the output around the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Hysteresis {
+    None = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+}
+
+/// Speed and power consumption of the comparator, see `CMPx_PM` in `CMPx_CS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerMode {
+    /// Shortest propagation delay, highest consumption
+    HighSpeed = 0,
+    MediumSpeed = 1,
+    LowPower = 2,
+    /// Longest propagation delay, lowest consumption
+    VeryLowPower = 3,
+}
+
+/// Timer input the output is routed to, see `CMPx_OSEL` in `CMPx_CS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Output {
+    /// No timer
+    None = 0,
+    /// Break input of TIMER0, see [`BreakInput::new_comparator`]
+    ///
+    /// [`BreakInput::new_comparator`]: crate::timer::complementary_pwm::BreakInput::new_comparator
+    Timer0Break = 1,
+    /// Break input of TIMER7, see [`BreakInput::new_comparator`]
+    ///
+    /// [`BreakInput::new_comparator`]: crate::timer::complementary_pwm::BreakInput::new_comparator
+    Timer7Break = 3,
+    /// Channel 0 input capture of TIMER0
+    Timer0Capture0 = 7,
+    /// Channel 3 input capture of TIMER1
+    Timer1Capture3 = 8,
+    /// Channel 0 input capture of TIMER2
+    Timer2Capture0 = 10,
+}
+
+/// Comparator configuration
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub hysteresis: Hysteresis,
+    pub power_mode: PowerMode,
+    /// Invert the output, so it's high while the input is below the reference.
+    pub invert: bool,
+    pub output: Output,
+}
+
+impl Default for Config {
+    /// No hysteresis, [`PowerMode::HighSpeed`], not inverted and not routed to a timer.
+    fn default() -> Self {
+        Self {
+            hysteresis: Hysteresis::None,
+            power_mode: PowerMode::HighSpeed,
+            invert: false,
+            output: Output::None,
+        }
+    }
+}
+
+/// Comparator driver
+pub struct Comparator<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    _inp: PeripheralRef<'d, AnyPin>,
+    _inm: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> Comparator<'d, T> {
+    /// Compare `inp` with `reference`, and enable the comparator.
+    pub fn new(
+        cmp: impl Peripheral<P = T> + 'd,
+        inp: impl Peripheral<P = impl NonInvertingPin<T>> + 'd,
+        reference: Reference,
+        config: Config,
+    ) -> Self {
+        into_ref!(inp);
+        Self::new_inner(cmp, inp.map_into(), None, reference as u32, config)
+    }
+
+    /// Compare `inp` with `inm`, and enable the comparator.
+    pub fn new_with_pin(
+        cmp: impl Peripheral<P = T> + 'd,
+        inp: impl Peripheral<P = impl NonInvertingPin<T>> + 'd,
+        inm: impl Peripheral<P = impl InvertingPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(inp, inm);
+        Self::new_inner(cmp, inp.map_into(), Some(inm.map_into()), MSEL_PIN, config)
+    }
+
+    fn new_inner(
+        cmp: impl Peripheral<P = T> + 'd,
+        inp: PeripheralRef<'d, AnyPin>,
+        inm: Option<PeripheralRef<'d, AnyPin>>,
+        msel: u32,
+        config: Config,
+    ) -> Self {
+        into_ref!(cmp);
+        unsafe { inp.set_as_analog() };
+        if let Some(inm) = &inm {
+            unsafe { inm.set_as_analog() };
+        }
+
+        let mut this = Self {
+            _peri: cmp,
+            _inp: inp,
+            _inm: inm,
+        };
+        write_cs::<T>(msel << CS_MSEL_OFFSET);
+        this.set_config(config);
+        modify_cs::<T>(CS_EN, 0);
+        this
+    }
+
+    /// Change the hysteresis, power mode, polarity and routing of the output.
+    ///
+    /// Panics if the comparator is locked, see [`Comparator::lock`].
+    pub fn set_config(&mut self, config: Config) {
+        assert!(!self.is_locked(), "comparator is locked");
+        let mut set = (config.hysteresis as u32) << CS_HST_OFFSET
+            | (config.power_mode as u32) << CS_PM_OFFSET
+            | (config.output as u32) << CS_OSEL_OFFSET;
+        if config.invert {
+            set |= CS_PL;
+        }
+        modify_cs::<T>(set, CS_HST | CS_PM | CS_OSEL | CS_PL);
+    }
+
+    /// Lock the configuration until the next system reset, so a fault of the software can't
+    /// disable an overcurrent protection. The comparator can't be disabled anymore, not even by
+    /// dropping the driver.
+    pub fn lock(&mut self) {
+        modify_cs::<T>(CS_LK, 0);
+    }
+
+    /// Whether the configuration is locked, see [`Comparator::lock`].
+    pub fn is_locked(&self) -> bool {
+        read_cs::<T>() & CS_LK != 0
+    }
+
+    /// The output level, after the polarity.
+    pub fn output(&self) -> Level {
+        match read_cs::<T>() & CS_OT != 0 {
+            true => Level::High,
+            false => Level::Low,
+        }
+    }
+
+    /// Wait until the output is at `level`. Returns right away if it's at `level` already.
+    pub async fn wait_for_output(&mut self, level: Level) {
+        let edge = match level {
+            Level::High => Edge::Rising,
+            Level::Low => Edge::Falling,
+        };
+        loop {
+            // Enable the line before checking the output, so no edge is missed.
+            let wait = exti::wait_for_internal(T::EXTI_LINE, edge);
+            if self.output() == level {
+                return;
+            }
+            wait.await;
+        }
+    }
+
+    /// Wait for the output to go from low to high.
+    pub async fn wait_for_rising_edge(&mut self) {
+        exti::wait_for_internal(T::EXTI_LINE, Edge::Rising).await
+    }
+
+    /// Wait for the output to go from high to low.
+    pub async fn wait_for_falling_edge(&mut self) {
+        exti::wait_for_internal(T::EXTI_LINE, Edge::Falling).await
+    }
+
+    /// Wait for the output to change.
+    pub async fn wait_for_any_edge(&mut self) {
+        exti::wait_for_internal(T::EXTI_LINE, Edge::Any).await
+    }
+}
+
+impl<'d, T: Instance> Drop for Comparator<'d, T> {
+    fn drop(&mut self) {
+        if !self.is_locked() {
+            write_cs::<T>(0);
+        }
+    }
+}
+
+fn cs<T: Instance>() -> *mut u32 {
+    (pac::CMP::ptr() as usize + T::CS) as *mut u32
+}
+
+fn read_cs<T: Instance>() -> u32 {
+    unsafe { cs::<T>().read_volatile() }
+}
+
+fn write_cs<T: Instance>(value: u32) {
+    unsafe { cs::<T>().write_volatile(value) }
+}
+
+/// Set the `set` bits and clear the `clear` bits of `CMPx_CS`.
+fn modify_cs<T: Instance>(set: u32, clear: u32) {
+    write_cs::<T>((read_cs::<T>() & !clear) | set)
+}
+
+pub(crate) mod sealed {
+    pub trait Instance {
+        /// Offset of `CMPx_CS`
+        const CS: usize;
+        /// EXTI line driven by the output
+        const EXTI_LINE: u8;
+    }
+}
+
+/// Comparator instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {}
+
+pin_trait!(NonInvertingPin, Instance);
+pin_trait!(InvertingPin, Instance);
+
+macro_rules! impl_cmp {
+    ($inst:ident, $cs:expr, $line:expr) => {
+        impl crate::cmp::sealed::Instance for peripherals::$inst {
+            const CS: usize = $cs;
+            const EXTI_LINE: u8 = $line;
+        }
+
+        impl crate::cmp::Instance for peripherals::$inst {}
+    };
+}
+
+// CMPx_CS
+const CS_EN: u32 = 1 << 0;
+const CS_PM_OFFSET: u32 = 2;
+const CS_PM: u32 = 0b11 << CS_PM_OFFSET;
+const CS_MSEL_OFFSET: u32 = 4;
+/// `CMPx_MSEL` value of the inverting input pin
+const MSEL_PIN: u32 = 6;
+const CS_OSEL_OFFSET: u32 = 10;
+const CS_OSEL: u32 = 0b1111 << CS_OSEL_OFFSET;
+const CS_PL: u32 = 1 << 15;
+const CS_HST_OFFSET: u32 = 16;
+const CS_HST: u32 = 0b11 << CS_HST_OFFSET;
+const CS_OT: u32 = 1 << 30;
+const CS_LK: u32 = 1 << 31;
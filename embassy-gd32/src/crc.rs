@@ -0,0 +1,215 @@
+//! CRC calculation unit
+//!
+//! [`Crc`] computes a CRC in hardware, e.g. to validate a firmware image or the frames of a
+//! protocol. The polynomial of 7, 8, 16 or 32 bits, the initial value and the bit reversal of
+//! the input and output are configurable, see [`Config`].
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::crc::{Config, Crc};
+//!
+//! // The CRC-32 of zlib and Ethernet
+//! let mut crc = Crc::new(p.CRC, Config::crc32());
+//! crc.feed(b"123456789");
+//! assert_eq!(crc.finish(), 0xCBF4_3926);
+//!
+//! // Large buffers go faster by DMA.
+//! let image = [0u8; 4096];
+//! crc.feed_dma(p.DMA0_CH1, &image).await;
+//! let checksum = crc.finish();
+//! # }
+//! ```
+//!
+//! Any DMA channel can feed the unit, the data is copied from memory without a peripheral
+//! request.
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+
+use crate::dma::{Channel, Transfer};
+use crate::pac;
+
+/// Generator polynomial, without the leading one
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polynomial {
+    Bits7(u8),
+    Bits8(u8),
+    Bits16(u16),
+    Bits32(u32),
+}
+
+impl Polynomial {
+    /// `PS` value and mask of the CRC width
+    fn size(self) -> (u32, u32) {
+        match self {
+            Polynomial::Bits32(_) => (0b00, 0xFFFF_FFFF),
+            Polynomial::Bits16(_) => (0b01, 0xFFFF),
+            Polynomial::Bits8(_) => (0b10, 0xFF),
+            Polynomial::Bits7(_) => (0b11, 0x7F),
+        }
+    }
+
+    fn value(self) -> u32 {
+        match self {
+            Polynomial::Bits7(p) | Polynomial::Bits8(p) => p as u32,
+            Polynomial::Bits16(p) => p as u32,
+            Polynomial::Bits32(p) => p,
+        }
+    }
+}
+
+/// Bit reversal of the input
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InputReverse {
+    /// The bits go in as they are, most significant bit first.
+    None,
+    /// The bits of each byte are reversed, so each byte goes in least significant bit first.
+    Byte,
+    /// The bits of each half-word are reversed.
+    HalfWord,
+    /// The bits of each word are reversed.
+    Word,
+}
+
+/// CRC configuration
+#[non_exhaustive]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub polynomial: Polynomial,
+    /// Initial value of the CRC
+    pub initial: u32,
+    pub reverse_input: InputReverse,
+    /// Reverse the bits of the result.
+    pub reverse_output: bool,
+    /// Value XORed with the result by [`Crc::finish`]
+    pub xor_output: u32,
+}
+
+impl Default for Config {
+    /// The reset configuration of the unit, the CRC-32 of MPEG-2: polynomial `0x04C11DB7`,
+    /// initial value `0xFFFFFFFF`, no reversal.
+    fn default() -> Self {
+        Self {
+            polynomial: Polynomial::Bits32(0x04C1_1DB7),
+            initial: 0xFFFF_FFFF,
+            reverse_input: InputReverse::None,
+            reverse_output: false,
+            xor_output: 0,
+        }
+    }
+}
+
+impl Config {
+    /// The CRC-32 of zlib, Ethernet and PNG
+    pub fn crc32() -> Self {
+        Self {
+            reverse_input: InputReverse::Byte,
+            reverse_output: true,
+            xor_output: 0xFFFF_FFFF,
+            ..Default::default()
+        }
+    }
+}
+
+/// CRC calculation unit
+pub struct Crc<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::CRC>,
+    config: Config,
+}
+
+impl<'d> Crc<'d> {
+    /// Create a CRC unit with the given configuration, ready for a first CRC.
+    pub fn new(inner: impl Peripheral<P = crate::peripherals::CRC> + 'd, config: Config) -> Self {
+        into_ref!(inner);
+        critical_section::with(|_| unsafe {
+            let rcu = &*pac::RCU::ptr();
+            rcu.ahben.modify(|r, w| w.bits(r.bits() | AHBEN_CRCEN));
+        });
+
+        let mut crc = Self { _inner: inner, config };
+        crc.reconfigure(config);
+        crc
+    }
+
+    /// Change the configuration, which starts a new CRC.
+    pub fn reconfigure(&mut self, config: Config) {
+        self.config = config;
+        let (size, _) = config.polynomial.size();
+        let reverse_input = match config.reverse_input {
+            InputReverse::None => 0b00,
+            InputReverse::Byte => 0b01,
+            InputReverse::HalfWord => 0b10,
+            InputReverse::Word => 0b11,
+        };
+        unsafe {
+            reg(IDATA).write_volatile(config.initial);
+            reg(POLY).write_volatile(config.polynomial.value());
+            reg(CTL).write_volatile(
+                size << CTL_PS_OFFSET
+                    | reverse_input << CTL_REV_I_OFFSET
+                    | if config.reverse_output { CTL_REV_O } else { 0 },
+            );
+        }
+        self.reset();
+    }
+
+    /// Start a new CRC from the initial value.
+    pub fn reset(&mut self) {
+        unsafe { reg(CTL).write_volatile(reg(CTL).read_volatile() | CTL_RST) };
+    }
+
+    /// Feed `data` into the CRC, one byte at a time.
+    pub fn feed(&mut self, data: &[u8]) {
+        let dst = reg(DATA) as *mut u8;
+        for &byte in data {
+            unsafe { dst.write_volatile(byte) };
+        }
+    }
+
+    /// Feed `words` into the CRC, one word at a time, which is four times faster than bytes. The
+    /// words go in most significant byte first, unless the input is reversed.
+    pub fn feed_words(&mut self, words: &[u32]) {
+        for &word in words {
+            unsafe { reg(DATA).write_volatile(word) };
+        }
+    }
+
+    /// Feed `data` into the CRC by DMA, over `dma`.
+    pub async fn feed_dma(&mut self, dma: impl Peripheral<P = impl Channel>, data: &[u8]) {
+        into_ref!(dma);
+        // A transfer takes at most 65535 bytes.
+        for chunk in data.chunks(0xFFFF) {
+            unsafe { Transfer::new_copy(dma.reborrow(), chunk, reg(DATA) as *mut u8) }.await;
+        }
+    }
+
+    /// The CRC of the data fed since the last reset, which starts a new CRC.
+    pub fn finish(&mut self) -> u32 {
+        let (_, mask) = self.config.polynomial.size();
+        let crc = unsafe { reg(DATA).read_volatile() } & mask;
+        self.reset();
+        (crc ^ self.config.xor_output) & mask
+    }
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (pac::CRC::ptr() as usize + offset) as *mut u32
+}
+
+// Register offsets
+const DATA: usize = 0x00;
+const CTL: usize = 0x08;
+const IDATA: usize = 0x10;
+const POLY: usize = 0x14;
+
+// CRC_CTL
+const CTL_RST: u32 = 1 << 0;
+const CTL_PS_OFFSET: u32 = 3;
+const CTL_REV_I_OFFSET: u32 = 5;
+const CTL_REV_O: u32 = 1 << 7;
+
+// RCU_AHBEN
+const AHBEN_CRCEN: u32 = 1 << 6;
@@ -0,0 +1,481 @@
+//! Digital to analog converter (DAC)
+//!
+//! [`Dac`] drives the two output channels of the DAC, OUT0 on PA4 and OUT1 on PA5, each with a
+//! 12-bit value from 0 at VSSA to 4095 at VREF+.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::dac::{Channel, Dac, Value};
+//!
+//! let mut dac = Dac::new_ch0(p.DAC, p.PA4);
+//! // About a quarter of VREF+
+//! dac.set_value(Channel::Ch0, Value::Bit12Right(1024));
+//! ```
+//!
+//! By default a value is output right away, one APB1 clock after it's written. With a
+//! [`Trigger`], see [`Dac::set_trigger`], the written value waits for the trigger instead, e.g. the
+//! update events of a timer for a steady sample rate, or [`Dac::trigger`] at a moment of the
+//! software's choosing.
+//!
+//! The output buffer lowers the output impedance to drive loads directly, at the cost of an
+//! output range that doesn't reach the supply rails. Without it, the output reaches from VSSA to
+//! VREF+ but needs a high impedance load, see [`Dac::set_output_buffer`].
+//!
+//! [`Dac::set_voltage`] sets a channel to a voltage rather than a value. It's as accurate as
+//! the voltage of VREF+ it's given with [`Dac::set_reference`], which follows the analog supply
+//! when measured with the ADC, see [`crate::adc::Adc::read_vref_mv`]:
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::adc::Adc;
+//! use embassy_gd32::dac::{Channel, Dac};
+//!
+//! let mut adc = Adc::new(p.ADC0);
+//! let mut dac = Dac::new_ch0(p.DAC, p.PA4);
+//! dac.set_reference(adc.read_vref_mv().await);
+//! dac.set_voltage(Channel::Ch0, 1_250);
+//! # }
+//! ```
+//!
+//! [`Dac::set_values`] writes both channels at once, through the concurrent data holding
+//! registers. With the same trigger on both channels, see [`Dac::set_trigger_both`], stereo or
+//! I/Q outputs change together.
+//!
+//! [`waveform::DacWaveform`] streams a buffer of samples to a channel at a timer's rate. Without
+//! a sample table, the channel's own [`Wave`] generator adds noise or a triangle to its value
+//! at each trigger, see [`Dac::set_wave`].
+#![macro_use]
+
+pub mod waveform;
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::AnyPin;
+use crate::Peripheral;
+
+/// Output channel of the DAC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    /// Channel 0, OUT0
+    Ch0 = 0,
+    /// Channel 1, OUT1
+    Ch1 = 1,
+}
+
+impl Channel {
+    /// Shift of the fields of the channel in `DAC_CTL`
+    fn shift(self) -> u32 {
+        16 * self as u32
+    }
+
+    /// Offset of the data registers of the channel
+    fn data_offset(self) -> usize {
+        0x0C * self as usize
+    }
+}
+
+/// Value of a channel, in one of the formats of the data holding registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value {
+    /// 8-bit value, the upper 8 bits of the 12-bit output
+    Bit8(u8),
+    /// 12-bit value in the low 12 bits
+    Bit12Right(u16),
+    /// 12-bit value in the high 12 bits, the low 4 bits are ignored
+    Bit12Left(u16),
+}
+
+impl Value {
+    /// Offset of the data holding register, from the first one of a channel, and its contents.
+    fn register(self) -> (usize, u32) {
+        match self {
+            Value::Bit12Right(v) => (R12DH, (v & 0xFFF) as u32),
+            Value::Bit12Left(v) => (L12DH, (v & 0xFFF0) as u32),
+            Value::Bit8(v) => (R8DH, v as u32),
+        }
+    }
+}
+
+/// Values of both channels, in one of the formats of the concurrent data holding registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DualValue {
+    /// 8-bit values of channel 0 and 1, like [`Value::Bit8`]
+    Bit8(u8, u8),
+    /// 12-bit values of channel 0 and 1 in their low 12 bits, like [`Value::Bit12Right`]
+    Bit12Right(u16, u16),
+    /// 12-bit values of channel 0 and 1 in their high 12 bits, like [`Value::Bit12Left`]
+    Bit12Left(u16, u16),
+}
+
+impl DualValue {
+    /// Offset of the concurrent data holding register, from the first one, and its contents.
+    fn register(self) -> (usize, u32) {
+        match self {
+            DualValue::Bit12Right(v0, v1) => (R12DH, (v0 & 0xFFF) as u32 | ((v1 & 0xFFF) as u32) << 16),
+            DualValue::Bit12Left(v0, v1) => (L12DH, (v0 & 0xFFF0) as u32 | ((v1 & 0xFFF0) as u32) << 16),
+            DualValue::Bit8(v0, v1) => (R8DH, v0 as u32 | (v1 as u32) << 8),
+        }
+    }
+}
+
+/// What moves a written value to the output of a channel, see `DTSELx` in `DAC_CTL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    /// The trigger output `TRGO` of TIMER5
+    Timer5Trgo = 0b000,
+    /// The trigger output `TRGO` of TIMER7
+    Timer7Trgo = 0b001,
+    /// The trigger output `TRGO` of TIMER6
+    Timer6Trgo = 0b010,
+    /// The trigger output `TRGO` of TIMER4
+    Timer4Trgo = 0b011,
+    /// The trigger output `TRGO` of TIMER1
+    Timer1Trgo = 0b100,
+    /// The trigger output `TRGO` of TIMER3
+    Timer3Trgo = 0b101,
+    /// The EXTI line 9
+    Exti9 = 0b110,
+    /// [`Dac::trigger`]
+    Software = 0b111,
+}
+
+/// Wave a channel adds to its value at each trigger, see `DWM` and `DWBW` in `DAC_CTL`
+///
+/// Both take a bit width from 1 to 12. The sum with the value saturates at 4095.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Wave {
+    /// The low `bits` bits of a pseudo-random LFSR, e.g. to dither the value
+    Noise(u8),
+    /// A triangle counting up from 0 to `2^bits - 1` and back down, one step per trigger
+    Triangle(u8),
+}
+
+/// DAC driver
+pub struct Dac<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    out0: Option<PeripheralRef<'d, AnyPin>>,
+    out1: Option<PeripheralRef<'d, AnyPin>>,
+    /// Voltage of VREF+ in millivolts, for [`Dac::set_voltage`]
+    vref_mv: u32,
+}
+
+impl<'d, T: Instance> Dac<'d, T> {
+    /// Drive both channels, with `out0` and `out1` as outputs.
+    pub fn new(
+        dac: impl Peripheral<P = T> + 'd,
+        out0: impl Peripheral<P = impl Out0Pin<T>> + 'd,
+        out1: impl Peripheral<P = impl Out1Pin<T>> + 'd,
+    ) -> Self {
+        into_ref!(out0, out1);
+        crate::afio::check_default_layout(&[out0.remaps(), out1.remaps()]);
+        Self::new_inner(dac, Some(out0.map_into()), Some(out1.map_into()))
+    }
+
+    /// Drive channel 0 only, with `out0` as output.
+    pub fn new_ch0(dac: impl Peripheral<P = T> + 'd, out0: impl Peripheral<P = impl Out0Pin<T>> + 'd) -> Self {
+        into_ref!(out0);
+        crate::afio::check_default_layout(&[out0.remaps()]);
+        Self::new_inner(dac, Some(out0.map_into()), None)
+    }
+
+    /// Drive channel 1 only, with `out1` as output.
+    pub fn new_ch1(dac: impl Peripheral<P = T> + 'd, out1: impl Peripheral<P = impl Out1Pin<T>> + 'd) -> Self {
+        into_ref!(out1);
+        crate::afio::check_default_layout(&[out1.remaps()]);
+        Self::new_inner(dac, None, Some(out1.map_into()))
+    }
+
+    /// Enable the channels that have an output, with the output buffer on and no trigger.
+    fn new_inner(
+        dac: impl Peripheral<P = T> + 'd,
+        out0: Option<PeripheralRef<'d, AnyPin>>,
+        out1: Option<PeripheralRef<'d, AnyPin>>,
+    ) -> Self {
+        into_ref!(dac);
+
+        T::enable();
+        T::reset();
+
+        let mut this = Self {
+            _peri: dac,
+            out0,
+            out1,
+            vref_mv: DEFAULT_VREF_MV,
+        };
+        for channel in [Channel::Ch0, Channel::Ch1] {
+            if let Some(pin) = this.pin(channel) {
+                // The analog mode disconnects the digital input, which would draw current at
+                // intermediate voltages.
+                unsafe { pin.set_as_analog() };
+                this.enable(channel);
+            }
+        }
+        this
+    }
+
+    fn pin(&self, channel: Channel) -> Option<&PeripheralRef<'d, AnyPin>> {
+        match channel {
+            Channel::Ch0 => self.out0.as_ref(),
+            Channel::Ch1 => self.out1.as_ref(),
+        }
+    }
+
+    /// Panic if `channel` has no output.
+    fn check_channel(&self, channel: Channel) {
+        assert!(self.pin(channel).is_some(), "the DAC channel has no output");
+    }
+
+    /// Enable `channel`, which outputs its value then. Panics if the channel has no output, like
+    /// all functions of a channel.
+    pub fn enable(&mut self, channel: Channel) {
+        self.check_channel(channel);
+        modify_ctl::<T>(CTL_DEN << channel.shift(), 0);
+    }
+
+    /// Disable `channel`, which saves power. The output is high impedance then, and keeps its
+    /// value.
+    pub fn disable(&mut self, channel: Channel) {
+        self.check_channel(channel);
+        modify_ctl::<T>(0, CTL_DEN << channel.shift());
+    }
+
+    /// Turn the output buffer of `channel` on or off. It's on by default.
+    pub fn set_output_buffer(&mut self, channel: Channel, enabled: bool) {
+        self.check_channel(channel);
+        // The bit turns the buffer off.
+        match enabled {
+            true => modify_ctl::<T>(0, CTL_DBOFF << channel.shift()),
+            false => modify_ctl::<T>(CTL_DBOFF << channel.shift(), 0),
+        }
+    }
+
+    /// Set what moves the written values of `channel` to its output, or `None` to output them
+    /// right away, the default.
+    pub fn set_trigger(&mut self, channel: Channel, trigger: Option<Trigger>) {
+        self.check_channel(channel);
+        let shift = channel.shift();
+        match trigger {
+            Some(trigger) => modify_ctl::<T>(
+                (CTL_DTEN | ((trigger as u32) << CTL_DTSEL_OFFSET)) << shift,
+                CTL_DTSEL << shift,
+            ),
+            None => modify_ctl::<T>(0, (CTL_DTEN | CTL_DTSEL) << shift),
+        }
+    }
+
+    /// Add `wave` to the value of `channel` at each trigger, or `None` to output the value alone,
+    /// the default.
+    ///
+    /// The wave only advances at the triggers, so it needs one, see [`Dac::set_trigger`], e.g. a
+    /// timer whose rate sets the pitch of the triangle. Panics if the bit width of the wave
+    /// isn't between 1 and 12.
+    pub fn set_wave(&mut self, channel: Channel, wave: Option<Wave>) {
+        self.check_channel(channel);
+        let (mode, bits) = match wave {
+            None => (CTL_DWM_DISABLED, 1),
+            Some(Wave::Noise(bits)) => (CTL_DWM_NOISE, bits),
+            Some(Wave::Triangle(bits)) => (CTL_DWM_TRIANGLE, bits),
+        };
+        assert!((1..=12).contains(&bits), "wave bit width must be between 1 and 12");
+        let shift = channel.shift();
+        modify_ctl::<T>(
+            (mode | ((bits as u32 - 1) << CTL_DWBW_OFFSET)) << shift,
+            (CTL_DWM | CTL_DWBW) << shift,
+        );
+    }
+
+    /// Output the written value of `channel`, with [`Trigger::Software`] as trigger.
+    pub fn trigger(&mut self, channel: Channel) {
+        self.check_channel(channel);
+        // The bit clears itself once the value is output.
+        regs::<T>().write(SWT, 1 << channel as u32);
+    }
+
+    /// Write `value` to `channel`. It's output right away, or at the next trigger, see
+    /// [`Dac::set_trigger`].
+    pub fn set_value(&mut self, channel: Channel, value: Value) {
+        self.check_channel(channel);
+        let (offset, bits) = value.register();
+        regs::<T>().write(DH0 + channel.data_offset() + offset, bits);
+    }
+
+    /// Write the values of both channels with a single access. Each is output right away, or at
+    /// the next trigger of its channel.
+    ///
+    /// Panics if a channel has no output.
+    pub fn set_values(&mut self, values: DualValue) {
+        self.check_channel(Channel::Ch0);
+        self.check_channel(Channel::Ch1);
+        let (offset, bits) = values.register();
+        regs::<T>().write(DUAL_DH + offset, bits);
+    }
+
+    /// Set the same trigger for both channels with a single access, so they output their values
+    /// at the same triggers, or `None` to output them right away. Panics if a channel has no
+    /// output.
+    pub fn set_trigger_both(&mut self, trigger: Option<Trigger>) {
+        self.check_channel(Channel::Ch0);
+        self.check_channel(Channel::Ch1);
+        let both = |bits: u32| bits | bits << Channel::Ch1.shift();
+        match trigger {
+            Some(trigger) => modify_ctl::<T>(both(CTL_DTEN | ((trigger as u32) << CTL_DTSEL_OFFSET)), both(CTL_DTSEL)),
+            None => modify_ctl::<T>(0, both(CTL_DTEN | CTL_DTSEL)),
+        }
+    }
+
+    /// Output the written values of both channels together, with [`Trigger::Software`] as the
+    /// trigger of both. Panics if a channel has no output.
+    pub fn trigger_both(&mut self) {
+        self.check_channel(Channel::Ch0);
+        self.check_channel(Channel::Ch1);
+        regs::<T>().write(SWT, 0b11);
+    }
+
+    /// Set the voltage of VREF+ in millivolts, which [`Dac::set_voltage`] converts with. It's
+    /// 3300 by default.
+    ///
+    /// Measuring it again from time to time, e.g. with [`crate::adc::Adc::read_vref_mv`], keeps
+    /// the output voltages accurate while the supply drifts. Panics if `vref_mv` is zero.
+    pub fn set_reference(&mut self, vref_mv: u32) {
+        assert!(vref_mv != 0, "reference voltage must not be zero");
+        self.vref_mv = vref_mv;
+    }
+
+    /// Set `channel` to `mv` millivolts, the nearest 12-bit value for the voltage of VREF+ set
+    /// with [`Dac::set_reference`]. Voltages above VREF+ output the largest value.
+    ///
+    /// The value is written like with [`Dac::set_value`]. With the output buffer on, voltages
+    /// close to the supply rails aren't reached, see [`Dac::set_output_buffer`].
+    pub fn set_voltage(&mut self, channel: Channel, mv: u32) {
+        let value = (mv as u64 * 4095 + self.vref_mv as u64 / 2) / self.vref_mv as u64;
+        self.set_value(channel, Value::Bit12Right(value.min(4095) as u16));
+    }
+
+    /// The value `channel` outputs, as a 12-bit value.
+    pub fn value(&self, channel: Channel) -> u16 {
+        self.check_channel(channel);
+        regs::<T>().read(DO0 + 4 * channel as usize) as u16
+    }
+}
+
+impl<'d, T: Instance> Drop for Dac<'d, T> {
+    fn drop(&mut self) {
+        regs::<T>().write(CTL, 0);
+        T::disable();
+    }
+}
+
+/// Voltage of VREF+ until [`Dac::set_reference`] is called, the usual 3.3 V supply
+const DEFAULT_VREF_MV: u32 = 3300;
+
+struct Regs(usize);
+
+impl Regs {
+    fn read(self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the `set` bits and clear the `clear` bits of the register at `offset`.
+    fn modify(self, offset: usize, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set)
+    }
+}
+
+fn regs<T: Instance>() -> Regs {
+    Regs(T::base())
+}
+
+/// Set the `set` bits and clear the `clear` bits of `DAC_CTL`.
+fn modify_ctl<T: Instance>(set: u32, clear: u32) {
+    critical_section::with(|_| regs::<T>().modify(CTL, set, clear))
+}
+
+pub(crate) mod sealed {
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        fn base() -> usize;
+    }
+
+    pub trait ChannelMarker {
+        const CHANNEL: super::Channel;
+    }
+
+    pub trait TriggerTimer<T> {
+        const TRIGGER: super::Trigger;
+    }
+}
+
+/// DAC peripheral instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {}
+
+/// Type-level [`Channel`], such as [`waveform::Ch0`]
+pub trait ChannelMarker: sealed::ChannelMarker {}
+
+/// Timer whose trigger output is a [`Trigger`] of DAC `T`
+pub trait TriggerTimer<T: Instance>: crate::timer::MasterInstance + sealed::TriggerTimer<T> {}
+
+pin_trait!(Out0Pin, Instance);
+pin_trait!(Out1Pin, Instance);
+dma_trait!(TxDma, Instance, ChannelMarker);
+
+macro_rules! impl_dac {
+    ($inst:ident) => {
+        impl crate::dac::sealed::Instance for peripherals::$inst {
+            fn base() -> usize {
+                crate::pac::$inst::ptr() as usize
+            }
+        }
+
+        impl crate::dac::Instance for peripherals::$inst {}
+    };
+}
+
+macro_rules! impl_dac_trigger_timer {
+    ($inst:ident, { $($timer:ident => $trigger:ident),* $(,)? }) => {
+        $(
+            impl crate::dac::sealed::TriggerTimer<peripherals::$inst> for peripherals::$timer {
+                const TRIGGER: crate::dac::Trigger = crate::dac::Trigger::$trigger;
+            }
+
+            impl crate::dac::TriggerTimer<peripherals::$inst> for peripherals::$timer {}
+        )*
+    };
+}
+
+// Register offsets
+const CTL: usize = 0x00;
+const SWT: usize = 0x04;
+/// First data holding register of channel 0, `DAC0_R12DH`. Those of channel 1 follow at
+/// [`Channel::data_offset`].
+const DH0: usize = 0x08;
+const R12DH: usize = 0x00;
+const L12DH: usize = 0x04;
+const R8DH: usize = 0x08;
+/// First concurrent data holding register of both channels, `DACC_R12DH`
+const DUAL_DH: usize = 0x20;
+/// Output data register of channel 0, followed by the one of channel 1
+const DO0: usize = 0x2C;
+
+// DAC_CTL, the fields of channel 0, those of channel 1 follow at `Channel::shift`
+const CTL_DEN: u32 = 1 << 0;
+const CTL_DBOFF: u32 = 1 << 1;
+const CTL_DTEN: u32 = 1 << 2;
+const CTL_DTSEL_OFFSET: u32 = 3;
+const CTL_DTSEL: u32 = 0b111 << 3;
+const CTL_DWM: u32 = 0b11 << 6;
+const CTL_DWM_DISABLED: u32 = 0b00 << 6;
+const CTL_DWM_NOISE: u32 = 0b01 << 6;
+const CTL_DWM_TRIANGLE: u32 = 0b10 << 6;
+const CTL_DWBW_OFFSET: u32 = 8;
+const CTL_DWBW: u32 = 0b1111 << 8;
+const CTL_DDMAEN: u32 = 1 << 12;
@@ -0,0 +1,152 @@
+//! Waveform output through a DMA ring buffer
+//!
+//! [`DacWaveform`] outputs the samples of a buffer at a steady sample rate, over and over: a
+//! timer triggers each conversion with its update events, and each trigger requests the next
+//! sample from a circular DMA transfer. Once started, the waveform needs no CPU time.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::dac::waveform::DacWaveform;
+//! use embassy_gd32::dac::Dac;
+//! use embassy_gd32::time::Hertz;
+//!
+//! let mut dac = Dac::new_ch0(p.DAC, p.PA4);
+//! let mut buf = [0; 64];
+//! // DMA1_CH2 carries the requests of channel 0.
+//! let mut wave = DacWaveform::play(&mut dac, p.TIMER5, p.DMA1_CH2, &mut buf, Hertz(48_000));
+//! let mut next = [0; 64];
+//! loop {
+//!     // Fill `next` with the following samples, e.g. audio.
+//!     if wave.swap(&next).await.is_err() {
+//!         // Part of the waveform played out of order.
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! [`DacWaveform::swap`] replaces the samples without a glitch: each half of the buffer is
+//! written while the DMA sends the other, so the new samples start at the beginning of the
+//! buffer, right after the last old one. The task is woken at each half of the buffer, which
+//! should be long enough to copy a half.
+
+use core::marker::PhantomData;
+
+use super::*;
+use crate::dma::WritableRingBuffer;
+use crate::time::Hertz;
+use crate::timer::low_level::{MasterMode, Timer};
+
+/// Channel 0, for [`TxDma`]
+pub enum Ch0 {}
+/// Channel 1, for [`TxDma`]
+pub enum Ch1 {}
+
+macro_rules! channel_marker_impl {
+    ($marker:ident, $channel:ident) => {
+        impl sealed::ChannelMarker for $marker {
+            const CHANNEL: Channel = Channel::$channel;
+        }
+
+        impl ChannelMarker for $marker {}
+    };
+}
+
+channel_marker_impl!(Ch0, Ch0);
+channel_marker_impl!(Ch1, Ch1);
+
+/// The DMA sent samples of the buffer before they were all replaced, so part of the waveform
+/// mixed old and new samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UnderrunError;
+
+/// A waveform played on a DAC channel
+pub struct DacWaveform<'a, T: Instance, Tm: TriggerTimer<T>> {
+    ring: WritableRingBuffer<'a, u16>,
+    channel: Channel,
+    timer: Timer<'a, Tm>,
+    _dac: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Instance, Tm: TriggerTimer<T>> DacWaveform<'a, T, Tm> {
+    /// Output the 12-bit right-aligned samples of `buf` at `sample_rate`, over and over, on the
+    /// channel whose DMA requests `dma` carries. The output starts right away.
+    ///
+    /// The channel keeps the output buffer setting of `dac`. Panics if the channel has no
+    /// output, or if `buf` is empty or has an odd length.
+    pub fn play<'d: 'a, C: ChannelMarker>(
+        dac: &'a mut Dac<'d, T>,
+        timer: impl Peripheral<P = Tm> + 'a,
+        dma: impl Peripheral<P = impl TxDma<T, C>> + 'a,
+        buf: &'a mut [u16],
+        sample_rate: Hertz,
+    ) -> Self {
+        let channel = C::CHANNEL;
+        dac.check_channel(channel);
+        assert!(!buf.is_empty() && buf.len() % 2 == 0, "buffer length must be even");
+
+        let mut timer = Timer::new(timer);
+        timer.set_frequency(sample_rate);
+        timer.set_master_mode(MasterMode::Update);
+
+        let shift = channel.shift();
+        let data = (T::base() + DH0 + channel.data_offset() + R12DH) as *mut u16;
+        let ring = unsafe { WritableRingBuffer::new(dma, buf, data) };
+        modify_ctl::<T>(
+            (CTL_DTEN | ((Tm::TRIGGER as u32) << CTL_DTSEL_OFFSET) | CTL_DDMAEN) << shift,
+            CTL_DTSEL << shift,
+        );
+        timer.start();
+
+        Self {
+            ring,
+            channel,
+            timer,
+            _dac: PhantomData,
+        }
+    }
+
+    /// Change the sample rate. Panics like [`Timer::set_frequency`].
+    pub fn set_sample_rate(&mut self, sample_rate: Hertz) {
+        self.timer.set_frequency(sample_rate);
+    }
+
+    /// Replace the samples of the buffer with `samples`, which start playing right after the
+    /// current pass over the buffer ends. Returns once they are all written, before they play.
+    ///
+    /// If the task fell behind, some samples were sent before they were replaced and an error is
+    /// returned, the buffer then holds `samples` anyway. Panics if `samples` doesn't have the
+    /// length of the buffer.
+    pub async fn swap(&mut self, samples: &[u16]) -> Result<(), UnderrunError> {
+        let len = self.ring.capacity();
+        assert!(samples.len() == len, "samples must have the length of the buffer");
+        let half = len / 2;
+        let pos = self.ring.read_pos();
+        let pass = pos - pos % len as u64;
+
+        // Each half is written while the DMA sends the other one.
+        self.ring.wait_for(pass + half as u64).await;
+        self.ring.write(0, &samples[..half]);
+        let first = self.check(pass + len as u64);
+        self.ring.wait_for(pass + len as u64).await;
+        self.ring.write(half, &samples[half..]);
+        first.and(self.check(pass + (len + half) as u64))
+    }
+
+    /// Fail if the DMA reached position `limit`, where it sends the samples just written.
+    fn check(&self, limit: u64) -> Result<(), UnderrunError> {
+        match self.ring.read_pos() >= limit {
+            true => Err(UnderrunError),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<'a, T: Instance, Tm: TriggerTimer<T>> Drop for DacWaveform<'a, T, Tm> {
+    fn drop(&mut self) {
+        self.timer.stop();
+        // The channel keeps outputting the last sample.
+        modify_ctl::<T>(0, (CTL_DTEN | CTL_DTSEL | CTL_DDMAEN) << self.channel.shift());
+    }
+}
@@ -0,0 +1,91 @@
+//! Debug support: peripherals held while the core is halted
+//!
+//! By default the watchdogs and timers keep running while a debugger halts the core, e.g. at a
+//! breakpoint or while single-stepping. A watchdog then resets the chip under the debugger, and
+//! a timer keeps counting and moving its outputs. [`set_hold`] stops them while the core is
+//! halted instead, so they pick up where they were when it resumes:
+//!
+//! ```no_run
+//! use embassy_gd32::dbg::{self, Hold};
+//!
+//! dbg::set_hold(Hold::Fwdgt, true);
+//! dbg::set_hold(Hold::Wwdgt, true);
+//! dbg::set_hold(Hold::Timer0, true);
+//! ```
+//!
+//! The setting is only reset by a power-on reset, not by a system reset, so it survives the
+//! resets of a debug session.
+
+use crate::pac;
+
+/// Peripheral that can be held while the core is halted
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Hold {
+    /// Free watchdog, see [`crate::fwdgt`]
+    Fwdgt,
+    /// Window watchdog, see [`crate::wwdgt`]
+    Wwdgt,
+    Timer0,
+    Timer1,
+    Timer2,
+    Timer3,
+    Timer4,
+    Timer5,
+    Timer6,
+    Timer7,
+    Timer8,
+    Timer9,
+    Timer10,
+    Timer11,
+    Timer12,
+    Timer13,
+    /// CAN0, whose reception and transmission stop
+    Can0,
+    /// CAN1, whose reception and transmission stop
+    Can1,
+}
+
+impl Hold {
+    /// Bit of the peripheral in `DBG_CTL0`
+    fn bit(self) -> u32 {
+        match self {
+            Hold::Fwdgt => 8,
+            Hold::Wwdgt => 9,
+            Hold::Timer0 => 10,
+            Hold::Timer1 => 11,
+            Hold::Timer2 => 12,
+            Hold::Timer3 => 13,
+            Hold::Can0 => 14,
+            Hold::Timer7 => 17,
+            Hold::Timer4 => 18,
+            Hold::Timer5 => 19,
+            Hold::Timer6 => 20,
+            Hold::Can1 => 21,
+            Hold::Timer11 => 25,
+            Hold::Timer12 => 26,
+            Hold::Timer13 => 27,
+            Hold::Timer8 => 28,
+            Hold::Timer9 => 29,
+            Hold::Timer10 => 30,
+        }
+    }
+}
+
+/// Hold `hold` while the core is halted by the debugger, or let it run on.
+pub fn set_hold(hold: Hold, enable: bool) {
+    let dbg = unsafe { &*pac::DBG::ptr() };
+    let mask = 1 << hold.bit();
+    critical_section::with(|_| unsafe {
+        dbg.ctl0.modify(|r, w| match enable {
+            true => w.bits(r.bits() | mask),
+            false => w.bits(r.bits() & !mask),
+        });
+    });
+}
+
+/// Whether `hold` is held while the core is halted, see [`set_hold`].
+pub fn is_held(hold: Hold) -> bool {
+    let dbg = unsafe { &*pac::DBG::ptr() };
+    dbg.ctl0.read().bits() & (1 << hold.bit()) != 0
+}
@@ -0,0 +1,598 @@
+//! Direct memory access (DMA) controller
+//!
+//! DMA0 has 7 channels and DMA1 has 5. The DMA request of each peripheral is wired to a fixed
+//! channel, drivers list the channels they can use in traits such as [`crate::i2c::TxDma`]. The
+//! requests of several peripherals share a channel, so only one of them may use it at a time,
+//! which owning the channel peripheral ensures.
+//!
+//! Drivers transfer buffers once, or stream through a circular buffer that the channel passes
+//! over and over, such as the continuous conversions of
+//! [`crate::adc::continuous::ContinuousAdc`] or the waveforms of
+//! [`crate::dac::waveform::DacWaveform`]. Any channel also copies from memory without a
+//! peripheral request, e.g. into [`crate::crc::Crc`].
+#![macro_use]
+
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use embassy_hal_common::{impl_peripheral, into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use self::sealed::Channel as _;
+use crate::interrupt::{Interrupt, InterruptExt, Priority};
+use crate::{interrupt, pac, Peripheral};
+
+/// Number of channels of both controllers
+const CHANNEL_COUNT: usize = 12;
+
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+static WAKERS: [AtomicWaker; CHANNEL_COUNT] = [NEW_AW; CHANNEL_COUNT];
+const NEW_PASSES: AtomicUsize = AtomicUsize::new(0);
+/// Passes of the circular transfers over their buffers, counted by the interrupt handler
+static PASSES: [AtomicUsize; CHANNEL_COUNT] = [NEW_PASSES; CHANNEL_COUNT];
+
+pub(crate) mod sealed {
+    pub trait Word {
+        /// `PWIDTH`/`MWIDTH` value
+        const WIDTH: u32;
+    }
+
+    pub trait Channel {
+        /// Controller and channel number, `dma * 8 + channel`.
+        fn dma_channel(&self) -> u8;
+
+        #[inline]
+        fn _dma(&self) -> u8 {
+            self.dma_channel() / 8
+        }
+        #[inline]
+        fn _channel(&self) -> u8 {
+            self.dma_channel() % 8
+        }
+    }
+}
+
+/// A word the DMA can transfer.
+pub trait Word: sealed::Word + Copy + 'static {}
+
+impl sealed::Word for u8 {
+    const WIDTH: u32 = 0b00;
+}
+impl Word for u8 {}
+
+impl sealed::Word for u16 {
+    const WIDTH: u32 = 0b01;
+}
+impl Word for u16 {}
+
+impl sealed::Word for u32 {
+    const WIDTH: u32 = 0b10;
+}
+impl Word for u32 {}
+
+/// DMA channel
+pub trait Channel: sealed::Channel + Peripheral<P = Self> + Into<AnyChannel> + Sized + 'static {
+    /// Convert from concrete channel type to type erased `AnyChannel`.
+    #[inline]
+    fn degrade(self) -> AnyChannel {
+        AnyChannel {
+            dma_channel: self.dma_channel(),
+        }
+    }
+}
+
+/// Type-erased DMA channel
+pub struct AnyChannel {
+    dma_channel: u8,
+}
+
+impl_peripheral!(AnyChannel);
+impl Channel for AnyChannel {}
+impl sealed::Channel for AnyChannel {
+    #[inline]
+    fn dma_channel(&self) -> u8 {
+        self.dma_channel
+    }
+}
+
+/// A DMA transfer between a peripheral data register and a buffer, stopped when dropped.
+///
+/// Completes when all words are transferred.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub(crate) struct Transfer<'a> {
+    channel: PeripheralRef<'a, AnyChannel>,
+}
+
+impl<'a> Transfer<'a> {
+    /// Start reading `buf.len()` words from the peripheral register at `peri_addr`.
+    ///
+    /// Safety: `peri_addr` must be a data register of a peripheral whose DMA request is wired to
+    /// `channel`, which is set up to request the transfers.
+    pub(crate) unsafe fn new_read<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        peri_addr: *const W,
+        buf: &'a mut [W],
+    ) -> Self {
+        into_ref!(channel);
+        let channel = channel.map_into();
+        start(
+            &channel,
+            0,
+            peri_addr as u32,
+            buf.as_mut_ptr() as u32,
+            buf.len(),
+            W::WIDTH,
+        );
+        Self { channel }
+    }
+
+    /// Start writing the words of `buf` to the peripheral register at `peri_addr`.
+    ///
+    /// Safety: like [`Transfer::new_read`].
+    pub(crate) unsafe fn new_write<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        buf: &'a [W],
+        peri_addr: *mut W,
+    ) -> Self {
+        into_ref!(channel);
+        let channel = channel.map_into();
+        start(
+            &channel,
+            CHCTL_DIR,
+            peri_addr as u32,
+            buf.as_ptr() as u32,
+            buf.len(),
+            W::WIDTH,
+        );
+        Self { channel }
+    }
+
+    /// Start copying the words of `buf` to the fixed address `dst`, e.g. a peripheral data
+    /// register, as fast as the channel goes without waiting for requests.
+    ///
+    /// Safety: `dst` must accept `buf.len()` writes until the transfer is done or dropped.
+    pub(crate) unsafe fn new_copy<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        buf: &'a [W],
+        dst: *mut W,
+    ) -> Self {
+        into_ref!(channel);
+        let channel = channel.map_into();
+        start(
+            &channel,
+            CHCTL_DIR | CHCTL_M2M,
+            dst as u32,
+            buf.as_ptr() as u32,
+            buf.len(),
+            W::WIDTH,
+        );
+        Self { channel }
+    }
+
+    /// Whether the transfer is still running.
+    pub(crate) fn is_running(&self) -> bool {
+        unsafe { chctl(&*self.channel).read_volatile() & CHCTL_CHEN != 0 }
+    }
+}
+
+impl<'a> Drop for Transfer<'a> {
+    fn drop(&mut self) {
+        unsafe { stop(&*self.channel) };
+    }
+}
+
+impl<'a> Unpin for Transfer<'a> {}
+impl<'a> Future for Transfer<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        WAKERS[index(&*self.channel)].register(cx.waker());
+        match self.is_running() {
+            true => Poll::Pending,
+            false => Poll::Ready(()),
+        }
+    }
+}
+
+/// The words read from a circular buffer were overwritten by the DMA before they were read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Overrun;
+
+/// A circular transfer from a peripheral data register into a buffer, stopped when dropped.
+///
+/// The channel fills the buffer over and over, and the words are read out in the order they were
+/// written, as long as the reader keeps up.
+pub(crate) struct ReadableRingBuffer<'a, W: Word> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    buf: *const W,
+    len: usize,
+    /// Position of the next word to read, counted over all passes
+    read_pos: u64,
+    _buf: PhantomData<&'a mut [W]>,
+}
+
+impl<'a, W: Word> ReadableRingBuffer<'a, W> {
+    /// Start reading words from the peripheral register at `peri_addr` into `buf`, over and over.
+    ///
+    /// Safety: like [`Transfer::new_read`].
+    pub(crate) unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        peri_addr: *const W,
+        buf: &'a mut [W],
+    ) -> Self {
+        into_ref!(channel);
+        let channel = channel.map_into();
+        stop(&channel);
+        PASSES[index(&channel)].store(0, Ordering::Relaxed);
+        start(
+            &channel,
+            CHCTL_CMEN | CHCTL_HTFIE,
+            peri_addr as u32,
+            buf.as_mut_ptr() as u32,
+            buf.len(),
+            W::WIDTH,
+        );
+        Self {
+            channel,
+            buf: buf.as_ptr(),
+            len: buf.len(),
+            read_pos: 0,
+            _buf: PhantomData,
+        }
+    }
+
+    /// Number of words in the buffer.
+    pub(crate) fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Position of the next word the DMA writes, counted over all passes.
+    fn write_pos(&self) -> u64 {
+        position(&self.channel, self.len)
+    }
+
+    /// Number of words written and not read yet, or an error if some of them were overwritten.
+    pub(crate) fn available(&self) -> Result<usize, Overrun> {
+        let available = self.write_pos() - self.read_pos;
+        match available > self.len as u64 {
+            true => Err(Overrun),
+            false => Ok(available as usize),
+        }
+    }
+
+    /// Read the oldest words into `out`, as many as are available and fit. Returns the number
+    /// of words read.
+    ///
+    /// If the DMA overwrote words before they were read, nothing is read and an error is
+    /// returned. Reading goes on from the oldest words left, see
+    /// [`ReadableRingBuffer::resync`].
+    pub(crate) fn read(&mut self, out: &mut [W]) -> Result<usize, Overrun> {
+        let n = self.available()?.min(out.len());
+        // Subsequent reads of the buffer can't be moved ahead of the read of the position.
+        fence(Ordering::SeqCst);
+        for (i, word) in out[..n].iter_mut().enumerate() {
+            let index = ((self.read_pos + i as u64) % self.len as u64) as usize;
+            *word = unsafe { self.buf.add(index).read_volatile() };
+        }
+        fence(Ordering::SeqCst);
+        // The DMA may have overwritten the words while they were copied.
+        self.available()?;
+        self.read_pos += n as u64;
+        Ok(n)
+    }
+
+    /// Skip the words that were overwritten after an [`Overrun`], keeping the position of the
+    /// next word to read in a block of `align` words.
+    pub(crate) fn resync(&mut self, align: usize) {
+        let write_pos = self.write_pos();
+        // Keep some room, the DMA goes on writing meanwhile.
+        let oldest = write_pos.saturating_sub(self.len as u64 / 2);
+        self.read_pos = oldest - oldest % align as u64;
+    }
+
+    /// Wait until at least `n` words are available, or the DMA overwrote some of them.
+    pub(crate) async fn wait(&mut self, n: usize) -> Result<(), Overrun> {
+        poll_fn(|cx| {
+            WAKERS[index(&self.channel)].register(cx.waker());
+            match self.available() {
+                Ok(available) if available < n => Poll::Pending,
+                Ok(_) => Poll::Ready(Ok(())),
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        })
+        .await
+    }
+}
+
+impl<'a, W: Word> Drop for ReadableRingBuffer<'a, W> {
+    fn drop(&mut self) {
+        unsafe { stop(&*self.channel) };
+    }
+}
+
+/// A circular transfer from a buffer into a peripheral data register, stopped when dropped.
+///
+/// The channel sends the buffer over and over. Words can be replaced in the half of the buffer
+/// the channel isn't sending.
+pub(crate) struct WritableRingBuffer<'a, W: Word> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    buf: *mut W,
+    len: usize,
+    _buf: PhantomData<&'a mut [W]>,
+}
+
+impl<'a, W: Word> WritableRingBuffer<'a, W> {
+    /// Start writing the words of `buf` to the peripheral register at `peri_addr`, over and
+    /// over.
+    ///
+    /// Safety: like [`Transfer::new_write`].
+    pub(crate) unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        buf: &'a mut [W],
+        peri_addr: *mut W,
+    ) -> Self {
+        into_ref!(channel);
+        let channel = channel.map_into();
+        stop(&channel);
+        PASSES[index(&channel)].store(0, Ordering::Relaxed);
+        start(
+            &channel,
+            CHCTL_DIR | CHCTL_CMEN | CHCTL_HTFIE,
+            peri_addr as u32,
+            buf.as_mut_ptr() as u32,
+            buf.len(),
+            W::WIDTH,
+        );
+        Self {
+            channel,
+            buf: buf.as_mut_ptr(),
+            len: buf.len(),
+            _buf: PhantomData,
+        }
+    }
+
+    /// Number of words in the buffer.
+    pub(crate) fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Position of the next word the DMA reads, counted over all passes.
+    pub(crate) fn read_pos(&self) -> u64 {
+        position(&self.channel, self.len)
+    }
+
+    /// Replace the words of the buffer from `index` on with `words`.
+    pub(crate) fn write(&mut self, index: usize, words: &[W]) {
+        assert!(index + words.len() <= self.len, "words out of the buffer");
+        for (i, word) in words.iter().enumerate() {
+            unsafe { self.buf.add(index + i).write_volatile(*word) };
+        }
+        // The words are written before the DMA is told about them.
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wait until the DMA has read the words before position `pos`, counted over all passes.
+    ///
+    /// The reader is woken at each half of the buffer, so `pos` should be at one of them.
+    pub(crate) async fn wait_for(&mut self, pos: u64) {
+        poll_fn(|cx| {
+            WAKERS[index(&self.channel)].register(cx.waker());
+            match self.read_pos() >= pos {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl<'a, W: Word> Drop for WritableRingBuffer<'a, W> {
+    fn drop(&mut self) {
+        unsafe { stop(&*self.channel) };
+    }
+}
+
+/// Position of a circular transfer over a buffer of `len` words, counted over all passes.
+fn position(ch: &AnyChannel, len: usize) -> u64 {
+    let shift = 4 * ch._channel() as u32;
+    let (passes, remaining) = critical_section::with(|_| unsafe {
+        // The interrupt handler can't count the passes meanwhile, but the transfer can still
+        // wrap around. Its flag tells whether it did before the counter was read.
+        let wrapped_before = (intf(ch._dma()).read_volatile() >> shift) & INTF_FTFIF != 0;
+        let mut remaining = chcnt(ch).read_volatile();
+        let wrapped = (intf(ch._dma()).read_volatile() >> shift) & INTF_FTFIF != 0;
+        if wrapped && !wrapped_before {
+            remaining = chcnt(ch).read_volatile();
+        }
+        let passes = PASSES[index(ch)].load(Ordering::Relaxed) + wrapped as usize;
+        (passes, remaining)
+    });
+    passes as u64 * len as u64 + (len - remaining as usize) as u64
+}
+
+unsafe fn start(ch: &AnyChannel, ctl: u32, peri_addr: u32, mem_addr: u32, len: usize, width: u32) {
+    assert!(len > 0 && len <= 0xFFFF, "DMA transfers are 1 to 65535 words");
+
+    stop(ch);
+    // Preceding reads and writes of the buffer can't be moved past the start of the transfer.
+    fence(Ordering::SeqCst);
+
+    chpaddr(ch).write_volatile(peri_addr);
+    chmaddr(ch).write_volatile(mem_addr);
+    chcnt(ch).write_volatile(len as u32);
+    chctl(ch).write_volatile(
+        CHCTL_CHEN
+            | CHCTL_FTFIE
+            | CHCTL_ERRIE
+            | ctl
+            | CHCTL_MNAGA
+            | (width << CHCTL_PWIDTH_OFFSET)
+            | (width << CHCTL_MWIDTH_OFFSET),
+    );
+}
+
+/// Disable the channel and clear its flags.
+unsafe fn stop(ch: &AnyChannel) {
+    chctl(ch).write_volatile(0);
+    intc(ch._dma()).write_volatile(INTF_MASK << (4 * ch._channel() as u32));
+    // Subsequent reads of the buffer can't be moved ahead of the end of the transfer.
+    fence(Ordering::SeqCst);
+}
+
+fn index(ch: &AnyChannel) -> usize {
+    ch._dma() as usize * 7 + ch._channel() as usize
+}
+
+unsafe fn on_irq(dma: u8, channel: u8) {
+    let ch = AnyChannel {
+        dma_channel: dma * 8 + channel,
+    };
+    let intf = intf(dma).read_volatile() >> (4 * channel as u32);
+
+    if intf & INTF_ERRIF != 0 {
+        panic!("DMA: transfer error on DMA{} channel {}", dma, channel);
+    }
+    let ctl = chctl(&ch).read_volatile();
+    if ctl & CHCTL_CMEN != 0 {
+        // A circular transfer goes on, the driver is woken at each half of the buffer.
+        let done = intf & (INTF_HTFIF | INTF_FTFIF);
+        if done != 0 {
+            if intf & INTF_FTFIF != 0 {
+                PASSES[index(&ch)].fetch_add(1, Ordering::Relaxed);
+            }
+            intc(dma).write_volatile((done | INTF_GIF) << (4 * channel as u32));
+            WAKERS[index(&ch)].wake();
+        }
+    } else if intf & INTF_FTFIF != 0 && ctl & CHCTL_FTFIE != 0 {
+        stop(&ch);
+        WAKERS[index(&ch)].wake();
+    }
+}
+
+fn base(dma: u8) -> usize {
+    match dma {
+        0 => pac::DMA0::ptr() as usize,
+        _ => pac::DMA1::ptr() as usize,
+    }
+}
+
+fn intf(dma: u8) -> *mut u32 {
+    base(dma) as *mut u32
+}
+
+fn intc(dma: u8) -> *mut u32 {
+    (base(dma) + 0x04) as *mut u32
+}
+
+/// Address of the channel register at `offset` from the channel's `CHxCTL`.
+fn ch_reg(ch: &AnyChannel, offset: usize) -> *mut u32 {
+    (base(ch._dma()) + 0x08 + 0x14 * ch._channel() as usize + offset) as *mut u32
+}
+
+fn chctl(ch: &AnyChannel) -> *mut u32 {
+    ch_reg(ch, 0x00)
+}
+
+fn chcnt(ch: &AnyChannel) -> *mut u32 {
+    ch_reg(ch, 0x04)
+}
+
+fn chpaddr(ch: &AnyChannel) -> *mut u32 {
+    ch_reg(ch, 0x08)
+}
+
+fn chmaddr(ch: &AnyChannel) -> *mut u32 {
+    ch_reg(ch, 0x0C)
+}
+
+macro_rules! impl_dma_channel {
+    ($type:ident, $dma:expr, $channel:expr) => {
+        impl crate::dma::sealed::Channel for peripherals::$type {
+            #[inline]
+            fn dma_channel(&self) -> u8 {
+                $dma * 8 + $channel
+            }
+        }
+        impl crate::dma::Channel for peripherals::$type {}
+
+        impl From<peripherals::$type> for crate::dma::AnyChannel {
+            fn from(val: peripherals::$type) -> Self {
+                crate::dma::Channel::degrade(val)
+            }
+        }
+    };
+}
+
+macro_rules! impl_irq {
+    ($e:ident, $dma:expr, $($channel:expr),+) => {
+        #[interrupt]
+        unsafe fn $e() {
+            $(on_irq($dma, $channel);)+
+        }
+    };
+}
+
+impl_irq!(DMA0_CHANNEL0, 0, 0);
+impl_irq!(DMA0_CHANNEL1, 0, 1);
+impl_irq!(DMA0_CHANNEL2, 0, 2);
+impl_irq!(DMA0_CHANNEL3, 0, 3);
+impl_irq!(DMA0_CHANNEL4, 0, 4);
+impl_irq!(DMA0_CHANNEL5, 0, 5);
+impl_irq!(DMA0_CHANNEL6, 0, 6);
+impl_irq!(DMA1_CHANNEL0, 1, 0);
+impl_irq!(DMA1_CHANNEL1, 1, 1);
+impl_irq!(DMA1_CHANNEL2, 1, 2);
+impl_irq!(DMA1_CHANNEL3_4, 1, 3, 4);
+
+macro_rules! enable_irq {
+    ($e:ident, $prio:expr) => {{
+        let irq = interrupt::$e::steal();
+        irq.unpend();
+        irq.set_priority($prio);
+        irq.enable();
+    }};
+}
+
+/// Enable the DMA clocks and interrupts.
+pub(crate) unsafe fn init(irq_prio: Priority) {
+    let rcu = &*pac::RCU::ptr();
+    rcu.ahben.modify(|r, w| w.bits(r.bits() | AHBEN_DMA0EN | AHBEN_DMA1EN));
+
+    enable_irq!(DMA0_CHANNEL0, irq_prio);
+    enable_irq!(DMA0_CHANNEL1, irq_prio);
+    enable_irq!(DMA0_CHANNEL2, irq_prio);
+    enable_irq!(DMA0_CHANNEL3, irq_prio);
+    enable_irq!(DMA0_CHANNEL4, irq_prio);
+    enable_irq!(DMA0_CHANNEL5, irq_prio);
+    enable_irq!(DMA0_CHANNEL6, irq_prio);
+    enable_irq!(DMA1_CHANNEL0, irq_prio);
+    enable_irq!(DMA1_CHANNEL1, irq_prio);
+    enable_irq!(DMA1_CHANNEL2, irq_prio);
+    enable_irq!(DMA1_CHANNEL3_4, irq_prio);
+}
+
+// DMA_INTF, four flags per channel
+const INTF_GIF: u32 = 1 << 0;
+const INTF_FTFIF: u32 = 1 << 1;
+const INTF_HTFIF: u32 = 1 << 2;
+const INTF_ERRIF: u32 = 1 << 3;
+const INTF_MASK: u32 = 0b1111;
+
+// DMA_CHxCTL
+const CHCTL_CHEN: u32 = 1 << 0;
+const CHCTL_FTFIE: u32 = 1 << 1;
+const CHCTL_HTFIE: u32 = 1 << 2;
+const CHCTL_ERRIE: u32 = 1 << 3;
+const CHCTL_DIR: u32 = 1 << 4;
+const CHCTL_CMEN: u32 = 1 << 5;
+const CHCTL_MNAGA: u32 = 1 << 7;
+const CHCTL_PWIDTH_OFFSET: u32 = 8;
+const CHCTL_MWIDTH_OFFSET: u32 = 10;
+const CHCTL_M2M: u32 = 1 << 14;
+
+// RCU_AHBEN
+const AHBEN_DMA0EN: u32 = 1 << 0;
+const AHBEN_DMA1EN: u32 = 1 << 1;
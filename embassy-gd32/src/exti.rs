@@ -0,0 +1,628 @@
+//! External interrupt/event controller (EXTI) driver.
+//!
+//! EXTI lines 0..15 can each be connected to the pin with the same number of one GPIO port. The
+//! source port is selected in `AFIO_EXTISSx` when an [`ExtiInput`] starts waiting.
+//!
+//! Lines 16 and up are connected to peripherals instead, see [`InternalExti`]. Like the GPIO
+//! lines, they can wake the core from deep-sleep. The lines 19 to 21 of the comparators are
+//! driven by [`crate::cmp::Comparator`].
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use atomic_polyfill::{AtomicU32, Ordering};
+use embassy_hal_common::{impl_peripheral, into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::gpio::{AnyPin, Input, Pin as GpioPin, Pull};
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::{interrupt, pac, peripherals, Peripheral};
+
+const EXTI_COUNT: usize = 22;
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+static EXTI_WAKERS: [AtomicWaker; EXTI_COUNT] = [NEW_AW; EXTI_COUNT];
+
+const NEW_COUNT: AtomicU32 = AtomicU32::new(0);
+static EXTI_COUNTS: [AtomicU32; EXTI_COUNT] = [NEW_COUNT; EXTI_COUNT];
+/// Lines owned by an `ExtiCounter`.
+static EXTI_COUNTING: AtomicU32 = AtomicU32::new(0);
+
+fn regs() -> &'static pac::exti::RegisterBlock {
+    unsafe { &*pac::EXTI::ptr() }
+}
+
+/// Handle the interrupt of one or more EXTI lines.
+///
+/// The lines 5..9 and 10..15 share an interrupt vector, so the lines that fired are taken from the
+/// pending register rather than from the vector that was entered. The internal lines have a
+/// vector each, but are handled the same way.
+unsafe fn on_irq() {
+    let r = regs();
+    let bits = r.pd.read().bits() & r.inten.read().bits() & ((1 << EXTI_COUNT) - 1);
+
+    // Count the edges of the lines owned by an `ExtiCounter`, these stay enabled.
+    let counting = bits & EXTI_COUNTING.load(Ordering::Relaxed);
+    for line in BitIter(counting) {
+        EXTI_COUNTS[line as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Mask all the other lines that fired, this is what the futures check for.
+    r.inten.modify(|r, w| w.bits(r.bits() & !(bits & !counting)));
+
+    // Clear pending before waking, so a task that starts waiting again right away doesn't
+    // have its new edge cleared.
+    r.pd.write(|w| w.bits(bits));
+
+    // Wake the tasks
+    for line in BitIter(bits) {
+        EXTI_WAKERS[line as usize].wake();
+    }
+}
+
+struct BitIter(u32);
+
+impl Iterator for BitIter {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.0.trailing_zeros() {
+            32 => None,
+            b => {
+                self.0 &= !(1 << b);
+                Some(b)
+            }
+        }
+    }
+}
+
+/// EXTI input driver
+pub struct ExtiInput<'d, T: GpioPin> {
+    pin: Input<'d, T>,
+    _ch: PeripheralRef<'d, AnyChannel>,
+}
+
+impl<'d, T: GpioPin> Unpin for ExtiInput<'d, T> {}
+
+impl<'d, T: GpioPin> ExtiInput<'d, T> {
+    /// Create an EXTI input on `pin`, configured as an input with the given `pull`.
+    ///
+    /// `ch` is the EXTI line of the pin, e.g. `EXTI3` for `PB3`, and is borrowed by the driver. A
+    /// wrong line doesn't compile, except for an [`AnyPin`] with an [`AnyChannel`], which panics.
+    pub fn new(
+        pin: impl Peripheral<P = T> + 'd,
+        ch: impl Peripheral<P = T::ExtiChannel> + 'd,
+        pull: Pull,
+    ) -> Self {
+        into_ref!(pin, ch);
+        assert_eq!(ch.number(), pin.pin() as usize, "EXTI line doesn't match the pin");
+        Self {
+            pin: Input::new(pin, pull),
+            _ch: ch.map_into(),
+        }
+    }
+
+    /// Convert into an EXTI input on a type erased pin.
+    pub fn degrade(self) -> ExtiInput<'d, AnyPin> {
+        ExtiInput {
+            pin: Input {
+                pin: self.pin.pin.degrade(),
+            },
+            _ch: self._ch,
+        }
+    }
+
+    /// Test if current pin level is high.
+    pub fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    /// Test if current pin level is low.
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Wait until the pin is high. If it is already high, return immediately.
+    pub async fn wait_for_high<'a>(&'a mut self) {
+        let fut = ExtiInputFuture::new(self.pin.pin.pin.pin(), Some(self.pin.pin.pin.port()), true, false);
+        if self.is_high() {
+            return;
+        }
+        fut.await
+    }
+
+    /// Wait until the pin is low. If it is already low, return immediately.
+    pub async fn wait_for_low<'a>(&'a mut self) {
+        let fut = ExtiInputFuture::new(self.pin.pin.pin.pin(), Some(self.pin.pin.pin.port()), false, true);
+        if self.is_low() {
+            return;
+        }
+        fut.await
+    }
+
+    /// Wait for the pin to undergo a transition from low to high.
+    pub async fn wait_for_rising_edge<'a>(&'a mut self) {
+        ExtiInputFuture::new(self.pin.pin.pin.pin(), Some(self.pin.pin.pin.port()), true, false).await
+    }
+
+    /// Wait for the pin to undergo a transition from high to low.
+    pub async fn wait_for_falling_edge<'a>(&'a mut self) {
+        ExtiInputFuture::new(self.pin.pin.pin.pin(), Some(self.pin.pin.pin.port()), false, true).await
+    }
+
+    /// Wait for the pin to undergo any transition, i.e low to high OR high to low.
+    pub async fn wait_for_any_edge<'a>(&'a mut self) {
+        ExtiInputFuture::new(self.pin.pin.pin.pin(), Some(self.pin.pin.pin.port()), true, true).await
+    }
+}
+
+mod eh02 {
+    use core::convert::Infallible;
+
+    use super::*;
+
+    impl<'d, T: GpioPin> embedded_hal_02::digital::v2::InputPin for ExtiInput<'d, T> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_low())
+        }
+    }
+}
+
+/// Debounced button event, see [`Debounced`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg(feature = "time")]
+pub enum ButtonEvent {
+    /// The input settled at the pressed level.
+    Pressed,
+    /// The input settled at the released level.
+    Released,
+}
+
+/// Software debounced button on top of an [`ExtiInput`].
+///
+/// A level change is only reported once the input has not changed for the stabilization period.
+/// Glitches shorter than that, and bounces that end at the previous level, are ignored.
+#[cfg(feature = "time")]
+pub struct Debounced<'d, T: GpioPin> {
+    input: ExtiInput<'d, T>,
+    pressed_level: crate::gpio::Level,
+    stable_for: embassy_time::Duration,
+    pressed: bool,
+}
+
+#[cfg(feature = "time")]
+impl<'d, T: GpioPin> Debounced<'d, T> {
+    /// Create a debounced button.
+    ///
+    /// `pressed_level` is the input level while the button is pressed, e.g. `Level::Low` for a
+    /// button to ground with a pull-up. The initial state is taken from the current level.
+    pub fn new(
+        input: ExtiInput<'d, T>,
+        pressed_level: crate::gpio::Level,
+        stable_for: embassy_time::Duration,
+    ) -> Self {
+        let pressed = input.pin.get_level() == pressed_level;
+        Self {
+            input,
+            pressed_level,
+            stable_for,
+            pressed,
+        }
+    }
+
+    /// Debounced state of the button.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Wait for the button to be pressed or released.
+    pub async fn wait_for_event(&mut self) -> ButtonEvent {
+        use embassy_futures::select::{select, Either};
+
+        loop {
+            // Wait for the input to leave the debounced level.
+            let debounced_high = self.pressed == (self.pressed_level == crate::gpio::Level::High);
+            if debounced_high {
+                self.input.wait_for_low().await;
+            } else {
+                self.input.wait_for_high().await;
+            }
+
+            // Wait for the input to stop bouncing.
+            loop {
+                match select(
+                    embassy_time::Timer::after(self.stable_for),
+                    self.input.wait_for_any_edge(),
+                )
+                .await
+                {
+                    Either::First(()) => break,
+                    Either::Second(()) => {}
+                }
+            }
+
+            let pressed = self.input.pin.get_level() == self.pressed_level;
+            if pressed != self.pressed {
+                self.pressed = pressed;
+                return match pressed {
+                    true => ButtonEvent::Pressed,
+                    false => ButtonEvent::Released,
+                };
+            }
+        }
+    }
+
+    /// Wait for the button to be pressed.
+    pub async fn wait_for_press(&mut self) {
+        while self.wait_for_event().await != ButtonEvent::Pressed {}
+    }
+
+    /// Wait for the button to be released.
+    pub async fn wait_for_release(&mut self) {
+        while self.wait_for_event().await != ButtonEvent::Released {}
+    }
+}
+
+/// Edge that triggers an EXTI line.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    /// Low to high transition.
+    Rising,
+    /// High to low transition.
+    Falling,
+    /// Any transition.
+    Any,
+}
+
+impl Edge {
+    /// Rising and falling trigger enables.
+    fn triggers(self) -> (bool, bool) {
+        match self {
+            Edge::Rising => (true, false),
+            Edge::Falling => (false, true),
+            Edge::Any => (true, true),
+        }
+    }
+}
+
+/// Driver for an EXTI line connected to a peripheral rather than a GPIO pin.
+///
+/// The EXTI only sees the event signal, enabling it is up to the peripheral: e.g. the RTC alarm
+/// must be set and the low voltage detector enabled in the PMU. The peripheral's own flag must be
+/// cleared after the event, otherwise there won't be another edge on the line.
+pub struct InternalExti<'d, T: InternalChannel> {
+    _ch: PeripheralRef<'d, T>,
+    edge: Edge,
+}
+
+impl<'d, T: InternalChannel> InternalExti<'d, T> {
+    /// Create a driver for the internal line `ch`, triggering on `edge`.
+    pub fn new(ch: impl Peripheral<P = T> + 'd, edge: Edge) -> Self {
+        into_ref!(ch);
+        Self { _ch: ch, edge }
+    }
+
+    /// Wait for the next event on the line.
+    pub async fn wait(&mut self) {
+        let (rising, falling) = self.edge.triggers();
+        ExtiInputFuture::new(T::NUMBER, None, rising, falling).await
+    }
+}
+
+/// Wait for `edge` on the internal `line`, for drivers that own the peripheral driving it. The
+/// line is enabled right away, not when the future is first polled.
+pub(crate) fn wait_for_internal(line: u8, edge: Edge) -> impl Future<Output = ()> {
+    let (rising, falling) = edge.triggers();
+    ExtiInputFuture::new(line, None, rising, falling)
+}
+
+/// Counts the edges of a pin in the EXTI interrupt handler.
+///
+/// Unlike [`ExtiInput`], the line stays enabled between waits, so no edge is missed. This is useful
+/// e.g. to count the pulses of a flow meter without using a timer input capture channel, as long
+/// as the pulse rate is well below the interrupt rate the application can take.
+pub struct ExtiCounter<'d, T: GpioPin> {
+    pin: Input<'d, T>,
+    _ch: PeripheralRef<'d, AnyChannel>,
+}
+
+impl<'d, T: GpioPin> ExtiCounter<'d, T> {
+    /// Create a counter of the given `edge`s on `pin`, configured as an input with the given `pull`.
+    ///
+    /// `ch` is the EXTI line of the pin, like for [`ExtiInput::new`]. Counting starts at zero.
+    pub fn new(
+        pin: impl Peripheral<P = T> + 'd,
+        ch: impl Peripheral<P = T::ExtiChannel> + 'd,
+        pull: Pull,
+        edge: Edge,
+    ) -> Self {
+        into_ref!(pin, ch);
+        assert_eq!(ch.number(), pin.pin() as usize, "EXTI line doesn't match the pin");
+        let pin = Input::new(pin, pull);
+        let line = pin.pin.pin.pin() as usize;
+        let port = pin.pin.pin.port();
+        let (rising, falling) = edge.triggers();
+
+        critical_section::with(|_| unsafe {
+            EXTI_COUNTS[line].store(0, Ordering::Relaxed);
+            EXTI_COUNTING.fetch_or(1 << line, Ordering::Relaxed);
+            enable_line(line, Some(port), rising, falling);
+        });
+
+        Self {
+            pin,
+            _ch: ch.map_into(),
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.pin.pin.pin.pin() as usize
+    }
+
+    /// Number of edges counted so far. Wraps around on overflow.
+    pub fn count(&self) -> u32 {
+        EXTI_COUNTS[self.line()].load(Ordering::Relaxed)
+    }
+
+    /// Reset the count to zero, returning the count before the reset.
+    pub fn reset(&mut self) -> u32 {
+        EXTI_COUNTS[self.line()].swap(0, Ordering::Relaxed)
+    }
+
+    /// Wait until at least `n` edges have been counted.
+    pub async fn wait_for_count(&mut self, n: u32) {
+        let line = self.line();
+        poll_fn(|cx| {
+            EXTI_WAKERS[line].register(cx.waker());
+            if EXTI_COUNTS[line].load(Ordering::Relaxed) >= n {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Test if current pin level is high.
+    pub fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    /// Test if current pin level is low.
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+}
+
+impl<'d, T: GpioPin> Drop for ExtiCounter<'d, T> {
+    fn drop(&mut self) {
+        let line = self.line();
+        critical_section::with(|_| unsafe {
+            EXTI_COUNTING.fetch_and(!(1 << line), Ordering::Relaxed);
+            disable_line(line);
+        });
+    }
+}
+
+struct ExtiInputFuture<'a> {
+    pin: u8,
+    phantom: PhantomData<&'a mut AnyPin>,
+}
+
+impl<'a> ExtiInputFuture<'a> {
+    /// Start waiting on EXTI line `pin`. `port` selects the GPIO port of GPIO lines and is `None`
+    /// for internal lines.
+    fn new(pin: u8, port: Option<u8>, rising: bool, falling: bool) -> Self {
+        critical_section::with(|_| unsafe { enable_line(pin as usize, port, rising, falling) });
+
+        Self {
+            pin,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a> Drop for ExtiInputFuture<'a> {
+    fn drop(&mut self) {
+        // The future may be dropped before the line fired. Leaving it enabled would make the next,
+        // unrelated edge raise an interrupt and leave a stale pending flag behind.
+        critical_section::with(|_| unsafe { disable_line(self.pin as usize) });
+    }
+}
+
+/// Connect `line` to `port` (for GPIO lines), set its triggers and unmask it.
+///
+/// Must be called in a critical section.
+unsafe fn enable_line(line: usize, port: Option<u8>, rising: bool, falling: bool) {
+    if let Some(port) = port {
+        let afio = &*pac::AFIO::ptr();
+        let extiss = match line / 4 {
+            0 => afio.extiss0.as_ptr(),
+            1 => afio.extiss1.as_ptr(),
+            2 => afio.extiss2.as_ptr(),
+            _ => afio.extiss3.as_ptr(),
+        };
+        let shift = (line % 4) * 4;
+        extiss.write_volatile((extiss.read_volatile() & !(0b1111 << shift)) | ((port as u32) << shift));
+    }
+
+    let r = regs();
+    r.rten.modify(|r, w| w.bits(set_bit(r.bits(), line, rising)));
+    r.ften.modify(|r, w| w.bits(set_bit(r.bits(), line, falling)));
+
+    // clear pending bit
+    r.pd.write(|w| w.bits(1 << line));
+
+    r.inten.modify(|r, w| w.bits(r.bits() | 1 << line));
+}
+
+/// Mask `line` and clear its pending flag.
+///
+/// Must be called in a critical section.
+unsafe fn disable_line(line: usize) {
+    let r = regs();
+    r.inten.modify(|r, w| w.bits(r.bits() & !(1 << line)));
+    r.pd.write(|w| w.bits(1 << line));
+}
+
+fn set_bit(bits: u32, n: usize, value: bool) -> u32 {
+    (bits & !(1 << n)) | ((value as u32) << n)
+}
+
+impl<'a> Future for ExtiInputFuture<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        EXTI_WAKERS[self.pin as usize].register(cx.waker());
+
+        if regs().inten.read().bits() & (1 << self.pin) == 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+macro_rules! impl_irq {
+    ($e:ident) => {
+        #[interrupt]
+        unsafe fn $e() {
+            on_irq()
+        }
+    };
+}
+
+impl_irq!(EXTI_LINE0);
+impl_irq!(EXTI_LINE1);
+impl_irq!(EXTI_LINE2);
+impl_irq!(EXTI_LINE3);
+impl_irq!(EXTI_LINE4);
+impl_irq!(EXTI_LINE9_5);
+impl_irq!(EXTI_LINE15_10);
+impl_irq!(LVD);
+impl_irq!(RTC_ALARM);
+impl_irq!(USBD_WKUP);
+impl_irq!(CMP);
+
+pub(crate) mod sealed {
+    pub trait Channel {}
+
+    pub trait InternalChannel {
+        const NUMBER: u8;
+    }
+}
+
+/// An EXTI line, connectable to the pin with the same number of any GPIO port.
+pub trait Channel: sealed::Channel + Peripheral<P = Self> + Into<AnyChannel> + Sized + 'static {
+    /// Number of the EXTI line.
+    fn number(&self) -> usize;
+
+    /// Convert from concrete channel type EXTIx to type erased `AnyChannel`.
+    fn degrade(self) -> AnyChannel {
+        AnyChannel {
+            number: self.number() as u8,
+        }
+    }
+}
+
+/// Type-erased EXTI line.
+pub struct AnyChannel {
+    number: u8,
+}
+impl_peripheral!(AnyChannel);
+impl sealed::Channel for AnyChannel {}
+impl Channel for AnyChannel {
+    fn number(&self) -> usize {
+        self.number as usize
+    }
+}
+
+macro_rules! impl_exti {
+    ($type:ident, $number:expr) => {
+        impl sealed::Channel for peripherals::$type {}
+        impl Channel for peripherals::$type {
+            fn number(&self) -> usize {
+                $number as usize
+            }
+        }
+
+        impl From<peripherals::$type> for AnyChannel {
+            fn from(val: peripherals::$type) -> Self {
+                Channel::degrade(val)
+            }
+        }
+    };
+}
+
+impl_exti!(EXTI0, 0);
+impl_exti!(EXTI1, 1);
+impl_exti!(EXTI2, 2);
+impl_exti!(EXTI3, 3);
+impl_exti!(EXTI4, 4);
+impl_exti!(EXTI5, 5);
+impl_exti!(EXTI6, 6);
+impl_exti!(EXTI7, 7);
+impl_exti!(EXTI8, 8);
+impl_exti!(EXTI9, 9);
+impl_exti!(EXTI10, 10);
+impl_exti!(EXTI11, 11);
+impl_exti!(EXTI12, 12);
+impl_exti!(EXTI13, 13);
+impl_exti!(EXTI14, 14);
+impl_exti!(EXTI15, 15);
+
+/// An EXTI line connected to a peripheral, see [`InternalExti`].
+pub trait InternalChannel: sealed::InternalChannel + Peripheral<P = Self> + 'static {}
+
+macro_rules! impl_internal_exti {
+    ($type:ident, $number:expr) => {
+        impl sealed::InternalChannel for peripherals::$type {
+            const NUMBER: u8 = $number;
+        }
+        impl InternalChannel for peripherals::$type {}
+    };
+}
+
+// Low voltage detector output, high while VDD is below the LVD threshold.
+impl_internal_exti!(EXTI16, 16);
+// RTC alarm.
+impl_internal_exti!(EXTI17, 17);
+// USB device wakeup from suspend.
+impl_internal_exti!(EXTI18, 18);
+
+macro_rules! enable_irq {
+    ($e:ident, $prio:expr) => {{
+        let irq = interrupt::$e::steal();
+        irq.unpend();
+        irq.set_priority($prio);
+        irq.enable();
+    }};
+}
+
+/// Enable the AFIO clock, needed for the EXTI source selection, and the EXTI interrupts.
+///
+/// safety: must be called only once
+pub(crate) unsafe fn init(irq_prio: crate::interrupt::Priority) {
+    let rcu = &*pac::RCU::ptr();
+    rcu.apb2en.modify(|_, w| w.afen().set_bit());
+
+    enable_irq!(EXTI_LINE0, irq_prio);
+    enable_irq!(EXTI_LINE1, irq_prio);
+    enable_irq!(EXTI_LINE2, irq_prio);
+    enable_irq!(EXTI_LINE3, irq_prio);
+    enable_irq!(EXTI_LINE4, irq_prio);
+    enable_irq!(EXTI_LINE9_5, irq_prio);
+    enable_irq!(EXTI_LINE15_10, irq_prio);
+    enable_irq!(LVD, irq_prio);
+    enable_irq!(RTC_ALARM, irq_prio);
+    enable_irq!(USBD_WKUP, irq_prio);
+    enable_irq!(CMP, irq_prio);
+}
@@ -0,0 +1,486 @@
+//! EEPROM emulation on two flash pages.
+//!
+//! [`Eeprom`] stores small values by 16-bit key, e.g. calibration constants and counters, in a log
+//! of records appended to the active one of two flash pages. Writing a key appends a record,
+//! reading it returns the last one. When the active page is full, the latest record of every key
+//! is copied to the other page, which becomes the active page, and the full page is erased. Each
+//! page is thus only erased once per page-full of writes.
+//!
+//! Records and pages are committed by a word that is written last, so after a reset or power
+//! loss at any point a key reads either its old or its new value.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::fmc::eeprom::Eeprom;
+//! use embassy_gd32::fmc::{Flash, FLASH_SIZE};
+//!
+//! const BOOT_COUNT: u16 = 1;
+//!
+//! // Use the last two pages of the flash.
+//! let flash = Flash::new(p.FMC);
+//! let last = flash.page_at(FLASH_SIZE as u32 - 1).unwrap();
+//! let mut eeprom = Eeprom::new(flash, last.offset - last.size).unwrap();
+//! let count = eeprom.read::<u32>(BOOT_COUNT).unwrap().unwrap_or(0);
+//! eeprom.write(BOOT_COUNT, &(count + 1)).unwrap();
+//! ```
+//!
+//! All operations block. Writing a full page can take several milliseconds for the erase.
+
+use super::{Flash, Page};
+
+/// Largest value that can be stored, in bytes.
+pub const MAX_VALUE_SIZE: usize = 256;
+
+/// Word of erased flash
+const ERASED: u32 = 0xFFFF_FFFF;
+/// Marks a valid page, written after its generation and records.
+const PAGE_MAGIC: u32 = 0xEE9A_6E55;
+/// Page header: generation, magic.
+const HEADER_SIZE: u32 = 8;
+
+/// EEPROM error
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Flash erase, write or read failed.
+    Flash(super::Error),
+    /// Value larger than [`MAX_VALUE_SIZE`].
+    ValueTooLarge,
+    /// The stored value has a different size than the requested type or is not a valid value of
+    /// it.
+    InvalidValue,
+    /// The latest values of all keys don't fit in a page.
+    Full,
+}
+
+impl From<super::Error> for Error {
+    fn from(e: super::Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+/// A value that can be stored in an [`Eeprom`].
+///
+/// Implemented for the primitive numbers, `bool` and byte arrays. Implement it to store your own
+/// types, e.g. a calibration struct serialized field by field.
+pub trait Value: Sized {
+    /// Size of the stored value, at most [`MAX_VALUE_SIZE`].
+    const SIZE: usize;
+
+    /// Serialize into `buf`, which is [`Self::SIZE`] bytes long.
+    fn store(&self, buf: &mut [u8]);
+
+    /// Deserialize from `buf`, which is [`Self::SIZE`] bytes long, or `None` if it doesn't hold a
+    /// valid value.
+    fn load(buf: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_value_num {
+    ($($type:ty),*) => {
+        $(
+            impl Value for $type {
+                const SIZE: usize = core::mem::size_of::<$type>();
+
+                fn store(&self, buf: &mut [u8]) {
+                    buf.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(buf: &[u8]) -> Option<Self> {
+                    Some(Self::from_le_bytes(buf.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_value_num!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Value for bool {
+    const SIZE: usize = 1;
+
+    fn store(&self, buf: &mut [u8]) {
+        buf[0] = *self as u8;
+    }
+
+    fn load(buf: &[u8]) -> Option<Self> {
+        match buf[0] {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl<const N: usize> Value for [u8; N] {
+    const SIZE: usize = N;
+
+    fn store(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(self);
+    }
+
+    fn load(buf: &[u8]) -> Option<Self> {
+        buf.try_into().ok()
+    }
+}
+
+/// A record in a page: header word with key and length, data padded to words, commit word.
+#[derive(Debug, Copy, Clone)]
+struct Record {
+    offset: u32,
+    key: u16,
+    len: usize,
+    /// The commit word matches the header and data.
+    valid: bool,
+}
+
+impl Record {
+    fn end(&self) -> u32 {
+        self.offset + record_size(self.len)
+    }
+
+    fn data(&self) -> u32 {
+        self.offset + 4
+    }
+}
+
+fn record_size(len: usize) -> u32 {
+    (4 + ((len + 3) & !3) + 4) as u32
+}
+
+/// Whether a record of `len` bytes of data fits at `offset` in a page of `size` bytes.
+fn fits(offset: u32, len: usize, size: u32) -> bool {
+    offset + record_size(len) <= size
+}
+
+/// Index of the newer of two pages of generations `a` and `b`. Generations wrap around, a page is
+/// newer if it's less than 2^31 copies ahead.
+fn newer_page(a: u32, b: u32) -> usize {
+    match b.wrapping_sub(a) as i32 > 0 {
+        true => 1,
+        false => 0,
+    }
+}
+
+/// FNV-1a over the header and data of a record, with the top bit cleared so it never reads as
+/// erased.
+struct Checksum(u32);
+
+impl Checksum {
+    fn new() -> Self {
+        Self(0x811C_9DC5)
+    }
+
+    fn add(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u32).wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.0 & 0x7FFF_FFFF
+    }
+}
+
+/// Key-value store on two flash pages, see the [module documentation](self).
+pub struct Eeprom<'d> {
+    flash: Flash<'d>,
+    pages: [Page; 2],
+    /// Index of the active page.
+    active: usize,
+    generation: u32,
+    /// Offset of the free space in the active page.
+    next: u32,
+}
+
+impl<'d> Eeprom<'d> {
+    /// Mount the EEPROM on the two pages at `offset`.
+    ///
+    /// `offset` must be the start of a page, see [`Flash::pages`], and this page and the next must
+    /// not be used for anything else. They may differ in size. If neither page holds a valid EEPROM,
+    /// both are erased and the EEPROM starts empty. Leftovers of an interrupted page copy are erased.
+    pub fn new(flash: Flash<'d>, offset: u32) -> Result<Self, Error> {
+        let page = |offset| match flash.page_at(offset) {
+            Some(page) if page.offset == offset => Ok(page),
+            Some(_) => Err(Error::Flash(super::Error::Unaligned)),
+            None => Err(Error::Flash(super::Error::OutOfBounds)),
+        };
+        let first = page(offset)?;
+        let second = page(first.end())?;
+
+        let mut this = Self {
+            flash,
+            pages: [first, second],
+            active: 0,
+            generation: 0,
+            next: HEADER_SIZE,
+        };
+
+        let generations = [this.page_generation(0)?, this.page_generation(1)?];
+        match generations {
+            [Some(a), Some(b)] => {
+                // Power loss after a page copy was committed, but before the old page was erased.
+                this.active = newer_page(a, b);
+                this.generation = unwrap!(generations[this.active]);
+                this.erase_page(1 - this.active)?;
+            }
+            [Some(generation), None] | [None, Some(generation)] => {
+                this.active = if generations[0].is_some() { 0 } else { 1 };
+                this.generation = generation;
+                this.erase_page(1 - this.active)?;
+            }
+            [None, None] => {
+                this.erase_page(0)?;
+                this.erase_page(1)?;
+                this.active = 0;
+                this.commit_page(0, 0)?;
+            }
+        }
+
+        // Find the end of the log.
+        let page = this.pages[this.active];
+        let mut offset = HEADER_SIZE;
+        while let Some(record) = this.record_at(page, offset)? {
+            offset = record.end();
+        }
+        // A torn header can't be appended to, copy the page on the next write instead.
+        this.next = match offset + 4 <= page.size && this.read_word(page.offset + offset)? == ERASED {
+            true => offset,
+            false => page.size,
+        };
+
+        Ok(this)
+    }
+
+    /// Read the value of `key`, or `None` if it was never written.
+    pub fn read<T: Value>(&mut self, key: u16) -> Result<Option<T>, Error> {
+        let record = match self.find(key)? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        if record.len != T::SIZE {
+            return Err(Error::InvalidValue);
+        }
+
+        let mut buf = [0; MAX_VALUE_SIZE];
+        let data = &mut buf[..record.len];
+        self.flash
+            .blocking_read(self.pages[self.active].offset + record.data(), data)?;
+        T::load(data).map(Some).ok_or(Error::InvalidValue)
+    }
+
+    /// Write `value` to `key`.
+    ///
+    /// Nothing is written if the key already holds the same value.
+    pub fn write<T: Value>(&mut self, key: u16, value: &T) -> Result<(), Error> {
+        if T::SIZE > MAX_VALUE_SIZE {
+            return Err(Error::ValueTooLarge);
+        }
+        let mut buf = [0; MAX_VALUE_SIZE];
+        let data = &mut buf[..T::SIZE];
+        value.store(data);
+
+        if let Some(record) = self.find(key)? {
+            if record.len == data.len() {
+                let mut old = [0; MAX_VALUE_SIZE];
+                let old = &mut old[..record.len];
+                self.flash
+                    .blocking_read(self.pages[self.active].offset + record.data(), old)?;
+                if old == data {
+                    return Ok(());
+                }
+            }
+        }
+
+        let page = self.pages[self.active];
+        if fits(self.next, data.len(), page.size) {
+            self.append(page.offset + self.next, key, data)?;
+            self.next += record_size(data.len());
+            Ok(())
+        } else {
+            self.copy_page(key, data)
+        }
+    }
+
+    /// Copy the latest records to the other page, followed by `key` with `data`, and make it the
+    /// active page.
+    fn copy_page(&mut self, key: u16, data: &[u8]) -> Result<(), Error> {
+        let (old, new) = (self.active, 1 - self.active);
+        let (old_page, new_page) = (self.pages[old], self.pages[new]);
+
+        self.erase_page(new)?;
+
+        let mut dst = HEADER_SIZE;
+        let mut offset = HEADER_SIZE;
+        while let Some(record) = self.record_at(old_page, offset)? {
+            offset = record.end();
+            if !record.valid || record.key == key || !self.is_latest(old_page, &record)? {
+                continue;
+            }
+            if !fits(dst, record.len, new_page.size) {
+                return Err(Error::Full);
+            }
+            for i in (0..record_size(record.len)).step_by(4) {
+                let word = self.read_word(old_page.offset + record.offset + i)?;
+                self.flash
+                    .blocking_write(new_page.offset + dst + i, &word.to_le_bytes())?;
+            }
+            dst += record_size(record.len);
+        }
+
+        if !fits(dst, data.len(), new_page.size) {
+            return Err(Error::Full);
+        }
+        self.append(new_page.offset + dst, key, data)?;
+        dst += record_size(data.len());
+
+        // From here on the new page is the valid one.
+        self.commit_page(new, self.generation.wrapping_add(1))?;
+        self.active = new;
+        self.generation = self.generation.wrapping_add(1);
+        self.next = dst;
+
+        self.erase_page(old)
+    }
+
+    /// Append a record at the absolute offset `offset`.
+    fn append(&mut self, offset: u32, key: u16, data: &[u8]) -> Result<(), Error> {
+        let header = key as u32 | (data.len() as u32) << 16;
+        let mut checksum = Checksum::new();
+        checksum.add(&header.to_le_bytes());
+        checksum.add(data);
+
+        self.flash.blocking_write(offset, &header.to_le_bytes())?;
+        for (i, chunk) in data.chunks(4).enumerate() {
+            let mut word = [0xFF; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.flash.blocking_write(offset + 4 + 4 * i as u32, &word)?;
+        }
+        let commit = offset + record_size(data.len()) - 4;
+        self.flash.blocking_write(commit, &checksum.finish().to_le_bytes())?;
+        Ok(())
+    }
+
+    /// The latest valid record of `key` in the active page.
+    fn find(&mut self, key: u16) -> Result<Option<Record>, Error> {
+        let page = self.pages[self.active];
+        let mut found = None;
+        let mut offset = HEADER_SIZE;
+        while let Some(record) = self.record_at(page, offset)? {
+            offset = record.end();
+            if record.valid && record.key == key {
+                found = Some(record);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Whether no valid record of the same key follows `record`.
+    fn is_latest(&mut self, page: Page, record: &Record) -> Result<bool, Error> {
+        let mut offset = record.end();
+        while let Some(next) = self.record_at(page, offset)? {
+            offset = next.end();
+            if next.valid && next.key == record.key {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The record at `offset` in `page`, or `None` at the end of the log.
+    fn record_at(&mut self, page: Page, offset: u32) -> Result<Option<Record>, Error> {
+        if offset + record_size(0) > page.size {
+            return Ok(None);
+        }
+        let header = self.read_word(page.offset + offset)?;
+        if header == ERASED {
+            return Ok(None);
+        }
+
+        let len = (header >> 16) as usize;
+        let record = Record {
+            offset,
+            key: header as u16,
+            len,
+            valid: false,
+        };
+        if len > MAX_VALUE_SIZE || record.end() > page.size {
+            // Torn header.
+            return Ok(None);
+        }
+
+        let mut checksum = Checksum::new();
+        checksum.add(&header.to_le_bytes());
+        for i in (0..len).step_by(4) {
+            let word = self.read_word(page.offset + record.data() + i as u32)?.to_le_bytes();
+            checksum.add(&word[..(len - i).min(4)]);
+        }
+        let commit = self.read_word(page.offset + record.end() - 4)?;
+
+        Ok(Some(Record {
+            valid: commit == checksum.finish(),
+            ..record
+        }))
+    }
+
+    /// The generation of page `page`, or `None` if it isn't a committed page.
+    fn page_generation(&mut self, page: usize) -> Result<Option<u32>, Error> {
+        let base = self.pages[page].offset;
+        match self.read_word(base + 4)? {
+            PAGE_MAGIC => Ok(Some(self.read_word(base)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Write the header of page `page`, the magic last.
+    fn commit_page(&mut self, page: usize, generation: u32) -> Result<(), Error> {
+        let base = self.pages[page].offset;
+        self.flash.blocking_write(base, &generation.to_le_bytes())?;
+        self.flash.blocking_write(base + 4, &PAGE_MAGIC.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Erase page `page`, unless it is erased already.
+    fn erase_page(&mut self, page: usize) -> Result<(), Error> {
+        let page = self.pages[page];
+        for offset in (0..page.size).step_by(4) {
+            if self.read_word(page.offset + offset)? != ERASED {
+                self.flash.blocking_erase(page.offset, page.end())?;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_word(&mut self, offset: u32) -> Result<u32, Error> {
+        let mut word = [0; 4];
+        self.flash.blocking_read(offset, &mut word)?;
+        Ok(u32::from_le_bytes(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_rolls_over_to_other_page() {
+        let size = 2048;
+        // A `u32` record takes 12 bytes.
+        assert_eq!(record_size(4), 12);
+        assert!(fits(size - 12, 4, size));
+        assert!(!fits(size - 8, 4, size));
+        // Data is padded to words.
+        assert!(fits(size - 12, 1, size));
+        assert!(!fits(size - 12, 5, size));
+        assert!(fits(HEADER_SIZE, MAX_VALUE_SIZE, size));
+    }
+
+    #[test]
+    fn newer_page_across_generation_rollover() {
+        assert_eq!(newer_page(0, 1), 1);
+        assert_eq!(newer_page(1, 0), 0);
+        assert_eq!(newer_page(u32::MAX, 0), 1);
+        assert_eq!(newer_page(0, u32::MAX), 0);
+        assert_eq!(newer_page(u32::MAX - 1, 1), 1);
+    }
+}
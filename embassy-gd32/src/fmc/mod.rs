@@ -0,0 +1,501 @@
+//! Flash memory controller (FMC).
+//!
+//! The flash is erased in pages and programmed in 32-bit words. Offsets passed to [`Flash`] are
+//! relative to [`FLASH_BASE`]. The page size may differ between areas of the flash, e.g. between
+//! banks; [`Flash::sectors`] and [`Flash::pages`] describe the layout.
+//!
+//! Erasing or writing is refused for ranges outside the flash and for a reserved region, which is
+//! by default the running program, see [`Flash::new`].
+//!
+//! Erasing a page takes several milliseconds. The async [`Flash::erase`] and [`Flash::write`] wait
+//! for the end of each operation with the FMC interrupt instead of polling, so other tasks can run
+//! meanwhile. Note that the CPU still stalls while the operation is in progress whenever it reads
+//! from the flash; only code and data in RAM or in the zero-wait-state area are unaffected.
+//!
+//! [`Flash`] implements the `embedded-storage` NOR flash traits, and the async ones with the
+//! `nightly` feature, so e.g. `embassy-boot` can write a firmware update to the DFU partition.
+
+use core::future::poll_fn;
+use core::ops::Range;
+use core::ptr::write_volatile;
+use core::task::Poll;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+pub use crate::chip::{FLASH_BASE, FLASH_SECTORS, FLASH_SIZE};
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::{interrupt, pac, Peripheral};
+
+pub mod eeprom;
+pub mod option_bytes;
+
+/// Programming granularity, in bytes.
+pub const WRITE_SIZE: usize = 4;
+
+/// Value of erased flash.
+pub const ERASE_VALUE: u8 = 0xFF;
+
+/// Erase granularity of the `embedded-storage` traits, in bytes: the largest page size.
+pub const ERASE_SIZE: usize = max_page_size();
+
+/// Area of the flash with a uniform page size, e.g. a bank.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlashSector {
+    /// Offset from [`FLASH_BASE`]
+    pub offset: u32,
+    /// Size in bytes
+    pub size: u32,
+    /// Size of the pages in this sector, in bytes
+    pub page_size: u32,
+}
+
+impl FlashSector {
+    /// The pages of this sector, in address order.
+    pub fn pages(self) -> impl Iterator<Item = Page> {
+        (self.offset..self.offset + self.size)
+            .step_by(self.page_size as usize)
+            .map(move |offset| Page {
+                offset,
+                size: self.page_size,
+            })
+    }
+}
+
+/// Flash page, the unit of erase.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Page {
+    /// Offset from [`FLASH_BASE`]
+    pub offset: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+impl Page {
+    /// Offset of the end of the page.
+    pub fn end(&self) -> u32 {
+        self.offset + self.size
+    }
+}
+
+/// Flash error
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Offset or length not a multiple of [`WRITE_SIZE`], or erase range not on page boundaries.
+    Unaligned,
+    /// Range not within [`FLASH_SIZE`], or its end is before its start.
+    OutOfBounds,
+    /// Range overlaps the reserved region, see [`Flash::new`].
+    Protected,
+    /// Programming a word that is not erased (`PGERR`).
+    Program,
+    /// Erasing or programming a page write protected by the option bytes (`WPERR`).
+    WriteProtected,
+}
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Flash driver.
+pub struct Flash<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::FMC>,
+    reserved: Range<u32>,
+}
+
+impl<'d> Flash<'d> {
+    /// Create a flash driver that refuses to erase or write the running program.
+    ///
+    /// The program is taken to extend from the start of the flash to the end of the `.data`
+    /// initializers, as laid out by the `cortex-m-rt` linker script.
+    pub fn new(p: impl Peripheral<P = crate::peripherals::FMC> + 'd) -> Self {
+        Self::new_with_reserved(p, program_range())
+    }
+
+    /// Create a flash driver that refuses to erase or write the `reserved` offsets.
+    ///
+    /// This is useful e.g. to also protect a bootloader or configuration area. An empty range
+    /// allows access to the whole flash, including the running program.
+    pub fn new_with_reserved(p: impl Peripheral<P = crate::peripherals::FMC> + 'd, reserved: Range<u32>) -> Self {
+        into_ref!(p);
+
+        let irq = unsafe { interrupt::FMC::steal() };
+        irq.unpend();
+        irq.enable();
+
+        Self { _inner: p, reserved }
+    }
+
+    /// The offsets protected from erase and write.
+    pub fn reserved(&self) -> Range<u32> {
+        self.reserved.clone()
+    }
+
+    /// The sectors of the flash, in address order.
+    pub fn sectors(&self) -> impl Iterator<Item = FlashSector> {
+        FLASH_SECTORS.iter().copied()
+    }
+
+    /// The pages of the flash, in address order.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        pages()
+    }
+
+    /// The page containing `offset`, or `None` if `offset` is outside the flash.
+    pub fn page_at(&self, offset: u32) -> Option<Page> {
+        page_at(offset)
+    }
+
+    /// Read `bytes.len()` bytes at `offset`.
+    ///
+    /// Unlike erase and write, reading is allowed anywhere in the flash, including the reserved
+    /// region.
+    pub fn blocking_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        let end = offset.checked_add(bytes.len() as u32).ok_or(Error::OutOfBounds)?;
+        if end as usize > FLASH_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+
+        let data = unsafe { core::slice::from_raw_parts((FLASH_BASE as u32 + offset) as *const u8, bytes.len()) };
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Erase the pages in `from..to`, blocking until done.
+    ///
+    /// `from` and `to` must be page boundaries, see [`Flash::pages`].
+    pub fn blocking_erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.check_erase(from, to)?;
+
+        let _lock = unlock();
+        for page in pages_in(from, to) {
+            start_erase(FLASH_BASE as u32 + page.offset);
+            blocking_wait_ready()?;
+        }
+        Ok(())
+    }
+
+    /// Program `buf` at `offset`, blocking until done.
+    ///
+    /// `offset` and the length of `buf` must be multiples of [`WRITE_SIZE`], and the words written
+    /// must be erased.
+    pub fn blocking_write(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        self.check_write(offset, buf.len())?;
+
+        let _lock = unlock();
+        for (i, word) in words(buf).enumerate() {
+            unsafe { start_write(FLASH_BASE as u32 + offset + (i * WRITE_SIZE) as u32, word) };
+            blocking_wait_ready()?;
+        }
+        Ok(())
+    }
+
+    /// Erase the pages in `from..to`.
+    ///
+    /// `from` and `to` must be page boundaries, see [`Flash::pages`]. If the future is dropped, the page
+    /// being erased is completed, the following ones are left untouched.
+    pub async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.check_erase(from, to)?;
+
+        let _lock = unlock();
+        for page in pages_in(from, to) {
+            start_erase(FLASH_BASE as u32 + page.offset);
+            wait_ready().await?;
+        }
+        Ok(())
+    }
+
+    /// Program `buf` at `offset`.
+    ///
+    /// `offset` and the length of `buf` must be multiples of [`WRITE_SIZE`], and the words written
+    /// must be erased. If the future is dropped, the word being programmed is completed, the
+    /// following ones are left untouched.
+    pub async fn write(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        self.check_write(offset, buf.len())?;
+
+        let _lock = unlock();
+        for (i, word) in words(buf).enumerate() {
+            unsafe { start_write(FLASH_BASE as u32 + offset + (i * WRITE_SIZE) as u32, word) };
+            wait_ready().await?;
+        }
+        Ok(())
+    }
+
+    /// Check that `from..to` is within the flash and outside the reserved region.
+    fn check_range(&self, from: u32, to: u32) -> Result<(), Error> {
+        if to < from || to as usize > FLASH_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+        if from < self.reserved.end && self.reserved.start < to {
+            return Err(Error::Protected);
+        }
+        Ok(())
+    }
+
+    fn check_erase(&self, from: u32, to: u32) -> Result<(), Error> {
+        self.check_range(from, to)?;
+        let is_boundary =
+            |offset: u32| offset as usize == FLASH_SIZE || page_at(offset).map(|p| p.offset) == Some(offset);
+        if !is_boundary(from) || !is_boundary(to) {
+            return Err(Error::Unaligned);
+        }
+        Ok(())
+    }
+
+    fn check_write(&self, offset: u32, len: usize) -> Result<(), Error> {
+        let end = offset.checked_add(len as u32).ok_or(Error::OutOfBounds)?;
+        self.check_range(offset, end)?;
+        if offset as usize % WRITE_SIZE != 0 || len % WRITE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        Ok(())
+    }
+}
+
+impl embedded_storage::nor_flash::NorFlashError for Error {
+    fn kind(&self) -> embedded_storage::nor_flash::NorFlashErrorKind {
+        match self {
+            Self::Unaligned => embedded_storage::nor_flash::NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => embedded_storage::nor_flash::NorFlashErrorKind::OutOfBounds,
+            _ => embedded_storage::nor_flash::NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<'d> embedded_storage::nor_flash::ErrorType for Flash<'d> {
+    type Error = Error;
+}
+
+impl<'d> embedded_storage::nor_flash::ReadNorFlash for Flash<'d> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl<'d> embedded_storage::nor_flash::NorFlash for Flash<'d> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.blocking_erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(offset, bytes)
+    }
+}
+
+#[cfg(feature = "nightly")]
+mod asynch {
+    use core::future::Future;
+
+    use embedded_storage_async::nor_flash::{AsyncNorFlash, AsyncReadNorFlash};
+
+    use super::*;
+
+    impl<'d> AsyncReadNorFlash for Flash<'d> {
+        const READ_SIZE: usize = 1;
+
+        type ReadFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+        fn read<'a>(&'a mut self, offset: u32, bytes: &'a mut [u8]) -> Self::ReadFuture<'a> {
+            async move { self.blocking_read(offset, bytes) }
+        }
+
+        fn capacity(&self) -> usize {
+            FLASH_SIZE
+        }
+    }
+
+    impl<'d> AsyncNorFlash for Flash<'d> {
+        const WRITE_SIZE: usize = WRITE_SIZE;
+        const ERASE_SIZE: usize = ERASE_SIZE;
+
+        type EraseFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+        fn erase<'a>(&'a mut self, from: u32, to: u32) -> Self::EraseFuture<'a> {
+            async move { Flash::erase(self, from, to).await }
+        }
+
+        type WriteFuture<'a> = impl Future<Output = Result<(), Self::Error>> + 'a where Self: 'a;
+        fn write<'a>(&'a mut self, offset: u32, bytes: &'a [u8]) -> Self::WriteFuture<'a> {
+            async move { Flash::write(self, offset, bytes).await }
+        }
+    }
+}
+
+const fn max_page_size() -> usize {
+    let mut size = 0;
+    let mut i = 0;
+    while i < FLASH_SECTORS.len() {
+        if FLASH_SECTORS[i].page_size as usize > size {
+            size = FLASH_SECTORS[i].page_size as usize;
+        }
+        i += 1;
+    }
+    size
+}
+
+fn pages() -> impl Iterator<Item = Page> {
+    FLASH_SECTORS.iter().flat_map(|sector| sector.pages())
+}
+
+fn page_at(offset: u32) -> Option<Page> {
+    let sector = FLASH_SECTORS
+        .iter()
+        .find(|s| (s.offset..s.offset + s.size).contains(&offset))?;
+    let start = offset - (offset - sector.offset) % sector.page_size;
+    Some(Page {
+        offset: start,
+        size: sector.page_size,
+    })
+}
+
+/// The pages in `from..to`.
+fn pages_in(from: u32, to: u32) -> impl Iterator<Item = Page> {
+    pages().filter(move |page| from <= page.offset && page.offset < to)
+}
+
+/// Flash offsets occupied by the running program.
+fn program_range() -> Range<u32> {
+    extern "C" {
+        // Load address of the `.data` initializers, placed after `.text` and `.rodata`.
+        static __sidata: u32;
+        static __sdata: u32;
+        static __edata: u32;
+    }
+
+    let end = unsafe {
+        let data_len = &__edata as *const u32 as u32 - &__sdata as *const u32 as u32;
+        &__sidata as *const u32 as u32 + data_len
+    };
+    0..end - FLASH_BASE as u32
+}
+
+fn words(buf: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    buf.chunks_exact(WRITE_SIZE)
+        .map(|chunk| u32::from_le_bytes(unwrap!(chunk.try_into())))
+}
+
+fn regs() -> &'static pac::fmc::RegisterBlock {
+    unsafe { &*pac::FMC::ptr() }
+}
+
+/// Unlock the FMC, returning a guard that locks it again.
+///
+/// If the guard is dropped while an operation is in progress, e.g. because an async erase was
+/// cancelled, it waits for the operation to complete, since `PG`/`PER` must not be cleared before.
+/// Option byte write access is revoked as well.
+fn unlock() -> OnDrop<impl FnOnce()> {
+    let fmc = regs();
+    if fmc.ctl.read().bits() & CTL_LK != 0 {
+        fmc.key.write(|w| unsafe { w.bits(KEY_1) });
+        fmc.key.write(|w| unsafe { w.bits(KEY_2) });
+    }
+
+    OnDrop::new(|| {
+        let fmc = regs();
+        while fmc.stat.read().bits() & STAT_BUSY != 0 {}
+        critical_section::with(|_| {
+            fmc.ctl
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(CTL_OP_MASK | CTL_ENDIE | CTL_ERRIE)) | CTL_LK) })
+        });
+    })
+}
+
+/// Clear the flags of the previous operation. A stale `ENDF` would fire the interrupt right away.
+fn clear_flags() {
+    regs()
+        .stat
+        .write(|w| unsafe { w.bits(STAT_ENDF | STAT_PGERR | STAT_WPERR) });
+}
+
+/// Result of the finished operation, clearing its flags.
+fn finish() -> Result<(), Error> {
+    let stat = regs().stat.read().bits();
+    clear_flags();
+    if stat & STAT_WPERR != 0 {
+        Err(Error::WriteProtected)
+    } else if stat & STAT_PGERR != 0 {
+        Err(Error::Program)
+    } else {
+        Ok(())
+    }
+}
+
+fn start_erase(addr: u32) {
+    let fmc = regs();
+    clear_flags();
+    fmc.ctl.modify(|r, w| unsafe { w.bits((r.bits() & !CTL_PG) | CTL_PER) });
+    fmc.addr.write(|w| unsafe { w.bits(addr) });
+    fmc.ctl.modify(|r, w| unsafe { w.bits(r.bits() | CTL_START) });
+}
+
+/// Safety: `addr` must be a word aligned flash address.
+unsafe fn start_write(addr: u32, word: u32) {
+    let fmc = regs();
+    clear_flags();
+    fmc.ctl.modify(|r, w| w.bits((r.bits() & !CTL_PER) | CTL_PG));
+    write_volatile(addr as *mut u32, word);
+}
+
+fn blocking_wait_ready() -> Result<(), Error> {
+    while regs().stat.read().bits() & STAT_BUSY != 0 {}
+    finish()
+}
+
+async fn wait_ready() -> Result<(), Error> {
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+
+        let fmc = regs();
+        if fmc.stat.read().bits() & STAT_BUSY == 0 {
+            return Poll::Ready(());
+        }
+        // If the operation ended in the meantime, ENDF is already set and fires the interrupt
+        // as soon as it is enabled.
+        critical_section::with(|_| {
+            fmc.ctl
+                .modify(|r, w| unsafe { w.bits(r.bits() | CTL_ENDIE | CTL_ERRIE) })
+        });
+        Poll::Pending
+    })
+    .await;
+    finish()
+}
+
+#[interrupt]
+unsafe fn FMC() {
+    // ENDF is cleared by the woken task, so mask the interrupt until the next operation.
+    regs().ctl.modify(|r, w| w.bits(r.bits() & !(CTL_ENDIE | CTL_ERRIE)));
+    WAKER.wake();
+}
+
+// FMC_KEY, FMC_OBKEY
+const KEY_1: u32 = 0x4567_0123;
+const KEY_2: u32 = 0xCDEF_89AB;
+
+// FMC_STAT
+const STAT_BUSY: u32 = 1 << 0;
+const STAT_PGERR: u32 = 1 << 2;
+const STAT_WPERR: u32 = 1 << 4;
+const STAT_ENDF: u32 = 1 << 5;
+
+// FMC_CTL
+const CTL_PG: u32 = 1 << 0;
+const CTL_PER: u32 = 1 << 1;
+const CTL_OBPG: u32 = 1 << 4;
+const CTL_OBER: u32 = 1 << 5;
+const CTL_START: u32 = 1 << 6;
+const CTL_LK: u32 = 1 << 7;
+const CTL_OBWEN: u32 = 1 << 9;
+const CTL_ERRIE: u32 = 1 << 10;
+const CTL_ENDIE: u32 = 1 << 12;
+/// Operation and option byte write enable bits, cleared when the FMC is locked again.
+const CTL_OP_MASK: u32 = CTL_PG | CTL_PER | CTL_OBPG | CTL_OBER | CTL_OBWEN;
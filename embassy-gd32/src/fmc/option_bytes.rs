@@ -0,0 +1,180 @@
+//! Option bytes.
+//!
+//! The option bytes hold the security (readout) protection, the user options, two user data bytes
+//! and the write protection bitmap. They are loaded into the FMC at reset, so [`read`] returns the
+//! values in effect, and changes made with [`program`] or [`modify`] only take effect after the
+//! next system reset.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::fmc::{option_bytes, Flash};
+//!
+//! let mut flash = Flash::new(p.FMC);
+//! option_bytes::modify(&mut flash, |ob| ob.protection = option_bytes::Protection::Enabled).unwrap();
+//! cortex_m::peripheral::SCB::sys_reset();
+//! ```
+//!
+//! Programming the option bytes requires erasing them first, which briefly enables the security
+//! protection. A reset or power loss in between leaves the chip protected, and the write
+//! protection and user options at their erased defaults.
+
+use core::ptr::write_volatile;
+
+use super::{
+    blocking_wait_ready, regs, unlock, Error, Flash, CTL_OBER, CTL_OBPG, CTL_OBWEN, CTL_PER, CTL_PG, CTL_START, KEY_1,
+    KEY_2,
+};
+
+/// Address of the option bytes. Each byte is stored in a half-word, next to its complement.
+const OB_BASE: u32 = 0x1FFF_F800;
+
+/// `SPC` value that disables the security protection. Any other value enables it.
+const SPC_DISABLED: u8 = 0xA5;
+/// `SPC` value written to enable the security protection.
+const SPC_ENABLED: u8 = 0xBB;
+
+/// Security protection
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protection {
+    /// The flash can be read and written through the debug port and from RAM or the bootloader.
+    Disabled,
+    /// The flash can only be read by code running from it. The debug port and the bootloader can
+    /// only erase the whole chip.
+    ///
+    /// Disabling the protection again erases the whole flash.
+    Enabled,
+}
+
+/// User options
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UserOptions {
+    /// Start the free watchdog in hardware at reset. It can't be stopped then.
+    pub hardware_watchdog: bool,
+    /// Reset instead of entering standby mode.
+    pub reset_on_standby: bool,
+    /// Reset instead of entering deep-sleep mode.
+    pub reset_on_deep_sleep: bool,
+}
+
+impl UserOptions {
+    fn from_bits(bits: u8) -> Self {
+        // All three options are active low.
+        Self {
+            hardware_watchdog: bits & USER_NWDG_HW == 0,
+            reset_on_standby: bits & USER_NRST_STDBY == 0,
+            reset_on_deep_sleep: bits & USER_NRST_DPSLP == 0,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        let mut bits = !(USER_NWDG_HW | USER_NRST_STDBY | USER_NRST_DPSLP);
+        if !self.hardware_watchdog {
+            bits |= USER_NWDG_HW;
+        }
+        if !self.reset_on_standby {
+            bits |= USER_NRST_STDBY;
+        }
+        if !self.reset_on_deep_sleep {
+            bits |= USER_NRST_DPSLP;
+        }
+        bits
+    }
+}
+
+/// Option byte values
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OptionBytes {
+    /// Security protection
+    pub protection: Protection,
+    /// User options
+    pub user: UserOptions,
+    /// Two bytes of user data, not used by the hardware.
+    pub data: u16,
+    /// Write protection bitmap. Bit `n` set protects the `n`th group of pages from erase and
+    /// programming; see the reference manual for the pages in each group.
+    pub write_protected: u32,
+}
+
+/// The option bytes in effect, as loaded at the last reset.
+pub fn read() -> OptionBytes {
+    let fmc = regs();
+    let obstat = fmc.obstat.read().bits();
+    OptionBytes {
+        protection: match obstat & OBSTAT_SPC != 0 {
+            true => Protection::Enabled,
+            false => Protection::Disabled,
+        },
+        user: UserOptions::from_bits((obstat >> OBSTAT_USER_OFFSET) as u8),
+        data: (obstat >> OBSTAT_DATA_OFFSET) as u16,
+        // A cleared bit protects its pages.
+        write_protected: !fmc.wp.read().bits(),
+    }
+}
+
+/// Erase and program the option bytes with `ob`.
+///
+/// Takes the flash driver to make sure no other flash operation is in progress. The new values
+/// take effect after the next system reset.
+pub fn program(_flash: &mut Flash<'_>, ob: &OptionBytes) -> Result<(), Error> {
+    let fmc = regs();
+
+    let _lock = unlock();
+    fmc.obkey.write(|w| unsafe { w.bits(KEY_1) });
+    fmc.obkey.write(|w| unsafe { w.bits(KEY_2) });
+    if fmc.ctl.read().bits() & CTL_OBWEN == 0 {
+        return Err(Error::WriteProtected);
+    }
+
+    fmc.ctl
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(CTL_PG | CTL_PER | CTL_OBPG)) | CTL_OBER) });
+    fmc.ctl.modify(|r, w| unsafe { w.bits(r.bits() | CTL_START) });
+    blocking_wait_ready()?;
+    fmc.ctl
+        .modify(|r, w| unsafe { w.bits((r.bits() & !CTL_OBER) | CTL_OBPG) });
+
+    let spc = match ob.protection {
+        Protection::Disabled => SPC_DISABLED,
+        Protection::Enabled => SPC_ENABLED,
+    };
+    let wp = (!ob.write_protected).to_le_bytes();
+    let data = ob.data.to_le_bytes();
+    let bytes = [spc, ob.user.bits(), data[0], data[1], wp[0], wp[1], wp[2], wp[3]];
+
+    for (i, byte) in bytes.into_iter().enumerate() {
+        // Erased bytes read as 0xFF already.
+        if byte == 0xFF {
+            continue;
+        }
+        // The hardware stores the complement in the upper byte.
+        unsafe { write_volatile((OB_BASE + 2 * i as u32) as *mut u16, byte as u16) };
+        blocking_wait_ready()?;
+    }
+
+    Ok(())
+}
+
+/// Read the option bytes, change them with `f` and program the result.
+///
+/// The option bytes are only reprogrammed if `f` changed them. See [`program`].
+pub fn modify(flash: &mut Flash<'_>, f: impl FnOnce(&mut OptionBytes)) -> Result<(), Error> {
+    let old = read();
+    let mut new = old;
+    f(&mut new);
+    if new == old {
+        return Ok(());
+    }
+    program(flash, &new)
+}
+
+// FMC_OBSTAT
+const OBSTAT_SPC: u32 = 1 << 1;
+const OBSTAT_USER_OFFSET: u32 = 2;
+const OBSTAT_DATA_OFFSET: u32 = 10;
+
+// User option byte
+const USER_NWDG_HW: u8 = 1 << 0;
+const USER_NRST_STDBY: u8 = 1 << 1;
+const USER_NRST_DPSLP: u8 = 1 << 2;
@@ -0,0 +1,95 @@
+//! Free watchdog timer (FWDGT)
+//!
+//! The free watchdog resets the chip unless it's fed before its timeout. It runs from IRC40K,
+//! which it starts by itself, so it keeps running when the main clocks fail and in deep-sleep.
+//! The reset is reported as [`ResetReason::FreeWatchdog`](crate::pmu::ResetReason::FreeWatchdog).
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::fwdgt::FreeWatchdog;
+//!
+//! let mut wdgt = FreeWatchdog::new(p.FWDGT, 2_000_000);
+//! wdgt.start();
+//! loop {
+//!     // Work...
+//!     wdgt.feed();
+//! }
+//! ```
+//!
+//! IRC40K isn't accurate, it runs between 30 kHz and 60 kHz, so the actual timeout ranges from
+//! two thirds to four thirds of the configured one. Once started, the watchdog can only be
+//! stopped by a reset.
+//!
+//! With several tasks to watch, [`supervisor::Supervisor`] only feeds the watchdog while all of
+//! them are alive.
+
+#[cfg(feature = "time")]
+pub mod supervisor;
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+
+use crate::cctl::IRC40K_FREQ;
+use crate::pac;
+
+/// Largest reload value, of the 12-bit counter
+const MAX_RELOAD: u32 = 0xFFF;
+
+/// Free watchdog timer
+pub struct FreeWatchdog<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::FWDGT>,
+}
+
+impl<'d> FreeWatchdog<'d> {
+    /// Configure the watchdog to reset the chip `timeout_us` microseconds after the last feed,
+    /// at the nominal IRC40K frequency.
+    ///
+    /// The watchdog only runs once [`start`](Self::start)ed. Panics if `timeout_us` is longer than
+    /// about 26 s.
+    pub fn new(inner: impl Peripheral<P = crate::peripherals::FWDGT> + 'd, timeout_us: u32) -> Self {
+        into_ref!(inner);
+
+        // The smallest prescaler that reaches the timeout, from 4 (2^2) to 256 (2^8).
+        let ticks = |psc_power: u32| timeout_us as u64 * IRC40K_FREQ.0 as u64 / (1_000_000 << psc_power);
+        let psc_power = unwrap!(
+            (2..=8).find(|&psc_power| ticks(psc_power) <= MAX_RELOAD as u64),
+            "watchdog timeout too long"
+        );
+        let reload = (ticks(psc_power) as u32).max(1);
+
+        let r = regs();
+        unsafe {
+            // The registers are write protected, and an update must finish before the next one.
+            r.ctl.write(|w| w.bits(KEY_UNLOCK));
+            while r.stat.read().bits() & (STAT_PUD | STAT_RUD) != 0 {}
+            r.psc.write(|w| w.bits(psc_power - 2));
+            r.rld.write(|w| w.bits(reload));
+            while r.stat.read().bits() & (STAT_PUD | STAT_RUD) != 0 {}
+            r.ctl.write(|w| w.bits(KEY_RELOAD));
+        }
+
+        Self { _inner: inner }
+    }
+
+    /// Start the watchdog, which is fed by starting it.
+    pub fn start(&mut self) {
+        unsafe { regs().ctl.write(|w| w.bits(KEY_START)) };
+    }
+
+    /// Feed the watchdog, restarting its timeout.
+    pub fn feed(&mut self) {
+        unsafe { regs().ctl.write(|w| w.bits(KEY_RELOAD)) };
+    }
+}
+
+fn regs() -> &'static pac::fwdgt::RegisterBlock {
+    unsafe { &*pac::FWDGT::ptr() }
+}
+
+// FWDGT_CTL keys
+const KEY_RELOAD: u32 = 0xAAAA;
+const KEY_UNLOCK: u32 = 0x5555;
+const KEY_START: u32 = 0xCCCC;
+
+// FWDGT_STAT
+const STAT_PUD: u32 = 1 << 0;
+const STAT_RUD: u32 = 1 << 1;
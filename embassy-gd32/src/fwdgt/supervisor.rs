@@ -0,0 +1,178 @@
+//! Watchdog supervisor of several tasks
+//!
+//! A single watchdog fed by one task only tells that this task runs. The [`Supervisor`] watches
+//! any number of tasks instead: each registers a [`TaskHandle`] with a deadline, and must
+//! [`pet`](TaskHandle::pet) it within the deadline. The monitor task, [`Supervisor::run`], only
+//! feeds the free watchdog while every registered task is alive, so a single stuck task resets
+//! the chip.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::fwdgt::supervisor::{self, Supervisor};
+//! use embassy_gd32::fwdgt::FreeWatchdog;
+//! use embassy_time::Duration;
+//!
+//! static SUPERVISOR: Supervisor<4> = Supervisor::new();
+//!
+//! if let Some(id) = supervisor::starved_task() {
+//!     // The last reset was caused by task `id`.
+//! }
+//!
+//! // In a task:
+//! let handle = SUPERVISOR.register(Duration::from_millis(500));
+//! loop {
+//!     // Work...
+//!     handle.pet();
+//! }
+//!
+//! // In the monitor task:
+//! let mut wdgt = FreeWatchdog::new(p.FWDGT, 2_000_000);
+//! SUPERVISOR.run(&mut wdgt, Duration::from_millis(250)).await;
+//! # }
+//! ```
+//!
+//! When a task misses its deadline, the monitor stores its ID in backup data register
+//! [`STARVED_REGISTER`] before the watchdog resets the chip, read back with [`starved_task`].
+
+use core::cell::Cell;
+use core::future::pending;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use super::FreeWatchdog;
+use crate::bkp;
+
+/// Backup data register that holds the ID of the starved task across the reset. It isn't used
+/// by the RTC nor by [`crate::bootloader`].
+pub const STARVED_REGISTER: usize = 35;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    deadline: Duration,
+    last_pet: Instant,
+}
+
+/// Supervisor of up to `N` tasks, usually in a `static`
+pub struct Supervisor<const N: usize> {
+    slots: Mutex<Cell<[Option<Slot>; N]>>,
+}
+
+impl<const N: usize> Supervisor<N> {
+    /// Create a supervisor without registered tasks.
+    pub const fn new() -> Self {
+        Self {
+            slots: Mutex::new(Cell::new([None; N])),
+        }
+    }
+
+    /// Register a task that must pet the returned handle at least every `deadline`, starting now.
+    /// The task is unregistered when the handle is dropped.
+    ///
+    /// Panics if `N` tasks are already registered.
+    pub fn register(&self, deadline: Duration) -> TaskHandle<'_, N> {
+        let id = critical_section::with(|cs| {
+            let cell = self.slots.borrow(cs);
+            let mut slots = cell.get();
+            let id = unwrap!(slots.iter().position(Option::is_none), "all supervisor slots taken");
+            slots[id] = Some(Slot {
+                deadline,
+                last_pet: Instant::now(),
+            });
+            cell.set(slots);
+            id
+        });
+        TaskHandle {
+            supervisor: self,
+            id: id as u8,
+        }
+    }
+
+    /// Start `watchdog` and feed it every `period` while all registered tasks are alive.
+    ///
+    /// Once a task missed its deadline, its ID is stored in [`STARVED_REGISTER`] and the
+    /// watchdog isn't fed anymore, so it resets the chip. `period` must be well below the
+    /// watchdog timeout, see [`FreeWatchdog::new`].
+    pub async fn run(&self, watchdog: &mut FreeWatchdog<'_>, period: Duration) -> ! {
+        watchdog.start();
+        loop {
+            if let Some(id) = self.starved() {
+                warn!("task {} starved, waiting for the watchdog reset", id);
+                critical_section::with(|_| unsafe {
+                    bkp::enable();
+                    bkp::data_write(STARVED_REGISTER, id as u16 + 1);
+                });
+                break;
+            }
+            watchdog.feed();
+            Timer::after(period).await;
+        }
+        loop {
+            pending::<()>().await;
+        }
+    }
+
+    /// The ID of the first registered task that missed its deadline.
+    fn starved(&self) -> Option<u8> {
+        // Read the slots first, a later pet isn't before `now`.
+        let slots = critical_section::with(|cs| self.slots.borrow(cs).get());
+        let now = Instant::now();
+        let id = slots.iter().position(|slot| match slot {
+            Some(slot) => now.duration_since(slot.last_pet) > slot.deadline,
+            None => false,
+        })?;
+        Some(id as u8)
+    }
+}
+
+/// Handle of a task registered with [`Supervisor::register`]
+pub struct TaskHandle<'a, const N: usize> {
+    supervisor: &'a Supervisor<N>,
+    id: u8,
+}
+
+impl<'a, const N: usize> TaskHandle<'a, N> {
+    /// ID of the task, from 0 to `N - 1`, as reported by [`starved_task`].
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Tell the supervisor that the task is alive, restarting its deadline.
+    pub fn pet(&self) {
+        critical_section::with(|cs| {
+            let cell = self.supervisor.slots.borrow(cs);
+            let mut slots = cell.get();
+            if let Some(slot) = &mut slots[self.id as usize] {
+                slot.last_pet = Instant::now();
+            }
+            cell.set(slots);
+        });
+    }
+}
+
+impl<'a, const N: usize> Drop for TaskHandle<'a, N> {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            let cell = self.supervisor.slots.borrow(cs);
+            let mut slots = cell.get();
+            slots[self.id as usize] = None;
+            cell.set(slots);
+        });
+    }
+}
+
+/// Take the ID of the task that starved the watchdog before the last reset, clearing it.
+/// Returns `None` if the last reset wasn't caused by a starved task.
+pub fn starved_task() -> Option<u8> {
+    critical_section::with(|_| unsafe {
+        bkp::enable();
+        match bkp::data_read(STARVED_REGISTER) {
+            0 => None,
+            id => {
+                bkp::data_write(STARVED_REGISTER, 0);
+                Some((id - 1) as u8)
+            }
+        }
+    })
+}
@@ -0,0 +1,1091 @@
+#![macro_use]
+use core::convert::Infallible;
+
+use embassy_hal_common::{impl_peripheral, into_ref, PeripheralRef};
+
+use self::sealed::Pin as _;
+use crate::pac::gpioa::RegisterBlock;
+use crate::{pac, Peripheral};
+
+/// GPIO flexible pin.
+///
+/// This pin can either be a disconnected, input, or output pin, or both. The mode can be changed
+/// at runtime as often as needed, e.g. to turn the bus around in bit-banged bidirectional protocols.
+///
+/// The output level is 'remembered' while the pin is not in output mode. On GD32 the output latch
+/// (`OCTL`) doubles as the pull-up/pull-down selector in input mode, so while the pin is an input
+/// with a pull resistor the requested output level is kept aside and written back to the latch
+/// when the pin is switched to an output mode again.
+pub struct Flex<'d, T: Pin> {
+    pub(crate) pin: PeripheralRef<'d, T>,
+    /// Output level while `OCTL` is used to select the pull direction.
+    saved_level: Option<Level>,
+}
+
+impl<'d, T: Pin> Flex<'d, T> {
+    /// Wrap the pin in a `Flex`.
+    ///
+    /// The pin remains disconnected. The initial output level is unspecified, but can be changed
+    /// before the pin is put into output mode.
+    ///
+    #[inline]
+    pub fn new(pin: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(pin);
+        // Pin will be in disconnected state.
+        Self { pin, saved_level: None }
+    }
+
+    /// Convert this pin into a type erased `Flex<AnyPin>`, keeping its current mode and level.
+    #[inline]
+    pub fn degrade(self) -> Flex<'d, AnyPin> {
+        let pin_port = self.pin.pin_port();
+        let saved_level = self.saved_level;
+        // Ownership of the pin moves to the new Flex, don't reset it.
+        core::mem::forget(self);
+        Flex {
+            pin: PeripheralRef::new(unsafe { AnyPin::steal(pin_port) }),
+            saved_level,
+        }
+    }
+
+    /// Put the pin into input mode.
+    ///
+    /// With a pull resistor, the output latch is used to select the pull direction. The output
+    /// level set before is preserved and restored when switching back to an output mode.
+    #[inline]
+    pub fn set_as_input(&mut self, pull: Pull) {
+        let ctl = match pull {
+            Pull::Up | Pull::Down => {
+                if self.saved_level.is_none() {
+                    self.saved_level = Some(self.latch_level());
+                }
+                match pull {
+                    Pull::Up => self.pin.set_high(),
+                    _ => self.pin.set_low(),
+                }
+                vals::CTL_IN_PULL
+            }
+            Pull::None => {
+                self.restore_latch();
+                vals::CTL_IN_FLOATING
+            }
+        };
+        self.pin.set_mode(ctl, vals::MD_INPUT);
+    }
+
+    /// Put the pin into output mode.
+    ///
+    /// The pin level will be whatever was set before (or low by default). If you want it to begin
+    /// at a specific level, call `set_high`/`set_low` on the pin first.
+    #[inline]
+    pub fn set_as_output(&mut self, speed: Speed) {
+        self.restore_latch();
+        self.pin.set_mode_and_speed(vals::CTL_OUT_PUSHPULL, speed);
+    }
+
+    /// Put the pin into input + output mode.
+    ///
+    /// The input data register keeps reflecting the actual pin level in output mode, so both
+    /// output types can be read back. With [`OutputType::OpenDrain`] this is commonly used for
+    /// "open drain" mode:
+    /// the hardware will drive the line low if you set it to low, and will leave it floating if you set
+    /// it to high, in which case you can read the input to figure out whether another device
+    /// is driving the line low.
+    ///
+    /// The pin level will be whatever was set before (or low by default). If you want it to begin
+    /// at a specific level, call `set_high`/`set_low` on the pin first.
+    ///
+    /// GD32 has no internal pull resistors in output mode, use an external pull-up.
+    #[inline]
+    pub fn set_as_input_output(&mut self, speed: Speed, output_type: OutputType) {
+        self.restore_latch();
+        self.pin.set_mode_and_speed(output_type.ctl(), speed);
+    }
+
+    /// Put the pin into analog mode.
+    ///
+    /// This disconnects the input Schmitt trigger and the pull resistors, so the pin can be
+    /// sampled by the ADC or driven by the DAC without leakage.
+    #[inline]
+    pub fn set_as_analog(&mut self) {
+        self.restore_latch();
+        unsafe { self.pin.set_as_analog() };
+    }
+
+    /// Get the pull resistor currently enabled on the pin.
+    ///
+    /// This is always `Pull::None` when the pin is not in input mode.
+    #[inline]
+    pub fn pull(&self) -> Pull {
+        if self.pin.mode() != (vals::CTL_IN_PULL << 2) | vals::MD_INPUT {
+            return Pull::None;
+        }
+        match self.latch_level() {
+            Level::High => Pull::Up,
+            Level::Low => Pull::Down,
+        }
+    }
+
+    #[inline]
+    fn latch_level(&self) -> Level {
+        (self.pin.block().octl.read().bits() & (1 << self.pin._pin()) != 0).into()
+    }
+
+    /// Write the saved output level back to the latch, if it was used as pull selector.
+    #[inline]
+    fn restore_latch(&mut self) {
+        match self.saved_level.take() {
+            Some(Level::High) => self.pin.set_high(),
+            Some(Level::Low) => self.pin.set_low(),
+            None => {}
+        }
+    }
+
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        !self.is_low()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self.pin.block().istat.read().bits() & (1 << self.pin._pin()) == 0
+    }
+
+    #[inline]
+    pub fn get_level(&self) -> Level {
+        self.is_high().into()
+    }
+
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        !self.is_set_low()
+    }
+
+    /// Is the output pin set as low?
+    #[inline]
+    pub fn is_set_low(&self) -> bool {
+        self.get_output_level() == Level::Low
+    }
+
+    /// What level output is set to
+    #[inline]
+    pub fn get_output_level(&self) -> Level {
+        match self.saved_level {
+            Some(level) => level,
+            None => self.latch_level(),
+        }
+    }
+
+    #[inline]
+    pub fn set_high(&mut self) {
+        self.set_level(Level::High);
+    }
+
+    /// Set the output as low.
+    #[inline]
+    pub fn set_low(&mut self) {
+        self.set_level(Level::Low);
+    }
+
+    /// Set the output level.
+    ///
+    /// While the pin is an input with a pull resistor, this only updates the level that will be
+    /// driven once the pin is switched back to an output mode.
+    #[inline]
+    pub fn set_level(&mut self, level: Level) {
+        match (&mut self.saved_level, level) {
+            (Some(saved), level) => *saved = level,
+            (None, Level::Low) => self.pin.set_low(),
+            (None, Level::High) => self.pin.set_high(),
+        }
+    }
+
+    /// Toggle pin output
+    #[inline]
+    pub fn toggle(&mut self) {
+        if self.is_set_low() {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+}
+
+impl<'d, T: Pin> Drop for Flex<'d, T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.pin.set_as_disconnected() };
+    }
+}
+
+/// Pull setting for an input.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Speed settings
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// 2 MHz
+    Low,
+    /// 10 MHz
+    Medium,
+    /// 50 MHz
+    High,
+    /// Maximum speed, above 50 MHz.
+    VeryHigh,
+}
+
+impl Speed {
+    fn md(self) -> u32 {
+        match self {
+            Speed::Low => vals::MD_OUTPUT_2MHZ,
+            Speed::Medium => vals::MD_OUTPUT_10MHZ,
+            Speed::High | Speed::VeryHigh => vals::MD_OUTPUT_50MHZ,
+        }
+    }
+}
+
+/// Output driver type.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutputType {
+    /// Drive both high and low levels.
+    PushPull,
+    /// Only drive the low level, leave the line floating otherwise.
+    OpenDrain,
+}
+
+impl OutputType {
+    fn ctl(self) -> u32 {
+        match self {
+            OutputType::PushPull => vals::CTL_OUT_PUSHPULL,
+            OutputType::OpenDrain => vals::CTL_OUT_OPENDRAIN,
+        }
+    }
+}
+
+/// GPIO input driver.
+pub struct Input<'d, T: Pin> {
+    pub(crate) pin: Flex<'d, T>,
+}
+
+impl<'d, T: Pin> Input<'d, T> {
+    #[inline]
+    pub fn new(pin: impl Peripheral<P = T> + 'd, pull: Pull) -> Self {
+        let mut pin = Flex::new(pin);
+        pin.set_as_input(pull);
+        Self { pin }
+    }
+
+    /// Change the pull resistor of the pin.
+    ///
+    /// This can be done at any time, e.g. to sense both the insertion and the removal of a jumper
+    /// by pulling the pin away from its current level.
+    #[inline]
+    pub fn set_pull(&mut self, pull: Pull) {
+        self.pin.set_as_input(pull);
+    }
+
+    /// Get the currently configured pull resistor.
+    #[inline]
+    pub fn pull(&self) -> Pull {
+        self.pin.pull()
+    }
+
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    #[inline]
+    pub fn get_level(&self) -> Level {
+        self.pin.get_level()
+    }
+
+    /// Turn this input into a push-pull output, keeping ownership of the pin.
+    ///
+    /// The output latch is set to `initial_output` before the pin starts driving, so the pin
+    /// never glitches to a stale level.
+    #[inline]
+    pub fn into_output(self, initial_output: Level, speed: Speed) -> Output<'d, T> {
+        let mut pin = self.pin;
+        pin.set_level(initial_output);
+        pin.set_as_output(speed);
+        Output { pin }
+    }
+
+    /// Turn this input into an open-drain output, keeping ownership of the pin.
+    ///
+    /// The output latch is set to `initial_output` before the pin starts driving.
+    #[inline]
+    pub fn into_output_open_drain(self, initial_output: Level, speed: Speed) -> OutputOpenDrain<'d, T> {
+        let mut pin = self.pin;
+        pin.set_level(initial_output);
+        pin.set_as_input_output(speed, OutputType::OpenDrain);
+        OutputOpenDrain { pin }
+    }
+}
+
+/// GPIO analog pin.
+///
+/// The pin is disconnected from the digital input and has its pull resistors disabled. This is
+/// the mode the ADC and DAC drivers expect their pins to be in.
+pub struct Analog<'d, T: Pin> {
+    pub(crate) pin: Flex<'d, T>,
+}
+
+impl<'d, T: Pin> Analog<'d, T> {
+    #[inline]
+    pub fn new(pin: impl Peripheral<P = T> + 'd) -> Self {
+        let mut pin = Flex::new(pin);
+        pin.set_as_analog();
+        Self { pin }
+    }
+}
+
+/// Digital input or output level.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    Low,
+    High,
+}
+
+impl From<bool> for Level {
+    fn from(val: bool) -> Self {
+        match val {
+            true => Self::High,
+            false => Self::Low,
+        }
+    }
+}
+
+impl Into<bool> for Level {
+    fn into(self) -> bool {
+        match self {
+            Level::Low => false,
+            Level::High => true,
+        }
+    }
+}
+
+/// GPIO output driver.
+pub struct Output<'d, T: Pin> {
+    pub(crate) pin: Flex<'d, T>,
+}
+
+impl<'d, T: Pin> Output<'d, T> {
+    /// Create a push-pull output.
+    ///
+    /// The output latch is written before the pin is switched to output mode, so the pin starts
+    /// driving `initial_output` right away without a glitch to the previous latch value.
+    #[inline]
+    pub fn new(pin: impl Peripheral<P = T> + 'd, initial_output: Level, speed: Speed) -> Self {
+        let mut pin = Flex::new(pin);
+        match initial_output {
+            Level::High => pin.set_high(),
+            Level::Low => pin.set_low(),
+        }
+        pin.set_as_output(speed);
+        Self { pin }
+    }
+
+    /// Turn this output into an input, keeping ownership of the pin.
+    ///
+    /// Without a pull resistor the output level stays in the latch, and is driven again after
+    /// converting back with [`Input::into_output`] unless a new level is given there.
+    #[inline]
+    pub fn into_input(self, pull: Pull) -> Input<'d, T> {
+        let mut pin = self.pin;
+        pin.set_as_input(pull);
+        Input { pin }
+    }
+
+    /// Set the output as high.
+    #[inline]
+    pub fn set_high(&mut self) {
+        self.pin.set_high();
+    }
+
+    /// Set the output as low.
+    #[inline]
+    pub fn set_low(&mut self) {
+        self.pin.set_low();
+    }
+
+    /// Set the output level.
+    #[inline]
+    pub fn set_level(&mut self, level: Level) {
+        self.pin.set_level(level)
+    }
+
+    /// Is the output pin set as high?
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        self.pin.is_set_high()
+    }
+
+    /// Is the output pin set as low?
+    #[inline]
+    pub fn is_set_low(&self) -> bool {
+        self.pin.is_set_low()
+    }
+
+    /// What level output is set to
+    #[inline]
+    pub fn get_output_level(&self) -> Level {
+        self.pin.get_output_level()
+    }
+
+    /// Toggle pin output
+    #[inline]
+    pub fn toggle(&mut self) {
+        self.pin.toggle();
+    }
+}
+
+/// GPIO output open-drain driver.
+pub struct OutputOpenDrain<'d, T: Pin> {
+    pub(crate) pin: Flex<'d, T>,
+}
+
+impl<'d, T: Pin> OutputOpenDrain<'d, T> {
+    #[inline]
+    pub fn new(pin: impl Peripheral<P = T> + 'd, initial_output: Level, speed: Speed) -> Self {
+        let mut pin = Flex::new(pin);
+
+        match initial_output {
+            Level::High => pin.set_high(),
+            Level::Low => pin.set_low(),
+        }
+
+        pin.set_as_input_output(speed, OutputType::OpenDrain);
+        Self { pin }
+    }
+
+    /// Turn this output into an input, keeping ownership of the pin.
+    #[inline]
+    pub fn into_input(self, pull: Pull) -> Input<'d, T> {
+        let mut pin = self.pin;
+        pin.set_as_input(pull);
+        Input { pin }
+    }
+
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        !self.pin.is_low()
+    }
+
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Returns current pin level
+    #[inline]
+    pub fn get_level(&self) -> Level {
+        self.pin.get_level()
+    }
+
+    /// Set the output as high.
+    #[inline]
+    pub fn set_high(&mut self) {
+        self.pin.set_high();
+    }
+
+    /// Set the output as low.
+    #[inline]
+    pub fn set_low(&mut self) {
+        self.pin.set_low();
+    }
+
+    /// Set the output level.
+    #[inline]
+    pub fn set_level(&mut self, level: Level) {
+        self.pin.set_level(level);
+    }
+
+    /// Is the output pin set as high?
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        self.pin.is_set_high()
+    }
+
+    /// Is the output pin set as low?
+    #[inline]
+    pub fn is_set_low(&self) -> bool {
+        self.pin.is_set_low()
+    }
+
+    /// What level output is set to
+    #[inline]
+    pub fn get_output_level(&self) -> Level {
+        self.pin.get_output_level()
+    }
+
+    /// Toggle pin output
+    #[inline]
+    pub fn toggle(&mut self) {
+        self.pin.toggle()
+    }
+}
+
+/// Group of up to 16 pins of the same port, accessed in parallel.
+///
+/// Bit `n` of the values written and read corresponds to pin `n` of the port, bits of pins that
+/// are not part of the group are ignored on write and read as zero. Writes update all pins with a
+/// single `BOP` access, so e.g. the data lines of an 8080-style parallel LCD interface change at
+/// the same time.
+pub struct PortBus<const N: usize> {
+    pins: [AnyPin; N],
+    mask: u16,
+}
+
+impl<const N: usize> PortBus<N> {
+    /// Create a bus from the given pins.
+    ///
+    /// All pins must be on the same port. The pins remain disconnected until a mode is set.
+    pub fn new(pins: [AnyPin; N]) -> Self {
+        assert!(N > 0 && N <= 16);
+        let port = pins[0]._port();
+        let mut mask = 0;
+        for pin in pins.iter() {
+            assert!(pin._port() == port, "all pins of a PortBus must be on the same port");
+            assert!(mask & (1 << pin._pin()) == 0, "pin used twice in PortBus");
+            mask |= 1 << pin._pin();
+        }
+        Self { pins, mask }
+    }
+
+    /// Bit mask of the pins in this group.
+    #[inline]
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    /// Put all pins into input mode.
+    #[inline]
+    pub fn set_as_input(&mut self, pull: Pull) {
+        let block = self.pins[0].block();
+        let ctl = match pull {
+            Pull::Up => {
+                block.bop.write(|w| unsafe { w.bits(self.mask as u32) });
+                vals::CTL_IN_PULL
+            }
+            Pull::Down => {
+                block.bc.write(|w| unsafe { w.bits(self.mask as u32) });
+                vals::CTL_IN_PULL
+            }
+            Pull::None => vals::CTL_IN_FLOATING,
+        };
+        modify_masked(port_mode(block, self.mask, ctl, vals::MD_INPUT));
+    }
+
+    /// Put all pins into push-pull output mode.
+    #[inline]
+    pub fn set_as_output(&mut self, speed: Speed) {
+        self.set_as_input_output(speed, OutputType::PushPull);
+    }
+
+    /// Put all pins into input + output mode, see [`Flex::set_as_input_output`].
+    #[inline]
+    pub fn set_as_input_output(&mut self, speed: Speed, output_type: OutputType) {
+        let block = self.pins[0].block();
+        let max = if matches!(speed, Speed::VeryHigh) { self.mask } else { 0 };
+        let [ctl0, ctl1] = port_mode(block, self.mask, output_type.ctl(), speed.md());
+        modify_masked([(block.spd.as_ptr(), self.mask as u32, max as u32), ctl0, ctl1]);
+    }
+
+    /// Set the output level of all pins of the group at once.
+    #[inline]
+    pub fn write(&mut self, value: u16) {
+        let set = value & self.mask;
+        let clear = !value & self.mask;
+        self.pins[0]
+            .block()
+            .bop
+            .write(|w| unsafe { w.bits(set as u32 | (clear as u32) << 16) });
+    }
+
+    /// Read the input level of all pins of the group.
+    #[inline]
+    pub fn read(&self) -> u16 {
+        self.pins[0].block().istat.read().bits() as u16 & self.mask
+    }
+
+    /// Read back the output level set for all pins of the group.
+    #[inline]
+    pub fn output(&self) -> u16 {
+        self.pins[0].block().octl.read().bits() as u16 & self.mask
+    }
+}
+
+impl<const N: usize> Drop for PortBus<N> {
+    #[inline]
+    fn drop(&mut self) {
+        let block = self.pins[0].block();
+        modify_masked(port_mode(block, self.mask, vals::CTL_IN_FLOATING, vals::MD_INPUT));
+    }
+}
+
+/// `CTL0` and `CTL1` updates for [`modify_masked`] that give all pins of a port in `mask` the same
+/// `CTL`/`MD` configuration.
+#[inline]
+fn port_mode(block: &RegisterBlock, mask: u16, ctl: u32, md: u32) -> [(*mut u32, u32, u32); 2] {
+    let mut field_mask = [0u32; 2];
+    let mut field_val = [0u32; 2];
+    for n in 0..16 {
+        if mask & (1 << n) != 0 {
+            let shift = (n % 8) * 4;
+            field_mask[n / 8] |= 0b1111 << shift;
+            field_val[n / 8] |= ((ctl << 2) | md) << shift;
+        }
+    }
+    [
+        (block.ctl0.as_ptr(), field_mask[0], field_val[0]),
+        (block.ctl1.as_ptr(), field_mask[1], field_val[1]),
+    ]
+}
+
+/// Raw values of the `CTL` and `MD` fields in `GPIOx_CTL0`/`GPIOx_CTL1`.
+pub(crate) mod vals {
+    pub const MD_INPUT: u32 = 0b00;
+    pub const MD_OUTPUT_10MHZ: u32 = 0b01;
+    pub const MD_OUTPUT_2MHZ: u32 = 0b10;
+    pub const MD_OUTPUT_50MHZ: u32 = 0b11;
+
+    pub const CTL_IN_ANALOG: u32 = 0b00;
+    pub const CTL_IN_FLOATING: u32 = 0b01;
+    pub const CTL_IN_PULL: u32 = 0b10;
+
+    pub const CTL_OUT_PUSHPULL: u32 = 0b00;
+    pub const CTL_OUT_OPENDRAIN: u32 = 0b01;
+    pub const CTL_OUT_AF_PUSHPULL: u32 = 0b10;
+    pub const CTL_OUT_AF_OPENDRAIN: u32 = 0b11;
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    /// Alternate function type settings
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum AFType {
+        Input,
+        OutputPushPull,
+        OutputOpenDrain,
+    }
+
+    pub trait Pin {
+        fn pin_port(&self) -> u8;
+
+        #[inline]
+        fn _pin(&self) -> u8 {
+            self.pin_port() % 16
+        }
+        #[inline]
+        fn _port(&self) -> u8 {
+            self.pin_port() / 16
+        }
+
+        #[inline]
+        fn block(&self) -> &'static RegisterBlock {
+            unsafe {
+                match self._port() {
+                    0 => &*pac::GPIOA::ptr(),
+                    1 => &*pac::GPIOB::ptr(),
+                    2 => &*pac::GPIOC::ptr(),
+                    3 => &*pac::GPIOD::ptr(),
+                    4 => &*pac::GPIOE::ptr(),
+                    5 => &*pac::GPIOF::ptr(),
+                    6 => &*pac::GPIOG::ptr(),
+                    // Pins are only ever created by `impl_pin!` or degraded from one.
+                    _ => core::hint::unreachable_unchecked(),
+                }
+            }
+        }
+
+        /// Set the output as high.
+        #[inline]
+        fn set_high(&self) {
+            let n = self._pin();
+            self.block().bop.write(|w| unsafe { w.bits(1 << n) });
+        }
+
+        /// Set the output as low.
+        #[inline]
+        fn set_low(&self) {
+            let n = self._pin();
+            self.block().bc.write(|w| unsafe { w.bits(1 << n) });
+        }
+
+        /// Read the 4-bit `CTL`/`MD` configuration of this pin.
+        #[inline]
+        fn mode(&self) -> u32 {
+            let n = self._pin() as usize;
+            let r = self.block();
+            let bits = if n < 8 { r.ctl0.read().bits() } else { r.ctl1.read().bits() };
+            (bits >> ((n % 8) * 4)) & 0b1111
+        }
+
+        /// Update of the `CTL`/`MD` bits of this pin in `GPIOx_CTL0`/`GPIOx_CTL1`, for
+        /// [`modify_masked`]. `mask` selects the bits of the 4-bit field that change.
+        #[inline]
+        fn mode_field(&self, mask: u32, value: u32) -> (*mut u32, u32, u32) {
+            let n = self._pin() as usize;
+            let shift = (n % 8) * 4;
+            let r = self.block();
+            let reg = if n < 8 { r.ctl0.as_ptr() } else { r.ctl1.as_ptr() };
+            (reg, mask << shift, value << shift)
+        }
+
+        /// Update of the maximum speed bit of this pin in `GPIOx_SPD`, for [`modify_masked`].
+        #[inline]
+        fn speed_field(&self, speed: Speed) -> (*mut u32, u32, u32) {
+            let n = self._pin();
+            let max = matches!(speed, Speed::VeryHigh);
+            (self.block().spd.as_ptr(), 1 << n, (max as u32) << n)
+        }
+
+        /// Write the 4-bit `CTL`/`MD` configuration of this pin.
+        #[inline]
+        fn set_mode(&self, ctl: u32, md: u32) {
+            modify_masked([self.mode_field(0b1111, (ctl << 2) | md)]);
+        }
+
+        /// Write the `CTL` configuration and the speed of this pin in an output mode, together.
+        #[inline]
+        fn set_mode_and_speed(&self, ctl: u32, speed: Speed) {
+            modify_masked([
+                self.speed_field(speed),
+                self.mode_field(0b1111, (ctl << 2) | speed.md()),
+            ]);
+        }
+
+        #[inline]
+        unsafe fn set_as_af(&self, af_type: AFType) {
+            self.set_as_af_pull(af_type, Pull::None);
+        }
+
+        /// Configure the pin for use by a peripheral.
+        ///
+        /// Which peripheral gets the pin is selected through the AFIO remap of that peripheral, see
+        /// [`crate::afio`].
+        #[inline]
+        unsafe fn set_as_af_pull(&self, af_type: AFType, pull: Pull) {
+            match af_type {
+                AFType::Input => {
+                    let ctl = match pull {
+                        Pull::Up => {
+                            self.set_high();
+                            vals::CTL_IN_PULL
+                        }
+                        Pull::Down => {
+                            self.set_low();
+                            vals::CTL_IN_PULL
+                        }
+                        Pull::None => vals::CTL_IN_FLOATING,
+                    };
+                    self.set_mode(ctl, vals::MD_INPUT);
+                }
+                AFType::OutputPushPull => self.set_mode(vals::CTL_OUT_AF_PUSHPULL, vals::MD_OUTPUT_50MHZ),
+                AFType::OutputOpenDrain => self.set_mode(vals::CTL_OUT_AF_OPENDRAIN, vals::MD_OUTPUT_50MHZ),
+            }
+        }
+
+        #[inline]
+        unsafe fn set_as_analog(&self) {
+            self.set_mode(vals::CTL_IN_ANALOG, vals::MD_INPUT);
+        }
+
+        /// Set the pin as "disconnected", ie doing nothing and consuming the lowest
+        /// amount of power possible.
+        ///
+        /// This is the floating input reset state of the pin.
+        /// Drivers should set_as_disconnected pins when dropped.
+        #[inline]
+        unsafe fn set_as_disconnected(&self) {
+            self.set_mode(vals::CTL_IN_FLOATING, vals::MD_INPUT);
+        }
+
+        #[inline]
+        unsafe fn set_speed(&self, speed: Speed) {
+            modify_masked([self.speed_field(speed), self.mode_field(0b11, speed.md())]);
+        }
+    }
+}
+
+/// Masked read-modify-writes of port configuration registers, as `(register, clear, set)`.
+///
+/// `CTL0`, `CTL1` and `SPD` are shared by all pins of a port, so the updates must not be interleaved
+/// with a reconfiguration of another pin from an interrupt. Callers compute the register addresses,
+/// masks and values up front, so interrupts are only masked for the loads and stores. All registers
+/// of one reconfiguration are written in the same critical section, so an interrupt never sees a
+/// pin with e.g. its new speed but its old mode.
+#[inline(always)]
+fn modify_masked<const N: usize>(updates: [(*mut u32, u32, u32); N]) {
+    critical_section::with(|_| unsafe {
+        for (reg, clear, set) in updates {
+            reg.write_volatile((reg.read_volatile() & !clear) | set);
+        }
+    });
+}
+
+pub trait Pin: Peripheral<P = Self> + Into<AnyPin> + sealed::Pin + Sized + 'static {
+    /// EXTI line this pin can be connected to, see [`ExtiInput`](crate::exti::ExtiInput).
+    type ExtiChannel: crate::exti::Channel;
+
+    /// Number of the pin within the port (0..15)
+    #[inline]
+    fn pin(&self) -> u8 {
+        self._pin()
+    }
+
+    /// Port of the pin (0 = GPIOA, 1 = GPIOB, ...)
+    #[inline]
+    fn port(&self) -> u8 {
+        self._port()
+    }
+
+    /// Put the pin into analog mode, see [`Analog`].
+    #[inline]
+    fn into_analog(self) -> Analog<'static, Self> {
+        Analog::new(self)
+    }
+
+    /// Convert from concrete pin type PX_XX to type erased `AnyPin`.
+    #[inline]
+    fn degrade(self) -> AnyPin {
+        AnyPin {
+            pin_port: self.pin_port(),
+        }
+    }
+}
+
+// Type-erased GPIO pin
+pub struct AnyPin {
+    pin_port: u8,
+}
+
+impl AnyPin {
+    /// Create an `AnyPin` for a specific pin.
+    ///
+    /// # Safety
+    /// - `pin_port` should not in use by another driver.
+    /// - `pin_port` must be a valid pin of this chip, i.e. `port * 16 + pin`.
+    #[inline]
+    pub unsafe fn steal(pin_port: u8) -> Self {
+        Self { pin_port }
+    }
+}
+
+impl_peripheral!(AnyPin);
+impl Pin for AnyPin {
+    type ExtiChannel = crate::exti::AnyChannel;
+}
+impl sealed::Pin for AnyPin {
+    #[inline]
+    fn pin_port(&self) -> u8 {
+        self.pin_port
+    }
+}
+
+// ====================
+
+macro_rules! impl_pin {
+    ($type:ident, $port_num:expr, $pin_num:expr, $exti_ch:ident) => {
+        impl crate::gpio::Pin for peripherals::$type {
+            type ExtiChannel = peripherals::$exti_ch;
+        }
+        impl crate::gpio::sealed::Pin for peripherals::$type {
+            #[inline]
+            fn pin_port(&self) -> u8 {
+                $port_num * 16 + $pin_num
+            }
+        }
+
+        impl From<peripherals::$type> for crate::gpio::AnyPin {
+            fn from(val: peripherals::$type) -> Self {
+                crate::gpio::Pin::degrade(val)
+            }
+        }
+    };
+}
+
+/// Enable the clocks of all GPIO ports.
+pub(crate) unsafe fn init() {
+    let rcu = &*pac::RCU::ptr();
+    rcu.apb2en.modify(|_, w| {
+        w.paen().set_bit();
+        w.pben().set_bit();
+        w.pcen().set_bit();
+        w.pden().set_bit();
+        w.peen().set_bit();
+        w.pfen().set_bit();
+        w.pgen().set_bit()
+    });
+}
+
+mod eh02 {
+    use embedded_hal_02::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+    use super::*;
+
+    impl<'d, T: Pin> InputPin for Input<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high())
+        }
+
+        #[inline]
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_low())
+        }
+    }
+
+    impl<'d, T: Pin> OutputPin for Output<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_high())
+        }
+
+        #[inline]
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_low())
+        }
+    }
+
+    impl<'d, T: Pin> StatefulOutputPin for Output<'d, T> {
+        #[inline]
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_high())
+        }
+
+        /// Is the output pin set as low?
+        #[inline]
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_low())
+        }
+    }
+
+    impl<'d, T: Pin> ToggleableOutputPin for Output<'d, T> {
+        type Error = Infallible;
+        #[inline]
+        fn toggle(&mut self) -> Result<(), Self::Error> {
+            Ok(self.toggle())
+        }
+    }
+
+    impl<'d, T: Pin> InputPin for OutputOpenDrain<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high())
+        }
+
+        #[inline]
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_low())
+        }
+    }
+
+    impl<'d, T: Pin> OutputPin for OutputOpenDrain<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_high())
+        }
+
+        #[inline]
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_low())
+        }
+    }
+
+    impl<'d, T: Pin> StatefulOutputPin for OutputOpenDrain<'d, T> {
+        #[inline]
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_high())
+        }
+
+        /// Is the output pin set as low?
+        #[inline]
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_low())
+        }
+    }
+
+    impl<'d, T: Pin> ToggleableOutputPin for OutputOpenDrain<'d, T> {
+        type Error = Infallible;
+        #[inline]
+        fn toggle(&mut self) -> Result<(), Self::Error> {
+            Ok(self.toggle())
+        }
+    }
+
+    impl<'d, T: Pin> InputPin for Flex<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high())
+        }
+
+        #[inline]
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_low())
+        }
+    }
+
+    impl<'d, T: Pin> OutputPin for Flex<'d, T> {
+        type Error = Infallible;
+
+        #[inline]
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_high())
+        }
+
+        #[inline]
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(self.set_low())
+        }
+    }
+
+    impl<'d, T: Pin> StatefulOutputPin for Flex<'d, T> {
+        #[inline]
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_high())
+        }
+
+        /// Is the output pin set as low?
+        #[inline]
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_set_low())
+        }
+    }
+
+    impl<'d, T: Pin> ToggleableOutputPin for Flex<'d, T> {
+        type Error = Infallible;
+        #[inline]
+        fn toggle(&mut self) -> Result<(), Self::Error> {
+            Ok(self.toggle())
+        }
+    }
+}
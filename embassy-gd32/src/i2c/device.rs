@@ -0,0 +1,130 @@
+//! Devices on a shared I2C bus
+//!
+//! [`I2cDevice`] lets several tasks or drivers use one [`I2c`] driver, which is kept in an async
+//! mutex. Each device has its own address and optionally its own [`Config`], which is applied when
+//! it takes the bus, e.g. for a slow device on a fast bus.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::i2c::device::I2cDevice;
+//! use embassy_gd32::i2c::{Config, I2c};
+//! use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+//! use embassy_sync::mutex::Mutex;
+//!
+//! let i2c = I2c::new(p.I2C0, p.PB6, p.PB7, Config::default());
+//! let bus = Mutex::<NoopRawMutex, _>::new(i2c);
+//! let mut sensor = I2cDevice::new(&bus, 0x76);
+//! let mut eeprom = I2cDevice::new(&bus, 0x50);
+//!
+//! let mut id = [0; 1];
+//! sensor.write_read(&[0xD0], &mut id).await.unwrap();
+//! eeprom.write(&[0x00, 0x10, id[0]]).await.unwrap();
+//! # }
+//! ```
+//!
+//! A failed transfer doesn't affect the next device's one. A NACK, a PEC or block length error
+//! ends with a STOP condition already, and after arbitration loss the bus belongs to the other
+//! master; after any other error the device frees the bus with [`I2c::recover_bus`] before
+//! releasing it, so the next device finds it idle.
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+use super::*;
+
+/// I2C device with a fixed address on a shared bus
+pub struct I2cDevice<'a, 'd, M: RawMutex, T: Instance> {
+    bus: &'a Mutex<M, I2c<'d, T>>,
+    address: Address,
+    config: Option<Config>,
+}
+
+impl<'a, 'd, M: RawMutex, T: Instance> I2cDevice<'a, 'd, M, T> {
+    /// Create a device with the `address`, using the bus' configuration.
+    pub fn new(bus: &'a Mutex<M, I2c<'d, T>>, address: impl Into<Address>) -> Self {
+        Self {
+            bus,
+            address: address.into(),
+            config: None,
+        }
+    }
+
+    /// Create a device with the `address` and its own configuration, which is applied for each of
+    /// its transfers.
+    pub fn new_with_config(
+        bus: &'a Mutex<M, I2c<'d, T>>,
+        address: impl Into<Address>,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        Timing::new(T::frequency(), &config)?;
+        Ok(Self {
+            bus,
+            address: address.into(),
+            config: Some(config),
+        })
+    }
+
+    /// The device's address.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Read into `buffer`.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut bus = self.bus.lock().await;
+        self.apply_config(&mut bus);
+        let res = bus.read(self.address, buffer).await;
+        Self::isolate(&mut bus, res)
+    }
+
+    /// Write `bytes`.
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut bus = self.bus.lock().await;
+        self.apply_config(&mut bus);
+        let res = bus.write(self.address, bytes).await;
+        Self::isolate(&mut bus, res)
+    }
+
+    /// Write `bytes`, then read into `buffer` after a repeated START condition.
+    pub async fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let mut bus = self.bus.lock().await;
+        self.apply_config(&mut bus);
+        let res = bus.write_read(self.address, bytes, buffer).await;
+        Self::isolate(&mut bus, res)
+    }
+
+    /// Run `operations` between one START and one STOP condition, see [`I2c::transaction`].
+    pub async fn transaction(&mut self, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        let mut bus = self.bus.lock().await;
+        self.apply_config(&mut bus);
+        let res = bus.transaction(self.address, operations).await;
+        Self::isolate(&mut bus, res)
+    }
+
+    /// Apply the device's configuration, or the bus' one if the device has none, which a previous
+    /// device may have replaced with its own.
+    fn apply_config(&self, bus: &mut I2c<'d, T>) {
+        // The device's configuration was checked in `new_with_config`, the bus' one by `I2c`.
+        unwrap!(bus.use_config(self.config.as_ref()));
+    }
+
+    /// Leave the bus idle for the next device after an error.
+    fn isolate<R>(bus: &mut I2c<'d, T>, res: Result<R, Error>) -> Result<R, Error> {
+        if let Err(e) = &res {
+            // After a NACK the STOP condition was sent already, and after arbitration loss the
+            // other master owns the bus: clocking it would corrupt that master's transfer. A PEC
+            // or block length error ends the transfer with a STOP condition or before it starts,
+            // and nothing was sent for a zero length read.
+            let bus_idle = matches!(
+                e,
+                Error::Nack | Error::Arbitration | Error::Pec | Error::InvalidBlockLength | Error::ZeroLengthTransfer
+            );
+            if !bus_idle {
+                // The transfer's error is the one to report.
+                let _ = bus.recover_bus();
+            }
+        }
+        res
+    }
+}
@@ -0,0 +1,1557 @@
+//! Inter-Integrated Circuit (I2C) master
+//!
+//! The driver supports 7-bit and 10-bit addressing, see [`Address`], in standard mode (up to
+//! 100 kHz), fast mode (up to 400 kHz) and fast mode plus (up to 1 MHz). The timing is computed from
+//! [`Config`] and checked against the APB1 clock, see [`ConfigError`]. The GPIO of the GD32E503
+//! can't pull up alternate function outputs, so the bus needs external pull-up resistors.
+//!
+//! After a bus error or arbitration loss to another master, the peripheral is reinitialized so
+//! the next transfer can start, and the transfer fails with [`Error::Bus`] or
+//! [`Error::Arbitration`].
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::i2c::{Config, I2c};
+//!
+//! let mut i2c = I2c::new(p.I2C0, p.PB6, p.PB7, Config::default());
+//! let mut id = [0; 1];
+//! i2c.blocking_write_read(0x76, &[0xD0], &mut id).unwrap();
+//! ```
+//!
+//! Transfers are available as blocking functions, and as async functions that wait for the
+//! peripheral's interrupts. With DMA channels passed to [`I2c::new_with_dma`], async transfers of
+//! at least [`DMA_THRESHOLD`] bytes are done by DMA. [`I2c::transaction`] chains reads and writes
+//! under one START and STOP, with repeated STARTs where the direction changes.
+//!
+//! With the `time` feature, each transfer fails with [`Error::Timeout`] after
+//! [`Config::timeout`], e.g. when a device stretches SCL forever. A device that was reset in the
+//! middle of a read may keep SDA low, which [`I2c::recover_bus`] clears.
+//!
+//! With the `unstable-traits` feature, the driver implements the embedded-hal 1.0 `I2c` trait, and
+//! with `nightly` as well the embedded-hal-async one.
+//!
+//! [`device::I2cDevice`] shares the bus between several devices.
+//!
+//! [`smbus::Smbus`] adds the SMBus protocols, PEC and alerts on top of the driver.
+//!
+//! I2C0 and I2C1 are supported. The I2C2 of the GD32E50x has a different register layout and is
+//! not supported by this driver.
+#![macro_use]
+
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, Ordering};
+use embassy_futures::block_on;
+#[cfg(feature = "time")]
+use embassy_futures::select::{select, Either};
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::dma::{AnyChannel, Transfer};
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Flex, OutputType, Pull, Speed};
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::time::Hertz;
+use crate::{cctl, interrupt, pac, peripherals, Peripheral};
+
+pub mod device;
+pub mod smbus;
+
+/// Smallest async transfer done by DMA. Shorter ones are done by interrupts, which is cheaper
+/// than setting up the DMA.
+pub const DMA_THRESHOLD: usize = 4;
+
+/// I2C error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Misplaced START or STOP condition on the bus. The peripheral is reinitialized for the next
+    /// transfer.
+    Bus,
+    /// Another master won arbitration, and owns the bus until its STOP condition. The peripheral
+    /// is reinitialized for the next transfer.
+    Arbitration,
+    /// The address or a data byte was not acknowledged.
+    Nack,
+    /// Data was lost, the clock is not stretched when it's disabled in slave mode.
+    Overrun,
+    /// A read of zero bytes was requested, which the hardware can't do.
+    ZeroLengthTransfer,
+    /// The transfer didn't complete within [`Config::timeout`], or a device held SCL low for
+    /// longer than the SMBus allows.
+    Timeout,
+    /// The PEC byte of an SMBus read doesn't match the data.
+    Pec,
+    /// An SMBus block is empty or longer than the buffer.
+    InvalidBlockLength,
+}
+
+/// Device address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    /// 7-bit address, without the R/W bit
+    SevenBit(u8),
+    /// 10-bit address, from 0 to 0x3FF
+    TenBit(u16),
+}
+
+impl From<u8> for Address {
+    fn from(addr: u8) -> Self {
+        Self::SevenBit(addr)
+    }
+}
+
+/// Configuration error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The frequency is zero or above 1 MHz.
+    InvalidFrequency,
+    /// The APB1 clock is below the minimum of the speed mode: 2 MHz in standard mode, 8 MHz in
+    /// fast mode and 24 MHz in fast mode plus.
+    ApbClockTooLow,
+    /// The frequency is too low for the APB1 clock, the SCL divider doesn't fit in `CLKC`.
+    FrequencyTooLow,
+    /// The rise time doesn't fit in `RISETIME`.
+    InvalidRiseTime,
+    /// The SCL low or high period is shorter, or the frequency higher, than the speed mode
+    /// allows.
+    TimingViolation,
+}
+
+/// I2C speed mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Standard mode, up to 100 kHz
+    Standard,
+    /// Fast mode, up to 400 kHz
+    Fast,
+    /// Fast mode plus, up to 1 MHz
+    FastPlus,
+}
+
+impl Mode {
+    /// Highest SCL frequency
+    fn max_frequency(self) -> u32 {
+        match self {
+            Mode::Standard => 100_000,
+            Mode::Fast => 400_000,
+            Mode::FastPlus => 1_000_000,
+        }
+    }
+
+    /// Lowest APB1 clock the peripheral needs
+    fn min_pclk(self) -> u32 {
+        match self {
+            Mode::Standard => 2_000_000,
+            Mode::Fast => 8_000_000,
+            Mode::FastPlus => 24_000_000,
+        }
+    }
+
+    /// Shortest SCL low and high periods in ns
+    fn min_low_high_ns(self) -> (u64, u64) {
+        match self {
+            Mode::Standard => (4700, 4000),
+            Mode::Fast => (1300, 600),
+            Mode::FastPlus => (500, 260),
+        }
+    }
+
+    /// Smallest `CLKC` value
+    fn min_clkc(self) -> u32 {
+        match self {
+            Mode::Standard => 4,
+            Mode::Fast | Mode::FastPlus => 1,
+        }
+    }
+
+    /// Longest SCL rise time in ns
+    fn max_rise_time_ns(self) -> u32 {
+        match self {
+            Mode::Standard => 1000,
+            Mode::Fast => 300,
+            Mode::FastPlus => 120,
+        }
+    }
+}
+
+/// Ratio of the SCL low to high period in fast mode and fast mode plus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DutyCycle {
+    /// Low for 2/3 of the period
+    Ratio2to1,
+    /// Low for 16/25 of the period, which reaches 400 kHz and 1 MHz exactly with APB1 clocks that
+    /// are a multiple of 10 MHz
+    Ratio16to9,
+}
+
+impl DutyCycle {
+    /// APB1 cycles per `CLKC` that SCL is low and high
+    fn low_high(self, mode: Mode) -> (u32, u32) {
+        match (mode, self) {
+            (Mode::Standard, _) => (1, 1),
+            (_, DutyCycle::Ratio2to1) => (2, 1),
+            (_, DutyCycle::Ratio16to9) => (16, 9),
+        }
+    }
+}
+
+/// Raw timing register values, see `I2C_CKCFG` and `I2C_RT` in the user manual
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timing {
+    /// Speed mode, `CKCFG.FAST` and `FMPCFG.FMPEN`
+    pub mode: Mode,
+    /// `CKCFG.DTCY`, ignored in standard mode
+    pub duty_cycle: DutyCycle,
+    /// SCL divider, `CKCFG.CLKC`
+    pub clkc: u16,
+    /// Maximum SCL rise time in APB1 cycles plus one, `RT.RISETIME`
+    pub rise_time: u8,
+}
+
+impl Timing {
+    /// Compute the timing for `config`, or check `config.timing`, for an APB1 clock of `pclk`.
+    fn new(pclk: Hertz, config: &Config) -> Result<Self, ConfigError> {
+        let timing = match config.timing {
+            Some(timing) => timing,
+            None => Self::compute(pclk.0, config)?,
+        };
+        timing.check(pclk.0)?;
+        Ok(timing)
+    }
+
+    fn compute(pclk: u32, config: &Config) -> Result<Self, ConfigError> {
+        let freq = config.frequency.0;
+        let mode = match freq {
+            0 => return Err(ConfigError::InvalidFrequency),
+            1..=100_000 => Mode::Standard,
+            100_001..=400_000 => Mode::Fast,
+            400_001..=1_000_000 => Mode::FastPlus,
+            _ => return Err(ConfigError::InvalidFrequency),
+        };
+        if pclk < mode.min_pclk() {
+            return Err(ConfigError::ApbClockTooLow);
+        }
+
+        // Round the divider up, so SCL is never faster than requested.
+        let (low, high) = config.duty_cycle.low_high(mode);
+        let cycles = freq * (low + high);
+        let clkc = (pclk + cycles - 1) / cycles;
+        if clkc > CKCFG_CLKC {
+            return Err(ConfigError::FrequencyTooLow);
+        }
+
+        let rise_time_ns = config.rise_time_ns.unwrap_or(mode.max_rise_time_ns());
+        let rise_time = pclk as u64 * rise_time_ns as u64 / 1_000_000_000 + 1;
+        if rise_time > RT_RISETIME as u64 {
+            return Err(ConfigError::InvalidRiseTime);
+        }
+
+        Ok(Self {
+            mode,
+            duty_cycle: config.duty_cycle,
+            clkc: clkc.max(mode.min_clkc()) as u16,
+            rise_time: rise_time as u8,
+        })
+    }
+
+    /// Check the timing against the limits of its speed mode.
+    fn check(&self, pclk: u32) -> Result<(), ConfigError> {
+        let mode = self.mode;
+        if pclk < mode.min_pclk() {
+            return Err(ConfigError::ApbClockTooLow);
+        }
+        if self.clkc as u32 > CKCFG_CLKC {
+            return Err(ConfigError::FrequencyTooLow);
+        }
+        if self.rise_time == 0 || self.rise_time as u32 > RT_RISETIME {
+            return Err(ConfigError::InvalidRiseTime);
+        }
+
+        let (low, high) = self.duty_cycle.low_high(mode);
+        let ns = |n: u32| self.clkc as u64 * n as u64 * 1_000_000_000 / pclk as u64;
+        let (min_low, min_high) = mode.min_low_high_ns();
+        let freq = pclk / (self.clkc as u32 * (low + high)).max(1);
+        if (self.clkc as u32) < mode.min_clkc()
+            || ns(low) < min_low
+            || ns(high) < min_high
+            || freq > mode.max_frequency()
+        {
+            return Err(ConfigError::TimingViolation);
+        }
+        Ok(())
+    }
+
+    fn ckcfg(&self) -> u32 {
+        let mode = match self.mode {
+            Mode::Standard => 0,
+            Mode::Fast | Mode::FastPlus => CKCFG_FAST,
+        };
+        let duty = match (self.mode, self.duty_cycle) {
+            (Mode::Standard, _) | (_, DutyCycle::Ratio2to1) => 0,
+            (_, DutyCycle::Ratio16to9) => CKCFG_DTCY,
+        };
+        mode | duty | self.clkc as u32
+    }
+}
+
+/// I2C configuration
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// SCL frequency, at most 1 MHz. The actual frequency is at most this. It selects the speed
+    /// mode: standard mode up to 100 kHz, fast mode up to 400 kHz and fast mode plus above.
+    pub frequency: Hertz,
+    /// SCL duty cycle in fast mode and fast mode plus
+    pub duty_cycle: DutyCycle,
+    /// Maximum SCL rise time of the bus in ns, which depends on the pull-ups and the bus
+    /// capacitance. `None` uses the maximum of the speed mode: 1000 ns, 300 ns or 120 ns.
+    pub rise_time_ns: Option<u32>,
+    /// Register values to use instead of the ones computed from the fields above. They're still
+    /// checked against the limits of their speed mode.
+    pub timing: Option<Timing>,
+    /// Time after which a transfer fails with [`Error::Timeout`]
+    #[cfg(feature = "time")]
+    pub timeout: embassy_time::Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frequency: Hertz::khz(100),
+            duty_cycle: DutyCycle::Ratio2to1,
+            rise_time_ns: None,
+            timing: None,
+            #[cfg(feature = "time")]
+            timeout: embassy_time::Duration::from_millis(1000),
+        }
+    }
+}
+
+/// How a transfer waits for the peripheral.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Wait {
+    /// Poll the status flags.
+    Blocking,
+    /// Sleep until the peripheral's interrupt fires, and use DMA if available.
+    Async,
+}
+
+/// Deadline of a transfer, see [`Config::timeout`]
+#[derive(Copy, Clone)]
+struct Timeout {
+    #[cfg(feature = "time")]
+    deadline: embassy_time::Instant,
+}
+
+impl Timeout {
+    fn check(self) -> Result<(), Error> {
+        #[cfg(feature = "time")]
+        if embassy_time::Instant::now() > self.deadline {
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Run `fut`, failing at the deadline. Futures that only wake on interrupts don't get to
+    /// [`Timeout::check`] once the interrupts stop.
+    async fn with<R>(self, fut: impl Future<Output = Result<R, Error>>) -> Result<R, Error> {
+        #[cfg(feature = "time")]
+        return match select(fut, embassy_time::Timer::at(self.deadline)).await {
+            Either::First(res) => res,
+            Either::Second(()) => Err(Error::Timeout),
+        };
+        #[cfg(not(feature = "time"))]
+        fut.await
+    }
+}
+
+/// Where a read is in a transaction
+#[derive(Copy, Clone)]
+struct Framing {
+    /// The previous read requested the repeated START already.
+    restarted: bool,
+    /// The read ends the transaction with a STOP condition. Otherwise it's followed by a
+    /// repeated START.
+    last: bool,
+}
+
+impl Framing {
+    /// A read on its own, or after a write
+    const SINGLE: Self = Self {
+        restarted: false,
+        last: true,
+    };
+
+    /// Condition that ends the read
+    fn end(self) -> u32 {
+        match self.last {
+            true => CTL0_STOP,
+            false => CTL0_START,
+        }
+    }
+}
+
+/// An operation of a transaction
+trait Op {
+    fn is_read(&self) -> bool;
+    /// The buffer of a read, empty for a write
+    fn read_buffer(&mut self) -> &mut [u8];
+    /// The bytes of a write, empty for a read
+    fn write_buffer(&self) -> &[u8];
+}
+
+/// An operation of a transaction, see [`I2c::transaction`]
+pub enum Operation<'a> {
+    /// Read into the buffer, which must not be empty.
+    Read(&'a mut [u8]),
+    /// Write the bytes.
+    Write(&'a [u8]),
+}
+
+impl<'a> Op for Operation<'a> {
+    fn is_read(&self) -> bool {
+        matches!(self, Self::Read(_))
+    }
+
+    fn read_buffer(&mut self) -> &mut [u8] {
+        match self {
+            Self::Read(buffer) => buffer,
+            Self::Write(_) => &mut [],
+        }
+    }
+
+    fn write_buffer(&self) -> &[u8] {
+        match self {
+            Self::Write(bytes) => bytes,
+            Self::Read(_) => &[],
+        }
+    }
+}
+
+/// Split off the leading operations that are in the same direction.
+fn split_group<O: Op>(ops: &mut [O]) -> (&mut [O], &mut [O]) {
+    let read = ops.first().map_or(false, |op| op.is_read());
+    let n = ops.iter().take_while(|op| op.is_read() == read).count();
+    ops.split_at_mut(n)
+}
+
+pub struct State {
+    waker: AtomicWaker,
+    /// An SMBus alert occurred while the interrupts were enabled.
+    alert: AtomicBool,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            alert: AtomicBool::new(false),
+        }
+    }
+}
+
+/// I2C master driver
+pub struct I2c<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    scl: PeripheralRef<'d, AnyPin>,
+    sda: PeripheralRef<'d, AnyPin>,
+    tx_dma: Option<PeripheralRef<'d, AnyChannel>>,
+    rx_dma: Option<PeripheralRef<'d, AnyChannel>>,
+    /// Configuration of the bus, restored after a device with its own, see [`device`].
+    config: Config,
+    timing: Timing,
+    #[cfg(feature = "time")]
+    timeout: embassy_time::Duration,
+}
+
+impl<'d, T: Instance> I2c<'d, T> {
+    /// Create an I2C master.
+    ///
+    /// The AFIO remap of the peripheral is selected from the pins. Panics if `config` can't be met
+    /// with the APB1 clock, see [`ConfigError`].
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, scl, sda);
+        Self::new_inner(peri, scl.map_into(), sda.map_into(), None, None, config)
+    }
+
+    /// Create an I2C master that does async transfers of at least [`DMA_THRESHOLD`] bytes by DMA.
+    pub fn new_with_dma(
+        peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, scl, sda, tx_dma, rx_dma);
+        Self::new_inner(
+            peri,
+            scl.map_into(),
+            sda.map_into(),
+            Some(tx_dma.map_into()),
+            Some(rx_dma.map_into()),
+            config,
+        )
+    }
+
+    fn new_inner(
+        peri: PeripheralRef<'d, T>,
+        scl: PeripheralRef<'d, AnyPin>,
+        sda: PeripheralRef<'d, AnyPin>,
+        tx_dma: Option<PeripheralRef<'d, AnyChannel>>,
+        rx_dma: Option<PeripheralRef<'d, AnyChannel>>,
+        config: Config,
+    ) -> Self {
+        let timing = unwrap!(Timing::new(T::frequency(), &config), "invalid I2C configuration");
+        T::remap(&[SclPin::<T>::remaps(&*scl), SdaPin::<T>::remaps(&*sda)]);
+        T::enable();
+        T::reset();
+        unsafe {
+            scl.set_as_af(AFType::OutputOpenDrain);
+            sda.set_as_af(AFType::OutputOpenDrain);
+        }
+
+        let this = Self {
+            _peri: peri,
+            scl,
+            sda,
+            tx_dma,
+            rx_dma,
+            config,
+            timing,
+            #[cfg(feature = "time")]
+            timeout: config.timeout,
+        };
+        Self::configure(&timing);
+
+        unsafe {
+            let irq = T::EventInterrupt::steal();
+            irq.unpend();
+            irq.enable();
+            let irq = T::ErrorInterrupt::steal();
+            irq.unpend();
+            irq.enable();
+        }
+
+        this
+    }
+
+    /// Change the configuration, e.g. to switch to a faster mode once all devices on the bus are
+    /// set up for it.
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        Timing::new(T::frequency(), config)?;
+        self.config = *config;
+        self.use_config(None)
+    }
+
+    /// Switch to `config` for the transfers of a device with its own configuration, or back to
+    /// the configuration of the bus for `None`. The peripheral is only reconfigured if the timing
+    /// changes.
+    pub(crate) fn use_config(&mut self, config: Option<&Config>) -> Result<(), ConfigError> {
+        let config = *config.unwrap_or(&self.config);
+        let timing = Timing::new(T::frequency(), &config)?;
+        if timing != self.timing {
+            Self::configure(&timing);
+            self.timing = timing;
+        }
+        #[cfg(feature = "time")]
+        {
+            self.timeout = config.timeout;
+        }
+        Ok(())
+    }
+
+    /// Free the bus from a device that holds SDA low, e.g. one that was reset in the middle of a
+    /// read, then reinitialize the peripheral.
+    ///
+    /// SCL is clocked as a GPIO at about 100 kHz until the device releases SDA, at most nine
+    /// times, and the bus is released with a STOP condition. Returns [`Error::Bus`] if SDA is
+    /// still low, or SCL is held low.
+    pub fn recover_bus(&mut self) -> Result<(), Error> {
+        unsafe { T::regs().ctl0.write(|w| w.bits(0)) };
+
+        // Half an SCL period at 100 kHz
+        let half_period = || cortex_m::asm::delay(cctl::clocks().sys.0 / 200_000);
+        let res = {
+            let mut scl = Flex::new(self.scl.reborrow());
+            let mut sda = Flex::new(self.sda.reborrow());
+            scl.set_high();
+            scl.set_as_input_output(Speed::Low, OutputType::OpenDrain);
+            sda.set_high();
+            sda.set_as_input_output(Speed::Low, OutputType::OpenDrain);
+            half_period();
+
+            // Each clock shifts out a bit the device is sending, until it is done with the byte
+            // and waits for the acknowledgement, which it doesn't get as SDA stays high.
+            for _ in 0..9 {
+                if sda.is_high() {
+                    break;
+                }
+                scl.set_low();
+                half_period();
+                scl.set_high();
+                half_period();
+            }
+
+            // STOP condition: SDA rises while SCL is high.
+            sda.set_low();
+            half_period();
+            sda.set_high();
+            half_period();
+            match scl.is_high() && sda.is_high() {
+                true => Ok(()),
+                false => Err(Error::Bus),
+            }
+        };
+
+        // The peripheral may still consider the bus busy.
+        T::reset();
+        unsafe {
+            self.scl.set_as_af(AFType::OutputOpenDrain);
+            self.sda.set_as_af(AFType::OutputOpenDrain);
+        }
+        Self::configure(&self.timing);
+        res
+    }
+
+    /// Deadline of a transfer starting now
+    fn timeout(&self) -> Timeout {
+        Timeout {
+            #[cfg(feature = "time")]
+            deadline: embassy_time::Instant::now() + self.timeout,
+        }
+    }
+
+    /// Write the timing configuration and enable the peripheral.
+    fn configure(timing: &Timing) {
+        let r = T::regs();
+        let mode = r.ctl0.read().bits() & CTL0_MODE;
+        let fmpen = match timing.mode {
+            Mode::FastPlus => FMPCFG_FMPEN,
+            Mode::Standard | Mode::Fast => 0,
+        };
+
+        unsafe {
+            // The timing registers can only be written while the peripheral is disabled.
+            r.ctl0.write(|w| w.bits(0));
+            r.ctl1.write(|w| w.bits(T::frequency().0 / 1_000_000));
+            r.ckcfg.write(|w| w.bits(timing.ckcfg()));
+            r.rt.write(|w| w.bits(timing.rise_time as u32));
+            fmpcfg::<T>().write_volatile(fmpen);
+            r.ctl0.write(|w| w.bits(CTL0_I2CEN | mode));
+        }
+    }
+
+    /// Read `STAT0`, returning the error flag that is set, if any.
+    ///
+    /// Error flags are cleared once they're reported. The bus is released with a STOP condition,
+    /// except after arbitration loss, when the other master owns the bus.
+    fn check_errors() -> Result<u32, Error> {
+        let r = T::regs();
+        let stat0 = r.stat0.read().bits();
+        let errors = stat0 & STAT0_ERRORS;
+        if errors == 0 {
+            return Ok(stat0);
+        }
+
+        // The error flags are cleared by writing zero, writing one has no effect. Only clear the
+        // flags that were read, so none raised in between is lost.
+        unsafe { r.stat0.write(|w| w.bits(!errors)) };
+
+        if errors & STAT0_LOSTARB != 0 {
+            return Err(Error::Arbitration);
+        }
+        // The hardware sends a STOP condition itself on an SMBus timeout. A wrong PEC is only
+        // detected on the last byte of a read, after the STOP condition was requested.
+        if errors & STAT0_SMBTO != 0 {
+            return Err(Error::Timeout);
+        }
+        if errors & STAT0_PECERR != 0 {
+            return Err(Error::Pec);
+        }
+        Self::update_ctl0(CTL0_STOP, 0);
+        if errors & STAT0_BERR != 0 {
+            Err(Error::Bus)
+        } else if errors & STAT0_AERR != 0 {
+            Err(Error::Nack)
+        } else {
+            Err(Error::Overrun)
+        }
+    }
+
+    /// Wait until one of `flags` is set in `STAT0`, or an error occurs.
+    async fn wait_for(flags: u32, wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        poll_fn(|cx| {
+            if wait == Wait::Async {
+                T::state().waker.register(cx.waker());
+            }
+            match Self::check_errors() {
+                Err(e) => return Poll::Ready(Err(e)),
+                Ok(stat0) if stat0 & flags != 0 => return Poll::Ready(Ok(())),
+                Ok(_) => {}
+            }
+            if let Err(e) = timeout.check() {
+                return Poll::Ready(Err(e));
+            }
+            if wait == Wait::Async {
+                // `BUFIE` raises the event interrupt for `TBE` and `RBNE` too. The interrupt
+                // handler disables the interrupts again.
+                let bufie = match flags & (STAT0_TBE | STAT0_RBNE) {
+                    0 => 0,
+                    _ => CTL1_BUFIE,
+                };
+                Self::update_ctl1(CTL1_EVIE | CTL1_ERRIE | bufie, 0);
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Wait for a DMA transfer to the data register to complete, or an error to occur.
+    async fn wait_for_dma(mut transfer: Transfer<'_>) -> Result<(), Error> {
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if let Err(e) = Self::check_errors() {
+                return Poll::Ready(Err(e));
+            }
+            Self::update_ctl1(CTL1_ERRIE, 0);
+            Pin::new(&mut transfer).poll(cx).map(Ok)
+        })
+        .await
+    }
+
+    /// Wait until the bus is idle.
+    fn wait_idle(timeout: Timeout) -> Result<(), Error> {
+        while T::regs().stat1.read().bits() & STAT1_I2CBSY != 0 {
+            timeout.check()?;
+        }
+        Ok(())
+    }
+
+    /// Send a START condition, or a repeated START if the bus is ours, and the address. If
+    /// `restarted`, the previous read requested the repeated START already.
+    ///
+    /// Returns once the address is acknowledged, without clearing `ADDSEND`, so the caller can
+    /// set up the acknowledgement of received bytes first.
+    async fn start(addr: Address, read: bool, restarted: bool, wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        let r = T::regs();
+
+        if !restarted {
+            Self::update_ctl0(CTL0_START, 0);
+        }
+        Self::wait_for(STAT0_SBSEND, wait, timeout).await?;
+
+        // `SBSEND` is cleared by reading `STAT0` and then writing the address. A NACK of the
+        // address sets `AERR` instead of `ADDSEND`.
+        match addr {
+            Address::SevenBit(addr) => {
+                unsafe { r.data.write(|w| w.bits(((addr as u32 & 0x7F) << 1) | read as u32)) };
+                Self::wait_for(STAT0_ADDSEND, wait, timeout).await
+            }
+            Address::TenBit(addr) => {
+                // The header holds the two high address bits. The whole address is sent for a
+                // write, a read is then started again with the header alone.
+                let header = HEADER_10BIT | ((addr as u32 >> 7) & 0b110);
+                unsafe { r.data.write(|w| w.bits(header)) };
+                // `ADD10SEND` is cleared by reading `STAT0` and then writing the low byte.
+                Self::wait_for(STAT0_ADD10SEND, wait, timeout).await?;
+                unsafe { r.data.write(|w| w.bits(addr as u32 & 0xFF)) };
+                Self::wait_for(STAT0_ADDSEND, wait, timeout).await?;
+                if read {
+                    Self::clear_addsend();
+                    Self::update_ctl0(CTL0_START, 0);
+                    Self::wait_for(STAT0_SBSEND, wait, timeout).await?;
+                    unsafe { r.data.write(|w| w.bits(header | 1)) };
+                    Self::wait_for(STAT0_ADDSEND, wait, timeout).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Clear `ADDSEND`, which releases SCL and starts the data phase.
+    ///
+    /// `ADDSEND` is only cleared by reading `STAT0` and then `STAT1`, in this order.
+    fn clear_addsend() {
+        let r = T::regs();
+        let _ = r.stat0.read();
+        let _ = r.stat1.read();
+    }
+
+    /// Send a STOP condition and wait until it is on the bus.
+    fn stop(timeout: Timeout) -> Result<(), Error> {
+        Self::update_ctl0(CTL0_STOP, 0);
+        Self::wait_stop(timeout)
+    }
+
+    /// Wait for a STOP condition requested before to be on the bus.
+    fn wait_stop(timeout: Timeout) -> Result<(), Error> {
+        // `STOP` is cleared by hardware once the STOP condition is detected.
+        while T::regs().ctl0.read().bits() & CTL0_STOP != 0 {
+            timeout.check()?;
+        }
+        Ok(())
+    }
+
+    /// Release the bus after an async transfer was cancelled, or a transfer timed out.
+    fn abort() {
+        Self::update_ctl1(0, CTL1_EVIE | CTL1_ERRIE | CTL1_BUFIE | CTL1_DMAON | CTL1_DMALST);
+        // A START that is still pending, e.g. on a busy bus, is withdrawn.
+        Self::update_ctl0(0, CTL0_START);
+        if T::regs().stat1.read().bits() & STAT1_MASTER != 0 {
+            Self::update_ctl0(CTL0_STOP, 0);
+        }
+    }
+
+    /// Clean up after a transfer: release the bus if it timed out, which leaves the transfer
+    /// unfinished, and reinitialize the peripheral after a bus error or arbitration loss, so it
+    /// can start the next transfer.
+    fn finish<R>(&self, res: Result<R, Error>) -> Result<R, Error> {
+        match res {
+            Err(Error::Timeout) => Self::abort(),
+            Err(Error::Bus | Error::Arbitration) => Self::reinit(&self.timing),
+            _ => {}
+        }
+        res
+    }
+
+    /// Reset and reconfigure the peripheral, keeping the SMBus mode.
+    fn reinit(timing: &Timing) {
+        let mode = T::regs().ctl0.read().bits() & CTL0_MODE;
+        T::reset();
+        Self::configure(timing);
+        Self::update_ctl0(mode, 0);
+    }
+
+    /// Set the `set` bits and clear the `clear` bits in `CTL0`.
+    fn update_ctl0(set: u32, clear: u32) {
+        unsafe { T::regs().ctl0.modify(|r, w| w.bits((r.bits() & !clear) | set)) };
+    }
+
+    /// Set the `set` bits and clear the `clear` bits in `CTL1`.
+    fn update_ctl1(set: u32, clear: u32) {
+        unsafe { T::regs().ctl1.modify(|r, w| w.bits((r.bits() & !clear) | set)) };
+    }
+
+    /// Send the address and `bytes`, without a STOP condition.
+    async fn write_bytes(&mut self, addr: Address, bytes: &[u8], wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        Self::start(addr, false, false, wait, timeout).await?;
+        Self::clear_addsend();
+        self.send(bytes, wait, timeout).await?;
+        Self::wait_sent(bytes.len(), wait, timeout).await
+    }
+
+    /// Send `bytes` after the address, returning once the last byte is in `DATA`.
+    async fn send(&mut self, bytes: &[u8], wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        let r = T::regs();
+
+        match &mut self.tx_dma {
+            Some(dma) if wait == Wait::Async && bytes.len() >= DMA_THRESHOLD => {
+                Self::update_ctl1(CTL1_DMAON, 0);
+                let transfer = unsafe { Transfer::new_write(dma.reborrow(), bytes, r.data.as_ptr() as *mut u8) };
+                let res = Self::wait_for_dma(transfer).await;
+                Self::update_ctl1(0, CTL1_DMAON);
+                res
+            }
+            _ => {
+                for byte in bytes {
+                    Self::wait_for(STAT0_TBE, wait, timeout).await?;
+                    unsafe { r.data.write(|w| w.bits(*byte as u32)) };
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait until the last of `len` bytes sent is acknowledged.
+    async fn wait_sent(len: usize, wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        // `BTC` is only set after a byte, the address is acknowledged already.
+        match len {
+            0 => Ok(()),
+            _ => Self::wait_for(STAT0_BTC, wait, timeout).await,
+        }
+    }
+
+    /// Receive `buffer.len()` bytes from the device at `addr`.
+    async fn read_bytes(
+        &mut self,
+        addr: Address,
+        buffer: &mut [u8],
+        framing: Framing,
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        let r = T::regs();
+        let pec = r.ctl0.read().bits() & CTL0_PECEN != 0;
+
+        match &mut self.rx_dma {
+            Some(dma) if wait == Wait::Async && buffer.len() >= DMA_THRESHOLD && !pec => {
+                Self::update_ctl0(CTL0_ACKEN, CTL0_POAP);
+                // `DMALST` NACKs the byte of the last DMA request.
+                Self::update_ctl1(CTL1_DMAON | CTL1_DMALST, 0);
+                let res = async {
+                    Self::start(addr, true, framing.restarted, wait, timeout).await?;
+                    let transfer = unsafe { Transfer::new_read(dma.reborrow(), r.data.as_ptr() as *const u8, buffer) };
+                    Self::clear_addsend();
+                    Self::wait_for_dma(transfer).await
+                }
+                .await;
+                Self::update_ctl1(0, CTL1_DMAON | CTL1_DMALST);
+                res?;
+                Self::update_ctl0(framing.end(), 0);
+                Self::finish_read(framing, timeout)
+            }
+            _ => Self::receive(addr, buffer.len(), buffer.iter_mut(), framing, wait, timeout).await,
+        }
+    }
+
+    /// Receive `len` bytes into `bytes` from the device at `addr`, without DMA.
+    async fn receive<'b>(
+        addr: Address,
+        len: usize,
+        mut bytes: impl Iterator<Item = &'b mut u8>,
+        framing: Framing,
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        let r = T::regs();
+        let mut read = || {
+            let byte = r.data.read().bits() as u8;
+            if let Some(b) = bytes.next() {
+                *b = byte;
+            }
+        };
+        let end = framing.end();
+        // With PEC enabled by `Smbus`, the last byte is the PEC byte, which the hardware checks
+        // if `PECTRANS` is set along with the NACK.
+        let last = match r.ctl0.read().bits() & CTL0_PECEN {
+            0 => 0,
+            _ => CTL0_PECTRANS,
+        };
+
+        // The last byte must be NACKed, which has to be set up before the byte is received. The
+        // sequences for one, two and more bytes follow the GD32 user manual.
+        match len {
+            0 => return Err(Error::ZeroLengthTransfer),
+            1 => {
+                Self::update_ctl0(0, CTL0_ACKEN | CTL0_POAP);
+                Self::start(addr, true, framing.restarted, wait, timeout).await?;
+                // STOP must be set before the byte is received, which starts with clearing
+                // `ADDSEND`.
+                critical_section::with(|_| {
+                    Self::clear_addsend();
+                    Self::update_ctl0(end | last, 0);
+                });
+                Self::wait_for(STAT0_RBNE, wait, timeout).await?;
+                read();
+            }
+            2 => {
+                // `POAP` makes `ACKEN` apply to the next byte, i.e. NACK the second byte.
+                Self::update_ctl0(CTL0_ACKEN | CTL0_POAP, 0);
+                Self::start(addr, true, framing.restarted, wait, timeout).await?;
+                Self::update_ctl0(last, CTL0_ACKEN);
+                Self::clear_addsend();
+                // Both bytes are received, SCL is stretched until the first is read.
+                Self::wait_for(STAT0_BTC, wait, timeout).await?;
+                Self::update_ctl0(end, 0);
+                read();
+                read();
+                Self::update_ctl0(0, CTL0_POAP);
+            }
+            n => {
+                Self::update_ctl0(CTL0_ACKEN, CTL0_POAP);
+                Self::start(addr, true, framing.restarted, wait, timeout).await?;
+                Self::clear_addsend();
+
+                for _ in 0..n - 3 {
+                    Self::wait_for(STAT0_RBNE, wait, timeout).await?;
+                    read();
+                }
+                // Byte N-2 is in `DATA` and N-1 in the shift register, SCL is stretched. NACK byte
+                // N, which is received once N-2 is read.
+                Self::wait_for(STAT0_BTC, wait, timeout).await?;
+                Self::update_ctl0(last, CTL0_ACKEN);
+                read();
+                Self::wait_for(STAT0_BTC, wait, timeout).await?;
+                Self::update_ctl0(end, 0);
+                read();
+                Self::wait_for(STAT0_RBNE, wait, timeout).await?;
+                read();
+            }
+        }
+
+        Self::finish_read(framing, timeout)
+    }
+
+    /// Wait for the STOP condition that ends a read, and check the PEC byte.
+    fn finish_read(framing: Framing, timeout: Timeout) -> Result<(), Error> {
+        if framing.last {
+            Self::wait_stop(timeout)?;
+        }
+        if T::regs().ctl0.read().bits() & CTL0_PECEN != 0 {
+            Self::check_errors()?;
+        }
+        Ok(())
+    }
+
+    /// Run `ops` as one transaction. Adjacent operations in the same direction are joined, and
+    /// direction changes are separated by repeated STARTs.
+    async fn transaction_inner<O: Op>(
+        &mut self,
+        addr: Address,
+        ops: &mut [O],
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        // Reads of no bytes can't be done, check them before taking the bus.
+        let mut rest = &mut *ops;
+        while !rest.is_empty() {
+            let (group, tail) = split_group(core::mem::take(&mut rest));
+            rest = tail;
+            if group[0].is_read() && group.iter_mut().all(|op| op.read_buffer().is_empty()) {
+                return Err(Error::ZeroLengthTransfer);
+            }
+        }
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        Self::wait_idle(timeout)?;
+        let mut restarted = false;
+        let mut rest = ops;
+        while !rest.is_empty() {
+            let (group, tail) = split_group(core::mem::take(&mut rest));
+            rest = tail;
+            let last = rest.is_empty();
+
+            if group[0].is_read() {
+                let framing = Framing { restarted, last };
+                match group {
+                    [op] => self.read_bytes(addr, op.read_buffer(), framing, wait, timeout).await?,
+                    _ => {
+                        let len = group.iter_mut().map(|op| op.read_buffer().len()).sum();
+                        let bytes = group.iter_mut().flat_map(|op| op.read_buffer().iter_mut());
+                        Self::receive(addr, len, bytes, framing, wait, timeout).await?
+                    }
+                }
+                restarted = !last;
+            } else {
+                Self::start(addr, false, restarted, wait, timeout).await?;
+                Self::clear_addsend();
+                let mut len = 0;
+                for op in group.iter() {
+                    self.send(op.write_buffer(), wait, timeout).await?;
+                    len += op.write_buffer().len();
+                }
+                Self::wait_sent(len, wait, timeout).await?;
+                restarted = false;
+                if last {
+                    Self::stop(timeout)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_inner(&mut self, addr: Address, bytes: &[u8], wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        Self::wait_idle(timeout)?;
+        self.write_bytes(addr, bytes, wait, timeout).await?;
+        Self::stop(timeout)
+    }
+
+    async fn read_inner(
+        &mut self,
+        addr: Address,
+        buffer: &mut [u8],
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Err(Error::ZeroLengthTransfer);
+        }
+        Self::wait_idle(timeout)?;
+        self.read_bytes(addr, buffer, Framing::SINGLE, wait, timeout).await
+    }
+
+    async fn write_read_inner(
+        &mut self,
+        addr: Address,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Err(Error::ZeroLengthTransfer);
+        }
+        Self::wait_idle(timeout)?;
+        self.write_bytes(addr, bytes, wait, timeout).await?;
+        self.read_bytes(addr, buffer, Framing::SINGLE, wait, timeout).await
+    }
+
+    /// Read `buffer.len()` bytes from the device at `addr`.
+    pub fn blocking_read(&mut self, addr: impl Into<Address>, buffer: &mut [u8]) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let res = block_on(self.read_inner(addr.into(), buffer, Wait::Blocking, timeout));
+        self.finish(res)
+    }
+
+    /// Write `bytes` to the device at `addr`.
+    ///
+    /// With no bytes, only the address is sent, e.g. to check whether a device is present.
+    pub fn blocking_write(&mut self, addr: impl Into<Address>, bytes: &[u8]) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let res = block_on(self.write_inner(addr.into(), bytes, Wait::Blocking, timeout));
+        self.finish(res)
+    }
+
+    /// Write `bytes` to the device at `addr`, then read `buffer.len()` bytes from it after a
+    /// repeated START, e.g. to read a register.
+    pub fn blocking_write_read(
+        &mut self,
+        addr: impl Into<Address>,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let res = block_on(self.write_read_inner(addr.into(), bytes, buffer, Wait::Blocking, timeout));
+        self.finish(res)
+    }
+
+    /// Run `operations` on the device at `addr` between one START and one STOP condition.
+    ///
+    /// Adjacent operations in the same direction are joined into one transfer, and a repeated
+    /// START separates the operations where the direction changes, e.g. for a write of a register
+    /// address followed by a read of the register without a STOP in between. Fails with
+    /// [`Error::ZeroLengthTransfer`] if a read is empty.
+    pub fn blocking_transaction(
+        &mut self,
+        addr: impl Into<Address>,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let res = block_on(self.transaction_inner(addr.into(), operations, Wait::Blocking, timeout));
+        self.finish(res)
+    }
+
+    /// Read `buffer.len()` bytes from the device at `addr`.
+    ///
+    /// If the future is dropped before it completes, the bus is released with a STOP condition.
+    pub async fn read(&mut self, addr: impl Into<Address>, buffer: &mut [u8]) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let on_drop = OnDrop::new(Self::abort);
+        let res = timeout
+            .with(self.read_inner(addr.into(), buffer, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.finish(res)
+    }
+
+    /// Write `bytes` to the device at `addr`, see [`I2c::read`].
+    pub async fn write(&mut self, addr: impl Into<Address>, bytes: &[u8]) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let on_drop = OnDrop::new(Self::abort);
+        let res = timeout
+            .with(self.write_inner(addr.into(), bytes, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.finish(res)
+    }
+
+    /// Write `bytes` to the device at `addr`, then read `buffer.len()` bytes from it after a
+    /// repeated START, see [`I2c::read`].
+    pub async fn write_read(&mut self, addr: impl Into<Address>, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let on_drop = OnDrop::new(Self::abort);
+        let res = timeout
+            .with(self.write_read_inner(addr.into(), bytes, buffer, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.finish(res)
+    }
+
+    /// Run `operations` on the device at `addr`, see [`I2c::blocking_transaction`] and
+    /// [`I2c::read`].
+    pub async fn transaction(
+        &mut self,
+        addr: impl Into<Address>,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Error> {
+        let timeout = self.timeout();
+        let on_drop = OnDrop::new(Self::abort);
+        let res = timeout
+            .with(self.transaction_inner(addr.into(), operations, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.finish(res)
+    }
+}
+
+impl<'d, T: Instance> Drop for I2c<'d, T> {
+    fn drop(&mut self) {
+        unsafe { T::regs().ctl0.write(|w| w.bits(0)) };
+        T::disable();
+        unsafe {
+            self.scl.set_as_disconnected();
+            self.sda.set_as_disconnected();
+        }
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_02::blocking::i2c::Read for I2c<'d, T> {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(addr, buffer)
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_02::blocking::i2c::Write for I2c<'d, T> {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(addr, bytes)
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_02::blocking::i2c::WriteRead for I2c<'d, T> {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_write_read(addr, bytes, buffer)
+    }
+}
+
+#[cfg(feature = "unstable-traits")]
+mod eh1 {
+    use super::*;
+
+    /// Most operations [`embedded_hal_1::i2c::I2c::transaction_iter`] takes
+    const MAX_ITER_OPERATIONS: usize = 16;
+
+    impl embedded_hal_1::i2c::Error for Error {
+        fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+            match *self {
+                Self::Bus => embedded_hal_1::i2c::ErrorKind::Bus,
+                Self::Arbitration => embedded_hal_1::i2c::ErrorKind::ArbitrationLoss,
+                Self::Nack => {
+                    embedded_hal_1::i2c::ErrorKind::NoAcknowledge(embedded_hal_1::i2c::NoAcknowledgeSource::Unknown)
+                }
+                Self::Overrun => embedded_hal_1::i2c::ErrorKind::Overrun,
+                Self::ZeroLengthTransfer | Self::Timeout | Self::Pec | Self::InvalidBlockLength => {
+                    embedded_hal_1::i2c::ErrorKind::Other
+                }
+            }
+        }
+    }
+
+    impl<'a> Op for embedded_hal_1::i2c::Operation<'a> {
+        fn is_read(&self) -> bool {
+            matches!(self, Self::Read(_))
+        }
+
+        fn read_buffer(&mut self) -> &mut [u8] {
+            match self {
+                Self::Read(buffer) => buffer,
+                Self::Write(_) => &mut [],
+            }
+        }
+
+        fn write_buffer(&self) -> &[u8] {
+            match self {
+                Self::Write(bytes) => bytes,
+                Self::Read(_) => &[],
+            }
+        }
+    }
+
+    impl<'d, T: Instance> I2c<'d, T> {
+        /// Send the address and the bytes of an iterator, without a STOP condition.
+        async fn write_iter_bytes(
+            addr: Address,
+            bytes: impl IntoIterator<Item = u8>,
+            timeout: Timeout,
+        ) -> Result<(), Error> {
+            let r = T::regs();
+
+            Self::start(addr, false, false, Wait::Blocking, timeout).await?;
+            Self::clear_addsend();
+            let mut len = 0;
+            for byte in bytes {
+                Self::wait_for(STAT0_TBE, Wait::Blocking, timeout).await?;
+                unsafe { r.data.write(|w| w.bits(byte as u32)) };
+                len += 1;
+            }
+            Self::wait_sent(len, Wait::Blocking, timeout).await
+        }
+    }
+
+    impl<'d, T: Instance> embedded_hal_1::i2c::ErrorType for I2c<'d, T> {
+        type Error = Error;
+    }
+
+    impl<'d, T: Instance> embedded_hal_1::i2c::I2c for I2c<'d, T> {
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+            self.blocking_read(address, buffer)
+        }
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.blocking_write(address, bytes)
+        }
+
+        fn write_iter<B>(&mut self, address: u8, bytes: B) -> Result<(), Self::Error>
+        where
+            B: IntoIterator<Item = u8>,
+        {
+            let timeout = self.timeout();
+            let res = block_on(async {
+                Self::wait_idle(timeout)?;
+                Self::write_iter_bytes(address.into(), bytes, timeout).await?;
+                Self::stop(timeout)
+            });
+            self.finish(res)
+        }
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            self.blocking_write_read(address, bytes, buffer)
+        }
+
+        fn write_iter_read<B>(&mut self, address: u8, bytes: B, buffer: &mut [u8]) -> Result<(), Self::Error>
+        where
+            B: IntoIterator<Item = u8>,
+        {
+            if buffer.is_empty() {
+                return Err(Error::ZeroLengthTransfer);
+            }
+            let timeout = self.timeout();
+            let res = block_on(async {
+                Self::wait_idle(timeout)?;
+                Self::write_iter_bytes(address.into(), bytes, timeout).await?;
+                self.read_bytes(address.into(), buffer, Framing::SINGLE, Wait::Blocking, timeout)
+                    .await
+            });
+            self.finish(res)
+        }
+
+        fn transaction<'a>(
+            &mut self,
+            address: u8,
+            operations: &mut [embedded_hal_1::i2c::Operation<'a>],
+        ) -> Result<(), Self::Error> {
+            let timeout = self.timeout();
+            let res = block_on(self.transaction_inner(address.into(), operations, Wait::Blocking, timeout));
+            self.finish(res)
+        }
+
+        /// Panics with more than 16 operations, which are collected first to find the adjacent
+        /// ones in the same direction.
+        fn transaction_iter<'a, O>(&mut self, address: u8, operations: O) -> Result<(), Self::Error>
+        where
+            O: IntoIterator<Item = embedded_hal_1::i2c::Operation<'a>>,
+        {
+            let mut ops: [embedded_hal_1::i2c::Operation<'a>; MAX_ITER_OPERATIONS] =
+                core::array::from_fn(|_| embedded_hal_1::i2c::Operation::Write(&[]));
+            let mut n = 0;
+            for op in operations {
+                assert!(n < MAX_ITER_OPERATIONS, "too many I2C operations");
+                ops[n] = op;
+                n += 1;
+            }
+            embedded_hal_1::i2c::I2c::transaction(self, address, &mut ops[..n])
+        }
+    }
+}
+
+#[cfg(all(feature = "unstable-traits", feature = "nightly"))]
+mod eha {
+    use super::*;
+
+    impl<'d, T: Instance> embedded_hal_async::i2c::I2c for I2c<'d, T> {
+        async fn read<'a>(&'a mut self, address: u8, read: &'a mut [u8]) -> Result<(), Self::Error> {
+            self.read(address, read).await
+        }
+
+        async fn write<'a>(&'a mut self, address: u8, write: &'a [u8]) -> Result<(), Self::Error> {
+            self.write(address, write).await
+        }
+
+        async fn write_read<'a>(
+            &'a mut self,
+            address: u8,
+            write: &'a [u8],
+            read: &'a mut [u8],
+        ) -> Result<(), Self::Error> {
+            self.write_read(address, write, read).await
+        }
+
+        async fn transaction<'a, 'b>(
+            &'a mut self,
+            address: u8,
+            operations: &'a mut [embedded_hal_1::i2c::Operation<'b>],
+        ) -> Result<(), Self::Error> {
+            let timeout = self.timeout();
+            let on_drop = OnDrop::new(Self::abort);
+            let res = timeout
+                .with(self.transaction_inner(address.into(), operations, Wait::Async, timeout))
+                .await;
+            on_drop.defuse();
+            self.finish(res)
+        }
+    }
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        fn regs() -> &'static pac::i2c0::RegisterBlock;
+        fn state() -> &'static State;
+
+        /// Select the AFIO layout of the pins, see [`crate::afio::remap_for_pins`].
+        fn remap(pins: &[crate::afio::RemapSet]);
+    }
+}
+
+/// I2C peripheral instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {
+    type EventInterrupt: Interrupt;
+    type ErrorInterrupt: Interrupt;
+}
+
+pin_trait!(SclPin, Instance);
+pin_trait!(SdaPin, Instance);
+pin_trait!(SmbaPin, Instance);
+dma_trait!(TxDma, Instance);
+dma_trait!(RxDma, Instance);
+
+/// Address of `I2C_FMPCFG`, which is apart from the other registers at offset 0x90.
+fn fmpcfg<T: Instance>() -> *mut u32 {
+    (T::regs() as *const _ as usize + 0x90) as *mut u32
+}
+
+/// Wake the transfer waiting for the interrupt.
+unsafe fn on_interrupt<T: Instance>() {
+    // The transfer enables the interrupts again while it waits. This keeps flags that are only
+    // cleared by the transfer from firing the interrupt over and over.
+    let r = T::regs();
+    r.ctl1
+        .modify(|r, w| w.bits(r.bits() & !(CTL1_EVIE | CTL1_ERRIE | CTL1_BUFIE)));
+    // An SMBus alert is kept for `Smbus::wait_for_alert`, as transfers ignore it.
+    if r.stat0.read().bits() & STAT0_SMBALT != 0 {
+        r.stat0.write(|w| w.bits(!STAT0_SMBALT));
+        T::state().alert.store(true, Ordering::Release);
+    }
+    T::state().waker.wake();
+}
+
+macro_rules! impl_irq {
+    ($e:ident, $inst:ident) => {
+        #[interrupt]
+        unsafe fn $e() {
+            on_interrupt::<peripherals::$inst>()
+        }
+    };
+}
+
+impl_irq!(I2C0_EV, I2C0);
+impl_irq!(I2C0_ER, I2C0);
+impl_irq!(I2C1_EV, I2C1);
+impl_irq!(I2C1_ER, I2C1);
+
+macro_rules! impl_i2c {
+    ($inst:ident, $ev:ident, $er:ident, $remap:ident) => {
+        impl_i2c!(@impl $inst, $ev, $er, pins => crate::afio::remap_for_pins::<crate::afio::$remap>(pins));
+    };
+    ($inst:ident, $ev:ident, $er:ident) => {
+        impl_i2c!(@impl $inst, $ev, $er, pins => crate::afio::check_default_layout(pins));
+    };
+    (@impl $inst:ident, $ev:ident, $er:ident, $pins:ident => $remap:expr) => {
+        impl crate::i2c::sealed::Instance for peripherals::$inst {
+            fn regs() -> &'static crate::pac::i2c0::RegisterBlock {
+                unsafe { &*crate::pac::$inst::ptr() }
+            }
+
+            fn state() -> &'static crate::i2c::State {
+                static STATE: crate::i2c::State = crate::i2c::State::new();
+                &STATE
+            }
+
+            fn remap($pins: &[crate::afio::RemapSet]) {
+                $remap
+            }
+        }
+
+        impl crate::i2c::Instance for peripherals::$inst {
+            type EventInterrupt = crate::interrupt::$ev;
+            type ErrorInterrupt = crate::interrupt::$er;
+        }
+    };
+}
+
+// I2C_CTL0
+const CTL0_I2CEN: u32 = 1 << 0;
+const CTL0_SMBEN: u32 = 1 << 1;
+const CTL0_SMBSEL: u32 = 1 << 3;
+const CTL0_PECEN: u32 = 1 << 5;
+const CTL0_START: u32 = 1 << 8;
+const CTL0_STOP: u32 = 1 << 9;
+const CTL0_ACKEN: u32 = 1 << 10;
+const CTL0_POAP: u32 = 1 << 11;
+const CTL0_PECTRANS: u32 = 1 << 12;
+/// SMBus mode bits, kept when the peripheral is reconfigured
+const CTL0_MODE: u32 = CTL0_SMBEN | CTL0_SMBSEL | CTL0_PECEN;
+
+// I2C_CTL1
+const CTL1_ERRIE: u32 = 1 << 8;
+const CTL1_EVIE: u32 = 1 << 9;
+const CTL1_BUFIE: u32 = 1 << 10;
+const CTL1_DMAON: u32 = 1 << 11;
+const CTL1_DMALST: u32 = 1 << 12;
+
+// I2C_STAT0
+const STAT0_SBSEND: u32 = 1 << 0;
+const STAT0_ADDSEND: u32 = 1 << 1;
+const STAT0_BTC: u32 = 1 << 2;
+const STAT0_ADD10SEND: u32 = 1 << 3;
+const STAT0_RBNE: u32 = 1 << 6;
+const STAT0_TBE: u32 = 1 << 7;
+const STAT0_BERR: u32 = 1 << 8;
+const STAT0_LOSTARB: u32 = 1 << 9;
+const STAT0_AERR: u32 = 1 << 10;
+const STAT0_OUERR: u32 = 1 << 11;
+const STAT0_PECERR: u32 = 1 << 12;
+const STAT0_SMBTO: u32 = 1 << 14;
+const STAT0_SMBALT: u32 = 1 << 15;
+const STAT0_ERRORS: u32 = STAT0_BERR | STAT0_LOSTARB | STAT0_AERR | STAT0_OUERR | STAT0_PECERR | STAT0_SMBTO;
+
+// I2C_STAT1
+const STAT1_MASTER: u32 = 1 << 0;
+const STAT1_I2CBSY: u32 = 1 << 1;
+
+// I2C_CKCFG
+const CKCFG_CLKC: u32 = 0xFFF;
+const CKCFG_DTCY: u32 = 1 << 14;
+const CKCFG_FAST: u32 = 1 << 15;
+
+// I2C_RT
+const RT_RISETIME: u32 = 0x7F;
+
+// I2C_FMPCFG
+const FMPCFG_FMPEN: u32 = 1 << 0;
+
+// First byte of a 10-bit address, `0b11110` followed by address bits 9:8 and the R/W bit
+const HEADER_10BIT: u32 = 0b1111_0000;
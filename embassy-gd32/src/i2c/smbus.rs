@@ -0,0 +1,388 @@
+//! System Management Bus (SMBus) host
+//!
+//! [`Smbus`] runs the SMBus protocols over an [`I2c`] driver: commands followed by data, and
+//! blocks that start with their length, as used by smart batteries and PMBus devices. With PEC
+//! enabled, the hardware appends the packet error code to writes and checks it on reads, which
+//! then fail with [`Error::Pec`] on a mismatch.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::i2c::smbus::Smbus;
+//! use embassy_gd32::i2c::{Config, I2c};
+//!
+//! let i2c = I2c::new(p.I2C0, p.PB6, p.PB7, Config::default());
+//! let mut smbus = Smbus::new(i2c, true);
+//! // Read the smart battery's voltage in mV.
+//! let mut voltage = [0; 2];
+//! smbus.blocking_read(0x0B, 0x09, &mut voltage).unwrap();
+//! let voltage = u16::from_le_bytes(voltage);
+//! ```
+//!
+//! Devices signal an alert by pulling the SMBALERT# line low, which is passed to
+//! [`Smbus::new_with_alert`]. The host then finds the device with [`Smbus::alert_response`].
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use atomic_polyfill::Ordering;
+use embassy_futures::block_on;
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::*;
+use crate::gpio::sealed::Pin as _;
+
+/// Longest block, as allowed since SMBus 3.0. Older devices send and take at most 32 bytes.
+pub const MAX_BLOCK_LEN: usize = 255;
+
+/// Alert response address, read by the host to find the device that signalled an alert.
+const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+/// SMBus host on top of an [`I2c`] driver
+pub struct Smbus<'d, T: Instance> {
+    i2c: I2c<'d, T>,
+    smba: Option<PeripheralRef<'d, AnyPin>>,
+    pec: bool,
+}
+
+impl<'d, T: Instance> Smbus<'d, T> {
+    /// Create an SMBus host. With `pec`, all transfers use packet error checking.
+    pub fn new(i2c: I2c<'d, T>, pec: bool) -> Self {
+        Self::begin(false);
+        Self { i2c, smba: None, pec }
+    }
+
+    /// Create an SMBus host that detects alerts on the SMBALERT# line, see
+    /// [`Smbus::wait_for_alert`].
+    ///
+    /// The line needs an external pull-up resistor.
+    pub fn new_with_alert(i2c: I2c<'d, T>, smba: impl Peripheral<P = impl SmbaPin<T>> + 'd, pec: bool) -> Self {
+        into_ref!(smba);
+        unsafe { smba.set_as_af(AFType::Input) };
+        Self::begin(false);
+
+        Self {
+            i2c,
+            smba: Some(smba.map_into()),
+            pec,
+        }
+    }
+
+    /// Enable or disable packet error checking.
+    pub fn set_pec(&mut self, pec: bool) {
+        self.pec = pec;
+    }
+
+    /// Free the bus from a stuck device, see [`I2c::recover_bus`].
+    pub fn recover_bus(&mut self) -> Result<(), Error> {
+        let res = self.i2c.recover_bus();
+        // Reinitializing the peripheral left SMBus mode.
+        Self::begin(false);
+        res
+    }
+
+    /// Whether an alert occurred since the last call, or since [`Smbus::wait_for_alert`]
+    /// returned.
+    pub fn alert_pending(&mut self) -> bool {
+        let r = T::regs();
+        let stat0 = r.stat0.read().bits();
+        if stat0 & STAT0_SMBALT != 0 {
+            unsafe { r.stat0.write(|w| w.bits(!STAT0_SMBALT)) };
+            T::state().alert.store(false, Ordering::Release);
+            return true;
+        }
+        T::state().alert.swap(false, Ordering::AcqRel)
+    }
+
+    /// Wait for a device to signal an alert.
+    ///
+    /// Returns right away if an alert occurred since the last call. Needs
+    /// [`Smbus::new_with_alert`].
+    pub async fn wait_for_alert(&mut self) {
+        assert!(self.smba.is_some(), "SMBus created without the SMBALERT# pin");
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if self.alert_pending() {
+                return Poll::Ready(());
+            }
+            // `SMBALT` raises the error interrupt.
+            I2c::<T>::update_ctl1(CTL1_ERRIE, 0);
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Enter SMBus host mode and restart the PEC calculation, with PEC enabled if `pec`.
+    fn begin(pec: bool) {
+        I2c::<T>::update_ctl0(CTL0_SMBEN | CTL0_SMBSEL, CTL0_PECEN);
+        if pec {
+            I2c::<T>::update_ctl0(CTL0_PECEN, 0);
+        }
+    }
+
+    /// Send the address and `bytes`, followed by the PEC byte if enabled, without a STOP
+    /// condition.
+    async fn write_bytes(addr: u8, bytes: &[u8], pec: bool, wait: Wait, timeout: Timeout) -> Result<(), Error> {
+        let r = T::regs();
+
+        I2c::<T>::start(addr.into(), false, false, wait, timeout).await?;
+        I2c::<T>::clear_addsend();
+        for byte in bytes {
+            I2c::<T>::wait_for(STAT0_TBE, wait, timeout).await?;
+            unsafe { r.data.write(|w| w.bits(*byte as u32)) };
+        }
+        if pec {
+            // The PEC byte follows the last byte written to `DATA`.
+            I2c::<T>::update_ctl0(CTL0_PECTRANS, 0);
+        }
+        I2c::<T>::wait_for(STAT0_BTC, wait, timeout).await
+    }
+
+    async fn write_inner(
+        &mut self,
+        addr: u8,
+        command: u8,
+        data: &[u8],
+        block: bool,
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        if block && (data.is_empty() || data.len() > MAX_BLOCK_LEN) {
+            return Err(Error::InvalidBlockLength);
+        }
+        // The command, the length of a block and the data
+        let mut buf = [0; MAX_BLOCK_LEN + 2];
+        let start = 1 + block as usize;
+        assert!(start + data.len() <= buf.len(), "SMBus write too long");
+        buf[0] = command;
+        buf[1] = data.len() as u8;
+        let len = start + data.len();
+        buf[start..len].copy_from_slice(data);
+
+        I2c::<T>::wait_idle(timeout)?;
+        Self::begin(self.pec);
+        Self::write_bytes(addr, &buf[..len], self.pec, wait, timeout).await?;
+        I2c::<T>::stop(timeout)
+    }
+
+    async fn read_inner(
+        &mut self,
+        addr: u8,
+        command: Option<u8>,
+        buffer: &mut [u8],
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Err(Error::ZeroLengthTransfer);
+        }
+        // Room for the PEC byte, which `I2c::read_bytes` receives last.
+        let mut buf = [0; MAX_BLOCK_LEN + 1];
+        let len = buffer.len() + self.pec as usize;
+        assert!(len <= buf.len(), "SMBus read too long");
+
+        I2c::<T>::wait_idle(timeout)?;
+        Self::begin(self.pec);
+        if let Some(command) = command {
+            Self::write_bytes(addr, &[command], false, wait, timeout).await?;
+        }
+        self.i2c
+            .read_bytes(addr.into(), &mut buf[..len], Framing::SINGLE, wait, timeout)
+            .await?;
+        buffer.copy_from_slice(&buf[..buffer.len()]);
+        Ok(())
+    }
+
+    async fn block_read_inner(
+        &mut self,
+        addr: u8,
+        command: u8,
+        buffer: &mut [u8],
+        wait: Wait,
+        timeout: Timeout,
+    ) -> Result<usize, Error> {
+        let r = T::regs();
+        let read = || r.data.read().bits() as u8;
+        let last = match self.pec {
+            true => CTL0_PECTRANS,
+            false => 0,
+        };
+
+        I2c::<T>::wait_idle(timeout)?;
+        Self::begin(self.pec);
+        Self::write_bytes(addr, &[command], false, wait, timeout).await?;
+
+        I2c::<T>::update_ctl0(CTL0_ACKEN, CTL0_POAP);
+        I2c::<T>::start(addr.into(), true, false, wait, timeout).await?;
+        I2c::<T>::clear_addsend();
+
+        // The length is only known once the count byte is read, while the next byte is already
+        // being received. The count byte and the bytes near the end are polled, so the NACK of
+        // the last byte is set up in time.
+        I2c::<T>::wait_for(STAT0_RBNE, Wait::Blocking, timeout).await?;
+        let count = read() as usize;
+        if count == 0 || count > buffer.len() {
+            // NACK the byte being received, which ends the read.
+            critical_section::with(|_| I2c::<T>::update_ctl0(CTL0_STOP, CTL0_ACKEN));
+            I2c::<T>::wait_for(STAT0_RBNE, wait, timeout).await?;
+            let _ = read();
+            I2c::<T>::wait_stop(timeout)?;
+            return Err(Error::InvalidBlockLength);
+        }
+
+        // The data, followed by the PEC byte if enabled
+        let mut buf = [0; MAX_BLOCK_LEN + 1];
+        let n = count + self.pec as usize;
+        let buf = &mut buf[..n];
+        match n {
+            1 => {
+                critical_section::with(|_| I2c::<T>::update_ctl0(CTL0_STOP | last, CTL0_ACKEN));
+                I2c::<T>::wait_for(STAT0_RBNE, wait, timeout).await?;
+                buf[0] = read();
+            }
+            2 => {
+                I2c::<T>::wait_for(STAT0_RBNE, Wait::Blocking, timeout).await?;
+                // The second byte is being received now.
+                critical_section::with(|_| I2c::<T>::update_ctl0(CTL0_STOP | last, CTL0_ACKEN));
+                buf[0] = read();
+                I2c::<T>::wait_for(STAT0_RBNE, wait, timeout).await?;
+                buf[1] = read();
+            }
+            _ => {
+                // Same as the end of `I2c::read_bytes`
+                let (head, tail) = buf.split_at_mut(n - 3);
+                for byte in head {
+                    I2c::<T>::wait_for(STAT0_RBNE, wait, timeout).await?;
+                    *byte = read();
+                }
+                I2c::<T>::wait_for(STAT0_BTC, wait, timeout).await?;
+                I2c::<T>::update_ctl0(last, CTL0_ACKEN);
+                tail[0] = read();
+                I2c::<T>::wait_for(STAT0_BTC, wait, timeout).await?;
+                I2c::<T>::update_ctl0(CTL0_STOP, 0);
+                tail[1] = read();
+                I2c::<T>::wait_for(STAT0_RBNE, wait, timeout).await?;
+                tail[2] = read();
+            }
+        }
+        I2c::<T>::wait_stop(timeout)?;
+        I2c::<T>::check_errors()?;
+
+        buffer[..count].copy_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    /// Send `command` followed by `data`, e.g. one byte for the Write Byte protocol and two for
+    /// Write Word, least significant byte first.
+    pub fn blocking_write(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let res = block_on(self.write_inner(addr, command, data, false, Wait::Blocking, timeout));
+        self.i2c.finish(res)
+    }
+
+    /// Send `command`, then read `buffer.len()` bytes after a repeated START, e.g. one byte for
+    /// the Read Byte protocol and two for Read Word, least significant byte first.
+    pub fn blocking_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let res = block_on(self.read_inner(addr, Some(command), buffer, Wait::Blocking, timeout));
+        self.i2c.finish(res)
+    }
+
+    /// Send `command` followed by the length of `data` and `data`, with the Block Write
+    /// protocol.
+    pub fn blocking_block_write(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let res = block_on(self.write_inner(addr, command, data, true, Wait::Blocking, timeout));
+        self.i2c.finish(res)
+    }
+
+    /// Send `command`, then read a block with the Block Read protocol, returning its length.
+    ///
+    /// Fails with [`Error::InvalidBlockLength`] if the block is empty or doesn't fit in `buffer`.
+    pub fn blocking_block_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        let timeout = self.i2c.timeout();
+        let res = block_on(self.block_read_inner(addr, command, buffer, Wait::Blocking, timeout));
+        self.i2c.finish(res)
+    }
+
+    /// Read the alert response address, returning the address of the device that signalled an
+    /// alert. If several did, the one with the lowest address wins.
+    pub fn blocking_alert_response(&mut self) -> Result<u8, Error> {
+        let timeout = self.i2c.timeout();
+        let mut addr = [0];
+        let res = block_on(self.read_inner(ALERT_RESPONSE_ADDRESS, None, &mut addr, Wait::Blocking, timeout));
+        self.i2c.finish(res)?;
+        Ok(addr[0] >> 1)
+    }
+
+    /// Send `command` followed by `data`, see [`Smbus::blocking_write`].
+    ///
+    /// If the future is dropped before it completes, the bus is released with a STOP condition.
+    pub async fn write(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let on_drop = OnDrop::new(I2c::<T>::abort);
+        let res = timeout
+            .with(self.write_inner(addr, command, data, false, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.i2c.finish(res)
+    }
+
+    /// Send `command`, then read `buffer.len()` bytes, see [`Smbus::blocking_read`] and
+    /// [`Smbus::write`].
+    pub async fn read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let on_drop = OnDrop::new(I2c::<T>::abort);
+        let res = timeout
+            .with(self.read_inner(addr, Some(command), buffer, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.i2c.finish(res)
+    }
+
+    /// Send `command` and a block, see [`Smbus::blocking_block_write`] and [`Smbus::write`].
+    pub async fn block_write(&mut self, addr: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+        let timeout = self.i2c.timeout();
+        let on_drop = OnDrop::new(I2c::<T>::abort);
+        let res = timeout
+            .with(self.write_inner(addr, command, data, true, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.i2c.finish(res)
+    }
+
+    /// Send `command`, then read a block, see [`Smbus::blocking_block_read`] and
+    /// [`Smbus::write`].
+    pub async fn block_read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        let timeout = self.i2c.timeout();
+        let on_drop = OnDrop::new(I2c::<T>::abort);
+        let res = timeout
+            .with(self.block_read_inner(addr, command, buffer, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.i2c.finish(res)
+    }
+
+    /// Read the alert response address, see [`Smbus::blocking_alert_response`] and
+    /// [`Smbus::write`].
+    pub async fn alert_response(&mut self) -> Result<u8, Error> {
+        let timeout = self.i2c.timeout();
+        let mut addr = [0];
+        let on_drop = OnDrop::new(I2c::<T>::abort);
+        let res = timeout
+            .with(self.read_inner(ALERT_RESPONSE_ADDRESS, None, &mut addr, Wait::Async, timeout))
+            .await;
+        on_drop.defuse();
+        self.i2c.finish(res)?;
+        Ok(addr[0] >> 1)
+    }
+}
+
+impl<'d, T: Instance> Drop for Smbus<'d, T> {
+    fn drop(&mut self) {
+        I2c::<T>::update_ctl0(0, CTL0_SMBEN | CTL0_SMBSEL | CTL0_PECEN);
+        if let Some(smba) = &self.smba {
+            unsafe { smba.set_as_disconnected() };
+        }
+    }
+}
@@ -1,15 +1,147 @@
+//! Embassy HAL for the GD32E503
+//!
+//! The GD32E503 has no cryptographic acceleration unit (CAU) nor hash acceleration unit (HAU), so
+//! there are no drivers for them: AES and SHA run in software, e.g. with the `aes` and `sha2`
+//! crates. For firmware images, the [`crc`] unit catches corruption but isn't a signature.
+
 #![no_std]
+#![cfg_attr(feature = "nightly", feature(type_alias_impl_trait, async_fn_in_trait, impl_trait_projections))]
+#![cfg_attr(feature = "nightly", allow(incomplete_features))]
 
 #[cfg(not(any(
     feature = "gd32e503",
 )))]
 compile_error!("No chip feature activated. You must activate one of the chip features.");
 
+#[cfg(any(
+    all(feature = "timedriver-systick", feature = "_timedriver-timer"),
+    all(feature = "timedriver-systick", feature = "timedriver-rtc"),
+    all(feature = "_timedriver-timer", feature = "timedriver-rtc"),
+    all(
+        feature = "timedriver-timer1",
+        any(feature = "timedriver-timer2", feature = "timedriver-timer3", feature = "timedriver-timer4")
+    ),
+    all(
+        feature = "timedriver-timer2",
+        any(feature = "timedriver-timer3", feature = "timedriver-timer4")
+    ),
+    all(feature = "timedriver-timer3", feature = "timedriver-timer4"),
+))]
+compile_error!("Multiple time drivers activated. You must activate at most one `timedriver-*` feature.");
 
-cfg_if::cfg_if! {
-    if #[cfg(feature = "gd32e503")] {
-        //pub use gd32e5::
-    }
+// This mod MUST go first, so that the others see its macros.
+pub(crate) mod fmt;
+pub mod time;
+mod traits;
+
+pub mod adc;
+pub mod afio;
+pub mod bkp;
+pub mod bootloader;
+pub mod can;
+pub mod cctl;
+pub mod cmp;
+pub mod crc;
+pub mod dac;
+pub mod dbg;
+pub mod dma;
+pub mod exti;
+pub mod fmc;
+pub mod fwdgt;
+pub mod gpio;
+pub mod i2c;
+pub mod pmu;
+pub mod rng;
+pub mod rtc;
+pub mod shrtimer;
+pub mod sysinfo;
+pub mod timer;
+pub mod tmu;
+pub mod usart;
+#[cfg(feature = "nightly")]
+pub mod usbd;
+pub mod wwdgt;
+#[cfg(feature = "_timedriver-timer")]
+mod time_driver;
+#[cfg(feature = "timedriver-rtc")]
+mod timedriver_rtc;
+#[cfg(feature = "timedriver-systick")]
+mod timedriver_systick;
+
+// This mod MUST go last, so that it sees all the `impl_foo!` macros
+#[cfg_attr(feature = "gd32e503", path = "chips/gd32e503.rs")]
+mod chip;
+
+pub mod interrupt {
+    //! GD32 interrupts for cortex-m devices.
+    pub use cortex_m::interrupt::{CriticalSection, Mutex};
+    pub use embassy_cortex_m::interrupt::*;
+
+    pub use crate::chip::irqs::*;
 }
 
+// Reexports
+
+pub(crate) use chip::pac;
+pub use chip::{peripherals, Peripherals};
 pub use embassy_cortex_m::executor;
+pub use embassy_cortex_m::interrupt::_export::interrupt;
+pub use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+
+/// Configuration for the HAL, passed to [`init`].
+#[non_exhaustive]
+pub struct Config {
+    pub cctl: cctl::Config,
+    /// EXTI interrupt priority, shared by all EXTI lines.
+    pub exti_interrupt_priority: crate::interrupt::Priority,
+    /// DMA interrupt priority, shared by all DMA channels.
+    pub dma_interrupt_priority: crate::interrupt::Priority,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cctl: Default::default(),
+            exti_interrupt_priority: crate::interrupt::Priority::P0,
+            dma_interrupt_priority: crate::interrupt::Priority::P0,
+        }
+    }
+}
+
+/// Initialize embassy.
+///
+/// Fails if the clock configuration is invalid or a clock doesn't start, see
+/// [`cctl::ClockError`]. The system then keeps running from IRC8M and nothing else is
+/// initialized, so `init` can be called again, e.g. with the default clocks to report the error:
+///
+/// ```no_run
+/// use embassy_gd32::time::Hertz;
+///
+/// let mut config = embassy_gd32::Config::default();
+/// config.cctl.hxtal = Some(Hertz::mhz(8));
+/// config.cctl.sys = embassy_gd32::cctl::SysClockSource::Hxtal;
+/// let p = match embassy_gd32::init(config) {
+///     Ok(p) => p,
+///     // E.g. no crystal fitted, fall back to IRC8M.
+///     Err(_) => embassy_gd32::init(Default::default()).unwrap(),
+/// };
+/// ```
+pub fn init(config: Config) -> Result<Peripherals, cctl::ClockError> {
+    // The clocks come first, the peripherals are only taken once they're running.
+    unsafe { cctl::init(config.cctl)? };
+    let p = Peripherals::take();
+
+    unsafe {
+        gpio::init();
+        exti::init(config.exti_interrupt_priority);
+        dma::init(config.dma_interrupt_priority);
+        #[cfg(feature = "timedriver-systick")]
+        timedriver_systick::init();
+        #[cfg(feature = "_timedriver-timer")]
+        time_driver::init();
+        #[cfg(feature = "timedriver-rtc")]
+        timedriver_rtc::init();
+    }
+
+    Ok(p)
+}
@@ -0,0 +1,154 @@
+//! Power management unit: low-power modes and reset reason
+//!
+//! In standby mode everything but the backup domain and the standby circuitry is powered down,
+//! which makes it the mode for long battery lifetimes. The chip wakes up through a reset, by the
+//! RTC alarm, the wakeup pin (PA0), `NRST` or the free watchdog. The RAM contents are lost, so state
+//! that must survive goes into the backup registers, see [`crate::bkp`].
+//!
+//! A periodic wakeup looks like this:
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::pmu::{self, ResetReason};
+//! use embassy_gd32::rtc::Rtc;
+//!
+//! let reason = pmu::reset_reason();
+//! pmu::clear_reset_flags();
+//!
+//! let mut rtc = Rtc::new(p.RTC).unwrap();
+//! if reason != ResetReason::StandbyWakeup {
+//!     // First boot, e.g. set the date and time.
+//! }
+//!
+//! // Measure and log...
+//!
+//! rtc.set_alarm_in(3600).unwrap();
+//! pmu::enter_standby();
+//! ```
+//!
+//! The RTC alarm needs the RTC clock to keep running in standby, so it must come from LXTAL or
+//! IRC40K, see [`crate::cctl::Config::rtc`].
+
+use cortex_m::peripheral::SCB;
+
+use crate::pac;
+
+/// Cause of the last reset
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    /// Power-on or brown-out
+    PowerOn,
+    /// Wakeup from standby mode, by the RTC alarm, the wakeup pin or the free watchdog
+    StandbyWakeup,
+    /// Entering deep-sleep or standby mode while the user option bytes reset instead, see
+    /// [`crate::fmc::option_bytes::UserOptions`]
+    LowPower,
+    /// Free watchdog timeout
+    FreeWatchdog,
+    /// Window watchdog timeout
+    WindowWatchdog,
+    /// Software reset, e.g. [`SCB::sys_reset`]
+    Software,
+    /// `NRST` pin pulled low
+    Pin,
+}
+
+/// The cause of the last reset.
+///
+/// The reset flags accumulate until cleared with [`clear_reset_flags`], so this reports the most
+/// significant cause since they were last cleared.
+pub fn reset_reason() -> ResetReason {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let pmu = unsafe { &*pac::PMU::ptr() };
+    critical_section::with(|_| unsafe { rcu.apb1en.modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN)) });
+    let rstsck = rcu.rstsck.read().bits();
+
+    if pmu.cs.read().bits() & CS_STBF != 0 {
+        ResetReason::StandbyWakeup
+    } else if rstsck & RSTSCK_PORRSTF != 0 {
+        ResetReason::PowerOn
+    } else if rstsck & RSTSCK_LPRSTF != 0 {
+        ResetReason::LowPower
+    } else if rstsck & RSTSCK_FWDGTRSTF != 0 {
+        ResetReason::FreeWatchdog
+    } else if rstsck & RSTSCK_WWDGTRSTF != 0 {
+        ResetReason::WindowWatchdog
+    } else if rstsck & RSTSCK_SWRSTF != 0 {
+        ResetReason::Software
+    } else {
+        // Every internal reset also drives NRST low, so the pin flag is checked last.
+        ResetReason::Pin
+    }
+}
+
+/// Clear the reset flags and the standby and wakeup flags, so the next [`reset_reason`] reports
+/// only the next reset.
+pub fn clear_reset_flags() {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let pmu = unsafe { &*pac::PMU::ptr() };
+    critical_section::with(|_| unsafe {
+        rcu.apb1en.modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN));
+        rcu.rstsck.modify(|r, w| w.bits(r.bits() | RSTSCK_RSTFC));
+        pmu.ctl.modify(|r, w| w.bits(r.bits() | CTL_STBRST | CTL_WURST));
+    });
+}
+
+/// Enable or disable wakeup from standby by a rising edge on the wakeup pin, PA0.
+///
+/// While enabled, PA0 is an input with pull-down, whatever its GPIO configuration.
+pub fn set_wakeup_pin(enable: bool) {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let pmu = unsafe { &*pac::PMU::ptr() };
+    critical_section::with(|_| unsafe {
+        rcu.apb1en.modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN));
+        pmu.cs.modify(|r, w| match enable {
+            true => w.bits(r.bits() | CS_WUPEN),
+            false => w.bits(r.bits() & !CS_WUPEN),
+        });
+    });
+}
+
+/// Enter standby mode.
+///
+/// Doesn't return, the chip wakes up through a reset, reported as
+/// [`ResetReason::StandbyWakeup`]. Pending wakeup events are cleared first, so only events after
+/// this call wake the chip; arm the RTC alarm before, see [`crate::rtc::Rtc::set_alarm`].
+pub fn enter_standby() -> ! {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    let pmu = unsafe { &*pac::PMU::ptr() };
+
+    cortex_m::interrupt::disable();
+    unsafe {
+        rcu.apb1en.modify(|r, w| w.bits(r.bits() | APB1EN_PMUEN));
+        pmu.ctl.modify(|r, w| w.bits(r.bits() | CTL_STBMOD | CTL_WURST));
+        (*SCB::PTR).scr.modify(|scr| scr | SCB_SCR_SLEEPDEEP);
+    }
+    loop {
+        cortex_m::asm::dsb();
+        cortex_m::asm::wfi();
+    }
+}
+
+// RCU_APB1EN
+const APB1EN_PMUEN: u32 = 1 << 28;
+
+// RCU_RSTSCK
+const RSTSCK_RSTFC: u32 = 1 << 24;
+const RSTSCK_PORRSTF: u32 = 1 << 27;
+const RSTSCK_SWRSTF: u32 = 1 << 28;
+const RSTSCK_FWDGTRSTF: u32 = 1 << 29;
+const RSTSCK_WWDGTRSTF: u32 = 1 << 30;
+const RSTSCK_LPRSTF: u32 = 1 << 31;
+
+// PMU_CTL
+const CTL_STBMOD: u32 = 1 << 1;
+const CTL_WURST: u32 = 1 << 2;
+const CTL_STBRST: u32 = 1 << 3;
+
+// PMU_CS
+const CS_STBF: u32 = 1 << 1;
+const CS_WUPEN: u32 = 1 << 8;
+
+// SCB_SCR
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
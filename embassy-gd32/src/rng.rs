@@ -0,0 +1,94 @@
+//! Random number source from ADC noise
+//!
+//! The GD32E503 has no true random number generator (TRNG). [`Rng`] collects the noise of the
+//! least significant bits of the ADC instead, converting the internal temperature sensor and
+//! voltage reference at the shortest sample time, and whitens it through the CRC unit. It
+//! implements `rand_core::RngCore`, e.g. to seed the random number generator of embassy-net:
+//!
+//! ```no_run
+//! # let mut p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::rng::Rng;
+//! use rand_core::RngCore;
+//!
+//! let seed = Rng::new(&mut p.ADC0, &mut p.CRC).next_u64();
+//! ```
+//!
+//! **This is not a cryptographically secure generator**, so it doesn't implement
+//! `rand_core::CryptoRng`. The amount of noise depends on the chip, the supply and the
+//! temperature, and the CRC is linear, it mixes the noise without hiding it. It's fine for
+//! seeds of TCP sequence numbers, port numbers or retry jitter, but the keys of TLS or other
+//! cryptography need a proper entropy source, e.g. an external secure element.
+//!
+//! Each word takes [`SAMPLES_PER_WORD`] conversions, a few microseconds each, while the ADC and
+//! the CRC unit are busy.
+
+use embassy_hal_common::Peripheral;
+
+use crate::adc::{Adc, SampleTime};
+use crate::crc::{self, Crc};
+use crate::peripherals;
+
+/// Number of ADC conversions collected into each random word, assuming at least one bit of
+/// noise per conversion
+pub const SAMPLES_PER_WORD: usize = 32;
+
+/// Random number source from the noise of ADC0, whitened by the CRC unit
+pub struct Rng<'d> {
+    adc: Adc<'d, peripherals::ADC0>,
+    crc: Crc<'d>,
+    /// Last word, fed back into the next one so the noise of all words adds up.
+    last: u32,
+}
+
+impl<'d> Rng<'d> {
+    /// Create a random number source from ADC0 and the CRC unit, which it takes over.
+    ///
+    /// Pass them by `&mut` to use them for other purposes once the source is dropped, e.g. after
+    /// taking a seed. ADC0 is reset and calibrated, like by [`Adc::new`].
+    pub fn new(
+        adc: impl Peripheral<P = peripherals::ADC0> + 'd,
+        crc: impl Peripheral<P = peripherals::CRC> + 'd,
+    ) -> Self {
+        let mut adc = Adc::new(adc);
+        // The shortest sample time leaves the most noise in the results.
+        adc.set_sample_time(SampleTime::Cycles2_5);
+        Self {
+            adc,
+            crc: Crc::new(crc, crc::Config::crc32()),
+            last: 0,
+        }
+    }
+
+    /// Collect a random word, blocking.
+    fn next_word(&mut self) -> u32 {
+        let (mut temperature, mut vrefint) = (self.adc.enable_temperature(), self.adc.enable_vrefint());
+        self.crc.feed(&self.last.to_le_bytes());
+        for _ in 0..SAMPLES_PER_WORD / 2 {
+            // The noise is in the low bits.
+            let t = self.adc.blocking_read(&mut temperature);
+            let v = self.adc.blocking_read(&mut vrefint);
+            self.crc.feed(&[t as u8, v as u8]);
+        }
+        self.last = self.crc.finish();
+        self.last
+    }
+}
+
+impl<'d> rand_core::RngCore for Rng<'d> {
+    fn next_u32(&mut self) -> u32 {
+        self.next_word()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use chrono::Datelike;
+
+/// Alias for [`chrono::NaiveDateTime`]
+pub type DateTime = chrono::NaiveDateTime;
+/// Alias for [`chrono::Weekday`]
+pub type DayOfWeek = chrono::Weekday;
+
+/// Errors regarding the [`DateTime`] struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The [DateTime] has an invalid year. The year must be between 1970 and 9999.
+    InvalidYear,
+}
+
+pub(super) fn validate_datetime(dt: &DateTime) -> Result<(), Error> {
+    if dt.year() < 1970 || dt.year() > 9999 {
+        Err(Error::InvalidYear)
+    } else {
+        // The rest of the chrono date is assumed to be valid
+        Ok(())
+    }
+}
+
+/// Seconds since 1970-01-01 00:00:00 of a validated `dt`.
+pub(super) fn to_timestamp(dt: &DateTime) -> u64 {
+    dt.timestamp() as u64
+}
+
+/// Date and time `timestamp` seconds after 1970-01-01 00:00:00.
+pub(super) fn from_timestamp(timestamp: u64) -> DateTime {
+    unwrap!(DateTime::from_timestamp_opt(timestamp as i64, 0))
+}
@@ -0,0 +1,277 @@
+//! Real time clock
+//!
+//! The RTC is a 32-bit counter in the backup domain. It keeps counting across resets, and on
+//! `VBAT` while the rest of the chip is powered down, from the RTC clock selected with
+//! [`cctl::Config::rtc`].
+//!
+//! [`Rtc`] keeps the wall-clock time as an offset from the counter, stored in backup data
+//! registers, so it can share the counter with the RTC time driver (the `timedriver-rtc` feature).
+//! The date and time set with [`Rtc::set_datetime`] survive resets and, with a battery, power
+//! loss.
+//!
+//! Without the time driver, the counter runs at 1 Hz and overflows after 136 years. The time
+//! driver runs the counter at the tick rate and keeps the offset up to date when it overflows, but
+//! overflows while the chip is powered down are missed: the wall-clock time then survives power
+//! loss of up to `2^32` counts, e.g. 36 hours with `tick-hz-32_768`.
+//!
+//! The offset uses the backup data registers [`BKP_DATA_FIRST`] to [`BKP_DATA_LAST`].
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+
+#[cfg_attr(feature = "chrono", path = "datetime_chrono.rs")]
+#[cfg_attr(not(feature = "chrono"), path = "datetime_no_deps.rs")]
+mod datetime;
+
+pub use self::datetime::{DateTime, DayOfWeek, Error as DateTimeError};
+use crate::bkp::{data_read, data_write, write_enable};
+use crate::{bkp, cctl, pac};
+
+/// First backup data register used by [`Rtc`]
+pub const BKP_DATA_FIRST: usize = 37;
+/// Last backup data register used by [`Rtc`]
+pub const BKP_DATA_LAST: usize = 41;
+
+/// Marks the offset registers as valid.
+const OFFSET_MAGIC: u16 = 0xCA1E;
+
+/// Errors that can occur on methods on [`Rtc`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RtcError {
+    /// An invalid DateTime was given.
+    InvalidDateTime(DateTimeError),
+    /// The RTC clock is not enabled, see [`cctl::Config::rtc`].
+    NotRunning,
+    /// The date and time were never set, or were lost with the backup domain.
+    NotSet,
+    /// The alarm time has passed already.
+    AlarmPassed,
+}
+
+/// Calendar on the RTC counter
+pub struct Rtc<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::RTC>,
+    /// RTC clock frequency
+    rtc_hz: u32,
+    /// RTC clock cycles per count
+    div: u32,
+}
+
+impl<'d> Rtc<'d> {
+    /// Create the calendar.
+    ///
+    /// The date and time are kept from before the reset, if they were set.
+    pub fn new(inner: impl Peripheral<P = crate::peripherals::RTC> + 'd) -> Result<Self, RtcError> {
+        into_ref!(inner);
+
+        let rtc_hz = cctl::clocks().rtc.ok_or(RtcError::NotRunning)?.0;
+        #[cfg(feature = "timedriver-rtc")]
+        let div = crate::timedriver_rtc::prescaler(crate::time::Hertz(rtc_hz)) + 1;
+        #[cfg(not(feature = "timedriver-rtc"))]
+        let div = rtc_hz;
+
+        critical_section::with(|_| unsafe {
+            enable();
+            // The time driver sets the prescaler itself.
+            #[cfg(not(feature = "timedriver-rtc"))]
+            if data_read(BKP_DATA_FIRST) != OFFSET_MAGIC {
+                configure(|r| {
+                    r.psch.write(|w| w.bits((div - 1) >> 16));
+                    r.pscl.write(|w| w.bits((div - 1) & 0xFFFF));
+                });
+            }
+        });
+
+        Ok(Self {
+            _inner: inner,
+            rtc_hz,
+            div,
+        })
+    }
+
+    /// Whether the date and time were set.
+    pub fn is_set(&self) -> bool {
+        data_read(BKP_DATA_FIRST) == OFFSET_MAGIC
+    }
+
+    /// Set the date and time.
+    pub fn set_datetime(&mut self, t: DateTime) -> Result<(), RtcError> {
+        datetime::validate_datetime(&t).map_err(RtcError::InvalidDateTime)?;
+        self.set_timestamp(datetime::to_timestamp(&t));
+        Ok(())
+    }
+
+    /// The current date and time.
+    pub fn now(&self) -> Result<DateTime, RtcError> {
+        self.timestamp().map(datetime::from_timestamp)
+    }
+
+    /// Set the time as seconds since 1970-01-01 00:00:00.
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        let counts = timestamp * self.rtc_hz as u64 / self.div as u64;
+        critical_section::with(|_| unsafe {
+            write_offset(counts.wrapping_sub(counts_now()));
+            data_write(BKP_DATA_FIRST, OFFSET_MAGIC);
+        })
+    }
+
+    /// The current time as seconds since 1970-01-01 00:00:00.
+    pub fn timestamp(&self) -> Result<u64, RtcError> {
+        if !self.is_set() {
+            return Err(RtcError::NotSet);
+        }
+        let counts = critical_section::with(|_| read_offset().wrapping_add(counts_now()));
+        Ok(counts / self.rtc_hz as u64 * self.div as u64
+            + counts % self.rtc_hz as u64 * self.div as u64 / self.rtc_hz as u64)
+    }
+
+    /// Set the alarm to the date and time `t`.
+    ///
+    /// The alarm wakes the chip from standby, see [`crate::pmu`]. With the `timedriver-rtc`
+    /// feature the alarm is shared with the time driver, so this should only be used right before
+    /// entering standby.
+    pub fn set_alarm(&mut self, t: DateTime) -> Result<(), RtcError> {
+        datetime::validate_datetime(&t).map_err(RtcError::InvalidDateTime)?;
+        self.set_alarm_timestamp(datetime::to_timestamp(&t))
+    }
+
+    /// Set the alarm to `secs` seconds from now, see [`Rtc::set_alarm`].
+    pub fn set_alarm_in(&mut self, secs: u64) -> Result<(), RtcError> {
+        self.set_alarm_timestamp(self.timestamp()? + secs)
+    }
+
+    /// Set the alarm to `timestamp` seconds since 1970-01-01 00:00:00, see [`Rtc::set_alarm`].
+    pub fn set_alarm_timestamp(&mut self, timestamp: u64) -> Result<(), RtcError> {
+        if timestamp <= self.timestamp()? {
+            return Err(RtcError::AlarmPassed);
+        }
+        let counts = timestamp * self.rtc_hz as u64 / self.div as u64;
+        critical_section::with(|_| unsafe {
+            // The alarm matches the low 32 bits of the counter.
+            let at = counts.wrapping_sub(read_offset()) as u32;
+            configure(|r| {
+                r.alrmh.write(|w| w.bits(at >> 16));
+                r.alrml.write(|w| w.bits(at & 0xFFFF));
+            });
+            // A stale flag would keep the chip from entering standby.
+            clear_flags(CTL_ALRMIF);
+        });
+        Ok(())
+    }
+
+    /// Whether the alarm fired since it was set, e.g. to tell an alarm wakeup from a wakeup pin
+    /// one after standby. The `timedriver-rtc` time driver clears the flag when it handles it.
+    pub fn alarm_fired(&self) -> bool {
+        regs().ctl.read().bits() & CTL_ALRMIF != 0
+    }
+}
+
+/// Account for a counter overflow, called by the time driver.
+///
+/// Safety: must be called in a critical section, with the backup domain clocks enabled.
+#[cfg(feature = "timedriver-rtc")]
+pub(crate) unsafe fn on_overflow() {
+    if data_read(BKP_DATA_FIRST) == OFFSET_MAGIC {
+        write_offset(read_offset().wrapping_add(1 << 32));
+    }
+}
+
+/// The counter value, including an overflow the time driver didn't handle yet.
+fn counts_now() -> u64 {
+    let cnt = read_counter();
+    #[cfg(feature = "timedriver-rtc")]
+    if regs().ctl.read().bits() & CTL_OVIF != 0 {
+        // Read again, the counter may have overflowed after the first read.
+        return (1 << 32) + read_counter() as u64;
+    }
+    cnt as u64
+}
+
+fn read_offset() -> u64 {
+    (BKP_DATA_FIRST + 1..=BKP_DATA_LAST).fold(0, |offset, n| (offset << 16) | data_read(n) as u64)
+}
+
+unsafe fn write_offset(offset: u64) {
+    for n in BKP_DATA_FIRST + 1..=BKP_DATA_LAST {
+        data_write(n, (offset >> (16 * (BKP_DATA_LAST - n))) as u16);
+    }
+}
+
+pub(crate) fn regs() -> &'static pac::rtc::RegisterBlock {
+    unsafe { &*pac::RTC::ptr() }
+}
+
+/// Enable the backup domain interface and wait for the RTC registers to be readable.
+///
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn enable() {
+    bkp::enable();
+
+    let r = regs();
+    // The registers read stale values until they are synchronized with the RTC clock.
+    r.ctl.modify(|r, w| w.bits(r.bits() & !CTL_RSYNF));
+    while r.ctl.read().bits() & CTL_RSYNF == 0 {}
+}
+
+/// Read the 32-bit counter, which is split across two registers.
+pub(crate) fn read_counter() -> u32 {
+    let r = regs();
+    loop {
+        let high = r.cnth.read().bits();
+        let low = r.cntl.read().bits();
+        // Read again, the low half may have overflowed into the high half.
+        if r.cnth.read().bits() == high {
+            return (high << 16) | (low & 0xFFFF);
+        }
+    }
+}
+
+/// Read the counter together with the prescaler divider, which counts the RTC clock cycles down
+/// from the prescaler value to zero within each count.
+pub(crate) fn read_counter_and_divider() -> (u32, u32) {
+    let r = regs();
+    loop {
+        let cnt = read_counter();
+        let high = r.divh.read().bits();
+        let low = r.divl.read().bits();
+        // Read again, the divider may have reloaded into the next count.
+        if r.divh.read().bits() == high && read_counter() == cnt {
+            return (cnt, ((high & 0xF) << 16) | (low & 0xFFFF));
+        }
+    }
+}
+
+/// Write RTC configuration registers in configuration mode.
+///
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn configure(f: impl FnOnce(&pac::rtc::RegisterBlock)) {
+    let r = regs();
+
+    // The RTC lives in the backup domain, which is write protected.
+    write_enable(true);
+    while r.ctl.read().bits() & CTL_LWOFF == 0 {}
+    r.ctl.modify(|r, w| w.bits(r.bits() | CTL_CMF));
+    f(r);
+    r.ctl.modify(|r, w| w.bits(r.bits() & !CTL_CMF));
+    // The registers are written when leaving configuration mode.
+    while r.ctl.read().bits() & CTL_LWOFF == 0 {}
+    write_enable(false);
+}
+
+/// Clear the `flags` in `RTC_CTL`.
+///
+/// Safety: must be called in a critical section.
+pub(crate) unsafe fn clear_flags(flags: u32) {
+    write_enable(true);
+    // The flags are cleared by writing zero, writing one has no effect.
+    regs().ctl.modify(|r, w| w.bits(r.bits() & !flags));
+    write_enable(false);
+}
+
+// RTC_CTL
+pub(crate) const CTL_SCIF: u32 = 1 << 0;
+pub(crate) const CTL_ALRMIF: u32 = 1 << 1;
+pub(crate) const CTL_OVIF: u32 = 1 << 2;
+const CTL_RSYNF: u32 = 1 << 3;
+const CTL_CMF: u32 = 1 << 4;
+const CTL_LWOFF: u32 = 1 << 5;
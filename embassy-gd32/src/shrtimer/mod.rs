@@ -0,0 +1,576 @@
+//! Super high-resolution timer (SHRTIMER)
+//!
+//! The SHRTIMER has a master timer and five slave timers with two outputs each. Its delay-locked
+//! loop (DLL) lets the counters count at 32 times the SHRTIMER clock, so the periods and edges of
+//! the outputs are placed to a fraction of a nanosecond. That's what digital power converters
+//! such as LLC resonant or interleaved PFC stages need, and what the other timers can't do.
+//!
+//! [`ShrTimer`] owns the common part: it calibrates the DLL, runs the master timer and controls
+//! the burst mode. [`pwm::HrPwm`] drives the outputs of a slave timer, and [`FaultInput`] turns
+//! outputs off in hardware when a fault input goes active.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::shrtimer::pwm::{Channel, HrPwm, HrPwmPin};
+//! use embassy_gd32::shrtimer::{FaultInput, ShrTimer};
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::simple_pwm::Polarity;
+//!
+//! let shrtimer = ShrTimer::new(p.SHRTIMER);
+//! let fault = FaultInput::new_flt0(&shrtimer, p.PA12, Polarity::ActiveLow, 4);
+//! let high = HrPwmPin::new_ch0(p.PA8);
+//! let low = HrPwmPin::new_ch1(p.PA9);
+//! let mut pwm = HrPwm::new(&shrtimer, p.SHRTIMER_ST0, Some(high), Some(low), Hertz::khz(250));
+//! // A half bridge, with 50 ns between the edges of the two outputs.
+//! pwm.set_dead_time(50, 50);
+//! pwm.enable_fault(&fault);
+//! pwm.set_duty(Channel::Ch0, pwm.period() / 2);
+//! pwm.enable(Channel::Ch0);
+//! pwm.enable(Channel::Ch1);
+//! pwm.start();
+//! ```
+//!
+//! The outputs are set and reset by events of the timers, such as the end of a period or a match
+//! of one of the four compare units of a timer, see [`pwm::Events`]. Together with the master
+//! timer, whose period and compare events all slave timers can use, that describes phase-shifted
+//! or interleaved waveforms without software in the loop.
+//!
+//! The counters only reach the full resolution with the DLL calibrated, which [`ShrTimer::new`]
+//! does, and then keeps up to date periodically against temperature and voltage drifts.
+//!
+//! A task can react to a fault with [`FaultInput::wait_for_fault`], and turn the outputs on again
+//! once the input is inactive with [`pwm::HrPwm::rearm`].
+#![macro_use]
+
+pub mod pwm;
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::cctl::sealed::CCTLPeripherial;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::AnyPin;
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::peripherals::SHRTIMER;
+use crate::time::Hertz;
+use crate::timer::simple_pwm::Polarity;
+use crate::{interrupt, Peripheral};
+
+/// Largest period of a counter
+const PERIOD_MAX: u32 = 0xFFDF;
+
+static FAULT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Registers of the SHRTIMER, or of one of its timers, accessed by offset, as the timers share a
+/// layout
+#[derive(Clone, Copy)]
+struct Regs(usize);
+
+impl Regs {
+    fn read(self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the `set` bits and clear the `clear` bits of the register at `offset`.
+    fn modify(self, offset: usize, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set)
+    }
+}
+
+/// The common registers and those of the master timer.
+fn regs() -> Regs {
+    Regs(<SHRTIMER as sealed::Instance>::base())
+}
+
+/// The registers of slave timer `index`.
+fn slave_regs(index: usize) -> Regs {
+    Regs(regs().0 + 0x80 * (index + 1))
+}
+
+pub(crate) mod sealed {
+    pub trait Instance {
+        fn base() -> usize;
+    }
+
+    pub trait SlaveInstance {
+        /// Index of the slave timer, from 0 to 4
+        const INDEX: usize;
+    }
+}
+
+/// SHRTIMER instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {}
+
+/// Slave timer of the SHRTIMER
+pub trait SlaveInstance: Peripheral<P = Self> + sealed::SlaveInstance + 'static {}
+
+pin_trait!(Channel0Pin, SlaveInstance);
+pin_trait!(Channel1Pin, SlaveInstance);
+pin_trait!(Fault0Pin, Instance);
+pin_trait!(Fault1Pin, Instance);
+pin_trait!(Fault2Pin, Instance);
+pin_trait!(Fault3Pin, Instance);
+pin_trait!(Fault4Pin, Instance);
+
+/// Compare unit of a timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Compare {
+    /// Compare unit 0
+    Cmp0 = 0,
+    /// Compare unit 1
+    Cmp1 = 1,
+    /// Compare unit 2
+    Cmp2 = 2,
+    /// Compare unit 3
+    Cmp3 = 3,
+}
+
+impl Compare {
+    /// Offset of the unit's compare value register in the registers of its timer. Unit 0 has a
+    /// composite register after it.
+    fn cmpv(self) -> usize {
+        match self {
+            Compare::Cmp0 => CMP0V,
+            _ => CMP0V + 4 + 4 * self as usize,
+        }
+    }
+}
+
+/// Clock divider `CNTCKDIV` and period of a counter that overflows at `freq`, with the highest
+/// resolution.
+///
+/// Panics if `freq` is zero, above the SHRTIMER clock divided by 3, or below the SHRTIMER clock
+/// times 32 divided by 128 times 65503.
+fn period_config(freq: Hertz) -> (u32, u16) {
+    assert!(freq.0 != 0, "SHRTIMER frequency must not be zero");
+    let ticks = SHRTIMER::frequency().0 as u64 * 32 / freq.0 as u64;
+    for div in 0..8 {
+        let period = ticks >> div;
+        if period <= PERIOD_MAX as u64 {
+            assert!(period >= min_compare(div) as u64, "SHRTIMER frequency too high");
+            return (div, period as u16);
+        }
+    }
+    panic!("SHRTIMER frequency too low")
+}
+
+/// Smallest compare value and period of a counter with clock divider `div`, three periods of
+/// the SHRTIMER clock.
+fn min_compare(div: u32) -> u16 {
+    (0x60 >> div).max(3)
+}
+
+/// SHRTIMER driver
+///
+/// Owns the common part of the SHRTIMER and the master timer. The slave timer drivers and fault
+/// inputs borrow it, so its methods take `&self`.
+pub struct ShrTimer<'d> {
+    _peri: PeripheralRef<'d, SHRTIMER>,
+}
+
+impl<'d> ShrTimer<'d> {
+    /// Enable the SHRTIMER and calibrate the DLL.
+    ///
+    /// The master timer is stopped, and counts at the highest resolution up to the largest
+    /// period. All outputs are disabled.
+    pub fn new(peri: impl Peripheral<P = SHRTIMER> + 'd) -> Self {
+        into_ref!(peri);
+
+        SHRTIMER::enable();
+        SHRTIMER::reset();
+        let r = regs();
+        r.write(DLLCCTL, DLLCCTL_CLBSTRT);
+        while r.read(INTF) & INTF_DLLCALIF == 0 {}
+        r.write(INTC, INTF_DLLCALIF);
+        // Recalibrate every 1048576 periods of the SHRTIMER clock.
+        r.write(DLLCCTL, DLLCCTL_CLBPEREN);
+
+        // Continuous, with the period and compare values buffered until the end of a period.
+        r.write(MTCTL0, CTL0_CTNM | CTL0_SHWEN | MTCTL0_UPREP);
+        r.write(CAR, PERIOD_MAX);
+        r.write(CTL1, MTSUP);
+
+        let irq = unsafe { interrupt::SHRTIMER_IRQ6::steal() };
+        irq.unpend();
+        irq.enable();
+
+        Self { _peri: peri }
+    }
+
+    /// Frequency of the SHRTIMER clock. The counters count at up to 32 times this frequency.
+    pub fn clock_frequency(&self) -> Hertz {
+        SHRTIMER::frequency()
+    }
+
+    /// Set the period of the master timer, so that its counter overflows at `freq`. The counter
+    /// counts as fast as possible for that, its compare values should be set again relative to
+    /// the new [`ShrTimer::master_period`].
+    ///
+    /// Takes effect at the end of the current period, and right away if the master timer is
+    /// stopped, while the clock divider can only be changed then. Panics if `freq` is zero,
+    /// above the SHRTIMER clock divided by 3, or so low that it needs another clock divider
+    /// while the master timer is running.
+    pub fn set_master_frequency(&self, freq: Hertz) {
+        set_period(regs(), MTSUP, freq, self.is_master_running())
+    }
+
+    /// The period of the master timer, in ticks of its counter.
+    pub fn master_period(&self) -> u16 {
+        regs().read(CAR) as u16
+    }
+
+    /// Set the value of compare unit `compare` of the master timer. Takes effect at the end of
+    /// the current period, the slave timers can set or reset their outputs on its match, see
+    /// [`pwm::Events`].
+    pub fn set_master_compare(&self, compare: Compare, value: u16) {
+        regs().write(compare.cmpv(), value as u32)
+    }
+
+    /// Start the master timer.
+    pub fn start_master(&self) {
+        critical_section::with(|_| regs().modify(MTCTL0, MTCTL0_MTCEN, 0))
+    }
+
+    /// Stop the master timer, keeping its counter value.
+    pub fn stop_master(&self) {
+        critical_section::with(|_| regs().modify(MTCTL0, 0, MTCTL0_MTCEN))
+    }
+
+    /// Whether the master timer is counting.
+    pub fn is_master_running(&self) -> bool {
+        regs().read(MTCTL0) & MTCTL0_MTCEN != 0
+    }
+
+    /// Configure the burst mode, which turns outputs off for `idle` of every `period` clocks of
+    /// `clock` once started, e.g. to keep a converter efficient at light load. It replaces the
+    /// running burst mode, if any.
+    ///
+    /// The outputs that take part are selected by [`pwm::HrPwm::set_burst_state`]. Panics
+    /// if `idle` isn't below `period`, or `period` is 0.
+    pub fn configure_burst(&self, clock: BurstClock, period: u16, idle: u16) {
+        assert!(idle < period, "burst idle time must be below the period");
+        let r = regs();
+        r.modify(BMCTL, 0, BMCTL_BMEN);
+        let bmctl = match clock {
+            BurstClock::Master => 0,
+            BurstClock::Slave(index) => {
+                assert!(index < 5, "no such slave timer");
+                (1 + index as u32) << BMCTL_BMCLKS_OFFSET
+            }
+            BurstClock::Clock(div) => {
+                assert!(div <= 15, "burst clock divider out of range");
+                (0b1010 << BMCTL_BMCLKS_OFFSET) | ((div as u32) << BMCTL_BMPSC_OFFSET)
+            }
+        };
+        // The outputs go idle from the trigger to the compare match, and run again until the end
+        // of the period. The counters keep counting meanwhile.
+        r.write(BMCMPV, idle as u32);
+        r.write(BMCAR, period as u32 - 1);
+        r.write(BMCTL, bmctl | BMCTL_BMCTN | BMCTL_BMEN);
+    }
+
+    /// Start the burst mode, after [`ShrTimer::configure_burst`]. The outputs go idle right away.
+    pub fn start_burst(&self) {
+        regs().write(BMSTRG, BMSTRG_SWTRG)
+    }
+
+    /// Stop the burst mode, at the end of the current burst period.
+    pub fn stop_burst(&self) {
+        // Writing one has no effect.
+        regs().modify(BMCTL, 0, BMCTL_BMOPTF)
+    }
+
+    /// Whether the burst mode is running.
+    pub fn is_burst_running(&self) -> bool {
+        regs().read(BMCTL) & BMCTL_BMOPTF != 0
+    }
+}
+
+impl<'d> Drop for ShrTimer<'d> {
+    fn drop(&mut self) {
+        unsafe { interrupt::SHRTIMER_IRQ6::steal() }.disable();
+        SHRTIMER::disable();
+    }
+}
+
+/// Set the clock divider and period of the timer at `r`, with its software update bit `sup`.
+fn set_period(r: Regs, sup: u32, freq: Hertz, running: bool) {
+    let (div, period) = period_config(freq);
+    if running {
+        assert!(
+            r.read(CTL0) & CTL0_CNTCKDIV == div,
+            "SHRTIMER clock divider can't change while running"
+        );
+    } else {
+        r.modify(CTL0, div, CTL0_CNTCKDIV);
+    }
+    r.write(CAR, period as u32);
+    if !running {
+        // Load the buffered values, which otherwise waits for the end of a period.
+        critical_section::with(|_| regs().modify(CTL1, sup, 0));
+    }
+}
+
+/// Clock of the burst mode counter, see `BMCLKS` in `SHRTIMER_BMCTL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BurstClock {
+    /// The end of each period of the master timer
+    Master,
+    /// The end of each period of the slave timer with this index
+    Slave(u8),
+    /// The SHRTIMER clock, divided by 2 to the power of this value, up to 15
+    Clock(u8),
+}
+
+/// Fault input of the SHRTIMER
+///
+/// A fault input is enabled while it exists. The slave timers that enable it with
+/// [`pwm::HrPwm::enable_fault`] turn their outputs off as soon as it goes active, without
+/// software, and keep them off until they are enabled again.
+pub struct FaultInput<'d> {
+    index: usize,
+    polarity: Polarity,
+    pin: PeripheralRef<'d, AnyPin>,
+}
+
+/// The fault input is still active, see [`FaultInput::clear`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FaultActive;
+
+macro_rules! fault_impl {
+    ($new_fltx:ident, $index:expr, $pin_trait:ident) => {
+        /// Use `pin` as the fault input, which is active at the `polarity` level. The input is
+        /// filtered with `filter` from 0 for none to 15, see `FLTxINFC` in `SHRTIMER_FLTINCFGx`.
+        ///
+        /// Panics if the filter is above 15.
+        pub fn $new_fltx(
+            _shrtimer: &'d ShrTimer<'_>,
+            pin: impl Peripheral<P = impl $pin_trait<SHRTIMER>> + 'd,
+            polarity: Polarity,
+            filter: u8,
+        ) -> Self {
+            into_ref!(pin);
+            crate::afio::check_default_layout(&[pin.remaps()]);
+            Self::new($index, pin.map_into(), polarity, filter)
+        }
+    };
+}
+
+impl<'d> FaultInput<'d> {
+    fault_impl!(new_flt0, 0, Fault0Pin);
+    fault_impl!(new_flt1, 1, Fault1Pin);
+    fault_impl!(new_flt2, 2, Fault2Pin);
+    fault_impl!(new_flt3, 3, Fault3Pin);
+    fault_impl!(new_flt4, 4, Fault4Pin);
+
+    fn new(index: usize, pin: PeripheralRef<'d, AnyPin>, polarity: Polarity, filter: u8) -> Self {
+        assert!(filter <= 15, "fault filter out of range");
+        unsafe { pin.set_as_af(AFType::Input) };
+
+        let mut config = FLTINCFG_FLTINEN | ((filter as u32) << FLTINCFG_FLTINFC_OFFSET);
+        if polarity == Polarity::ActiveHigh {
+            config |= FLTINCFG_FLTINP;
+        }
+        let (reg, shift) = fault_config(index);
+        let r = regs();
+        critical_section::with(|_| {
+            // The polarity and filter can only be changed while the input is disabled.
+            r.modify(reg, 0, 0xFF << shift);
+            r.modify(reg, (config & !FLTINCFG_FLTINEN) << shift, 0);
+            r.modify(reg, config << shift, 0);
+        });
+        r.write(INTC, INTF_FLTIF << index);
+
+        Self { index, polarity, pin }
+    }
+
+    /// Whether the input has gone active since it was enabled or last cleared.
+    pub fn has_fired(&self) -> bool {
+        regs().read(INTF) & (INTF_FLTIF << self.index) != 0
+    }
+
+    /// Whether the input is active now, by the level of its pin before the filter.
+    pub fn is_active(&self) -> bool {
+        let high = self.pin.block().istat.read().bits() & (1 << self.pin._pin()) != 0;
+        high == (self.polarity == Polarity::ActiveHigh)
+    }
+
+    /// Wait for the input to go active. The outputs that enable it are already off when this
+    /// returns, turned off by the hardware. Returns right away if it has fired since it was
+    /// enabled or last cleared.
+    pub async fn wait_for_fault(&mut self) {
+        let flag = INTF_FLTIF << self.index;
+        let r = regs();
+        poll_fn(|cx| {
+            FAULT_WAKER.register(cx.waker());
+            if r.read(INTF) & flag != 0 {
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again.
+            critical_section::with(|_| r.modify(INTEN, flag, 0));
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Clear the fired flag. The outputs turned off by the fault stay off, see
+    /// [`pwm::HrPwm::rearm`] to turn them on again.
+    ///
+    /// Fails, leaving the flag set, while the input is still active.
+    pub fn clear(&mut self) -> Result<(), FaultActive> {
+        if self.is_active() {
+            return Err(FaultActive);
+        }
+        regs().write(INTC, INTF_FLTIF << self.index);
+        Ok(())
+    }
+}
+
+impl<'d> Drop for FaultInput<'d> {
+    fn drop(&mut self) {
+        let (reg, shift) = fault_config(self.index);
+        critical_section::with(|_| {
+            regs().modify(INTEN, 0, INTF_FLTIF << self.index);
+            regs().modify(reg, 0, FLTINCFG_FLTINEN << shift);
+        });
+        unsafe { self.pin.set_as_disconnected() };
+    }
+}
+
+/// Offset of the `SHRTIMER_FLTINCFGx` register of fault input `index`, and its shift in it.
+fn fault_config(index: usize) -> (usize, u32) {
+    (FLTINCFG0 + 4 * (index / 4), 8 * (index as u32 % 4))
+}
+
+#[interrupt]
+unsafe fn SHRTIMER_IRQ6() {
+    // The flags are cleared by the woken task, so disable the interrupts that are pending.
+    let r = regs();
+    let pending = r.read(INTF) & r.read(INTEN) & INTEN_FLTIE;
+    r.modify(INTEN, 0, pending);
+    FAULT_WAKER.wake();
+}
+
+macro_rules! impl_shrtimer {
+    ($inst:ident) => {
+        impl crate::shrtimer::sealed::Instance for peripherals::$inst {
+            fn base() -> usize {
+                crate::pac::$inst::ptr() as usize
+            }
+        }
+
+        impl crate::shrtimer::Instance for peripherals::$inst {}
+    };
+}
+
+macro_rules! impl_shrtimer_slave {
+    ($inst:ident, $index:expr) => {
+        impl crate::shrtimer::sealed::SlaveInstance for peripherals::$inst {
+            const INDEX: usize = $index;
+        }
+
+        impl crate::shrtimer::SlaveInstance for peripherals::$inst {}
+    };
+}
+
+// The master and slave timers share the layout of their first registers, and the slave timers
+// are 0x80 bytes apart after the master timer. The common registers start at 0x380.
+
+// SHRTIMER_MTCTL0 and SHRTIMER_STxCTL0
+const CTL0: usize = 0x00;
+const CTL0_CNTCKDIV: u32 = 0b111;
+const CTL0_CTNM: u32 = 1 << 3;
+const CTL0_SHWEN: u32 = 1 << 27;
+const MTCTL0: usize = CTL0;
+const MTCTL0_MTCEN: u32 = 1 << 16;
+const MTCTL0_STCEN: u32 = 1 << 17;
+const MTCTL0_UPREP: u32 = 1 << 29;
+const STCTL0_UPREP: u32 = 1 << 17;
+
+// SHRTIMER_MTCAR and SHRTIMER_STxCAR
+const CAR: usize = 0x14;
+
+// SHRTIMER_MTCMP0V and SHRTIMER_STxCMP0V
+const CMP0V: usize = 0x1C;
+
+// SHRTIMER_STxDTCTL
+const DTCTL: usize = 0x38;
+const DTCTL_DTRCFG: u32 = 0x1FF;
+const DTCTL_DTGCKDIV_OFFSET: u32 = 10;
+const DTCTL_DTFCFG_OFFSET: u32 = 16;
+const DTCTL_DTFCFG: u32 = 0x1FF << 16;
+
+// SHRTIMER_STxCH0SET and SHRTIMER_STxCH0RST, those of channel 1 are 8 bytes above
+const CH0SET: usize = 0x3C;
+const CH0RST: usize = 0x40;
+
+// SHRTIMER_STxCHOCTL, the fields of channel 1 are 16 bits above these
+const CHOCTL: usize = 0x64;
+const CHOCTL_CH0P: u32 = 1 << 1;
+const CHOCTL_CH0IMS: u32 = 1 << 2;
+const CHOCTL_CH0ISO: u32 = 1 << 3;
+const CHOCTL_CH0FLTOS_OFFSET: u32 = 4;
+const CHOCTL_CH0FLTOS: u32 = 0b11 << 4;
+const CHOCTL_DTEN: u32 = 1 << 8;
+
+// SHRTIMER_STxFLTCTL
+const FLTCTL: usize = 0x68;
+
+// SHRTIMER_CTL1
+const CTL1: usize = 0x384;
+const MTSUP: u32 = 1 << 0;
+const STSUP: u32 = 1 << 1;
+const STSRST: u32 = 1 << 9;
+
+// SHRTIMER_INTF and SHRTIMER_INTC, bit `x` is the flag of fault input `x`
+const INTF: usize = 0x388;
+const INTC: usize = 0x38C;
+const INTF_FLTIF: u32 = 1 << 0;
+const INTF_DLLCALIF: u32 = 1 << 16;
+
+// SHRTIMER_INTEN, the interrupt enables match the flags in SHRTIMER_INTF
+const INTEN: usize = 0x390;
+const INTEN_FLTIE: u32 = 0x1F;
+
+// SHRTIMER_CHOUTEN, SHRTIMER_CHOUTDIS and SHRTIMER_CHOUTDISF, bit `2 * x + y` is channel `y` of
+// slave timer `x`
+const CHOUTEN: usize = 0x394;
+const CHOUTDIS: usize = 0x398;
+const CHOUTDISF: usize = 0x39C;
+
+// SHRTIMER_BMCTL
+const BMCTL: usize = 0x3A0;
+const BMCTL_BMEN: u32 = 1 << 0;
+const BMCTL_BMCTN: u32 = 1 << 1;
+const BMCTL_BMCLKS_OFFSET: u32 = 2;
+const BMCTL_BMPSC_OFFSET: u32 = 6;
+const BMCTL_BMOPTF: u32 = 1 << 31;
+
+// SHRTIMER_BMSTRG
+const BMSTRG: usize = 0x3A4;
+const BMSTRG_SWTRG: u32 = 1 << 0;
+
+// SHRTIMER_BMCMPV and SHRTIMER_BMCAR
+const BMCMPV: usize = 0x3A8;
+const BMCAR: usize = 0x3AC;
+
+// SHRTIMER_DLLCCTL
+const DLLCCTL: usize = 0x3CC;
+const DLLCCTL_CLBSTRT: u32 = 1 << 0;
+const DLLCCTL_CLBPEREN: u32 = 1 << 1;
+
+// SHRTIMER_FLTINCFG0 and SHRTIMER_FLTINCFG1, one byte per fault input
+const FLTINCFG0: usize = 0x3D0;
+const FLTINCFG_FLTINEN: u32 = 1 << 0;
+const FLTINCFG_FLTINP: u32 = 1 << 1;
+const FLTINCFG_FLTINFC_OFFSET: u32 = 3;
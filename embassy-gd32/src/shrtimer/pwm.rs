@@ -0,0 +1,403 @@
+//! High-resolution PWM on a slave timer
+//!
+//! [`HrPwm`] drives the two outputs of a slave timer of the SHRTIMER. By default each output is
+//! set at the end of a period and reset at its duty, with compare unit 0 for channel 0 and 1 for
+//! channel 1. [`HrPwm::set_output_events`] replaces that with any combination of [`Events`], and
+//! [`HrPwm::set_dead_time`] turns channel 1 into the complement of channel 0, e.g. for a half
+//! bridge.
+//!
+//! Periods, duties and compare values are counted in ticks of the counter, which counts at 32
+//! times the SHRTIMER clock when the period fits, see [`HrPwm::set_frequency`].
+
+use core::marker::PhantomData;
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::*;
+use crate::gpio::sealed::Pin as _;
+
+/// Output channel of a slave timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    /// Channel 0
+    Ch0 = 0,
+    /// Channel 1
+    Ch1 = 1,
+}
+
+/// Type-level [`Channel::Ch0`]
+pub struct Ch0;
+/// Type-level [`Channel::Ch1`]
+pub struct Ch1;
+
+/// Output pin of channel `C` of slave timer `S`
+pub struct HrPwmPin<'d, S, C> {
+    pin: PeripheralRef<'d, AnyPin>,
+    _phantom: PhantomData<(S, C)>,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $pin_trait:ident) => {
+        impl<'d, S: SlaveInstance> HrPwmPin<'d, S, $channel> {
+            /// Use `pin` as the channel's output. The pin is configured by [`HrPwm::new`].
+            pub fn $new_chx(pin: impl Peripheral<P = impl $pin_trait<S>> + 'd) -> Self {
+                into_ref!(pin);
+                crate::afio::check_default_layout(&[pin.remaps()]);
+                Self {
+                    pin: pin.map_into(),
+                    _phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+channel_impl!(new_ch0, Ch0, Channel0Pin);
+channel_impl!(new_ch1, Ch1, Channel1Pin);
+
+/// Set of events that set or reset an output, see `SHRTIMER_STxCHySET` and `SHRTIMER_STxCHyRST`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Events(u32);
+
+impl Events {
+    /// No event.
+    pub const NONE: Self = Self(0);
+    /// The end of a period of the slave timer.
+    pub const PERIOD: Self = Self(1 << 2);
+    /// A match of compare unit 0 of the slave timer.
+    pub const CMP0: Self = Self(1 << 3);
+    /// A match of compare unit 1 of the slave timer.
+    pub const CMP1: Self = Self(1 << 4);
+    /// A match of compare unit 2 of the slave timer.
+    pub const CMP2: Self = Self(1 << 5);
+    /// A match of compare unit 3 of the slave timer.
+    pub const CMP3: Self = Self(1 << 6);
+    /// The end of a period of the master timer.
+    pub const MASTER_PERIOD: Self = Self(1 << 7);
+    /// A match of compare unit 0 of the master timer.
+    pub const MASTER_CMP0: Self = Self(1 << 8);
+    /// A match of compare unit 1 of the master timer.
+    pub const MASTER_CMP1: Self = Self(1 << 9);
+    /// A match of compare unit 2 of the master timer.
+    pub const MASTER_CMP2: Self = Self(1 << 10);
+    /// A match of compare unit 3 of the master timer.
+    pub const MASTER_CMP3: Self = Self(1 << 11);
+
+    /// Events contained in either set.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Whether the set contains all events of `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// State of an output while a fault input it enables is active, see `CHyFLTOS` in
+/// `SHRTIMER_STxCHOCTL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FaultState {
+    /// The output keeps running
+    Running = 0,
+    /// The output is active
+    Active = 1,
+    /// The output is inactive
+    Inactive = 2,
+    /// The output floats
+    HighZ = 3,
+}
+
+/// State of an output while the burst mode is idle, see `CHyIMS` and `CHyISO` in
+/// `SHRTIMER_STxCHOCTL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BurstState {
+    /// The output doesn't take part in the burst mode, and keeps running
+    Running,
+    /// The output is inactive
+    Inactive,
+    /// The output is active
+    Active,
+}
+
+/// High-resolution PWM driver of a slave timer
+pub struct HrPwm<'d, S: SlaveInstance> {
+    _peri: PeripheralRef<'d, S>,
+    pins: [Option<PeripheralRef<'d, AnyPin>>; 2],
+}
+
+impl<'d, S: SlaveInstance> HrPwm<'d, S> {
+    /// Create a PWM driver for slave timer `tim` of `shrtimer`, with outputs on the given pins,
+    /// at `freq`.
+    ///
+    /// The timer is stopped. Both outputs are disabled, with a duty of 0, [`Polarity::ActiveHigh`],
+    /// no dead time and [`FaultState::Inactive`]. Panics like [`HrPwm::set_frequency`].
+    pub fn new(
+        _shrtimer: &'d ShrTimer<'_>,
+        tim: impl Peripheral<P = S> + 'd,
+        ch0: Option<HrPwmPin<'d, S, Ch0>>,
+        ch1: Option<HrPwmPin<'d, S, Ch1>>,
+        freq: Hertz,
+    ) -> Self {
+        into_ref!(tim);
+        let r = slave_regs(S::INDEX);
+
+        // Continuous, with the period, compare values and output events buffered until the end
+        // of a period.
+        r.write(CTL0, CTL0_CTNM | CTL0_SHWEN | STCTL0_UPREP);
+        let fault = FaultState::Inactive as u32;
+        r.write(CHOCTL, (fault << CHOCTL_CH0FLTOS_OFFSET) * 0x1_0001);
+        r.write(DTCTL, 0);
+        r.write(FLTCTL, 0);
+
+        let pins = [ch0.map(|p| p.pin), ch1.map(|p| p.pin)];
+        for pin in pins.iter().flatten() {
+            unsafe { pin.set_as_af(AFType::OutputPushPull) };
+        }
+
+        let mut this = Self { _peri: tim, pins };
+        this.set_frequency(freq);
+        this.set_duty(Channel::Ch0, 0);
+        this.set_duty(Channel::Ch1, 0);
+        this.update();
+        this
+    }
+
+    /// Load the buffered values while the timer is stopped, which otherwise waits for the end of
+    /// a period.
+    fn update(&mut self) {
+        if !self.is_running() {
+            critical_section::with(|_| regs().modify(CTL1, STSUP << S::INDEX, 0));
+        }
+    }
+
+    /// Set the PWM frequency. The counter counts as fast as possible for that, up to 32 times the
+    /// SHRTIMER clock, and the duties and compare values should be set again relative to the new
+    /// [`HrPwm::period`].
+    ///
+    /// Takes effect at the end of the current period, and right away if the timer is stopped,
+    /// while the clock divider can only be changed then. Panics if `freq` is zero, above the
+    /// SHRTIMER clock divided by 3, or so low that it needs another clock divider while the
+    /// timer is running.
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        set_period(slave_regs(S::INDEX), STSUP << S::INDEX, freq, self.is_running())
+    }
+
+    /// The PWM frequency.
+    pub fn frequency(&self) -> Hertz {
+        let div = slave_regs(S::INDEX).read(CTL0) & CTL0_CNTCKDIV;
+        let ticks = SHRTIMER::frequency().0 as u64 * 32 >> div;
+        Hertz((ticks / self.period() as u64) as u32)
+    }
+
+    /// The period, in ticks of the counter. It's also the duty of an output that is always
+    /// active.
+    pub fn period(&self) -> u16 {
+        slave_regs(S::INDEX).read(CAR) as u16
+    }
+
+    /// Set the duty of `channel`, which is active for `duty` ticks from the end of each period,
+    /// with compare unit 0 for channel 0 and compare unit 1 for channel 1. This replaces the
+    /// output events of the channel.
+    ///
+    /// Duties below three periods of the SHRTIMER clock, except 0, are rounded up to that. Takes
+    /// effect at the end of the current period. Panics if `duty` is above [`HrPwm::period`].
+    pub fn set_duty(&mut self, channel: Channel, duty: u16) {
+        let period = self.period();
+        assert!(duty <= period, "PWM duty above the period");
+        let compare = match channel {
+            Channel::Ch0 => Compare::Cmp0,
+            Channel::Ch1 => Compare::Cmp1,
+        };
+        let (set, reset) = match duty {
+            0 => (Events::NONE, Events::PERIOD),
+            d if d == period => (Events::PERIOD, Events::NONE),
+            _ => {
+                let div = slave_regs(S::INDEX).read(CTL0) & CTL0_CNTCKDIV;
+                self.set_compare(compare, duty.max(min_compare(div)));
+                let reset = match channel {
+                    Channel::Ch0 => Events::CMP0,
+                    Channel::Ch1 => Events::CMP1,
+                };
+                (Events::PERIOD, reset)
+            }
+        };
+        self.set_output_events(channel, set, reset);
+    }
+
+    /// Set the value of compare unit `compare`, in ticks of the counter from the end of a period.
+    /// Takes effect at the end of the current period.
+    ///
+    /// Values below three periods of the SHRTIMER clock never match, nor do values above
+    /// [`HrPwm::period`].
+    pub fn set_compare(&mut self, compare: Compare, value: u16) {
+        slave_regs(S::INDEX).write(compare.cmpv(), value as u32);
+        self.update();
+    }
+
+    /// The value of compare unit `compare`.
+    pub fn compare(&self, compare: Compare) -> u16 {
+        slave_regs(S::INDEX).read(compare.cmpv()) as u16
+    }
+
+    /// Set the events that make the output of `channel` active, and those that make it
+    /// inactive. Takes effect at the end of the current period.
+    pub fn set_output_events(&mut self, channel: Channel, set: Events, reset: Events) {
+        let r = slave_regs(S::INDEX);
+        r.write(CH0SET + 8 * channel as usize, set.0);
+        r.write(CH0RST + 8 * channel as usize, reset.0);
+        self.update();
+    }
+
+    /// Set the level of the output of `channel` while it's active.
+    pub fn set_polarity(&mut self, channel: Channel, polarity: Polarity) {
+        let bit = CHOCTL_CH0P << (16 * channel as u32);
+        match polarity {
+            Polarity::ActiveHigh => slave_regs(S::INDEX).modify(CHOCTL, 0, bit),
+            Polarity::ActiveLow => slave_regs(S::INDEX).modify(CHOCTL, bit, 0),
+        }
+    }
+
+    /// Make channel 1 the complement of channel 0, with dead times in nanoseconds, rounded up,
+    /// after the rising and the falling edge of channel 0. The output events of channel 1 are
+    /// ignored meanwhile.
+    ///
+    /// Must be called while both outputs are disabled. Panics if a dead time is above 511 times
+    /// 16 periods of the SHRTIMER clock.
+    pub fn set_dead_time(&mut self, rising: u32, falling: u32) {
+        // The dead-time generator counts at 8 times the SHRTIMER clock, divided by 2 to the power
+        // of `DTGCKDIV`.
+        let freq = SHRTIMER::frequency().0 as u64 * 8;
+        let ticks = |ns: u32| (ns as u64 * freq + 999_999_999) / 1_000_000_000;
+        let longest = ticks(rising).max(ticks(falling));
+        let div = unwrap!((0..8).find(|div| longest >> div <= 511), "dead time too long");
+        // Round up to the divided clock.
+        let value = |ns: u32| ((ticks(ns) + (1 << div) - 1) >> div) as u32;
+
+        let r = slave_regs(S::INDEX);
+        r.write(
+            DTCTL,
+            value(rising) | (div << DTCTL_DTGCKDIV_OFFSET) | (value(falling) << DTCTL_DTFCFG_OFFSET),
+        );
+        r.modify(CHOCTL, CHOCTL_DTEN, 0);
+    }
+
+    /// Let channel 1 run from its own output events again, after [`HrPwm::set_dead_time`]. Must
+    /// be called while both outputs are disabled.
+    pub fn disable_dead_time(&mut self) {
+        let r = slave_regs(S::INDEX);
+        r.modify(CHOCTL, 0, CHOCTL_DTEN);
+        r.modify(DTCTL, 0, DTCTL_DTRCFG | DTCTL_DTFCFG);
+    }
+
+    /// Turn the outputs off when `fault` goes active, see [`HrPwm::set_fault_state`].
+    pub fn enable_fault(&mut self, fault: &FaultInput<'_>) {
+        slave_regs(S::INDEX).modify(FLTCTL, 1 << fault.index, 0)
+    }
+
+    /// Ignore `fault` again.
+    pub fn disable_fault(&mut self, fault: &FaultInput<'_>) {
+        slave_regs(S::INDEX).modify(FLTCTL, 0, 1 << fault.index)
+    }
+
+    /// Set the state of the output of `channel` while an enabled fault input is active. Must be
+    /// called while the output is disabled.
+    pub fn set_fault_state(&mut self, channel: Channel, state: FaultState) {
+        let shift = 16 * channel as u32;
+        slave_regs(S::INDEX).modify(
+            CHOCTL,
+            (state as u32) << (CHOCTL_CH0FLTOS_OFFSET + shift),
+            CHOCTL_CH0FLTOS << shift,
+        );
+    }
+
+    /// Set the state of the output of `channel` while the burst mode is idle, see
+    /// [`ShrTimer::configure_burst`]. A disabled output is at the same level, unless it's
+    /// [`BurstState::Running`], which makes it inactive.
+    pub fn set_burst_state(&mut self, channel: Channel, state: BurstState) {
+        let set = match state {
+            BurstState::Running => 0,
+            BurstState::Inactive => CHOCTL_CH0IMS,
+            BurstState::Active => CHOCTL_CH0IMS | CHOCTL_CH0ISO,
+        };
+        let shift = 16 * channel as u32;
+        slave_regs(S::INDEX).modify(CHOCTL, set << shift, (CHOCTL_CH0IMS | CHOCTL_CH0ISO) << shift);
+    }
+
+    /// Bit of the output of `channel` in `SHRTIMER_CHOUTEN`, `SHRTIMER_CHOUTDIS` and
+    /// `SHRTIMER_CHOUTDISF`.
+    fn output_bit(channel: Channel) -> u32 {
+        1 << (2 * S::INDEX as u32 + channel as u32)
+    }
+
+    /// Enable the output of `channel`. This also turns it on again after a fault, once the fault
+    /// input is inactive.
+    pub fn enable(&mut self, channel: Channel) {
+        regs().write(CHOUTEN, Self::output_bit(channel))
+    }
+
+    /// Disable the output of `channel`, which goes inactive.
+    pub fn disable(&mut self, channel: Channel) {
+        regs().write(CHOUTDIS, Self::output_bit(channel))
+    }
+
+    /// Whether the output of `channel` is enabled. Outputs are disabled by faults too.
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        regs().read(CHOUTEN) & Self::output_bit(channel) != 0
+    }
+
+    /// Whether the output of `channel` was disabled by a fault, rather than by
+    /// [`HrPwm::disable`].
+    pub fn is_faulted(&self, channel: Channel) -> bool {
+        !self.is_enabled(channel) && regs().read(CHOUTDISF) & Self::output_bit(channel) != 0
+    }
+
+    /// Clear `fault` and turn the outputs that it turned off on again, see [`FaultInput::clear`].
+    ///
+    /// Fails, leaving the outputs off, while the input is still active.
+    pub fn rearm(&mut self, fault: &mut FaultInput<'_>) -> Result<(), FaultActive> {
+        fault.clear()?;
+        for channel in [Channel::Ch0, Channel::Ch1] {
+            if self.is_faulted(channel) {
+                self.enable(channel);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the counter.
+    pub fn start(&mut self) {
+        critical_section::with(|_| regs().modify(MTCTL0, MTCTL0_STCEN << S::INDEX, 0))
+    }
+
+    /// Stop the counter, keeping its value. The outputs keep their levels.
+    pub fn stop(&mut self) {
+        critical_section::with(|_| regs().modify(MTCTL0, 0, MTCTL0_STCEN << S::INDEX))
+    }
+
+    /// Whether the counter is counting.
+    pub fn is_running(&self) -> bool {
+        regs().read(MTCTL0) & (MTCTL0_STCEN << S::INDEX) != 0
+    }
+
+    /// Reset the counter to 0, which ends the current period.
+    pub fn reset(&mut self) {
+        critical_section::with(|_| regs().modify(CTL1, STSRST << S::INDEX, 0))
+    }
+}
+
+impl<'d, S: SlaveInstance> Drop for HrPwm<'d, S> {
+    fn drop(&mut self) {
+        self.disable(Channel::Ch0);
+        self.disable(Channel::Ch1);
+        self.stop();
+        slave_regs(S::INDEX).write(FLTCTL, 0);
+        for pin in self.pins.iter().flatten() {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Device information: reset reason, unique ID and memory sizes
+//!
+//! ```no_run
+//! use embassy_gd32::sysinfo;
+//!
+//! let reason = sysinfo::reset_reason();
+//! let id = sysinfo::unique_id();
+//! // A locally administered MAC address derived from the unique ID
+//! let mac = [0x02, id[0], id[1], id[2] ^ id[6], id[3] ^ id[7], id[4] ^ id[8]];
+//! ```
+
+use core::cell::Cell;
+use core::ptr::read_volatile;
+
+use critical_section::Mutex;
+
+use crate::chip::{MEMORY_DENSITY, UID_BASE};
+use crate::pmu;
+pub use crate::pmu::ResetReason;
+
+/// The reset reason read by the first [`reset_reason`] call
+static RESET_REASON: Mutex<Cell<Option<ResetReason>>> = Mutex::new(Cell::new(None));
+
+/// The cause of the last reset.
+///
+/// The first call reads and clears the reset flags, so they don't accumulate across resets.
+/// Later calls return the same value. Use [`pmu::reset_reason`] to read the flags without
+/// clearing them.
+pub fn reset_reason() -> ResetReason {
+    critical_section::with(|cs| {
+        let cached = RESET_REASON.borrow(cs);
+        match cached.get() {
+            Some(reason) => reason,
+            None => {
+                let reason = pmu::reset_reason();
+                pmu::clear_reset_flags();
+                cached.set(Some(reason));
+                reason
+            }
+        }
+    })
+}
+
+/// The 96-bit unique device ID, least significant byte first.
+pub fn unique_id() -> [u8; 12] {
+    let mut id = [0; 12];
+    for (i, chunk) in id.chunks_mut(4).enumerate() {
+        let word = unsafe { read_volatile((UID_BASE + 4 * i) as *const u32) };
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    id
+}
+
+/// Size of the flash of this device in bytes.
+///
+/// This may be smaller than [`crate::fmc::FLASH_SIZE`], which is the size of the largest variant
+/// of the chip family.
+pub fn flash_size() -> u32 {
+    (memory_density() & 0xFFFF) * 1024
+}
+
+/// Size of the SRAM of this device in bytes.
+pub fn sram_size() -> u32 {
+    (memory_density() >> 16) * 1024
+}
+
+fn memory_density() -> u32 {
+    unsafe { read_volatile(MEMORY_DENSITY as *const u32) }
+}
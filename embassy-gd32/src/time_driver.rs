@@ -0,0 +1,397 @@
+//! `embassy-time` driver on a general-purpose timer.
+//!
+//! The timer counts ticks with its prescaler, so it needs a timer clock that is a multiple of
+//! `TICK_HZ`, and at most 65536 times `TICK_HZ`. Channel 0 marks the middle of each overflow, the
+//! other three channels are the alarms. Timestamps are tracked like in `embassy-stm32`, see
+//! [`calc_now`].
+
+use core::cell::Cell;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::{mem, ptr};
+
+use atomic_polyfill::{AtomicBool, AtomicU32, AtomicU8};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::driver::{AlarmHandle, Driver};
+use embassy_time::TICK_HZ;
+
+use crate::interrupt::{CriticalSection, Interrupt, InterruptExt};
+use crate::time::Hertz;
+use crate::{cctl, interrupt, pac};
+
+const ALARM_COUNT: usize = 3;
+
+#[cfg(feature = "timedriver-timer1")]
+type T = pac::TIMER1;
+#[cfg(feature = "timedriver-timer2")]
+type T = pac::TIMER2;
+#[cfg(feature = "timedriver-timer3")]
+type T = pac::TIMER3;
+#[cfg(feature = "timedriver-timer4")]
+type T = pac::TIMER4;
+
+#[cfg(feature = "timedriver-timer1")]
+type Irq = interrupt::TIMER1;
+#[cfg(feature = "timedriver-timer2")]
+type Irq = interrupt::TIMER2;
+#[cfg(feature = "timedriver-timer3")]
+type Irq = interrupt::TIMER3;
+#[cfg(feature = "timedriver-timer4")]
+type Irq = interrupt::TIMER4;
+
+/// Bit of the timer in `RCU_APB1EN` and `RCU_APB1RST`
+#[cfg(feature = "timedriver-timer1")]
+const APB1_BIT: u32 = 1 << 0;
+#[cfg(feature = "timedriver-timer2")]
+const APB1_BIT: u32 = 1 << 1;
+#[cfg(feature = "timedriver-timer3")]
+const APB1_BIT: u32 = 1 << 2;
+#[cfg(feature = "timedriver-timer4")]
+const APB1_BIT: u32 = 1 << 3;
+
+#[cfg(feature = "timedriver-timer1")]
+#[interrupt]
+fn TIMER1() {
+    DRIVER.on_interrupt()
+}
+
+#[cfg(feature = "timedriver-timer2")]
+#[interrupt]
+fn TIMER2() {
+    DRIVER.on_interrupt()
+}
+
+#[cfg(feature = "timedriver-timer3")]
+#[interrupt]
+fn TIMER3() {
+    DRIVER.on_interrupt()
+}
+
+#[cfg(feature = "timedriver-timer4")]
+#[interrupt]
+fn TIMER4() {
+    DRIVER.on_interrupt()
+}
+
+fn regs() -> &'static pac::timer1::RegisterBlock {
+    unsafe { &*T::ptr() }
+}
+
+// Clock timekeeping works with something we call "periods", which are time intervals
+// of 2^15 ticks. The Clock counter value is 16 bits, so one "overflow cycle" is 2 periods.
+//
+// A `period` count is maintained in parallel to the Timer hardware `counter`, like this:
+// - `period` and `counter` start at 0
+// - `period` is incremented on overflow (at counter value 0)
+// - `period` is incremented "midway" between overflows (at counter value 0x8000)
+//
+// Therefore, when `period` is even, counter is in 0..0x7FFF. When odd, counter is in 0x8000..0xFFFF
+// This allows for now() to return the correct value even if it races an overflow.
+//
+// To get `now()`, `period` is read first, then `counter` is read. If the counter value matches
+// the expected range for the `period` parity, we're done. If it doesn't, this means that
+// a new period start has raced us between reading `period` and `counter`, so we assume the `counter` value
+// corresponds to the next period.
+//
+// `period` is a 32bit integer, so It overflows on 2^32 * 2^15 / 32768 seconds of uptime, which is 136 years.
+fn calc_now(period: u32, counter: u16) -> u64 {
+    ((period as u64) << 15) + ((counter as u32 ^ ((period & 1) << 15)) as u64)
+}
+
+/// Prescaler dividing `timer_freq` down to `TICK_HZ`, `None` if `timer_freq` isn't a multiple of
+/// `TICK_HZ` or is above 65536 times `TICK_HZ`. Checked by `cctl` before the clocks change.
+pub(crate) fn prescaler(timer_freq: Hertz) -> Option<u16> {
+    let div = timer_freq.0 as u64 / TICK_HZ;
+    match div >= 1 && div <= 1 << 16 && div * TICK_HZ == timer_freq.0 as u64 {
+        true => Some((div - 1) as u16),
+        false => None,
+    }
+}
+
+/// [`prescaler`], or the one closest to `TICK_HZ` if there is no exact one.
+///
+/// `cctl` rejects such configurations, but the reset state and the fallback to IRC8M after a
+/// failure are applied regardless, e.g. 8 MHz with `tick-hz-32_768`. Time runs off then rather
+/// than panicking.
+fn nearest_prescaler(timer_freq: Hertz) -> u16 {
+    prescaler(timer_freq).unwrap_or_else(|| {
+        warn!("timer clock is not a multiple of TICK_HZ, time is off");
+        let div = (timer_freq.0 as u64 + TICK_HZ / 2) / TICK_HZ;
+        (div.clamp(1, 1 << 16) - 1) as u16
+    })
+}
+
+struct AlarmState {
+    timestamp: Cell<u64>,
+
+    // This is really a Option<(fn(*mut ()), *mut ())>
+    // but fn pointers aren't allowed in const yet
+    callback: Cell<*const ()>,
+    ctx: Cell<*mut ()>,
+}
+
+unsafe impl Send for AlarmState {}
+
+impl AlarmState {
+    const fn new() -> Self {
+        Self {
+            timestamp: Cell::new(u64::MAX),
+            callback: Cell::new(ptr::null()),
+            ctx: Cell::new(ptr::null_mut()),
+        }
+    }
+}
+
+struct TimerDriver {
+    /// Number of 2^15 periods elapsed since boot.
+    period: AtomicU32,
+    alarm_count: AtomicU8,
+    started: AtomicBool,
+    /// Timestamp at which to fire alarm. u64::MAX if no alarm is scheduled.
+    alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+}
+
+const ALARM_STATE_NEW: AlarmState = AlarmState::new();
+
+embassy_time::time_driver_impl!(static DRIVER: TimerDriver = TimerDriver {
+    period: AtomicU32::new(0),
+    alarm_count: AtomicU8::new(0),
+    started: AtomicBool::new(false),
+    alarms: Mutex::const_new(CriticalSectionRawMutex::new(), [ALARM_STATE_NEW; ALARM_COUNT]),
+});
+
+impl TimerDriver {
+    fn init(&'static self) {
+        let r = regs();
+
+        critical_section::with(|_| unsafe {
+            let rcu = &*pac::RCU::ptr();
+            rcu.apb1en.modify(|r, w| w.bits(r.bits() | APB1_BIT));
+            rcu.apb1rst.modify(|r, w| w.bits(r.bits() | APB1_BIT));
+            rcu.apb1rst.modify(|r, w| w.bits(r.bits() & !APB1_BIT));
+
+            r.cnt.write(|w| w.bits(0));
+            let psc = nearest_prescaler(cctl::clocks().apb1_timer);
+            r.psc.write(|w| w.bits(psc as u32));
+            r.car.write(|w| w.bits(u16::MAX as u32));
+
+            // Set UPS, generate update to load the prescaler, and clear UPS
+            r.ctl0.modify(|r, w| w.bits(r.bits() | CTL0_UPS));
+            r.swevg.write(|w| w.bits(SWEVG_UPG));
+            r.ctl0.modify(|r, w| w.bits(r.bits() & !CTL0_UPS));
+
+            // Mid-way point
+            r.ch0cv.write(|w| w.bits(0x8000));
+
+            // Enable overflow and half-overflow interrupts
+            r.dmainten.write(|w| w.bits(DMAINTEN_UPIE | dmainten_chie(0)));
+
+            let irq = Irq::steal();
+            irq.unpend();
+            irq.enable();
+
+            r.ctl0.modify(|r, w| w.bits(r.bits() | CTL0_CEN));
+        });
+
+        self.started.store(true, Ordering::Relaxed);
+    }
+
+    fn set_clock_freq(&self, timer_freq: Hertz) {
+        if !self.started.load(Ordering::Relaxed) {
+            return;
+        }
+        let psc = nearest_prescaler(timer_freq);
+        let r = regs();
+        critical_section::with(|_| unsafe {
+            // The prescaler is buffered until the next update event, which is up to 2^16 ticks
+            // away. Generate one right away, with UPS set so it doesn't count as an overflow. It
+            // also clears the counter, which is restored so `period` still matches it.
+            let cnt = r.cnt.read().bits();
+            r.psc.write(|w| w.bits(psc as u32));
+            r.ctl0.modify(|r, w| w.bits(r.bits() | CTL0_UPS));
+            r.swevg.write(|w| w.bits(SWEVG_UPG));
+            r.ctl0.modify(|r, w| w.bits(r.bits() & !CTL0_UPS));
+            r.cnt.write(|w| w.bits(cnt));
+        });
+    }
+
+    fn on_interrupt(&self) {
+        let r = regs();
+
+        // XXX: reduce the size of this critical section ?
+        critical_section::with(|cs| {
+            let intf = r.intf.read().bits();
+            let dmainten = r.dmainten.read().bits();
+
+            // Clear all interrupt flags. Bits in INTF are "write 0 to clear", so write the bitwise NOT.
+            // Other approaches such as writing all zeros, or RMWing won't work, they can
+            // miss interrupts.
+            r.intf.write(|w| unsafe { w.bits(!intf) });
+
+            // Overflow
+            if intf & INTF_UPIF != 0 {
+                self.next_period();
+            }
+
+            // Half overflow
+            if intf & intf_chif(0) != 0 {
+                self.next_period();
+            }
+
+            for n in 0..ALARM_COUNT {
+                if intf & intf_chif(n + 1) != 0 && dmainten & dmainten_chie(n + 1) != 0 {
+                    self.trigger_alarm(n, cs);
+                }
+            }
+        })
+    }
+
+    fn next_period(&self) {
+        let r = regs();
+
+        let period = self.period.fetch_add(1, Ordering::Relaxed) + 1;
+        let t = (period as u64) << 15;
+
+        critical_section::with(move |cs| {
+            r.dmainten.modify(move |r, w| {
+                let mut bits = r.bits();
+                for n in 0..ALARM_COUNT {
+                    let alarm = &self.alarms.borrow(cs)[n];
+                    let at = alarm.timestamp.get();
+
+                    if at < t + 0xc000 {
+                        // just enable it. `set_alarm` has already set the correct compare value.
+                        bits |= dmainten_chie(n + 1);
+                    }
+                }
+                unsafe { w.bits(bits) }
+            })
+        })
+    }
+
+    fn get_alarm<'a>(&'a self, cs: CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
+        // safety: we're allowed to assume the AlarmState is created by us, and
+        // we never create one that's out of bounds.
+        unsafe { self.alarms.borrow(cs).get_unchecked(alarm.id() as usize) }
+    }
+
+    fn trigger_alarm(&self, n: usize, cs: CriticalSection) {
+        let alarm = &self.alarms.borrow(cs)[n];
+        alarm.timestamp.set(u64::MAX);
+
+        // Call after clearing alarm, so the callback can set another alarm.
+
+        // safety:
+        // - we can ignore the possiblity of `f` being unset (null) because of the safety contract of `allocate_alarm`.
+        // - other than that we only store valid function pointers into alarm.callback
+        let f: fn(*mut ()) = unsafe { mem::transmute(alarm.callback.get()) };
+        f(alarm.ctx.get());
+    }
+}
+
+impl Driver for TimerDriver {
+    fn now(&self) -> u64 {
+        let period = self.period.load(Ordering::Relaxed);
+        compiler_fence(Ordering::Acquire);
+        let counter = regs().cnt.read().bits() as u16;
+        calc_now(period, counter)
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+        let id = self.alarm_count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+            if x < ALARM_COUNT as u8 {
+                Some(x + 1)
+            } else {
+                None
+            }
+        });
+
+        match id {
+            Ok(id) => Some(AlarmHandle::new(id)),
+            Err(_) => None,
+        }
+    }
+
+    fn set_alarm_callback(&self, alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            alarm.callback.set(callback as *const ());
+            alarm.ctx.set(ctx);
+        })
+    }
+
+    fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool {
+        critical_section::with(|cs| {
+            let r = regs();
+
+            let n = alarm.id() as usize;
+            let alarm = self.get_alarm(cs, alarm);
+            alarm.timestamp.set(timestamp);
+
+            let t = self.now();
+            if timestamp <= t {
+                // If alarm timestamp has passed the alarm will not fire.
+                // Disarm the alarm and return `false` to indicate that.
+                r.dmainten
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !dmainten_chie(n + 1)) });
+
+                alarm.timestamp.set(u64::MAX);
+
+                return false;
+            }
+
+            let safe_timestamp = timestamp.max(t + 3) as u16 as u32;
+
+            // Write the compare value regardless of whether we're going to enable it now or not.
+            // This way, when we enable it later, the right value is already set.
+            unsafe {
+                match n {
+                    0 => r.ch1cv.write(|w| w.bits(safe_timestamp)),
+                    1 => r.ch2cv.write(|w| w.bits(safe_timestamp)),
+                    _ => r.ch3cv.write(|w| w.bits(safe_timestamp)),
+                }
+            }
+
+            // Enable it if it'll happen soon. Otherwise, `next_period` will enable it.
+            let diff = timestamp - t;
+            r.dmainten.modify(|r, w| {
+                let bits = match diff < 0xc000 {
+                    true => r.bits() | dmainten_chie(n + 1),
+                    false => r.bits() & !dmainten_chie(n + 1),
+                };
+                unsafe { w.bits(bits) }
+            });
+
+            true
+        })
+    }
+}
+
+pub(crate) fn init() {
+    DRIVER.init()
+}
+
+/// Update the prescaler after the APB1 timer clock changed.
+pub(crate) fn set_clock_freq(timer_freq: Hertz) {
+    DRIVER.set_clock_freq(timer_freq)
+}
+
+// TIMER_CTL0
+const CTL0_CEN: u32 = 1 << 0;
+const CTL0_UPS: u32 = 1 << 2;
+
+// TIMER_DMAINTEN
+const DMAINTEN_UPIE: u32 = 1 << 0;
+const fn dmainten_chie(ch: usize) -> u32 {
+    1 << (1 + ch)
+}
+
+// TIMER_INTF
+const INTF_UPIF: u32 = 1 << 0;
+const fn intf_chif(ch: usize) -> u32 {
+    1 << (1 + ch)
+}
+
+// TIMER_SWEVG
+const SWEVG_UPG: u32 = 1 << 0;
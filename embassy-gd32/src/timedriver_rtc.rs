@@ -0,0 +1,282 @@
+//! `embassy-time` driver on the RTC.
+//!
+//! The RTC keeps counting in deep-sleep, so this driver allows waiting for timers while the chip
+//! sleeps. It runs from the RTC clock configured with [`cctl::Config::rtc`], or starts IRC40K if
+//! no RTC clock is enabled, for boards without LXTAL.
+//!
+//! The prescaler divides the RTC clock down to `TICK_HZ` if possible. If `TICK_HZ` is not a
+//! divisor of the RTC clock, e.g. IRC40K with `tick-hz-32_768`, the counter runs at the slowest
+//! rate not below `TICK_HZ` and its value is scaled to ticks. Time within a count is interpolated
+//! from the prescaler divider, so it advances with every RTC clock cycle. If `TICK_HZ` is above the
+//! RTC clock, e.g. with the default 1 MHz, time advances in steps of several ticks.
+//!
+//! The RTC has a single alarm, so only one alarm can be allocated.
+
+use core::cell::Cell;
+use core::{mem, ptr};
+
+use atomic_polyfill::{AtomicU8, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::driver::{AlarmHandle, Driver};
+use embassy_time::TICK_HZ;
+
+use crate::interrupt::{CriticalSection, Interrupt, InterruptExt};
+use crate::rtc::{
+    clear_flags, configure, read_counter, read_counter_and_divider, regs, CTL_ALRMIF, CTL_OVIF, CTL_SCIF,
+};
+use crate::time::Hertz;
+use crate::{cctl, interrupt, rtc};
+
+const ALARM_COUNT: usize = 1;
+
+/// Largest prescaler value, `PSC` is 20 bits.
+const MAX_PSC: u32 = (1 << 20) - 1;
+
+#[interrupt]
+fn RTC() {
+    DRIVER.on_interrupt()
+}
+
+/// Prescaler value dividing `rtc_freq` down to the slowest counter rate not below `TICK_HZ`.
+pub(crate) fn prescaler(rtc_freq: Hertz) -> u32 {
+    (rtc_freq.0 as u64 / TICK_HZ).clamp(1, MAX_PSC as u64 + 1) as u32 - 1
+}
+
+/// Convert RTC clock `cycles` to ticks, rounding down.
+fn cycles_to_ticks(cycles: u64, rtc_hz: u32) -> u64 {
+    let rtc_hz = rtc_hz as u64;
+    cycles / rtc_hz * TICK_HZ + cycles % rtc_hz * TICK_HZ / rtc_hz
+}
+
+/// Convert `ticks` to counts, rounding up, so alarms never fire early. The counter runs at
+/// `rtc_hz / div`.
+fn ticks_to_counts(ticks: u64, rtc_hz: u32, div: u32) -> u64 {
+    let rtc_hz = rtc_hz as u64;
+    let cycles = ticks / TICK_HZ * rtc_hz + (ticks % TICK_HZ * rtc_hz + TICK_HZ - 1) / TICK_HZ;
+    (cycles + div as u64 - 1) / div as u64
+}
+
+struct AlarmState {
+    timestamp: Cell<u64>,
+
+    // This is really a Option<(fn(*mut ()), *mut ())>
+    // but fn pointers aren't allowed in const yet
+    callback: Cell<*const ()>,
+    ctx: Cell<*mut ()>,
+}
+
+unsafe impl Send for AlarmState {}
+
+impl AlarmState {
+    const fn new() -> Self {
+        Self {
+            timestamp: Cell::new(u64::MAX),
+            callback: Cell::new(ptr::null()),
+            ctx: Cell::new(ptr::null_mut()),
+        }
+    }
+}
+
+struct Counter {
+    /// RTC clock frequency
+    rtc_hz: Cell<u32>,
+    /// RTC clock cycles per count, the prescaler value plus one
+    div: Cell<u32>,
+    /// Counter value at `init`, which is time zero. The RTC keeps counting across resets.
+    start: Cell<u32>,
+    /// Number of counter overflows since `init`
+    overflows: Cell<u32>,
+    /// Last value returned by `ticks`, which keeps time monotonic if the divider reloads before
+    /// the counter increments.
+    last: Cell<u64>,
+}
+
+struct RtcDriver {
+    alarm_count: AtomicU8,
+    counter: Mutex<CriticalSectionRawMutex, Counter>,
+    alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+}
+
+const ALARM_STATE_NEW: AlarmState = AlarmState::new();
+
+embassy_time::time_driver_impl!(static DRIVER: RtcDriver = RtcDriver {
+    alarm_count: AtomicU8::new(0),
+    counter: Mutex::const_new(CriticalSectionRawMutex::new(), Counter {
+        rtc_hz: Cell::new(0),
+        div: Cell::new(1),
+        start: Cell::new(0),
+        overflows: Cell::new(0),
+        last: Cell::new(0),
+    }),
+    alarms: Mutex::const_new(CriticalSectionRawMutex::new(), [ALARM_STATE_NEW; ALARM_COUNT]),
+});
+
+impl RtcDriver {
+    fn init(&'static self) {
+        let rtc_freq = unwrap!(cctl::rtc_clock_or_irc40k(), "RTC clock failed to start");
+        let psc = prescaler(rtc_freq);
+
+        critical_section::with(|cs| unsafe {
+            let r = regs();
+            rtc::enable();
+
+            configure(|r| {
+                r.psch.write(|w| w.bits(psc >> 16));
+                r.pscl.write(|w| w.bits(psc & 0xFFFF));
+            });
+            // The new prescaler takes effect at the next count.
+            let counter = self.counter.borrow(cs);
+            counter.rtc_hz.set(rtc_freq.0);
+            counter.div.set(psc + 1);
+            counter.start.set(read_counter());
+
+            // The counter may have overflowed during the reset.
+            if r.ctl.read().bits() & CTL_OVIF != 0 {
+                rtc::on_overflow();
+            }
+            clear_flags(CTL_OVIF | CTL_ALRMIF | CTL_SCIF);
+            configure(|r| r.inten.write(|w| w.bits(INTEN_OVIE | INTEN_ALRMIE)));
+
+            let irq = interrupt::RTC::steal();
+            irq.unpend();
+            irq.enable();
+        })
+    }
+
+    /// RTC clock cycles elapsed since `init`, accounting for an overflow that wasn't handled yet.
+    ///
+    /// The cycles within the current count are always below `div`, so dividing by `div` gives
+    /// the counts elapsed.
+    fn cycles(&self, counter: &Counter) -> u64 {
+        let r = regs();
+
+        let mut overflows = counter.overflows.get();
+        let (mut cnt, mut divider) = read_counter_and_divider();
+        if r.ctl.read().bits() & CTL_OVIF != 0 {
+            // Read again, the counter may have overflowed after the first read.
+            (cnt, divider) = read_counter_and_divider();
+            overflows += 1;
+        }
+        let counts = (((overflows as u64) << 32) + cnt as u64) - counter.start.get() as u64;
+        let div = counter.div.get();
+        // The divider exceeds the prescaler value until the count after `init`.
+        counts * div as u64 + (div - 1).saturating_sub(divider) as u64
+    }
+
+    fn ticks(&self, counter: &Counter) -> u64 {
+        let ticks = cycles_to_ticks(self.cycles(counter), counter.rtc_hz.get()).max(counter.last.get());
+        counter.last.set(ticks);
+        ticks
+    }
+
+    fn on_interrupt(&self) {
+        let r = regs();
+
+        critical_section::with(|cs| {
+            let counter = self.counter.borrow(cs);
+            let ctl = r.ctl.read().bits();
+
+            if ctl & CTL_OVIF != 0 {
+                counter.overflows.set(counter.overflows.get() + 1);
+                unsafe { rtc::on_overflow() };
+            }
+            unsafe { clear_flags(ctl & (CTL_OVIF | CTL_ALRMIF)) };
+
+            if ctl & CTL_ALRMIF != 0 {
+                // The alarm matches the low 32 bits of the count, so it may be an overflow early.
+                let alarm = &self.alarms.borrow(cs)[0];
+                if alarm.timestamp.get() <= self.ticks(counter) {
+                    self.trigger_alarm(0, cs);
+                }
+            }
+        })
+    }
+
+    fn get_alarm<'a>(&'a self, cs: CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
+        // safety: we're allowed to assume the AlarmState is created by us, and
+        // we never create one that's out of bounds.
+        unsafe { self.alarms.borrow(cs).get_unchecked(alarm.id() as usize) }
+    }
+
+    fn trigger_alarm(&self, n: usize, cs: CriticalSection) {
+        let alarm = &self.alarms.borrow(cs)[n];
+        alarm.timestamp.set(u64::MAX);
+
+        // Call after clearing alarm, so the callback can set another alarm.
+
+        // safety:
+        // - we can ignore the possiblity of `f` being unset (null) because of the safety contract of `allocate_alarm`.
+        // - other than that we only store valid function pointers into alarm.callback
+        let f: fn(*mut ()) = unsafe { mem::transmute(alarm.callback.get()) };
+        f(alarm.ctx.get());
+    }
+}
+
+impl Driver for RtcDriver {
+    fn now(&self) -> u64 {
+        critical_section::with(|cs| self.ticks(self.counter.borrow(cs)))
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+        let id = self.alarm_count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+            if x < ALARM_COUNT as u8 {
+                Some(x + 1)
+            } else {
+                None
+            }
+        });
+
+        match id {
+            Ok(id) => Some(AlarmHandle::new(id)),
+            Err(_) => None,
+        }
+    }
+
+    fn set_alarm_callback(&self, alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            alarm.callback.set(callback as *const ());
+            alarm.ctx.set(ctx);
+        })
+    }
+
+    fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+            let counter = self.counter.borrow(cs);
+
+            let cycles = self.cycles(counter);
+            let now = cycles / counter.div.get() as u64;
+            if timestamp <= cycles_to_ticks(cycles, counter.rtc_hz.get()).max(counter.last.get()) {
+                // If alarm timestamp has passed the alarm will not fire.
+                // Disarm the alarm and return `false` to indicate that.
+                alarm.timestamp.set(u64::MAX);
+                return false;
+            }
+            alarm.timestamp.set(timestamp);
+
+            // The alarm fires when the counter reaches the alarm value, so it has to be ahead of
+            // the counter by the time it's written.
+            let at = ticks_to_counts(timestamp, counter.rtc_hz.get(), counter.div.get()).max(now + 2);
+            let at = (at + counter.start.get() as u64) as u32;
+            unsafe {
+                configure(|r| {
+                    r.alrmh.write(|w| w.bits(at >> 16));
+                    r.alrml.write(|w| w.bits(at & 0xFFFF));
+                })
+            };
+
+            true
+        })
+    }
+}
+
+pub(crate) fn init() {
+    DRIVER.init()
+}
+
+// RTC_INTEN
+const INTEN_ALRMIE: u32 = 1 << 1;
+const INTEN_OVIE: u32 = 1 << 2;
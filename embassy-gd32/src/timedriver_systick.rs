@@ -0,0 +1,14 @@
+//! `embassy-time` driver on SysTick, see [`embassy_cortex_m::systick`].
+//!
+//! SysTick runs from the AHB clock. [`crate::cctl`] updates the driver when the clocks change.
+
+use crate::cctl;
+
+#[cortex_m_rt::exception]
+fn SysTick() {
+    embassy_cortex_m::systick::on_interrupt();
+}
+
+pub(crate) unsafe fn init() {
+    embassy_cortex_m::systick::init(cctl::clocks().ahb.0);
+}
@@ -0,0 +1,144 @@
+//! 32-bit counter of two chained timers
+//!
+//! [`ChainedTimer`] counts the overflows of a master timer with a slave timer, clocked by the
+//! master's update event through an internal trigger. The master's counter is the low half of a
+//! 32-bit counter and the slave's the high half, so long durations are counted by the hardware
+//! without the overflow interrupts.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::chained::ChainedTimer;
+//!
+//! let mut timer = ChainedTimer::new(p.TIMER2, p.TIMER3, Hertz::mhz(1));
+//! timer.start();
+//! // An hour, in microseconds.
+//! timer.wait_until(3_600_000_000).await;
+//! # }
+//! ```
+//!
+//! The slave timer must list the master in [`InternalTrigger`], e.g. TIMER3 takes the trigger
+//! output of TIMER2.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use super::low_level::{MasterMode, SlaveMode, Timer};
+use super::*;
+use crate::time::Hertz;
+
+/// Two timers chained into a 32-bit counter
+pub struct ChainedTimer<'d, M: MasterInstance, S: InternalTrigger<M>> {
+    master: Timer<'d, M>,
+    slave: Timer<'d, S>,
+}
+
+impl<'d, M: MasterInstance, S: InternalTrigger<M>> ChainedTimer<'d, M, S> {
+    /// Chain `master` and `slave` into a 32-bit counter, which counts at `freq`, rounded. The
+    /// counter is stopped at 0.
+    ///
+    /// Panics like [`Timer::set_tick_frequency`].
+    pub fn new(master: impl Peripheral<P = M> + 'd, slave: impl Peripheral<P = S> + 'd, freq: Hertz) -> Self {
+        let mut master = Timer::new(master);
+        let mut slave = Timer::new(slave);
+
+        // Loading the prescaler and auto-reload values generates update events, which would
+        // clock the slave once the master mode is set.
+        master.set_tick_frequency(freq);
+        master.set_auto_reload(u16::MAX);
+        slave.set_auto_reload(u16::MAX);
+        master.set_master_mode(MasterMode::Update);
+        slave.set_slave_mode(SlaveMode::ExternalClock, S::INPUT);
+        // The slave only counts when the master overflows.
+        slave.start();
+
+        Self { master, slave }
+    }
+
+    /// Frequency of the counter.
+    pub fn frequency(&self) -> Hertz {
+        Hertz(M::frequency().0 / (self.master.prescaler() as u32 + 1))
+    }
+
+    /// The counter value.
+    pub fn counter(&self) -> u32 {
+        loop {
+            // The high half may be incremented between the reads, the low half is only right if
+            // it wasn't.
+            let high = self.slave.counter();
+            let low = self.master.counter();
+            if self.slave.counter() == high {
+                return ((high as u32) << 16) | low as u32;
+            }
+        }
+    }
+
+    /// Set the counter value. The counter should be stopped for that, it may be off by an overflow
+    /// of the low half otherwise.
+    pub fn set_counter(&mut self, value: u32) {
+        self.slave.set_counter((value >> 16) as u16);
+        self.master.set_counter(value as u16);
+    }
+
+    /// Start counting.
+    pub fn start(&mut self) {
+        self.master.start()
+    }
+
+    /// Stop counting, keeping the counter value.
+    pub fn stop(&mut self) {
+        self.master.stop()
+    }
+
+    /// Whether the counter is counting.
+    pub fn is_running(&self) -> bool {
+        self.master.is_running()
+    }
+}
+
+impl<'d, M, S> ChainedTimer<'d, M, S>
+where
+    M: MasterInstance + CaptureCompareInstance,
+    S: InternalTrigger<M> + CaptureCompareInstance,
+{
+    /// Wait until the counter reaches `target`. Returns right away if it's already past it,
+    /// i.e. less than 2^31 ticks after it.
+    ///
+    /// Channel 0 of both timers compares the halves of the counter for that, so their outputs
+    /// can't be used.
+    pub async fn wait_until(&mut self, target: u32) {
+        loop {
+            // Ticks until `target`, which are above 2^31 once it has passed.
+            let remaining = |now: u32| target.wrapping_sub(now);
+            let remaining_now = remaining(self.counter());
+            if remaining_now == 0 || remaining_now > u32::MAX / 2 {
+                return;
+            }
+
+            // Wait for the high half to reach the target's, then for the low half. The counter has
+            // reached the target's high half once at most the target's low half remains.
+            let (r, state, value, limit) = match remaining_now > target & 0xFFFF {
+                true => (regs::<S>(), S::state(), target >> 16, target & 0xFFFF),
+                false => (regs::<M>(), M::state(), target & 0xFFFF, 0),
+            };
+            r.write(CH0CV, value);
+            r.write(INTF, !INTF_CH0IF);
+            poll_fn(|cx| {
+                state.waker.register(cx.waker());
+                if r.read(INTF) & INTF_CH0IF != 0 {
+                    return Poll::Ready(());
+                }
+                // The interrupt handler disables the interrupt again.
+                critical_section::with(|_| r.modify(DMAINTEN, INTF_CH0IF, 0));
+                // The counter may have matched before the flag was cleared.
+                let remaining_now = remaining(self.counter());
+                match remaining_now <= limit || remaining_now > u32::MAX / 2 {
+                    true => Poll::Ready(()),
+                    false => Poll::Pending,
+                }
+            })
+            .await;
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! Complementary PWM outputs of the advanced timers
+//!
+//! [`ComplementaryPwm`] drives channels 0 to 2 of TIMER0 or TIMER7 on a pair of pins each, the
+//! output `CHx` and its complement `CHxN`, as needed for the half bridges of motor inverters and
+//! full-bridge converters. A dead time delays the rising edge of both outputs, so the two switches
+//! of a bridge are never on at the same time.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::complementary_pwm::{
+//!     BreakInput, ComplementaryPwm, ComplementaryPwmPin, CountingMode,
+//! };
+//! use embassy_gd32::timer::simple_pwm::{Polarity, PwmPin};
+//! use embassy_gd32::timer::Channel;
+//!
+//! let high = PwmPin::new_ch0(p.PA8);
+//! let low = ComplementaryPwmPin::new_ch0(p.PB13);
+//! let brk = BreakInput::new(p.PB12, Polarity::ActiveLow);
+//! let mut pwm = ComplementaryPwm::new(
+//!     p.TIMER0,
+//!     Some(high),
+//!     Some(low),
+//!     None,
+//!     None,
+//!     None,
+//!     None,
+//!     None,
+//!     Some(brk),
+//!     Hertz::khz(20),
+//!     CountingMode::CenterAlignedBoth,
+//! );
+//! pwm.set_dead_time(100);
+//! pwm.set_duty(Channel::Ch0, pwm.max_duty() / 2);
+//! pwm.enable(Channel::Ch0);
+//! ```
+//!
+//! An active break input, or a failure of the HXTAL detected by the clock monitor, turns all
+//! outputs off at once: they are driven to their idle levels, see
+//! [`ComplementaryPwm::set_idle_state`], until [`ComplementaryPwm::enable_outputs`] is called, or
+//! the next update event after the break is gone with
+//! [`ComplementaryPwm::set_automatic_output_enable`].
+//!
+//! A task can react to a break, e.g. an overcurrent trip, with [`ComplementaryPwm::wait_for_fault`],
+//! and turn the outputs on again once the break input is inactive with
+//! [`ComplementaryPwm::rearm`].
+//!
+//! Instead of a pin, the break input can be the output of a comparator, see
+//! [`BreakInput::new_comparator`], which trips on an analog threshold without external parts.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::low_level::{Timer, UpdateEvents};
+use super::simple_pwm::{Ch0, Ch1, Ch2, Ch3, Polarity, PwmPin};
+use super::*;
+use crate::afio::RemapSet;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Level};
+use crate::time::Hertz;
+
+/// Complementary output pin of channel `C` of timer `T`
+pub struct ComplementaryPwmPin<'d, T, C> {
+    pin: PeripheralRef<'d, AnyPin>,
+    remaps: RemapSet,
+    _phantom: PhantomData<(T, C)>,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $pin_trait:ident) => {
+        impl<'d, T: AdvancedInstance> ComplementaryPwmPin<'d, T, $channel> {
+            /// Use `pin` as the channel's complementary output. The pin is configured by
+            /// [`ComplementaryPwm::new`].
+            pub fn $new_chx(pin: impl Peripheral<P = impl $pin_trait<T>> + 'd) -> Self {
+                into_ref!(pin);
+                let remaps = pin.remaps();
+                Self {
+                    pin: pin.map_into(),
+                    remaps,
+                    _phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+channel_impl!(new_ch0, Ch0, Channel0ComplementaryPin);
+channel_impl!(new_ch1, Ch1, Channel1ComplementaryPin);
+channel_impl!(new_ch2, Ch2, Channel2ComplementaryPin);
+
+/// Break input of timer `T`, a pin or a comparator
+pub struct BreakInput<'d, T> {
+    pin: Option<PeripheralRef<'d, AnyPin>>,
+    remaps: RemapSet,
+    polarity: Polarity,
+    _phantom: PhantomData<T>,
+}
+
+impl<'d, T: AdvancedInstance> BreakInput<'d, T> {
+    /// Use `pin` as the break input, which turns the outputs off while it's at the `polarity`
+    /// level. The pin is configured as a floating input by [`ComplementaryPwm::new`].
+    pub fn new(pin: impl Peripheral<P = impl BreakInputPin<T>> + 'd, polarity: Polarity) -> Self {
+        into_ref!(pin);
+        let remaps = pin.remaps();
+        Self {
+            pin: Some(pin.map_into()),
+            remaps,
+            polarity,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Break on the output of a comparator routed to the break input of `T`, see
+    /// [`crate::cmp::Output`]. The outputs turn off while the comparator output is high.
+    pub fn new_comparator() -> Self {
+        Self {
+            pin: None,
+            remaps: RemapSet::ALL,
+            polarity: Polarity::ActiveHigh,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Direction of the counter, see `CAM` in `TIMER_CTL0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CountingMode {
+    /// Count up to the auto-reload value, then start again from 0. The outputs are active at the
+    /// start of each period.
+    EdgeAligned = 0,
+    /// Count up to the auto-reload value, then down to 0. The outputs are active around the middle
+    /// of each period, and the compare flags are set while counting down.
+    CenterAlignedDown = 1,
+    /// Like [`CountingMode::CenterAlignedDown`], with the compare flags set while counting up
+    CenterAlignedUp = 2,
+    /// Like [`CountingMode::CenterAlignedDown`], with the compare flags set in both directions
+    CenterAlignedBoth = 3,
+}
+
+/// The break input is still active, see [`ComplementaryPwm::rearm`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FaultActive;
+
+/// Complementary PWM driver
+pub struct ComplementaryPwm<'d, T: AdvancedInstance> {
+    timer: Timer<'d, T>,
+    counting_mode: CountingMode,
+    pins: [Option<PeripheralRef<'d, AnyPin>>; 8],
+}
+
+impl<'d, T: AdvancedInstance> ComplementaryPwm<'d, T> {
+    /// Create a PWM driver with outputs on the given pins, at `freq`.
+    ///
+    /// All channels are disabled, with a duty of 0, [`Polarity::ActiveHigh`] outputs, low idle
+    /// levels and no dead time. The break input is enabled if given. Panics like
+    /// [`ComplementaryPwm::set_frequency`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        ch0: Option<PwmPin<'d, T, Ch0>>,
+        ch0n: Option<ComplementaryPwmPin<'d, T, Ch0>>,
+        ch1: Option<PwmPin<'d, T, Ch1>>,
+        ch1n: Option<ComplementaryPwmPin<'d, T, Ch1>>,
+        ch2: Option<PwmPin<'d, T, Ch2>>,
+        ch2n: Option<ComplementaryPwmPin<'d, T, Ch2>>,
+        ch3: Option<PwmPin<'d, T, Ch3>>,
+        brk: Option<BreakInput<'d, T>>,
+        freq: Hertz,
+        counting_mode: CountingMode,
+    ) -> Self {
+        let timer = Timer::new(tim);
+        let r = regs::<T>();
+
+        // `ALL` leaves the layout to the other pins.
+        let remaps = [
+            ch0.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch0n.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch1.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch1n.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch2.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch2n.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch3.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            brk.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+        ];
+        T::remap(&remaps);
+
+        // Drive the outputs to their idle levels while they are off, rather than leaving them
+        // floating.
+        let mut cchp = CCHP_IOS | CCHP_ROS;
+        if let Some(brk) = &brk {
+            cchp |= CCHP_BRKEN;
+            if brk.polarity == Polarity::ActiveHigh {
+                cchp |= CCHP_BRKP;
+            }
+        }
+        r.write(CCHP, cchp);
+        // The flag is cleared by writing zero, writing one has no effect.
+        r.write(INTF, !INTF_BRKIF);
+
+        let pins = [
+            ch0.map(|p| p.pin),
+            ch0n.map(|p| p.pin),
+            ch1.map(|p| p.pin),
+            ch1n.map(|p| p.pin),
+            ch2.map(|p| p.pin),
+            ch2n.map(|p| p.pin),
+            ch3.map(|p| p.pin),
+            brk.and_then(|p| p.pin),
+        ];
+        for pin in pins[..7].iter().flatten() {
+            unsafe { pin.set_as_af(AFType::OutputPushPull) };
+        }
+        if let Some(pin) = &pins[7] {
+            unsafe { pin.set_as_af(AFType::Input) };
+        }
+
+        // The counting mode can only be changed while the counter is stopped.
+        r.modify(CTL0, (counting_mode as u32) << CTL0_CAM_OFFSET, CTL0_CAM);
+        let mut this = Self {
+            timer,
+            counting_mode,
+            pins,
+        };
+        this.set_frequency(freq);
+        this.enable_outputs();
+        this.timer.start();
+        this
+    }
+
+    fn center_aligned(&self) -> bool {
+        self.counting_mode != CountingMode::EdgeAligned
+    }
+
+    /// Set the PWM frequency. The duties are counted in ticks, so they should be set again,
+    /// relative to the new [`ComplementaryPwm::max_duty`].
+    ///
+    /// Panics if `freq` is zero, above the timer clock (half of it when center-aligned), or below
+    /// the timer clock divided by 65535 times 65536 (times 131072 when center-aligned).
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        assert!(freq.0 != 0, "PWM frequency must not be zero");
+        let mut ticks = T::frequency().0 / freq.0;
+        if self.center_aligned() {
+            // The counter goes up and down in a period.
+            ticks /= 2;
+        }
+        assert!(ticks != 0, "PWM frequency above the timer clock");
+
+        let psc = (ticks - 1) / 0xFFFF;
+        assert!(psc <= 0xFFFF, "PWM frequency too low");
+        let car = match self.center_aligned() {
+            true => ticks / (psc + 1),
+            false => ticks / (psc + 1) - 1,
+        };
+        self.timer.set_prescaler(psc as u16);
+        self.timer.set_auto_reload(car as u16);
+    }
+
+    /// The overflows of the counter from now on, as a stream of ticks, see [`UpdateEvents`]. They
+    /// happen once per PWM period, or twice when center-aligned, at both ends of the count. A
+    /// duty set on a tick takes effect from the next one.
+    pub fn update_events(&mut self) -> UpdateEvents<'_, T> {
+        self.timer.update_events()
+    }
+
+    /// The PWM frequency.
+    pub fn frequency(&self) -> Hertz {
+        let psc = self.timer.prescaler() as u32 + 1;
+        let ticks = match self.center_aligned() {
+            true => psc * 2 * self.timer.auto_reload() as u32,
+            false => psc * (self.timer.auto_reload() as u32 + 1),
+        };
+        Hertz(T::frequency().0 / ticks)
+    }
+
+    /// The duty of an output that is always active. It's the number of ticks of a period, or
+    /// half of it when center-aligned.
+    pub fn max_duty(&self) -> u16 {
+        match self.center_aligned() {
+            true => self.timer.auto_reload(),
+            false => self.timer.auto_reload() + 1,
+        }
+    }
+
+    /// Set the duty of `channel`. Its output is active for `duty` ticks of each period, or twice
+    /// as many when center-aligned, less the dead time. Panics if `duty` is above
+    /// [`ComplementaryPwm::max_duty`].
+    pub fn set_duty(&mut self, channel: Channel, duty: u16) {
+        assert!(duty <= self.max_duty(), "PWM duty above the maximum");
+        regs::<T>().write(channel.chcv(), duty as u32)
+    }
+
+    /// The duty of `channel`.
+    pub fn duty(&self, channel: Channel) -> u16 {
+        regs::<T>().read(channel.chcv()) as u16
+    }
+
+    /// Set the dead time in ticks of the timer clock, rounded up to what the timer supports. Both
+    /// outputs of a channel are inactive for that long after each edge of the PWM signal.
+    ///
+    /// This also sets the clock divider of the input filters. Panics if `ticks` is above 4032.
+    pub fn set_dead_time(&mut self, ticks: u16) {
+        let (ckdiv, dtcfg) = dead_time_config(ticks as u32);
+        let r = regs::<T>();
+        r.modify(CTL0, ckdiv << CTL0_CKDIV_OFFSET, CTL0_CKDIV);
+        r.modify(CCHP, dtcfg, CCHP_DTCFG);
+    }
+
+    /// Set the level of the output and the complementary output of `channel` while it's active.
+    /// Channel 3 has no complementary output, `complementary` is ignored for it.
+    pub fn set_polarity(&mut self, channel: Channel, output: Polarity, complementary: Polarity) {
+        let shift = 4 * channel as u32;
+        let mut set = 0;
+        if output == Polarity::ActiveLow {
+            set |= CHCTL2_CHP;
+        }
+        if complementary == Polarity::ActiveLow && channel != Channel::Ch3 {
+            set |= CHCTL2_CHNP;
+        }
+        regs::<T>().modify(CHCTL2, set << shift, (CHCTL2_CHP | CHCTL2_CHNP) << shift);
+    }
+
+    /// Set the levels of the output and the complementary output of `channel` while the outputs
+    /// are turned off by a break, or by [`ComplementaryPwm::disable_outputs`]. Channel 3 has no
+    /// complementary output, `complementary` is ignored for it.
+    pub fn set_idle_state(&mut self, channel: Channel, output: Level, complementary: Level) {
+        let shift = 2 * channel as u32;
+        let mut set = 0;
+        if output == Level::High {
+            set |= CTL1_ISO0;
+        }
+        if complementary == Level::High && channel != Channel::Ch3 {
+            set |= CTL1_ISO0N;
+        }
+        regs::<T>().modify(CTL1, set << shift, (CTL1_ISO0 | CTL1_ISO0N) << shift);
+    }
+
+    /// Enable the output and the complementary output of `channel`.
+    pub fn enable(&mut self, channel: Channel) {
+        let r = regs::<T>();
+        let (chctl, shift) = channel.chctl();
+        // PWM mode 0 with the compare value buffered until the next update event.
+        r.modify(
+            chctl,
+            ((CHCOMCTL_PWM0 << CHCTL_CHCOMCTL_OFFSET) | CHCTL_CHCOMSEN) << shift,
+            (CHCTL_CHMS | CHCTL_CHCOMCTL | CHCTL_CHCOMSEN) << shift,
+        );
+        let mut en = CHCTL2_CHEN;
+        if channel != Channel::Ch3 {
+            en |= CHCTL2_CHNEN;
+        }
+        r.modify(CHCTL2, en << (4 * channel as u32), 0);
+    }
+
+    /// Disable the output and the complementary output of `channel`. Both are driven to their
+    /// inactive levels.
+    pub fn disable(&mut self, channel: Channel) {
+        regs::<T>().modify(CHCTL2, 0, (CHCTL2_CHEN | CHCTL2_CHNEN) << (4 * channel as u32));
+    }
+
+    /// Whether the outputs of `channel` are enabled.
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        regs::<T>().read(CHCTL2) & (CHCTL2_CHEN << (4 * channel as u32)) != 0
+    }
+
+    /// Turn all outputs on again after a break, or [`ComplementaryPwm::disable_outputs`]. Has no
+    /// effect while the break input is active.
+    pub fn enable_outputs(&mut self) {
+        regs::<T>().modify(CCHP, CCHP_POEN, 0)
+    }
+
+    /// Turn all outputs off, driving them to their idle levels, like a break does.
+    pub fn disable_outputs(&mut self) {
+        regs::<T>().modify(CCHP, 0, CCHP_POEN)
+    }
+
+    /// Whether the outputs are on, i.e. not turned off by a break or
+    /// [`ComplementaryPwm::disable_outputs`].
+    pub fn outputs_enabled(&self) -> bool {
+        regs::<T>().read(CCHP) & CCHP_POEN != 0
+    }
+
+    /// Turn the outputs on again at the next update event after a break is gone, instead of
+    /// waiting for [`ComplementaryPwm::enable_outputs`].
+    pub fn set_automatic_output_enable(&mut self, enabled: bool) {
+        match enabled {
+            true => regs::<T>().modify(CCHP, CCHP_OAEN, 0),
+            false => regs::<T>().modify(CCHP, 0, CCHP_OAEN),
+        }
+    }
+
+    /// Whether a break has occurred since the driver was created or last re-armed.
+    pub fn fault_occurred(&self) -> bool {
+        regs::<T>().read(INTF) & INTF_BRKIF != 0
+    }
+
+    /// Wait for a break. The outputs are already off when this returns, turned off by the
+    /// hardware. Returns right away if a break has occurred since the driver was created or
+    /// last re-armed.
+    pub async fn wait_for_fault(&mut self) {
+        let r = regs::<T>();
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            if r.read(INTF) & INTF_BRKIF != 0 {
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again.
+            critical_section::with(|_| r.modify(DMAINTEN, INTF_BRKIF, 0));
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Clear the break and turn the outputs on again, like [`ComplementaryPwm::enable_outputs`].
+    ///
+    /// Fails, leaving the outputs off and the break pending, while the break input is still
+    /// active.
+    pub fn rearm(&mut self) -> Result<(), FaultActive> {
+        let r = regs::<T>();
+        r.write(INTF, !INTF_BRKIF);
+        // The flag can't be cleared while the break input is active.
+        if r.read(INTF) & INTF_BRKIF != 0 {
+            return Err(FaultActive);
+        }
+        self.enable_outputs();
+        Ok(())
+    }
+}
+
+impl<'d, T: AdvancedInstance> Drop for ComplementaryPwm<'d, T> {
+    fn drop(&mut self) {
+        for pin in self.pins.iter().flatten() {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
+
+/// The clock divider `CKDIV` and the `DTCFG` value for a dead time of at least `ticks`.
+///
+/// The dead time is counted in ticks of `t_DTS`, the timer clock divided by 1, 2 or 4, and
+/// `DTCFG` encodes 0 to 127 ticks one by one, 128 to 254 by twos, 256 to 504 by eights and 512
+/// to 1008 by sixteens.
+fn dead_time_config(ticks: u32) -> (u32, u32) {
+    for ckdiv in 0..3 {
+        let dts = (ticks + (1 << ckdiv) - 1) >> ckdiv;
+        let dtcfg = match dts {
+            0..=127 => dts,
+            128..=254 => 0x80 | ((dts + 1) / 2 - 64),
+            255..=504 => 0xC0 | ((dts + 7) / 8 - 32),
+            505..=1008 => 0xE0 | ((dts + 15) / 16 - 32),
+            _ => continue,
+        };
+        return (ckdiv, dtcfg);
+    }
+    panic!("dead time too long");
+}
@@ -0,0 +1,168 @@
+//! Delays timed by a timer
+//!
+//! [`Delay`] waits for a number of ticks of the timer clock, counted by a dedicated timer in
+//! single pulse mode, e.g. one of the basic timers TIMER5 and TIMER6. It doesn't need the
+//! `embassy-time` driver, and is accurate to a tick of the timer clock for short delays, so it
+//! suits drivers that wait for sub-microsecond setup times.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::timer::delay::Delay;
+//!
+//! let mut delay = Delay::new(p.TIMER5);
+//! delay.blocking_delay_ns(250);
+//! delay.delay_us(10).await;
+//! # }
+//! ```
+//!
+//! Delays are rounded up to whole ticks, and last a bit longer than requested by the time it
+//! takes to start the timer. Delays above 65536 ticks are counted with the prescaler, and rounded
+//! up to a multiple of it.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use super::low_level::Timer;
+use super::*;
+
+/// Most ticks of a run of the counter, with the largest prescaler and auto-reload value
+const MAX_RUN: u64 = 1 << 32;
+
+/// Delay provider
+pub struct Delay<'d, T: Instance> {
+    timer: Timer<'d, T>,
+}
+
+impl<'d, T: Instance> Delay<'d, T> {
+    /// Create a delay provider.
+    pub fn new(tim: impl Peripheral<P = T> + 'd) -> Self {
+        let timer = Timer::new(tim);
+        // The counter stops at the end of each delay.
+        regs::<T>().modify(CTL0, CTL0_SPM, 0);
+        Self { timer }
+    }
+
+    /// Ticks of the timer clock in `amount` units of `1 / per_second` seconds, rounded up.
+    fn ticks(&self, amount: u32, per_second: u64) -> u64 {
+        let freq = self.timer.clock_frequency().0 as u64;
+        (amount as u64 * freq + per_second - 1) / per_second
+    }
+
+    /// Start counting `ticks`, at most [`MAX_RUN`].
+    fn start(&mut self, ticks: u64) {
+        let psc = (ticks - 1) >> 16;
+        let car = (ticks + psc) / (psc + 1) - 1;
+        self.timer.set_prescaler(psc as u16);
+        self.timer.set_auto_reload(car as u16);
+        // The flag is cleared by writing zero, writing one has no effect.
+        regs::<T>().write(INTF, !INTF_UPIF);
+        self.timer.start();
+    }
+
+    /// Wait for `ticks` ticks of the timer clock, blocking.
+    pub fn blocking_delay_ticks(&mut self, mut ticks: u64) {
+        while ticks != 0 {
+            let run = ticks.min(MAX_RUN);
+            self.start(run);
+            while self.timer.is_running() {}
+            ticks -= run;
+        }
+    }
+
+    /// Wait for `ns` nanoseconds, blocking.
+    pub fn blocking_delay_ns(&mut self, ns: u32) {
+        self.blocking_delay_ticks(self.ticks(ns, 1_000_000_000))
+    }
+
+    /// Wait for `us` microseconds, blocking.
+    pub fn blocking_delay_us(&mut self, us: u32) {
+        self.blocking_delay_ticks(self.ticks(us, 1_000_000))
+    }
+
+    /// Wait for `ms` milliseconds, blocking.
+    pub fn blocking_delay_ms(&mut self, ms: u32) {
+        self.blocking_delay_ticks(self.ticks(ms, 1_000))
+    }
+
+    /// Wait for `ticks` ticks of the timer clock.
+    pub async fn delay_ticks(&mut self, mut ticks: u64) {
+        while ticks != 0 {
+            let run = ticks.min(MAX_RUN);
+            self.start(run);
+            let r = regs::<T>();
+            poll_fn(|cx| {
+                T::state().waker.register(cx.waker());
+                if r.read(INTF) & INTF_UPIF != 0 {
+                    return Poll::Ready(());
+                }
+                // The interrupt handler disables the interrupt again.
+                critical_section::with(|_| r.modify(DMAINTEN, DMAINTEN_UPIE, 0));
+                Poll::Pending
+            })
+            .await;
+            ticks -= run;
+        }
+    }
+
+    /// Wait for `ns` nanoseconds.
+    pub async fn delay_ns(&mut self, ns: u32) {
+        self.delay_ticks(self.ticks(ns, 1_000_000_000)).await
+    }
+
+    /// Wait for `us` microseconds.
+    pub async fn delay_us(&mut self, us: u32) {
+        self.delay_ticks(self.ticks(us, 1_000_000)).await
+    }
+
+    /// Wait for `ms` milliseconds.
+    pub async fn delay_ms(&mut self, ms: u32) {
+        self.delay_ticks(self.ticks(ms, 1_000)).await
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_02::blocking::delay::DelayUs<u32> for Delay<'d, T> {
+    fn delay_us(&mut self, us: u32) {
+        self.blocking_delay_us(us)
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_02::blocking::delay::DelayMs<u32> for Delay<'d, T> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.blocking_delay_ms(ms)
+    }
+}
+
+#[cfg(feature = "unstable-traits")]
+mod eh1 {
+    use super::*;
+
+    impl<'d, T: Instance> embedded_hal_1::delay::DelayUs for Delay<'d, T> {
+        type Error = core::convert::Infallible;
+
+        fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+            Ok(self.blocking_delay_us(us))
+        }
+
+        fn delay_ms(&mut self, ms: u32) -> Result<(), Self::Error> {
+            Ok(self.blocking_delay_ms(ms))
+        }
+    }
+}
+
+#[cfg(all(feature = "unstable-traits", feature = "nightly"))]
+mod eha {
+    use super::*;
+
+    impl<'d, T: Instance> embedded_hal_async::delay::DelayUs for Delay<'d, T> {
+        type Error = core::convert::Infallible;
+
+        async fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+            Ok(Delay::delay_us(self, us).await)
+        }
+
+        async fn delay_ms(&mut self, ms: u32) -> Result<(), Self::Error> {
+            Ok(Delay::delay_ms(self, ms).await)
+        }
+    }
+}
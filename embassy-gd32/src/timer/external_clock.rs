@@ -0,0 +1,218 @@
+//! Counting external pulses
+//!
+//! [`ExternalCounter`] clocks the counter of a timer with an input signal instead of the timer
+//! clock, so it counts pulses without the CPU, e.g. of an energy meter or a flow sensor. The
+//! timer keeps counting in sleep mode.
+//!
+//! The pulses can come from
+//!
+//! - the external trigger input `ETI`, with a prescaler, in external clock mode 1, see
+//!   [`ExternalCounter::new_eti`],
+//! - or the input of channel 0 or 1, in external clock mode 0, see [`ExternalCounter::new_ch0`]
+//!   and [`ExternalCounter::new_ch1`].
+//!
+//! Both have a digital filter against glitches, see `ETFC` and `CHxCAPFLT` in the user manual.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::gpio::Pull;
+//! use embassy_gd32::timer::external_clock::{EtiConfig, ExternalCounter};
+//!
+//! let mut counter = ExternalCounter::new_eti(p.TIMER2, p.PD2, Pull::Up, EtiConfig::default());
+//! // Wake up every 1000 pulses.
+//! counter.set_limit(1000);
+//! loop {
+//!     counter.wait_for_limit().await;
+//! }
+//! # }
+//! ```
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::input_capture::{CapturePin, Edge};
+use super::low_level::{SlaveMode, Timer, TriggerInput};
+use super::simple_pwm::{Ch0, Ch1};
+use super::*;
+use crate::afio::RemapSet;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Pull};
+
+/// Divider of the `ETI` signal, see `ETPSC` in `TIMER_SMCFG`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EtiPrescaler {
+    /// Count every pulse
+    Div1 = 0,
+    /// Count every second pulse
+    Div2 = 1,
+    /// Count every fourth pulse
+    Div4 = 2,
+    /// Count every eighth pulse
+    Div8 = 3,
+}
+
+/// Configuration of the external trigger input
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EtiConfig {
+    /// Edge that is counted, [`Edge::Rising`] or [`Edge::Falling`]
+    pub edge: Edge,
+    /// Divider of the input, after the polarity and before the filter. The divided signal must
+    /// stay below a quarter of the timer clock.
+    pub prescaler: EtiPrescaler,
+    /// Digital filter, from 0 for none to 15, see `ETFC` in `TIMER_SMCFG`
+    pub filter: u8,
+}
+
+impl Default for EtiConfig {
+    fn default() -> Self {
+        Self {
+            edge: Edge::Rising,
+            prescaler: EtiPrescaler::Div1,
+            filter: 0,
+        }
+    }
+}
+
+/// External pulse counter
+pub struct ExternalCounter<'d, T: SlaveInstance> {
+    timer: Timer<'d, T>,
+    pin: PeripheralRef<'d, AnyPin>,
+}
+
+impl<'d, T: ExternalTriggerInstance> ExternalCounter<'d, T> {
+    /// Count the pulses on the external trigger input `pin`, with `pull`.
+    ///
+    /// The counter starts at 0, and counts up to 65535. Panics if the edge is [`Edge::Both`],
+    /// or the filter is above 15.
+    pub fn new_eti(
+        tim: impl Peripheral<P = T> + 'd,
+        pin: impl Peripheral<P = impl ExternalTriggerPin<T>> + 'd,
+        pull: Pull,
+        config: EtiConfig,
+    ) -> Self {
+        into_ref!(pin);
+        assert!(config.edge != Edge::Both, "ETI counts one edge");
+        assert!(config.filter <= 15, "input filter out of range");
+
+        let timer = Timer::new(tim);
+        T::remap(&[pin.remaps()]);
+        unsafe { pin.set_as_af_pull(AFType::Input, pull) };
+
+        let mut smcfg = ((config.filter as u32) << SMCFG_ETFC_OFFSET)
+            | ((config.prescaler as u32) << SMCFG_ETPSC_OFFSET)
+            | SMCFG_SMC1;
+        if config.edge == Edge::Falling {
+            smcfg |= SMCFG_ETP;
+        }
+        regs::<T>().modify(SMCFG, smcfg, SMCFG_ETFC | SMCFG_ETPSC | SMCFG_ETP);
+
+        Self::start(timer, pin.map_into())
+    }
+}
+
+impl<'d, T: SlaveInstance + CaptureCompareInstance> ExternalCounter<'d, T> {
+    /// Count `edge` of channel 0's input on `pin`, filtered with `filter` from 0 for none to 15,
+    /// see `CH0CAPFLT` in `TIMER_CHCTL0`.
+    ///
+    /// The counter starts at 0, and counts up to 65535. Panics if the filter is above 15.
+    pub fn new_ch0(tim: impl Peripheral<P = T> + 'd, pin: CapturePin<'d, T, Ch0>, edge: Edge, filter: u8) -> Self {
+        // Both edges come from before the polarity selection.
+        let trigger = match edge {
+            Edge::Both => TriggerInput::Channel0Edge,
+            _ => TriggerInput::Channel0,
+        };
+        Self::new_channel(tim, pin.remaps, pin.configure(), Channel::Ch0, edge, filter, trigger)
+    }
+
+    /// Count `edge` of channel 1's input on `pin`, like [`ExternalCounter::new_ch0`]. Panics if
+    /// the edge is [`Edge::Both`], or the filter is above 15.
+    pub fn new_ch1(tim: impl Peripheral<P = T> + 'd, pin: CapturePin<'d, T, Ch1>, edge: Edge, filter: u8) -> Self {
+        assert!(edge != Edge::Both, "channel 1 counts one edge");
+        Self::new_channel(
+            tim,
+            pin.remaps,
+            pin.configure(),
+            Channel::Ch1,
+            edge,
+            filter,
+            TriggerInput::Channel1,
+        )
+    }
+
+    fn new_channel(
+        tim: impl Peripheral<P = T> + 'd,
+        remaps: RemapSet,
+        pin: PeripheralRef<'d, AnyPin>,
+        channel: Channel,
+        edge: Edge,
+        filter: u8,
+        trigger: TriggerInput,
+    ) -> Self {
+        assert!(filter <= 15, "input filter out of range");
+        let mut timer = Timer::new(tim);
+        T::remap(&[remaps]);
+
+        let r = regs::<T>();
+        let (chctl, shift) = channel.chctl();
+        r.modify(
+            chctl,
+            (CHMS_INPUT | ((filter as u32) << CHCTL_CHCAPFLT_OFFSET)) << shift,
+            (CHCTL_CHMS | CHCTL_CHCAPPSC | CHCTL_CHCAPFLT) << shift,
+        );
+        if edge == Edge::Falling {
+            r.modify(CHCTL2, CHCTL2_CHP << (4 * channel as u32), 0);
+        }
+        timer.set_slave_mode(SlaveMode::ExternalClock, trigger);
+
+        Self::start(timer, pin)
+    }
+}
+
+impl<'d, T: SlaveInstance> ExternalCounter<'d, T> {
+    fn start(mut timer: Timer<'d, T>, pin: PeripheralRef<'d, AnyPin>) -> Self {
+        timer.set_auto_reload(u16::MAX);
+        timer.start();
+        Self { timer, pin }
+    }
+
+    /// The number of pulses counted, since the counter was last reset or wrapped.
+    pub fn count(&self) -> u16 {
+        self.timer.counter()
+    }
+
+    /// Set the number of pulses counted.
+    pub fn set_count(&mut self, count: u16) {
+        self.timer.set_counter(count)
+    }
+
+    /// Set the number of pulses after which the counter wraps to 0, from 1 to 65536. A
+    /// [`ExternalCounter::wait_for_limit`] in progress ends when the new limit is reached.
+    pub fn set_limit(&mut self, limit: u32) {
+        assert!((1..=1 << 16).contains(&limit), "counter limit out of range");
+        self.timer.set_auto_reload((limit - 1) as u16)
+    }
+
+    /// Wait until the counter wraps to 0 at the limit, see [`ExternalCounter::set_limit`].
+    pub async fn wait_for_limit(&mut self) {
+        self.timer.wait_for_update().await
+    }
+
+    /// Start counting, after [`ExternalCounter::stop`].
+    pub fn resume(&mut self) {
+        self.timer.start()
+    }
+
+    /// Stop counting, keeping the count.
+    pub fn stop(&mut self) {
+        self.timer.stop()
+    }
+}
+
+impl<'d, T: SlaveInstance> Drop for ExternalCounter<'d, T> {
+    fn drop(&mut self) {
+        unsafe { self.pin.set_as_disconnected() };
+    }
+}
@@ -0,0 +1,256 @@
+//! Timestamps of input edges
+//!
+//! [`InputCapture`] runs the counter of a timer freely, and captures its value on the edges of
+//! the channel inputs. The overflows of the counter are counted by the interrupt handler, which
+//! extends the captures to 64-bit timestamps. These only wrap after 2^48 ticks.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::gpio::Pull;
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::input_capture::{CapturePin, Edge, InputCapture};
+//! use embassy_gd32::timer::Channel;
+//!
+//! let pin = CapturePin::new_ch0(p.PA6, Pull::Down);
+//! let mut capture = InputCapture::new(p.TIMER2, Some(pin), None, None, None, Hertz::mhz(1));
+//! capture.enable(Channel::Ch0, Edge::Both);
+//! loop {
+//!     let rise = capture.next_capture(Channel::Ch0).await;
+//!     let fall = capture.next_capture(Channel::Ch0).await;
+//!     let width_us = fall.wrapping_sub(rise);
+//! }
+//! # }
+//! ```
+//!
+//! The interrupt handler reads the capture of an awaited edge and extends it right away, so the
+//! timestamp is right however late the task runs, as long as the interrupt is handled less than
+//! 65536 ticks after the edge. Edges that occur before the task takes the timestamp are lost,
+//! see [`InputCapture::overcaptured`].
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::low_level::Timer;
+use super::simple_pwm::{Ch0, Ch1, Ch2, Ch3};
+use super::*;
+use crate::afio::RemapSet;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Pull};
+use crate::time::Hertz;
+
+/// Input pin of channel `C` of timer `T`
+pub struct CapturePin<'d, T, C> {
+    pin: PeripheralRef<'d, AnyPin>,
+    pub(super) remaps: RemapSet,
+    pull: Pull,
+    _phantom: PhantomData<(T, C)>,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $pin_trait:ident) => {
+        impl<'d, T: CaptureCompareInstance> CapturePin<'d, T, $channel> {
+            /// Use `pin` as the channel's input, with `pull`. The pin is configured by
+            /// [`InputCapture::new`].
+            pub fn $new_chx(pin: impl Peripheral<P = impl $pin_trait<T>> + 'd, pull: Pull) -> Self {
+                into_ref!(pin);
+                let remaps = pin.remaps();
+                Self {
+                    pin: pin.map_into(),
+                    remaps,
+                    pull,
+                    _phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+impl<'d, T, C> CapturePin<'d, T, C> {
+    /// Configure the pin as an input, and take it.
+    pub(super) fn configure(self) -> PeripheralRef<'d, AnyPin> {
+        unsafe { self.pin.set_as_af_pull(AFType::Input, self.pull) };
+        self.pin
+    }
+}
+
+channel_impl!(new_ch0, Ch0, Channel0Pin);
+channel_impl!(new_ch1, Ch1, Channel1Pin);
+channel_impl!(new_ch2, Ch2, Channel2Pin);
+channel_impl!(new_ch3, Ch3, Channel3Pin);
+
+/// Edges of an input that are captured, see `CHxP` and `CHxNP` in `TIMER_CHCTL2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    /// Rising edges
+    Rising,
+    /// Falling edges
+    Falling,
+    /// Rising and falling edges
+    Both,
+}
+
+/// Input capture driver
+pub struct InputCapture<'d, T: CaptureCompareInstance> {
+    timer: Timer<'d, T>,
+    pins: [Option<PeripheralRef<'d, AnyPin>>; 4],
+}
+
+impl<'d, T: CaptureCompareInstance> InputCapture<'d, T> {
+    /// Create an input capture driver with inputs on the given pins, whose counter counts at
+    /// `freq`, rounded.
+    ///
+    /// All channels are disabled. Panics like [`Timer::set_tick_frequency`].
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        ch0: Option<CapturePin<'d, T, Ch0>>,
+        ch1: Option<CapturePin<'d, T, Ch1>>,
+        ch2: Option<CapturePin<'d, T, Ch2>>,
+        ch3: Option<CapturePin<'d, T, Ch3>>,
+        freq: Hertz,
+    ) -> Self {
+        let mut timer = Timer::new(tim);
+
+        // `ALL` leaves the layout to the other pins.
+        let remaps = [
+            ch0.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch1.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch2.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch3.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+        ];
+        T::remap(&remaps);
+        let pins = [
+            ch0.map(CapturePin::configure),
+            ch1.map(CapturePin::configure),
+            ch2.map(CapturePin::configure),
+            ch3.map(CapturePin::configure),
+        ];
+
+        timer.set_tick_frequency(freq);
+        timer.set_auto_reload(u16::MAX);
+
+        let state = T::state();
+        state.overflows.store(0, Ordering::Relaxed);
+        state.count_overflows.store(true, Ordering::Relaxed);
+        regs::<T>().modify(DMAINTEN, DMAINTEN_UPIE, 0);
+        timer.start();
+
+        Self { timer, pins }
+    }
+
+    /// Frequency of the counter, the resolution of the timestamps.
+    pub fn frequency(&self) -> Hertz {
+        Hertz(T::frequency().0 / (self.timer.prescaler() as u32 + 1))
+    }
+
+    /// The current time, in counter ticks since the driver was created.
+    pub fn now(&self) -> u64 {
+        critical_section::with(|_| extended_now::<T>()).0
+    }
+
+    /// Set the digital filter of `channel`'s input, from 0 for none to 15, see `CHxCAPFLT` in
+    /// `TIMER_CHCTLx`. Only takes effect when the channel is enabled.
+    pub fn set_input_filter(&mut self, channel: Channel, filter: u8) {
+        assert!(filter <= 15, "input filter out of range");
+        let (chctl, shift) = channel.chctl();
+        regs::<T>().modify(
+            chctl,
+            ((filter as u32) << CHCTL_CHCAPFLT_OFFSET) << shift,
+            CHCTL_CHCAPFLT << shift,
+        );
+    }
+
+    /// Capture the counter on `edge` of `channel`'s input.
+    pub fn enable(&mut self, channel: Channel, edge: Edge) {
+        let r = regs::<T>();
+        let shift = 4 * channel as u32;
+        // The channel's mode can only be changed while it's disabled.
+        r.modify(CHCTL2, 0, (CHCTL2_CHEN | CHCTL2_CHP | CHCTL2_CHNP) << shift);
+
+        let (chctl, chctl_shift) = channel.chctl();
+        r.modify(
+            chctl,
+            CHMS_INPUT << chctl_shift,
+            (CHCTL_CHMS | CHCTL_CHCAPPSC) << chctl_shift,
+        );
+
+        let polarity = match edge {
+            Edge::Rising => 0,
+            Edge::Falling => CHCTL2_CHP,
+            Edge::Both => CHCTL2_CHP | CHCTL2_CHNP,
+        };
+        r.modify(CHCTL2, (CHCTL2_CHEN | polarity) << shift, 0);
+    }
+
+    /// Stop capturing on `channel`.
+    pub fn disable(&mut self, channel: Channel) {
+        regs::<T>().modify(CHCTL2, 0, CHCTL2_CHEN << (4 * channel as u32));
+    }
+
+    /// Whether `channel` captures.
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        regs::<T>().read(CHCTL2) & (CHCTL2_CHEN << (4 * channel as u32)) != 0
+    }
+
+    /// Whether an edge of `channel` was lost since the last capture was read, because another
+    /// one was captured before its timestamp was taken. Clears the flag.
+    pub fn overcaptured(&mut self, channel: Channel) -> bool {
+        let r = regs::<T>();
+        let flag = INTF_CH0OF << channel as u32;
+        let set = r.read(INTF) & flag != 0;
+        r.write(INTF, !flag);
+        set
+    }
+
+    /// Wait for the next captured edge of `channel`, and return its timestamp in counter ticks
+    /// since the driver was created.
+    pub async fn next_timestamp(&mut self, channel: Channel) -> u64 {
+        let r = regs::<T>();
+        let flag = INTF_CH0IF << channel as u32;
+        let captured = &T::state().captures[channel as usize];
+        // Edges captured before the call are skipped.
+        critical_section::with(|cs| {
+            r.write(INTF, !(flag | (INTF_CH0OF << channel as u32)));
+            captured.borrow(cs).set(None);
+        });
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            critical_section::with(|cs| {
+                // Extended by the interrupt handler.
+                if let Some(timestamp) = captured.borrow(cs).take() {
+                    return Poll::Ready(timestamp);
+                }
+                if r.read(INTF) & flag == 0 {
+                    // The interrupt handler disables the interrupt again.
+                    r.modify(DMAINTEN, flag, 0);
+                    return Poll::Pending;
+                }
+
+                // Captured before the interrupt was enabled, i.e. since the call. Reading the
+                // capture clears the flag.
+                Poll::Ready(extend_capture::<T>(r.read(channel.chcv())))
+            })
+        })
+        .await
+    }
+
+    /// Wait for the next captured edge of `channel`, and return the low 32 bits of its
+    /// timestamp, see [`InputCapture::next_timestamp`]. The difference of two captures is
+    /// right as long as they are less than 2^32 ticks apart.
+    pub async fn next_capture(&mut self, channel: Channel) -> u32 {
+        self.next_timestamp(channel).await as u32
+    }
+}
+
+impl<'d, T: CaptureCompareInstance> Drop for InputCapture<'d, T> {
+    fn drop(&mut self) {
+        T::state().count_overflows.store(false, Ordering::Relaxed);
+        for pin in self.pins.iter().flatten() {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
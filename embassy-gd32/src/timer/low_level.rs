@@ -0,0 +1,308 @@
+//! Direct control of a timer's counter
+//!
+//! [`Timer`] owns a timer and exposes its counter, prescaler and auto-reload value, the update
+//! event and the trigger connections between timers. It's the base of the other timer drivers,
+//! and can be used directly for what they don't cover.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::low_level::Timer;
+//!
+//! let mut timer = Timer::new(p.TIMER5);
+//! timer.set_frequency(Hertz(1_000));
+//! timer.start();
+//! loop {
+//!     timer.wait_for_update().await;
+//!     // Runs once per millisecond.
+//! }
+//! # }
+//! ```
+//!
+//! The update event occurs when the counter overflows, and when [`Timer::reset`] or a trigger
+//! in [`SlaveMode::Restart`] resets it. Only overflows set the update interrupt flag, which
+//! [`Timer::wait_for_update`] waits for. [`Timer::update_events`] ticks with them, as a stream
+//! that doesn't need the `embassy-time` driver.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::*;
+use crate::time::Hertz;
+
+/// Source of the trigger output `TRGO`, see `MMC` in `TIMER_CTL1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MasterMode {
+    /// [`Timer::reset`] or a reset by the slave mode controller
+    Reset = 0,
+    /// The counter enable, e.g. to start slave timers together with this one
+    Enable = 1,
+    /// The update event
+    Update = 2,
+    /// A capture or compare match on channel 0
+    CaptureCompare0 = 3,
+    /// The output reference signal of channel 0
+    Compare0 = 4,
+    /// The output reference signal of channel 1
+    Compare1 = 5,
+    /// The output reference signal of channel 2
+    Compare2 = 6,
+    /// The output reference signal of channel 3
+    Compare3 = 7,
+}
+
+/// What the trigger input does to the counter, see `SMC` in `TIMER_SMCFG`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveMode {
+    /// The trigger input is ignored
+    Disabled = 0,
+    /// A rising edge of the trigger resets the counter, and generates an update event
+    Restart = 4,
+    /// The counter counts while the trigger is high
+    Pause = 5,
+    /// A rising edge of the trigger starts the counter
+    Event = 6,
+    /// The counter counts rising edges of the trigger instead of the timer clock
+    ExternalClock = 7,
+}
+
+/// Trigger input of the slave mode controller, see `TRGS` in `TIMER_SMCFG`
+///
+/// Which timer drives which internal trigger is listed in the user manual and by
+/// [`InternalTrigger`], e.g. ITI0 of TIMER2 is the trigger output of TIMER0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerInput {
+    /// Internal trigger 0, the trigger output of another timer
+    Internal0 = 0,
+    /// Internal trigger 1
+    Internal1 = 1,
+    /// Internal trigger 2
+    Internal2 = 2,
+    /// Internal trigger 3
+    Internal3 = 3,
+    /// Both edges of the channel 0 input
+    Channel0Edge = 4,
+    /// Filtered channel 0 input
+    Channel0 = 5,
+    /// Filtered channel 1 input
+    Channel1 = 6,
+    /// Filtered external trigger input `ETI`
+    External = 7,
+}
+
+/// Timer with direct access to its counter
+pub struct Timer<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: Instance> Timer<'d, T> {
+    /// Enable and reset the timer. The counter is stopped, and counts at the timer clock up to
+    /// the largest value.
+    pub fn new(peri: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(peri);
+
+        T::enable();
+        T::reset();
+        let r = regs::<T>();
+        // Only overflows raise the update interrupt, and auto-reload values take effect at the
+        // next update event.
+        r.write(CTL0, CTL0_UPS | CTL0_ARSE);
+        T::enable_interrupts();
+
+        Self { _peri: peri }
+    }
+
+    /// Frequency of the timer clock, before the prescaler.
+    pub fn clock_frequency(&self) -> Hertz {
+        T::frequency()
+    }
+
+    /// Set the prescaler and auto-reload value, so that the counter overflows at `freq`. The
+    /// counter counts as fast as possible for that.
+    ///
+    /// The new values take effect at the next update event, or right away if the counter is
+    /// stopped. Panics if `freq` is zero or above the timer clock.
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        assert!(freq.0 != 0, "timer frequency must not be zero");
+        let ticks = T::frequency().0 / freq.0;
+        assert!(ticks != 0, "timer frequency above the timer clock");
+
+        let psc = (ticks - 1) / (1 << 16);
+        let car = ticks / (psc + 1) - 1;
+        self.set_prescaler(psc as u16);
+        self.set_auto_reload(car as u16);
+    }
+
+    /// Set the prescaler, so that the counter counts at `freq`, rounded, up to the auto-reload
+    /// value.
+    ///
+    /// Takes effect like [`Timer::set_frequency`]. Panics if `freq` is zero, above the timer
+    /// clock, or below the timer clock divided by 65536.
+    pub fn set_tick_frequency(&mut self, freq: Hertz) {
+        assert!(freq.0 != 0, "timer frequency must not be zero");
+        let div = (T::frequency().0 + freq.0 / 2) / freq.0;
+        assert!((1..=1 << 16).contains(&div), "timer tick frequency out of range");
+        self.set_prescaler((div - 1) as u16);
+    }
+
+    /// Set the prescaler, which divides the timer clock by `psc + 1`. Takes effect like
+    /// [`Timer::set_frequency`].
+    pub fn set_prescaler(&mut self, psc: u16) {
+        let r = regs::<T>();
+        r.write(PSC, psc as u32);
+        if !self.is_running() {
+            // The update event loads the prescaler, and doesn't raise the interrupt with `UPS`.
+            r.write(SWEVG, SWEVG_UPG);
+        }
+    }
+
+    /// The prescaler, which divides the timer clock by its value plus one.
+    pub fn prescaler(&self) -> u16 {
+        regs::<T>().read(PSC) as u16
+    }
+
+    /// Set the auto-reload value, the largest counter value before it overflows. Takes effect
+    /// like [`Timer::set_frequency`].
+    pub fn set_auto_reload(&mut self, car: u16) {
+        let r = regs::<T>();
+        r.write(CAR, car as u32);
+        if !self.is_running() {
+            r.write(SWEVG, SWEVG_UPG);
+        }
+    }
+
+    /// The auto-reload value.
+    pub fn auto_reload(&self) -> u16 {
+        regs::<T>().read(CAR) as u16
+    }
+
+    /// The counter value.
+    pub fn counter(&self) -> u16 {
+        regs::<T>().read(CNT) as u16
+    }
+
+    /// Set the counter value.
+    pub fn set_counter(&mut self, value: u16) {
+        regs::<T>().write(CNT, value as u32)
+    }
+
+    /// Start counting.
+    pub fn start(&mut self) {
+        regs::<T>().modify(CTL0, CTL0_CEN, 0)
+    }
+
+    /// Stop counting, keeping the counter value.
+    pub fn stop(&mut self) {
+        regs::<T>().modify(CTL0, 0, CTL0_CEN)
+    }
+
+    /// Whether the counter is counting.
+    pub fn is_running(&self) -> bool {
+        regs::<T>().read(CTL0) & CTL0_CEN != 0
+    }
+
+    /// Reset the counter and load the prescaler and auto-reload value with an update event.
+    pub fn reset(&mut self) {
+        regs::<T>().write(SWEVG, SWEVG_UPG)
+    }
+
+    /// Wait for the next overflow of the counter.
+    pub async fn wait_for_update(&mut self) {
+        // The flag is cleared by writing zero, writing one has no effect.
+        regs::<T>().write(INTF, !INTF_UPIF);
+        poll_fn(poll_update::<T>).await
+    }
+
+    /// The overflows of the counter from now on, as a stream of ticks locked to the counter,
+    /// e.g. to run a control loop once per PWM period.
+    pub fn update_events(&mut self) -> UpdateEvents<'_, T> {
+        UpdateEvents::new()
+    }
+}
+
+/// Consume a pending update interrupt flag, or wait for it to be set.
+fn poll_update<T: Instance>(cx: &mut Context<'_>) -> Poll<()> {
+    let r = regs::<T>();
+    T::state().waker.register(cx.waker());
+    if r.read(INTF) & INTF_UPIF != 0 {
+        r.write(INTF, !INTF_UPIF);
+        return Poll::Ready(());
+    }
+    // The interrupt handler disables the interrupt again.
+    critical_section::with(|_| r.modify(DMAINTEN, DMAINTEN_UPIE, 0));
+    Poll::Pending
+}
+
+/// Stream of the update events of a timer, see [`Timer::update_events`]
+///
+/// Unlike repeated calls of [`Timer::wait_for_update`], an overflow that happens while the task
+/// is busy between two ticks isn't lost, the next tick is ready right away. Several overflows in
+/// that time are a single tick though, a task that must not miss any should use
+/// [`UpdateEvents::is_late`].
+pub struct UpdateEvents<'a, T: Instance> {
+    _timer: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Instance> UpdateEvents<'a, T> {
+    fn new() -> Self {
+        regs::<T>().write(INTF, !INTF_UPIF);
+        Self { _timer: PhantomData }
+    }
+
+    /// Wait for the next update event.
+    pub async fn next(&mut self) {
+        poll_fn(poll_update::<T>).await
+    }
+
+    /// Whether an update event has happened since the last tick, i.e. the next tick is ready.
+    pub fn is_late(&self) -> bool {
+        regs::<T>().read(INTF) & INTF_UPIF != 0
+    }
+}
+
+impl<'a, T: Instance> Unpin for UpdateEvents<'a, T> {}
+
+impl<'a, T: Instance> futures::Stream for UpdateEvents<'a, T> {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        poll_update::<T>(cx).map(Some)
+    }
+}
+
+impl<'d, T: MasterInstance> Timer<'d, T> {
+    /// Select the source of the trigger output, which other timers, the ADCs and the DAC can
+    /// use.
+    pub fn set_master_mode(&mut self, mode: MasterMode) {
+        regs::<T>().modify(CTL1, (mode as u32) << CTL1_MMC_OFFSET, CTL1_MMC)
+    }
+}
+
+impl<'d, T: SlaveInstance> Timer<'d, T> {
+    /// Control the counter with `trigger`, e.g. to start it with another timer.
+    pub fn set_slave_mode(&mut self, mode: SlaveMode, trigger: TriggerInput) {
+        let r = regs::<T>();
+        // The trigger must be selected while the slave mode is disabled.
+        r.modify(SMCFG, 0, SMCFG_SMC);
+        r.modify(SMCFG, (trigger as u32) << SMCFG_TRGS_OFFSET, SMCFG_TRGS);
+        r.modify(SMCFG, mode as u32, SMCFG_SMC);
+    }
+}
+
+impl<'d, T: Instance> Drop for Timer<'d, T> {
+    fn drop(&mut self) {
+        // The interrupt stays enabled in the NVIC, some timers share theirs.
+        let r = regs::<T>();
+        r.write(CTL0, 0);
+        r.write(DMAINTEN, 0);
+        T::disable();
+    }
+}
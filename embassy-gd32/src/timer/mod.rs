@@ -0,0 +1,448 @@
+//! Timers
+//!
+//! The GD32E503 has three kinds of timers, which share the register layout, each kind only
+//! having a subset of the registers and features:
+//!
+//! - the advanced timers TIMER0 and TIMER7, with four channels, complementary outputs and a
+//!   repetition counter,
+//! - the general timers TIMER1 to TIMER4 with four channels, TIMER8 and TIMER11 with two channels,
+//!   and TIMER9, TIMER10, TIMER12 and TIMER13 with one,
+//! - the basic timers TIMER5 and TIMER6, which only count, e.g. to trigger the DAC.
+//!
+//! All timers count at the frequency of their APB timer clock divided by a prescaler, up to an
+//! auto-reload value. [`low_level::Timer`] gives access to these parts, and the other drivers of
+//! this module are built on it. Timers that can trigger other peripherals implement
+//! [`MasterInstance`], those that can be started, paused or reset by a trigger
+//! [`SlaveInstance`].
+//!
+//! [`simple_pwm::SimplePwm`] generates PWM signals on the channels of a timer, and
+//! [`complementary_pwm::ComplementaryPwm`] pairs of complementary signals with dead time on the
+//! advanced timers. [`input_capture::InputCapture`] timestamps the edges of input signals, and
+//! [`qei::Qei`] counts the steps of a quadrature encoder. [`one_pulse::OnePulse`] generates
+//! single pulses timed by the hardware, and [`delay::Delay`] waits for short delays without the
+//! `embassy-time` driver. [`chained::ChainedTimer`] chains two timers into a 32-bit counter, and
+//! [`external_clock::ExternalCounter`] counts external pulses.
+//!
+//! The timer used by the `timedriver-timer*` features must not be used with these drivers.
+#![macro_use]
+
+use core::cell::Cell;
+
+use atomic_polyfill::{AtomicBool, AtomicU32, Ordering};
+use critical_section::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::Interrupt;
+use crate::{interrupt, peripherals, Peripheral};
+
+pub mod chained;
+pub mod complementary_pwm;
+pub mod delay;
+pub mod external_clock;
+pub mod input_capture;
+pub mod low_level;
+pub mod one_pulse;
+pub mod qei;
+pub mod simple_pwm;
+
+pub struct State {
+    waker: AtomicWaker,
+    /// Whether the interrupt handler counts the overflows of the counter, for the drivers that
+    /// extend it in software
+    count_overflows: AtomicBool,
+    overflows: AtomicU32,
+    /// Captures of the channels, extended with the overflow count by the interrupt handler while
+    /// it counts the overflows
+    captures: [Mutex<Cell<Option<u64>>>; 4],
+}
+
+const CAPTURE_NEW: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: AtomicWaker::new(),
+            count_overflows: AtomicBool::new(false),
+            overflows: AtomicU32::new(0),
+            captures: [CAPTURE_NEW; 4],
+        }
+    }
+}
+
+/// The counter extended with the overflow count, and the overflow count it's based on. Must be
+/// called in a critical section, so the interrupt handler doesn't count an overflow in between.
+fn extended_now<T: Instance>() -> (u64, u32) {
+    let r = regs::<T>();
+    let cnt = r.read(CNT);
+    let mut overflows = T::state().overflows.load(Ordering::Relaxed);
+    // An overflow the interrupt handler hasn't counted yet. It happened before `cnt` was read
+    // if `cnt` is small.
+    if r.read(INTF) & INTF_UPIF != 0 && cnt < 0x8000 {
+        overflows += 1;
+    }
+    (((overflows as u64) << 16) | cnt as u64, overflows)
+}
+
+/// Extend `capture` with the overflow count, assuming it was captured less than 65536 ticks ago.
+/// Must be called in a critical section, like [`extended_now`].
+fn extend_capture<T: Instance>(capture: u32) -> u64 {
+    let (now, overflows) = extended_now::<T>();
+    // The counter has overflowed since the capture if it's now below it.
+    let overflows = match capture <= now as u32 & 0xFFFF {
+        true => overflows,
+        false => overflows.wrapping_sub(1),
+    };
+    ((overflows as u64) << 16) | capture as u64
+}
+
+/// Capture/compare channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    /// Channel 0
+    Ch0 = 0,
+    /// Channel 1
+    Ch1 = 1,
+    /// Channel 2
+    Ch2 = 2,
+    /// Channel 3
+    Ch3 = 3,
+}
+
+impl Channel {
+    /// Offset of the channel's `TIMER_CHCTLx` register, and the channel's shift in it.
+    fn chctl(self) -> (usize, u32) {
+        let n = self as usize;
+        (CHCTL0 + 4 * (n / 2), 8 * (n as u32 % 2))
+    }
+
+    /// Offset of the channel's `TIMER_CHxCV` register.
+    fn chcv(self) -> usize {
+        CH0CV + 4 * self as usize
+    }
+}
+
+/// Type-level [`Channel`], such as [`simple_pwm::Ch0`]
+pub trait ChannelMarker: sealed::ChannelMarker {}
+
+/// Registers of an instance, accessed by offset, as each kind of timer has its own PAC register
+/// block for the same layout
+#[derive(Clone, Copy)]
+struct Regs(usize);
+
+impl Regs {
+    fn read(self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the `set` bits and clear the `clear` bits of the register at `offset`.
+    fn modify(self, offset: usize, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set)
+    }
+}
+
+fn regs<T: Instance>() -> Regs {
+    Regs(T::base())
+}
+
+pub(crate) mod sealed {
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        /// Whether the timer is an advanced one, whose outputs are gated by `POEN`
+        const ADVANCED: bool;
+
+        fn base() -> usize;
+        fn state() -> &'static super::State;
+
+        /// Select the AFIO layout of the pins, see [`crate::afio::remap_for_pins`].
+        fn remap(pins: &[crate::afio::RemapSet]);
+
+        /// Unpend and enable the interrupts of the timer in the NVIC. The advanced timers have
+        /// separate interrupts for the break, the channels and the trigger.
+        fn enable_interrupts();
+    }
+
+    pub trait ChannelMarker {
+        const CHANNEL: super::Channel;
+    }
+
+    pub trait InternalTrigger<M> {
+        /// The internal trigger input that `M`'s trigger output is wired to
+        const INPUT: super::low_level::TriggerInput;
+    }
+}
+
+/// Timer instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {
+    /// The interrupt of the update event, which may be shared with another timer
+    type Interrupt: Interrupt;
+}
+
+/// Timer with a trigger output to other timers, the ADCs or the DAC, see `MMC` in `TIMER_CTL1`
+pub trait MasterInstance: Instance {}
+
+/// Timer with a slave mode controller, see `TIMER_SMCFG`
+pub trait SlaveInstance: Instance {}
+
+/// Timer with capture/compare channels, at least channel 0
+pub trait CaptureCompareInstance: Instance {}
+
+/// Timer with a quadrature decoder on the inputs of channels 0 and 1, and channels 2 and 3, see
+/// `SMC` in `TIMER_SMCFG`
+pub trait EncoderInstance: CaptureCompareInstance + SlaveInstance {}
+
+/// Timer with an external trigger input `ETI`, see `TIMER_SMCFG`
+pub trait ExternalTriggerInstance: SlaveInstance {}
+
+/// Advanced timer, with complementary outputs, dead-time insertion and a break input
+pub trait AdvancedInstance: CaptureCompareInstance + MasterInstance + SlaveInstance + EncoderInstance {}
+
+/// Slave timer whose internal trigger inputs include the trigger output of `M`, see
+/// [`low_level::TriggerInput`]
+pub trait InternalTrigger<M: MasterInstance>: SlaveInstance + sealed::InternalTrigger<M> {}
+
+pin_trait!(Channel0Pin, CaptureCompareInstance);
+pin_trait!(Channel1Pin, CaptureCompareInstance);
+pin_trait!(Channel2Pin, CaptureCompareInstance);
+pin_trait!(Channel3Pin, CaptureCompareInstance);
+pin_trait!(Channel0ComplementaryPin, AdvancedInstance);
+pin_trait!(Channel1ComplementaryPin, AdvancedInstance);
+pin_trait!(Channel2ComplementaryPin, AdvancedInstance);
+pin_trait!(BreakInputPin, AdvancedInstance);
+pin_trait!(ExternalTriggerPin, ExternalTriggerInstance);
+
+dma_trait!(UpDma, Instance);
+dma_trait!(CcDma, CaptureCompareInstance, ChannelMarker);
+
+/// Wake the task waiting for the timer's interrupt.
+unsafe fn on_interrupt<T: Instance>() {
+    // The waiting task enables the interrupts again. Disabling them here keeps flags that are
+    // only cleared by the task from firing the interrupt over and over.
+    let r = regs::<T>();
+    let state = T::state();
+    let mut pending = r.read(INTF) & r.read(DMAINTEN) & DMAINTEN_IE;
+    if pending & INTF_UPIF != 0 && state.count_overflows.load(Ordering::Relaxed) {
+        // Overflows are counted here, and nobody waits for them.
+        r.write(INTF, !INTF_UPIF);
+        state.overflows.fetch_add(1, Ordering::Relaxed);
+        pending &= !INTF_UPIF;
+    }
+    if state.count_overflows.load(Ordering::Relaxed) {
+        // Extend the captures right away, the task may only run after the next overflow.
+        for ch in [Channel::Ch0, Channel::Ch1, Channel::Ch2, Channel::Ch3] {
+            if pending & (INTF_CH0IF << ch as u32) != 0 {
+                critical_section::with(|cs| {
+                    // Reading the capture clears the flag.
+                    let timestamp = extend_capture::<T>(r.read(ch.chcv()));
+                    state.captures[ch as usize].borrow(cs).set(Some(timestamp));
+                });
+            }
+        }
+    }
+    if pending != 0 {
+        r.modify(DMAINTEN, 0, pending);
+        state.waker.wake();
+    }
+}
+
+macro_rules! impl_irq {
+    ($e:ident, $($inst:ident),+) => {
+        #[interrupt]
+        unsafe fn $e() {
+            $(on_interrupt::<peripherals::$inst>();)+
+        }
+    };
+}
+
+impl_irq!(TIMER0_BRK_TIMER8, TIMER0, TIMER8);
+impl_irq!(TIMER0_UP_TIMER9, TIMER0, TIMER9);
+impl_irq!(TIMER0_TRG_CMT_TIMER10, TIMER0, TIMER10);
+impl_irq!(TIMER0_CHANNEL, TIMER0);
+#[cfg(not(feature = "timedriver-timer1"))]
+impl_irq!(TIMER1, TIMER1);
+#[cfg(not(feature = "timedriver-timer2"))]
+impl_irq!(TIMER2, TIMER2);
+#[cfg(not(feature = "timedriver-timer3"))]
+impl_irq!(TIMER3, TIMER3);
+#[cfg(not(feature = "timedriver-timer4"))]
+impl_irq!(TIMER4, TIMER4);
+impl_irq!(TIMER5, TIMER5);
+impl_irq!(TIMER6, TIMER6);
+impl_irq!(TIMER7_BRK_TIMER11, TIMER7, TIMER11);
+impl_irq!(TIMER7_UP_TIMER12, TIMER7, TIMER12);
+impl_irq!(TIMER7_TRG_CMT_TIMER13, TIMER7, TIMER13);
+impl_irq!(TIMER7_CHANNEL, TIMER7);
+
+macro_rules! impl_timer {
+    ($inst:ident, $irq:ident $(+ $other_irq:ident)*, $remap:ident, [$($kind:ident),*]) => {
+        impl_timer!(
+            @impl $inst, [$irq $(, $other_irq)*],
+            pins => crate::afio::remap_for_pins::<crate::afio::$remap>(pins),
+            [$($kind),*]
+        );
+    };
+    ($inst:ident, $irq:ident $(+ $other_irq:ident)*, [$($kind:ident),*]) => {
+        impl_timer!(
+            @impl $inst, [$irq $(, $other_irq)*],
+            pins => crate::afio::check_default_layout(pins),
+            [$($kind),*]
+        );
+    };
+    (@impl $inst:ident, [$irq:ident $(, $other_irq:ident)*], $pins:ident => $remap:expr, [$($kind:ident),*]) => {
+        impl crate::timer::sealed::Instance for peripherals::$inst {
+            const ADVANCED: bool = impl_timer!(@advanced $($kind)*);
+
+            fn base() -> usize {
+                crate::pac::$inst::ptr() as usize
+            }
+
+            fn state() -> &'static crate::timer::State {
+                static STATE: crate::timer::State = crate::timer::State::new();
+                &STATE
+            }
+
+            fn remap($pins: &[crate::afio::RemapSet]) {
+                $remap
+            }
+
+            fn enable_interrupts() {
+                use crate::interrupt::InterruptExt;
+                unsafe {
+                    let irq = crate::interrupt::$irq::steal();
+                    irq.unpend();
+                    irq.enable();
+                }
+                $(unsafe {
+                    let irq = crate::interrupt::$other_irq::steal();
+                    irq.unpend();
+                    irq.enable();
+                })*
+            }
+        }
+
+        impl crate::timer::Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::$irq;
+        }
+
+        $(impl crate::timer::$kind for peripherals::$inst {})*
+    };
+    (@advanced AdvancedInstance $($rest:ident)*) => {
+        true
+    };
+    (@advanced $first:ident $($rest:ident)*) => {
+        impl_timer!(@advanced $($rest)*)
+    };
+    (@advanced) => {
+        false
+    };
+}
+
+macro_rules! impl_internal_trigger {
+    ($slave:ident, { $($master:ident => $input:ident),* $(,)? }) => {
+        $(
+            impl crate::timer::sealed::InternalTrigger<peripherals::$master> for peripherals::$slave {
+                const INPUT: crate::timer::low_level::TriggerInput = crate::timer::low_level::TriggerInput::$input;
+            }
+
+            impl crate::timer::InternalTrigger<peripherals::$master> for peripherals::$slave {}
+        )*
+    };
+}
+
+// Register offsets
+const CTL0: usize = 0x00;
+const CTL1: usize = 0x04;
+const SMCFG: usize = 0x08;
+const DMAINTEN: usize = 0x0C;
+const INTF: usize = 0x10;
+const SWEVG: usize = 0x14;
+const CHCTL0: usize = 0x18;
+const CHCTL1: usize = 0x1C;
+const CHCTL2: usize = 0x20;
+const CNT: usize = 0x24;
+const PSC: usize = 0x28;
+const CAR: usize = 0x2C;
+const CH0CV: usize = 0x34;
+const CCHP: usize = 0x44;
+const DMACFG: usize = 0x48;
+const DMATB: usize = 0x4C;
+
+// TIMER_CTL0
+const CTL0_CEN: u32 = 1 << 0;
+const CTL0_UPS: u32 = 1 << 2;
+const CTL0_SPM: u32 = 1 << 3;
+const CTL0_DIR: u32 = 1 << 4;
+const CTL0_CAM_OFFSET: u32 = 5;
+const CTL0_CAM: u32 = 0b11 << 5;
+const CTL0_ARSE: u32 = 1 << 7;
+const CTL0_CKDIV_OFFSET: u32 = 8;
+const CTL0_CKDIV: u32 = 0b11 << 8;
+
+// TIMER_CTL1
+const CTL1_MMC_OFFSET: u32 = 4;
+const CTL1_MMC: u32 = 0b111 << 4;
+// The idle levels of channel `n` are `2 * n` bits above these
+const CTL1_ISO0: u32 = 1 << 8;
+const CTL1_ISO0N: u32 = 1 << 9;
+
+// TIMER_SMCFG
+const SMCFG_SMC: u32 = 0b111;
+const SMCFG_TRGS_OFFSET: u32 = 4;
+const SMCFG_TRGS: u32 = 0b111 << 4;
+const SMCFG_ETFC_OFFSET: u32 = 8;
+const SMCFG_ETFC: u32 = 0b1111 << 8;
+const SMCFG_ETPSC_OFFSET: u32 = 12;
+const SMCFG_ETPSC: u32 = 0b11 << 12;
+const SMCFG_SMC1: u32 = 1 << 14;
+const SMCFG_ETP: u32 = 1 << 15;
+
+// TIMER_CHCTL0 and TIMER_CHCTL1, the fields of the odd channels are 8 bits above these
+const CHCTL_CHMS: u32 = 0b11;
+const CHMS_INPUT: u32 = 0b01;
+const CHCTL_CHCAPPSC: u32 = 0b11 << 2;
+const CHCTL_CHCAPFLT_OFFSET: u32 = 4;
+const CHCTL_CHCAPFLT: u32 = 0b1111 << 4;
+const CHCTL_CHCOMSEN: u32 = 1 << 3;
+const CHCTL_CHCOMCTL_OFFSET: u32 = 4;
+const CHCTL_CHCOMCTL: u32 = 0b111 << 4;
+const CHCOMCTL_PWM0: u32 = 0b110;
+const CHCOMCTL_PWM1: u32 = 0b111;
+
+// TIMER_CHCTL2, the fields of channel `n` are `4 * n` bits above these
+const CHCTL2_CHEN: u32 = 1 << 0;
+const CHCTL2_CHP: u32 = 1 << 1;
+const CHCTL2_CHNEN: u32 = 1 << 2;
+const CHCTL2_CHNP: u32 = 1 << 3;
+
+// TIMER_CCHP
+const CCHP_DTCFG: u32 = 0xFF;
+const CCHP_IOS: u32 = 1 << 10;
+const CCHP_ROS: u32 = 1 << 11;
+const CCHP_BRKEN: u32 = 1 << 12;
+const CCHP_BRKP: u32 = 1 << 13;
+const CCHP_OAEN: u32 = 1 << 14;
+const CCHP_POEN: u32 = 1 << 15;
+
+// TIMER_DMAINTEN, the interrupt enables match the flags in TIMER_INTF
+const DMAINTEN_UPIE: u32 = 1 << 0;
+const DMAINTEN_IE: u32 = 0xFF;
+const DMAINTEN_UPDEN: u32 = 1 << 8;
+// The request enable of channel `n` is `n` bits above this
+const DMAINTEN_CH0DEN: u32 = 1 << 9;
+
+// TIMER_DMACFG
+const DMACFG_DMATA_OFFSET: u32 = 0;
+const DMACFG_DMATC_OFFSET: u32 = 8;
+
+// TIMER_INTF
+const INTF_UPIF: u32 = 1 << 0;
+const INTF_BRKIF: u32 = 1 << 7;
+// The flags of channel `n` are `n` bits above these
+const INTF_CH0IF: u32 = 1 << 1;
+const INTF_CH0OF: u32 = 1 << 9;
+
+// TIMER_SWEVG
+const SWEVG_UPG: u32 = 1 << 0;
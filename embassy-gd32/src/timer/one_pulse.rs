@@ -0,0 +1,196 @@
+//! Single pulses timed by the hardware
+//!
+//! [`OnePulse`] runs the counter of a timer once per pulse, in single pulse mode: the output of a
+//! channel goes active `delay` ticks after the pulse is fired, and inactive again `width` ticks
+//! later, when the counter stops by itself. Neither edge depends on software timing, so pulses
+//! are exact for camera strobes or gate drivers.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::one_pulse::OnePulse;
+//! use embassy_gd32::timer::simple_pwm::PwmPin;
+//!
+//! let strobe = PwmPin::new_ch0(p.PA6);
+//! let mut pulse = OnePulse::new(p.TIMER2, strobe, Hertz::mhz(1));
+//! // Active from 10 µs to 60 µs after firing.
+//! pulse.set_pulse(10, 50);
+//! pulse.fire();
+//! pulse.wait_for_end().await;
+//! # }
+//! ```
+//!
+//! A pulse can also be fired by the hardware, by the trigger output of another timer or an edge
+//! on channel 0's input, see [`OnePulse::arm`] and [`OnePulse::arm_on_input`]. The EXTI lines
+//! can't trigger timers, an input signal must be connected to channel 0 instead.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::PeripheralRef;
+
+use super::input_capture::{CapturePin, Edge};
+use super::low_level::{SlaveMode, Timer, TriggerInput};
+use super::simple_pwm::{Ch0, Polarity, PwmPin};
+use super::*;
+use crate::afio::RemapSet;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::AnyPin;
+use crate::time::Hertz;
+
+/// One-pulse driver
+pub struct OnePulse<'d, T: CaptureCompareInstance> {
+    timer: Timer<'d, T>,
+    channel: Channel,
+    pin: PeripheralRef<'d, AnyPin>,
+    remaps: RemapSet,
+    trigger_pin: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d, T: CaptureCompareInstance> OnePulse<'d, T> {
+    /// Create a one-pulse driver with the output on `pin`, whose counter counts at `freq`,
+    /// rounded.
+    ///
+    /// The pulse has a delay and a width of one tick, and [`Polarity::ActiveHigh`]. Panics like
+    /// [`Timer::set_tick_frequency`].
+    pub fn new<C: ChannelMarker>(tim: impl Peripheral<P = T> + 'd, pin: PwmPin<'d, T, C>, freq: Hertz) -> Self {
+        let mut timer = Timer::new(tim);
+        let channel = C::CHANNEL;
+
+        T::remap(&[pin.remaps]);
+        unsafe { pin.pin.set_as_af(AFType::OutputPushPull) };
+
+        let r = regs::<T>();
+        r.modify(CTL0, CTL0_SPM, 0);
+        // PWM mode 1 is inactive until the counter reaches the delay. The compare value is
+        // buffered, so a pulse in progress isn't changed.
+        let (chctl, shift) = channel.chctl();
+        r.modify(
+            chctl,
+            ((CHCOMCTL_PWM1 << CHCTL_CHCOMCTL_OFFSET) | CHCTL_CHCOMSEN) << shift,
+            (CHCTL_CHMS | CHCTL_CHCOMCTL | CHCTL_CHCOMSEN) << shift,
+        );
+        r.modify(CHCTL2, CHCTL2_CHEN << (4 * channel as u32), 0);
+        if T::ADVANCED {
+            r.modify(CCHP, CCHP_POEN, 0);
+        }
+
+        timer.set_tick_frequency(freq);
+        let mut this = Self {
+            timer,
+            channel,
+            pin: pin.pin,
+            remaps: pin.remaps,
+            trigger_pin: None,
+        };
+        this.set_pulse(1, 1);
+        this
+    }
+
+    /// Frequency of the counter, the unit of the delay and width of the pulse.
+    pub fn frequency(&self) -> Hertz {
+        Hertz(T::frequency().0 / (self.timer.prescaler() as u32 + 1))
+    }
+
+    /// Set the delay from firing to the start of the pulse, and the width of the pulse, in ticks.
+    /// A pulse in progress isn't changed.
+    ///
+    /// Panics if `delay` or `width` is 0, or their sum is above 65536.
+    pub fn set_pulse(&mut self, delay: u16, width: u16) {
+        assert!(delay != 0, "pulse delay must not be zero");
+        assert!(width != 0, "pulse width must not be zero");
+        let car = delay as u32 + width as u32 - 1;
+        assert!(car <= 0xFFFF, "pulse delay and width too long");
+
+        regs::<T>().write(self.channel.chcv(), delay as u32);
+        // Loads the compare value too if the counter is stopped.
+        self.timer.set_auto_reload(car as u16);
+    }
+
+    /// Set the level of the output during the pulse.
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        let bit = CHCTL2_CHP << (4 * self.channel as u32);
+        match polarity {
+            Polarity::ActiveHigh => regs::<T>().modify(CHCTL2, 0, bit),
+            Polarity::ActiveLow => regs::<T>().modify(CHCTL2, bit, 0),
+        }
+    }
+
+    /// Fire a pulse. Has no effect while a pulse is in progress.
+    pub fn fire(&mut self) {
+        self.timer.start()
+    }
+
+    /// Whether a pulse is in progress, from firing to its end.
+    pub fn is_busy(&self) -> bool {
+        self.timer.is_running()
+    }
+
+    /// Wait for the end of the pulse in progress. Returns right away if there's none.
+    pub async fn wait_for_end(&mut self) {
+        let r = regs::<T>();
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            // The counter stops with an overflow, which sets the flag if it happens after this.
+            r.write(INTF, !INTF_UPIF);
+            if !self.timer.is_running() {
+                return Poll::Ready(());
+            }
+            // The interrupt handler disables the interrupt again.
+            critical_section::with(|_| r.modify(DMAINTEN, DMAINTEN_UPIE, 0));
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Wait for the end of the next pulse, which may be fired by the hardware.
+    pub async fn wait_for_pulse(&mut self) {
+        self.timer.wait_for_update().await
+    }
+}
+
+impl<'d, T: CaptureCompareInstance + SlaveInstance> OnePulse<'d, T> {
+    /// Fire a pulse on each rising edge of `trigger`, e.g. the trigger output of another timer.
+    /// Edges during a pulse are ignored.
+    ///
+    /// The inputs of the channels and `ETI` need their pin configured, see
+    /// [`OnePulse::arm_on_input`].
+    pub fn arm(&mut self, trigger: TriggerInput) {
+        self.timer.set_slave_mode(SlaveMode::Event, trigger)
+    }
+
+    /// Fire a pulse on each `edge` of channel 0's input on `pin`. Edges during a pulse are
+    /// ignored. Panics if the output is on channel 0, or `edge` is [`Edge::Both`].
+    pub fn arm_on_input(&mut self, pin: CapturePin<'d, T, Ch0>, edge: Edge) {
+        assert!(self.channel != Channel::Ch0, "output on the trigger channel");
+        assert!(edge != Edge::Both, "one pulse trigger on both edges");
+        let r = regs::<T>();
+        // The channel's mode and polarity can only be changed while it's disabled.
+        r.modify(CHCTL2, 0, CHCTL2_CHEN | CHCTL2_CHP | CHCTL2_CHNP);
+        r.modify(CHCTL0, CHMS_INPUT, CHCTL_CHMS | CHCTL_CHCAPPSC);
+        if edge == Edge::Falling {
+            r.modify(CHCTL2, CHCTL2_CHP, 0);
+        }
+
+        T::remap(&[self.remaps, pin.remaps]);
+        if let Some(old) = self.trigger_pin.replace(pin.configure()) {
+            unsafe { old.set_as_disconnected() };
+        }
+        self.timer.set_slave_mode(SlaveMode::Event, TriggerInput::Channel0);
+    }
+
+    /// Stop firing pulses by the hardware. A pulse in progress isn't stopped.
+    pub fn disarm(&mut self) {
+        self.timer.set_slave_mode(SlaveMode::Disabled, TriggerInput::Internal0)
+    }
+}
+
+impl<'d, T: CaptureCompareInstance> Drop for OnePulse<'d, T> {
+    fn drop(&mut self) {
+        unsafe { self.pin.set_as_disconnected() };
+        if let Some(pin) = &self.trigger_pin {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
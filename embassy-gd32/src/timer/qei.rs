@@ -0,0 +1,149 @@
+//! Quadrature encoder interface
+//!
+//! [`Qei`] counts the steps of a quadrature encoder, e.g. of a motor shaft or a rotary knob,
+//! whose two signals are on the inputs of channels 0 and 1. The counter counts up or down with
+//! each step, depending on which signal leads, and wraps between 0 and 65535.
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::gpio::Pull;
+//! use embassy_gd32::timer::input_capture::CapturePin;
+//! use embassy_gd32::timer::qei::{EncoderMode, Qei};
+//!
+//! let a = CapturePin::new_ch0(p.PA6, Pull::Up);
+//! let b = CapturePin::new_ch1(p.PA7, Pull::Up);
+//! let mut qei = Qei::new(p.TIMER2, a, b, EncoderMode::BothEdges);
+//! loop {
+//!     qei.wait_for_delta(4).await;
+//!     let position = qei.count();
+//! }
+//! # }
+//! ```
+//!
+//! [`Qei::wait_for_delta`] uses the compare channels 2 and 3, whose pins stay free.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::PeripheralRef;
+
+use super::input_capture::CapturePin;
+use super::low_level::Timer;
+use super::simple_pwm::{Ch0, Ch1};
+use super::*;
+use crate::gpio::sealed::Pin as _;
+use crate::gpio::AnyPin;
+
+/// Edges that are counted, see `SMC` in `TIMER_SMCFG`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncoderMode {
+    /// Both edges of channel 0's input, two counts per cycle of the encoder signals
+    Channel0Edges = 1,
+    /// Both edges of channel 1's input, two counts per cycle of the encoder signals
+    Channel1Edges = 2,
+    /// Both edges of both inputs, four counts per cycle of the encoder signals
+    BothEdges = 3,
+}
+
+/// Counting direction of the encoder, see `DIR` in `TIMER_CTL0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// The count increases
+    Up,
+    /// The count decreases
+    Down,
+}
+
+/// Quadrature encoder driver
+pub struct Qei<'d, T: EncoderInstance> {
+    _timer: Timer<'d, T>,
+    pins: [PeripheralRef<'d, AnyPin>; 2],
+}
+
+impl<'d, T: EncoderInstance> Qei<'d, T> {
+    /// Create an encoder driver with the encoder signals on the given pins. The count starts at
+    /// 0.
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        ch0: CapturePin<'d, T, Ch0>,
+        ch1: CapturePin<'d, T, Ch1>,
+        mode: EncoderMode,
+    ) -> Self {
+        let mut timer = Timer::new(tim);
+
+        T::remap(&[ch0.remaps, ch1.remaps]);
+        let pins = [ch0.configure(), ch1.configure()];
+
+        let r = regs::<T>();
+        timer.set_auto_reload(u16::MAX);
+        // Channels 0 and 1 take their inputs unfiltered and not inverted, channels 2 and 3 only
+        // compare. None of them needs to be enabled for that.
+        r.write(CHCTL0, CHMS_INPUT | (CHMS_INPUT << Channel::Ch1.chctl().1));
+        r.write(CHCTL1, 0);
+        r.write(CHCTL2, 0);
+        r.modify(SMCFG, mode as u32, SMCFG_SMC);
+        timer.start();
+
+        Self { _timer: timer, pins }
+    }
+
+    /// The count.
+    pub fn count(&self) -> u16 {
+        regs::<T>().read(CNT) as u16
+    }
+
+    /// Set the count.
+    pub fn set_count(&mut self, count: u16) {
+        regs::<T>().write(CNT, count as u32)
+    }
+
+    /// Direction of the last step.
+    pub fn direction(&self) -> Direction {
+        match regs::<T>().read(CTL0) & CTL0_DIR {
+            0 => Direction::Up,
+            _ => Direction::Down,
+        }
+    }
+
+    /// Wait until the count has changed by `delta` in either direction, from its value at the
+    /// call, and return the direction. Panics if `delta` is 0, or above 32767.
+    pub async fn wait_for_delta(&mut self, delta: u16) -> Direction {
+        assert!((1..0x8000).contains(&delta), "encoder delta out of range");
+        let r = regs::<T>();
+        let up = INTF_CH0IF << Channel::Ch2 as u32;
+        let down = INTF_CH0IF << Channel::Ch3 as u32;
+
+        critical_section::with(|_| {
+            let count = self.count();
+            r.write(Channel::Ch2.chcv(), count.wrapping_add(delta) as u32);
+            r.write(Channel::Ch3.chcv(), count.wrapping_sub(delta) as u32);
+            r.write(INTF, !(up | down));
+        });
+        poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            // The counter steps by one, so it matches a compare value on its way past it.
+            let flags = r.read(INTF);
+            if flags & up != 0 {
+                return Poll::Ready(Direction::Up);
+            }
+            if flags & down != 0 {
+                return Poll::Ready(Direction::Down);
+            }
+            // The interrupt handler disables the interrupts again.
+            critical_section::with(|_| r.modify(DMAINTEN, up | down, 0));
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<'d, T: EncoderInstance> Drop for Qei<'d, T> {
+    fn drop(&mut self) {
+        for pin in self.pins.iter() {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
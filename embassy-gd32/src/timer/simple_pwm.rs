@@ -0,0 +1,347 @@
+//! PWM outputs
+//!
+//! [`SimplePwm`] drives the channels of a timer with the same frequency and a duty each, in
+//! timer ticks from 0 to [`SimplePwm::max_duty`].
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::time::Hertz;
+//! use embassy_gd32::timer::simple_pwm::{PwmPin, SimplePwm};
+//! use embassy_gd32::timer::Channel;
+//!
+//! let led = PwmPin::new_ch0(p.PA6);
+//! let mut pwm = SimplePwm::new(p.TIMER2, Some(led), None, None, None, Hertz::khz(1));
+//! pwm.set_duty(Channel::Ch0, pwm.max_duty() / 4);
+//! pwm.enable(Channel::Ch0);
+//! ```
+//!
+//! The channels use PWM mode 0: an output is active while the counter is below the channel's
+//! duty. New duties and frequencies take effect at the end of the current period, so the output
+//! never glitches.
+//!
+//! Sequences of duties, e.g. the bits of WS2812 LEDs or a sine table, can be streamed to the
+//! channels with DMA, see [`SimplePwm::waveform_up`], [`SimplePwm::waveform_up_burst`] and
+//! [`SimplePwm::waveform`].
+
+use core::marker::PhantomData;
+
+use embassy_hal_common::drop::OnDrop;
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use super::low_level::{Timer, UpdateEvents};
+use super::*;
+use crate::afio::RemapSet;
+use crate::dma::Transfer;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::AnyPin;
+use crate::time::Hertz;
+
+/// Channel 0, for [`PwmPin`]
+pub enum Ch0 {}
+/// Channel 1, for [`PwmPin`]
+pub enum Ch1 {}
+/// Channel 2, for [`PwmPin`]
+pub enum Ch2 {}
+/// Channel 3, for [`PwmPin`]
+pub enum Ch3 {}
+
+macro_rules! channel_marker_impl {
+    ($marker:ident, $channel:ident) => {
+        impl sealed::ChannelMarker for $marker {
+            const CHANNEL: Channel = Channel::$channel;
+        }
+
+        impl ChannelMarker for $marker {}
+    };
+}
+
+channel_marker_impl!(Ch0, Ch0);
+channel_marker_impl!(Ch1, Ch1);
+channel_marker_impl!(Ch2, Ch2);
+channel_marker_impl!(Ch3, Ch3);
+
+/// Output pin of channel `C` of timer `T`
+pub struct PwmPin<'d, T, C> {
+    pub(super) pin: PeripheralRef<'d, AnyPin>,
+    pub(super) remaps: RemapSet,
+    _phantom: PhantomData<(T, C)>,
+}
+
+macro_rules! channel_impl {
+    ($new_chx:ident, $channel:ident, $pin_trait:ident) => {
+        impl<'d, T: CaptureCompareInstance> PwmPin<'d, T, $channel> {
+            /// Use `pin` as the channel's output. The pin is configured by [`SimplePwm::new`].
+            pub fn $new_chx(pin: impl Peripheral<P = impl $pin_trait<T>> + 'd) -> Self {
+                into_ref!(pin);
+                let remaps = pin.remaps();
+                Self {
+                    pin: pin.map_into(),
+                    remaps,
+                    _phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+channel_impl!(new_ch0, Ch0, Channel0Pin);
+channel_impl!(new_ch1, Ch1, Channel1Pin);
+channel_impl!(new_ch2, Ch2, Channel2Pin);
+channel_impl!(new_ch3, Ch3, Channel3Pin);
+
+/// Level of a signal while it's active, see `CHxP` in `TIMER_CHCTL2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polarity {
+    /// High while active
+    ActiveHigh,
+    /// Low while active
+    ActiveLow,
+}
+
+/// PWM driver
+pub struct SimplePwm<'d, T: CaptureCompareInstance> {
+    timer: Timer<'d, T>,
+    pins: [Option<PeripheralRef<'d, AnyPin>>; 4],
+}
+
+impl<'d, T: CaptureCompareInstance> SimplePwm<'d, T> {
+    /// Create a PWM driver with outputs on the given pins, at `freq`.
+    ///
+    /// All channels are disabled, with a duty of 0 and [`Polarity::ActiveHigh`]. Panics like
+    /// [`SimplePwm::set_frequency`].
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        ch0: Option<PwmPin<'d, T, Ch0>>,
+        ch1: Option<PwmPin<'d, T, Ch1>>,
+        ch2: Option<PwmPin<'d, T, Ch2>>,
+        ch3: Option<PwmPin<'d, T, Ch3>>,
+        freq: Hertz,
+    ) -> Self {
+        let timer = Timer::new(tim);
+
+        // `ALL` leaves the layout to the other pins.
+        let remaps = [
+            ch0.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch1.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch2.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+            ch3.as_ref().map_or(RemapSet::ALL, |p| p.remaps),
+        ];
+        T::remap(&remaps);
+        let pins = [
+            ch0.map(|p| p.pin),
+            ch1.map(|p| p.pin),
+            ch2.map(|p| p.pin),
+            ch3.map(|p| p.pin),
+        ];
+        for pin in pins.iter().flatten() {
+            unsafe { pin.set_as_af(AFType::OutputPushPull) };
+        }
+
+        let mut this = Self { timer, pins };
+        this.set_frequency(freq);
+        if T::ADVANCED {
+            // The outputs of the advanced timers are off until `POEN` is set, which the break
+            // input clears.
+            regs::<T>().modify(CCHP, CCHP_POEN, 0);
+        }
+        this.timer.start();
+        this
+    }
+
+    /// Set the PWM frequency. The duties are counted in ticks, so they should be set again,
+    /// relative to the new [`SimplePwm::max_duty`].
+    ///
+    /// Panics if `freq` is zero, above the timer clock, or below the timer clock divided by
+    /// 65535 times 65536.
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        assert!(freq.0 != 0, "PWM frequency must not be zero");
+        let ticks = T::frequency().0 / freq.0;
+        assert!(ticks != 0, "PWM frequency above the timer clock");
+
+        // Keep the auto-reload value below 0xFFFF, so that a duty of `max_duty` fits the
+        // compare registers.
+        let psc = (ticks - 1) / 0xFFFF;
+        assert!(psc <= 0xFFFF, "PWM frequency too low");
+        let car = ticks / (psc + 1) - 1;
+        self.timer.set_prescaler(psc as u16);
+        self.timer.set_auto_reload(car as u16);
+    }
+
+    /// The overflows of the counter from now on, once per PWM period, as a stream of ticks, see
+    /// [`UpdateEvents`]. A duty set on a tick takes effect in the next period.
+    pub fn update_events(&mut self) -> UpdateEvents<'_, T> {
+        self.timer.update_events()
+    }
+
+    /// The PWM frequency.
+    pub fn frequency(&self) -> Hertz {
+        let ticks = (self.timer.prescaler() as u32 + 1) * (self.timer.auto_reload() as u32 + 1);
+        Hertz(T::frequency().0 / ticks)
+    }
+
+    /// The duty of an output that is always active, the number of ticks of a period.
+    pub fn max_duty(&self) -> u16 {
+        self.timer.auto_reload() + 1
+    }
+
+    /// Set the duty of `channel`, which is active for `duty` ticks of each period. Panics if
+    /// `duty` is above [`SimplePwm::max_duty`].
+    pub fn set_duty(&mut self, channel: Channel, duty: u16) {
+        assert!(duty <= self.max_duty(), "PWM duty above the maximum");
+        regs::<T>().write(channel.chcv(), duty as u32)
+    }
+
+    /// The duty of `channel`.
+    pub fn duty(&self, channel: Channel) -> u16 {
+        regs::<T>().read(channel.chcv()) as u16
+    }
+
+    /// Set the level of `channel` while it's active.
+    pub fn set_polarity(&mut self, channel: Channel, polarity: Polarity) {
+        let bit = CHCTL2_CHP << (4 * channel as u32);
+        match polarity {
+            Polarity::ActiveHigh => regs::<T>().modify(CHCTL2, 0, bit),
+            Polarity::ActiveLow => regs::<T>().modify(CHCTL2, bit, 0),
+        }
+    }
+
+    /// Enable the output of `channel`.
+    pub fn enable(&mut self, channel: Channel) {
+        let r = regs::<T>();
+        let (chctl, shift) = channel.chctl();
+        // PWM mode 0 with the compare value buffered until the next update event.
+        r.modify(
+            chctl,
+            ((CHCOMCTL_PWM0 << CHCTL_CHCOMCTL_OFFSET) | CHCTL_CHCOMSEN) << shift,
+            (CHCTL_CHMS | CHCTL_CHCOMCTL | CHCTL_CHCOMSEN) << shift,
+        );
+        r.modify(CHCTL2, CHCTL2_CHEN << (4 * channel as u32), 0);
+    }
+
+    /// Disable the output of `channel`.
+    pub fn disable(&mut self, channel: Channel) {
+        regs::<T>().modify(CHCTL2, 0, CHCTL2_CHEN << (4 * channel as u32));
+    }
+
+    /// Whether the output of `channel` is enabled.
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        regs::<T>().read(CHCTL2) & (CHCTL2_CHEN << (4 * channel as u32)) != 0
+    }
+
+    /// Enable `channel` and set its duty to each value of `duty` in turn, one per period, with
+    /// the DMA request of the update event. The last duty stays set.
+    ///
+    /// The first duty is written at the next update event, and takes effect in the period after
+    /// it. Panics like [`SimplePwm::set_duty`].
+    pub async fn waveform_up(&mut self, dma: impl Peripheral<P = impl UpDma<T>>, channel: Channel, duty: &[u16]) {
+        self.waveform_up_burst(dma, channel, 1, duty).await
+    }
+
+    /// Enable `channels` consecutive channels from `first`, and set their duties from `duty` each
+    /// period, with a DMA burst at each update event. `duty` holds a duty for each of the
+    /// channels per period, e.g. `[ch0, ch1, ch0, ch1, ..]` for two channels from
+    /// [`Channel::Ch0`]. The last duties stay set.
+    ///
+    /// Panics if the channels go past [`Channel::Ch3`], the length of `duty` isn't a multiple of
+    /// `channels`, or like [`SimplePwm::set_duty`].
+    pub async fn waveform_up_burst(
+        &mut self,
+        dma: impl Peripheral<P = impl UpDma<T>>,
+        first: Channel,
+        channels: usize,
+        duty: &[u16],
+    ) {
+        into_ref!(dma);
+        assert!(
+            (1..=4 - first as usize).contains(&channels),
+            "PWM burst past the last channel"
+        );
+        assert!(duty.len() % channels == 0, "PWM burst of partial periods");
+        let max_duty = self.max_duty();
+        assert!(duty.iter().all(|&d| d <= max_duty), "PWM duty above the maximum");
+
+        for channel in &[Channel::Ch0, Channel::Ch1, Channel::Ch2, Channel::Ch3][first as usize..][..channels] {
+            self.enable(*channel);
+        }
+
+        let r = regs::<T>();
+        // A burst writes `TIMER_DMATB` once per channel, which goes to consecutive registers
+        // from the `DMATA`-th one, in words from `TIMER_CTL0`.
+        r.write(
+            DMACFG,
+            ((first.chcv() as u32 / 4) << DMACFG_DMATA_OFFSET) | ((channels as u32 - 1) << DMACFG_DMATC_OFFSET),
+        );
+        r.modify(DMAINTEN, DMAINTEN_UPDEN, 0);
+        let _disable = OnDrop::new(|| r.modify(DMAINTEN, 0, DMAINTEN_UPDEN));
+
+        unsafe { Transfer::new_write(dma, duty, (T::base() + DMATB) as *mut u16) }.await;
+    }
+
+    /// Enable channel `C` and set its duty to each value of `duty` in turn, with the DMA request
+    /// of the channel's compare event. Unlike [`SimplePwm::waveform_up`], each channel has its
+    /// own DMA channel, so several can be streamed independently. The last duty stays set.
+    ///
+    /// The compare event happens once per period if the duty is below
+    /// [`SimplePwm::max_duty`], never otherwise, which stalls the transfer. Panics like
+    /// [`SimplePwm::set_duty`].
+    pub async fn waveform<C: ChannelMarker>(&mut self, dma: impl Peripheral<P = impl CcDma<T, C>>, duty: &[u16]) {
+        into_ref!(dma);
+        let channel = C::CHANNEL;
+        let max_duty = self.max_duty();
+        assert!(duty.iter().all(|&d| d <= max_duty), "PWM duty above the maximum");
+
+        self.enable(channel);
+        let r = regs::<T>();
+        let den = DMAINTEN_CH0DEN << channel as u32;
+        r.modify(DMAINTEN, den, 0);
+        let _disable = OnDrop::new(|| r.modify(DMAINTEN, 0, den));
+
+        unsafe { Transfer::new_write(dma, duty, (T::base() + channel.chcv()) as *mut u16) }.await;
+    }
+}
+
+impl<'d, T: CaptureCompareInstance> Drop for SimplePwm<'d, T> {
+    fn drop(&mut self) {
+        for pin in self.pins.iter().flatten() {
+            unsafe { pin.set_as_disconnected() };
+        }
+    }
+}
+
+impl<'d, T: CaptureCompareInstance> embedded_hal_02::Pwm for SimplePwm<'d, T> {
+    type Channel = Channel;
+    type Time = Hertz;
+    type Duty = u16;
+
+    fn disable(&mut self, channel: Channel) {
+        self.disable(channel)
+    }
+
+    fn enable(&mut self, channel: Channel) {
+        self.enable(channel)
+    }
+
+    fn get_period(&self) -> Hertz {
+        self.frequency()
+    }
+
+    fn get_duty(&self, channel: Channel) -> u16 {
+        self.duty(channel)
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.max_duty()
+    }
+
+    fn set_duty(&mut self, channel: Channel, duty: u16) {
+        self.set_duty(channel, duty)
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Hertz>,
+    {
+        self.set_frequency(period.into())
+    }
+}
@@ -0,0 +1,216 @@
+//! Trigonometric math unit (TMU)
+//!
+//! The TMU computes sine, cosine, arctangent, square roots and vector magnitudes in hardware,
+//! which takes the trigonometry of e.g. the Park and Clarke transforms of a field-oriented motor
+//! control loop off the CPU.
+//!
+//! The operands and results are fixed-point numbers in q1.31 format, an `i32` with 31
+//! fractional bits, so `0x4000_0000` is 0.5 and `i32::MIN` is -1. Angles are in units of π:
+//! `0x4000_0000` is π/2 and `i32::MIN` is -π, wrapping around like the angle does.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::tmu::Tmu;
+//!
+//! let mut tmu = Tmu::new(p.TMU);
+//! let angle = 0x2000_0000; // π/4
+//! let sin = tmu.sin(angle);
+//! let cos = tmu.cos(angle);
+//! let angle = tmu.atan2(sin, cos);
+//! ```
+//!
+//! Each function also has a `start_` variant, which returns a [`Computation`] right away. The CPU
+//! goes on with other work while the TMU computes, and takes the result with
+//! [`Computation::wait`], or [`Computation::result`] in async code:
+//!
+//! ```no_run
+//! # async fn example() {
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! # let mut tmu = embassy_gd32::tmu::Tmu::new(p.TMU);
+//! # let angle = 0;
+//! let sin = tmu.start_sin(angle);
+//! // Other work...
+//! let sin = sin.wait();
+//! let cos = tmu.start_cos(angle).result().await;
+//! # }
+//! ```
+//!
+//! The driver takes no locks, a computation borrows the [`Tmu`] mutably until its result is
+//! taken. A control loop that runs in an interrupt handler owns the driver there, e.g. moved in
+//! once at startup.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::{interrupt, pac};
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Trigonometric math unit
+pub struct Tmu<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::TMU>,
+}
+
+impl<'d> Tmu<'d> {
+    /// Enable the TMU.
+    pub fn new(inner: impl Peripheral<P = crate::peripherals::TMU> + 'd) -> Self {
+        into_ref!(inner);
+        critical_section::with(|_| unsafe {
+            let rcu = &*pac::RCU::ptr();
+            rcu.ahben.modify(|r, w| w.bits(r.bits() | AHBEN_TMUEN));
+        });
+        unsafe {
+            reg(CTL).write_volatile(0);
+            let irq = interrupt::TMU::steal();
+            irq.unpend();
+            irq.enable();
+        }
+
+        Self { _inner: inner }
+    }
+
+    /// Start a computation of `mode` on `x` and `y`.
+    fn start(&mut self, mode: u32, x: i32, y: i32) -> Computation<'_, 'd> {
+        // Drop the result of a computation that wasn't taken.
+        if unsafe { reg(STAT).read_volatile() } & STAT_CFIF != 0 {
+            read_result();
+        }
+        unsafe {
+            reg(CTL).write_volatile(mode << CTL_MODE_OFFSET);
+            reg(IDATA1).write_volatile(y as u32);
+            // Writing the first operand starts the computation.
+            reg(IDATA0).write_volatile(x as u32);
+        }
+        Computation { _tmu: self }
+    }
+
+    /// Start computing the sine of `angle`, in units of π.
+    pub fn start_sin(&mut self, angle: i32) -> Computation<'_, 'd> {
+        self.start(MODE_SIN, angle, 0)
+    }
+
+    /// Start computing the cosine of `angle`, in units of π.
+    pub fn start_cos(&mut self, angle: i32) -> Computation<'_, 'd> {
+        self.start(MODE_COS, angle, 0)
+    }
+
+    /// Start computing the angle of the vector (`x`, `y`), in units of π, like `f32::atan2(y, x)`.
+    pub fn start_atan2(&mut self, y: i32, x: i32) -> Computation<'_, 'd> {
+        self.start(MODE_ATAN2, x, y)
+    }
+
+    /// Start computing the magnitude of the vector (`x`, `y`), √(x² + y²), which saturates at
+    /// `i32::MAX`.
+    pub fn start_magnitude(&mut self, x: i32, y: i32) -> Computation<'_, 'd> {
+        self.start(MODE_MAGNITUDE, x, y)
+    }
+
+    /// Start computing the square root of `x`, which must not be negative.
+    pub fn start_sqrt(&mut self, x: i32) -> Computation<'_, 'd> {
+        assert!(x >= 0, "square root of a negative number");
+        self.start(MODE_SQRT, x, 0)
+    }
+
+    /// The sine of `angle`, in units of π.
+    pub fn sin(&mut self, angle: i32) -> i32 {
+        self.start_sin(angle).wait()
+    }
+
+    /// The cosine of `angle`, in units of π.
+    pub fn cos(&mut self, angle: i32) -> i32 {
+        self.start_cos(angle).wait()
+    }
+
+    /// The angle of the vector (`x`, `y`), in units of π, see [`Tmu::start_atan2`].
+    pub fn atan2(&mut self, y: i32, x: i32) -> i32 {
+        self.start_atan2(y, x).wait()
+    }
+
+    /// The magnitude of the vector (`x`, `y`), see [`Tmu::start_magnitude`].
+    pub fn magnitude(&mut self, x: i32, y: i32) -> i32 {
+        self.start_magnitude(x, y).wait()
+    }
+
+    /// The square root of `x`, which must not be negative.
+    pub fn sqrt(&mut self, x: i32) -> i32 {
+        self.start_sqrt(x).wait()
+    }
+}
+
+/// Computation running on the TMU, see [`Tmu::start_sin`] and the like
+#[must_use = "the result of the computation must be taken before the next one"]
+pub struct Computation<'a, 'd> {
+    _tmu: &'a mut Tmu<'d>,
+}
+
+impl<'a, 'd> Computation<'a, 'd> {
+    /// Whether the result is ready.
+    pub fn is_done(&self) -> bool {
+        unsafe { reg(STAT).read_volatile() & STAT_CFIF != 0 }
+    }
+
+    /// Take the result, blocking until it's ready.
+    pub fn wait(self) -> i32 {
+        while !self.is_done() {}
+        read_result()
+    }
+
+    /// Take the result, waiting for the end of computation interrupt.
+    pub async fn result(self) -> i32 {
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            match self.is_done() {
+                true => Poll::Ready(()),
+                false => {
+                    unsafe { reg(CTL).write_volatile(reg(CTL).read_volatile() | CTL_CFIE) };
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        read_result()
+    }
+}
+
+/// Read the result, which clears the end of computation flag.
+fn read_result() -> i32 {
+    unsafe { reg(DATA0).read_volatile() as i32 }
+}
+
+#[interrupt]
+unsafe fn TMU() {
+    reg(CTL).write_volatile(reg(CTL).read_volatile() & !CTL_CFIE);
+    WAKER.wake();
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (pac::TMU::ptr() as usize + offset) as *mut u32
+}
+
+// Register offsets
+const IDATA0: usize = 0x00;
+const IDATA1: usize = 0x04;
+const CTL: usize = 0x08;
+const DATA0: usize = 0x0C;
+const STAT: usize = 0x14;
+
+// TMU_CTL
+const CTL_MODE_OFFSET: u32 = 0;
+const CTL_CFIE: u32 = 1 << 4;
+
+// TMU_CTL modes
+const MODE_SQRT: u32 = 2;
+const MODE_SIN: u32 = 3;
+const MODE_COS: u32 = 4;
+const MODE_ATAN2: u32 = 6;
+const MODE_MAGNITUDE: u32 = 7;
+
+// TMU_STAT
+const STAT_CFIF: u32 = 1 << 0;
+
+// RCU_AHBEN
+const AHBEN_TMUEN: u32 = 1 << 24;
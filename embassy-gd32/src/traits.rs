@@ -0,0 +1,71 @@
+#![macro_use]
+
+/// Declare a trait for the pins that can carry a signal of a peripheral instance.
+///
+/// Besides the concrete pin types, every such trait is also implemented by
+/// [`AnyPin`](crate::gpio::AnyPin), with the AFIO layouts looked up at runtime. This lets drivers
+/// accept degraded pins chosen from a table at runtime, and `Option<AnyPin>` for optional
+/// signals, e.g. `None::<AnyPin>` for an unused RTS line.
+macro_rules! pin_trait {
+    ($signal:ident, $instance:path) => {
+        pub trait $signal<T: $instance>: crate::gpio::Pin {
+            /// The AFIO layouts of `T` that route this signal to this pin.
+            ///
+            /// This is empty if the pin can't carry the signal, which can only happen for
+            /// [`AnyPin`](crate::gpio::AnyPin).
+            fn remaps(&self) -> crate::afio::RemapSet;
+        }
+    };
+}
+
+/// Implement a pin trait declared with `pin_trait!` for the listed pins and for `AnyPin`.
+///
+/// Each pin lists the layouts of the instance it is used in. A pin can appear in several layouts,
+/// e.g. USART2 CTS is on PB13 both without remap and with the partial remap.
+macro_rules! pin_trait_impl {
+    (crate::$mod:ident::$trait:ident, $instance:ident, { $($pin:ident => [$($remap:ident),+]),* $(,)? }) => {
+        $(
+            impl crate::$mod::$trait<peripherals::$instance> for peripherals::$pin {
+                #[inline]
+                fn remaps(&self) -> crate::afio::RemapSet {
+                    crate::afio::RemapSet::EMPTY$(.with(crate::afio::Remap::$remap))+
+                }
+            }
+        )*
+
+        impl crate::$mod::$trait<peripherals::$instance> for crate::gpio::AnyPin {
+            fn remaps(&self) -> crate::afio::RemapSet {
+                use crate::gpio::sealed::Pin as _;
+
+                let pin_port = self.pin_port();
+                $(
+                    if pin_port == unsafe { peripherals::$pin::steal() }.pin_port() {
+                        return crate::afio::RemapSet::EMPTY$(.with(crate::afio::Remap::$remap))+;
+                    }
+                )*
+                crate::afio::RemapSet::EMPTY
+            }
+        }
+    };
+}
+
+/// Declare a trait for the DMA channels that serve a request of a peripheral instance, or of one
+/// of its sub-units, such as a timer channel, named by a marker type.
+macro_rules! dma_trait {
+    ($signal:ident, $instance:path) => {
+        pub trait $signal<T: $instance>: crate::dma::Channel {}
+    };
+    ($signal:ident, $instance:path, $marker:path) => {
+        pub trait $signal<T: $instance, C: $marker>: crate::dma::Channel {}
+    };
+}
+
+/// Implement a DMA trait declared with `dma_trait!` for the channel the request is wired to.
+macro_rules! dma_trait_impl {
+    (crate::$mod:ident::$trait:ident, $instance:ident, $channel:ident) => {
+        impl crate::$mod::$trait<peripherals::$instance> for peripherals::$channel {}
+    };
+    (crate::$mod:ident::$trait:ident, $instance:ident, $marker:path, $channel:ident) => {
+        impl crate::$mod::$trait<peripherals::$instance, $marker> for peripherals::$channel {}
+    };
+}
@@ -0,0 +1,150 @@
+//! Universal synchronous/asynchronous receiver transmitter (USART) pins
+//!
+//! There is no USART driver yet. [`Pins`] routes the pins of a USART through AFIO and sets them
+//! up, for code that drives the USART registers through the PAC. The remap layout is picked from
+//! the pins, like the other drivers of remappable peripherals do: a pin that can't carry a signal
+//! doesn't compile, and pins of different layouts, e.g. TX on PB6 (full remap of USART0) with RX
+//! on PA10 (no remap), panic in [`Pins::new`] instead of failing silently on the line.
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::usart::Pins;
+//!
+//! // Selects the full remap of USART0.
+//! let _pins = Pins::new(p.USART0, p.PB6, p.PB7);
+//! // Configure and use USART0 through the PAC.
+//! ```
+//!
+//! USART0 is clocked from APB2, USART1 and USART2 from APB1. [`Pins::new`] enables the clock of
+//! the instance.
+#![macro_use]
+
+use embassy_hal_common::{into_ref, PeripheralRef};
+
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Pull};
+use crate::Peripheral;
+
+/// Pins of a USART, routed through AFIO
+///
+/// Dropping it disconnects the pins, but leaves the USART clock enabled.
+pub struct Pins<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    tx: PeripheralRef<'d, AnyPin>,
+    rx: PeripheralRef<'d, AnyPin>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+    cts: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> Pins<'d, T> {
+    /// Route TX and RX of `peri` to `tx` and `rx`, and enable its clock.
+    ///
+    /// Panics if the pins don't share a remap layout of the instance.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+    ) -> Self {
+        into_ref!(tx, rx);
+        T::remap(&[TxPin::<T>::remaps(&*tx), RxPin::<T>::remaps(&*rx)]);
+        Self::new_inner(peri, tx.map_into(), rx.map_into(), None, None)
+    }
+
+    /// Route TX, RX and the hardware flow control signals of `peri`, and enable its clock.
+    ///
+    /// Panics if the pins don't share a remap layout of the instance.
+    pub fn new_with_rtscts(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T>> + 'd,
+        rts: impl Peripheral<P = impl RtsPin<T>> + 'd,
+        cts: impl Peripheral<P = impl CtsPin<T>> + 'd,
+    ) -> Self {
+        into_ref!(tx, rx, rts, cts);
+        T::remap(&[
+            TxPin::<T>::remaps(&*tx),
+            RxPin::<T>::remaps(&*rx),
+            RtsPin::<T>::remaps(&*rts),
+            CtsPin::<T>::remaps(&*cts),
+        ]);
+        Self::new_inner(
+            peri,
+            tx.map_into(),
+            rx.map_into(),
+            Some(rts.map_into()),
+            Some(cts.map_into()),
+        )
+    }
+
+    fn new_inner(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: PeripheralRef<'d, AnyPin>,
+        rx: PeripheralRef<'d, AnyPin>,
+        rts: Option<PeripheralRef<'d, AnyPin>>,
+        cts: Option<PeripheralRef<'d, AnyPin>>,
+    ) -> Self {
+        into_ref!(peri);
+        T::enable();
+        unsafe {
+            tx.set_as_af(AFType::OutputPushPull);
+            // An idle line is high, a floating RX would see noise as start bits.
+            rx.set_as_af_pull(AFType::Input, Pull::Up);
+            if let Some(rts) = &rts {
+                rts.set_as_af(AFType::OutputPushPull);
+            }
+            if let Some(cts) = &cts {
+                cts.set_as_af_pull(AFType::Input, Pull::Up);
+            }
+        }
+
+        Self {
+            _peri: peri,
+            tx,
+            rx,
+            rts,
+            cts,
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Pins<'d, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.tx.set_as_disconnected();
+            self.rx.set_as_disconnected();
+            if let Some(rts) = &self.rts {
+                rts.set_as_disconnected();
+            }
+            if let Some(cts) = &self.cts {
+                cts.set_as_disconnected();
+            }
+        }
+    }
+}
+
+pub(crate) mod sealed {
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        /// Select the AFIO layout of the pins, see [`crate::afio::remap_for_pins`].
+        fn remap(pins: &[crate::afio::RemapSet]);
+    }
+}
+
+/// USART instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {}
+
+pin_trait!(TxPin, Instance);
+pin_trait!(RxPin, Instance);
+pin_trait!(RtsPin, Instance);
+pin_trait!(CtsPin, Instance);
+
+macro_rules! impl_usart {
+    ($inst:ident, $remap:ident) => {
+        impl crate::usart::sealed::Instance for peripherals::$inst {
+            fn remap(pins: &[crate::afio::RemapSet]) {
+                crate::afio::remap_for_pins::<crate::afio::$remap>(pins)
+            }
+        }
+
+        impl crate::usart::Instance for peripherals::$inst {}
+    };
+}
@@ -0,0 +1,1228 @@
+//! USB full-speed device (USBD)
+//!
+//! [`Driver`] implements [`embassy_usb_driver::Driver`] for the USB device controller, so the
+//! classes of `embassy-usb` such as CDC-ACM or HID run on it. It needs the `nightly` feature,
+//! and the 48 MHz USB clock, see [`crate::cctl::Config::usb`].
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::usbd::Driver;
+//!
+//! let driver = Driver::new(p.USBD, p.PA12, p.PA11);
+//! // Build the device with `embassy_usb::Builder::new(driver, ...)`.
+//! ```
+//!
+//! The controller has [`ENDPOINT_COUNT`] endpoints, each with an IN and an OUT direction of the
+//! same type, and endpoint 0 is the control endpoint. Their packet buffers are allocated in the
+//! [`PACKET_MEMORY_SIZE`] bytes of the dedicated packet SRAM, after the buffer descriptor table.
+//!
+//! Isochronous endpoints, e.g. for USB audio, are double-buffered: the controller sends or
+//! receives the packet of a frame in one buffer while the application handles the other one.
+//! Such an endpoint takes an endpoint number for a single direction and twice its packet size
+//! of packet SRAM. A read returns the packet received in the previous frame, and a write queues
+//! the packet sent in the frame after the next one, so both have to keep up with one packet per
+//! frame.
+//!
+//! D+ needs an external 1.5 kΩ pull-up to 3.3 V, which tells the host that a full-speed device is
+//! attached.
+//!
+//! # VBUS detection
+//!
+//! The controller has no VBUS input, so [`Driver::new`] assumes the bus is powered, as it is for
+//! a bus-powered device. A self-powered device has to tell when the host powers the bus: it must
+//! not pull D+ up while VBUS is off, and has to stop when the cable is unplugged.
+//! [`Driver::new_with_vbus`] watches VBUS through a GPIO with EXTI, e.g. behind a divider to keep
+//! it below VDD, and switches the D+ pull-up with another GPIO. The device attaches when VBUS
+//! comes and detaches when it goes, reported to `embassy-usb` as power events.
+//!
+//! # Suspend and remote wakeup
+//!
+//! The host suspends the bus when it sleeps, and a bus-powered device must then draw no more
+//! than 2.5 mA. The driver puts the transceiver into low power by itself. Hooks set with
+//! [`Driver::set_suspend_hooks`] do the rest from the USB interrupt: e.g. the suspend hook lowers
+//! the system clock and turns off the LEDs, and the wakeup hook brings the clocks back with
+//! [`crate::cctl::reconfigure`], before the controller needs its 48 MHz clock again.
+//!
+//! To sleep in deep-sleep mode instead, where the USB clock stops, wait on
+//! [`InternalExti`](crate::exti::InternalExti) of `EXTI18` with a rising edge, which wakes the
+//! core when the bus resumes. The wakeup hook restores the clocks there as well.
+//!
+//! A device such as a keyboard can wake the host up while the bus is suspended, if the host
+//! enabled remote wakeup: `embassy_usb::UsbDevice::remote_wakeup` calls the wakeup hook and then
+//! signals resume on the bus.
+//!
+//! The controller shares its interrupts, and the default pins PA11/PA12, with CAN0, so
+//! both can't be used at the same time.
+//!
+//! The GD32E503 only has this full-speed controller. The high-speed USBHS core, with its ULPI
+//! interface and DMA, is on the GD32E505/E507/E508, which this crate has no chip feature for.
+#![macro_use]
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use embassy_hal_common::into_ref;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_usb_driver as driver;
+use embassy_usb_driver::{
+    Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointInfo, EndpointType, Event, Unsupported,
+};
+
+use embassy_futures::select::{select, Either};
+
+use crate::exti::ExtiInput;
+use crate::gpio::sealed::{AFType, Pin as _};
+use crate::gpio::{AnyPin, Output};
+use crate::interrupt::{Interrupt, InterruptExt, Mutex};
+use crate::Peripheral;
+
+/// Number of endpoints, including the control endpoint 0
+pub const ENDPOINT_COUNT: usize = 8;
+
+/// Size of the packet SRAM in bytes, for the buffer descriptor table and the packet buffers
+pub const PACKET_MEMORY_SIZE: usize = 512;
+
+/// Address of the packet SRAM. Each 16-bit word of the USB side takes 32 bits on the CPU side,
+/// of which the upper half is unused.
+const PACKET_MEMORY: usize = 0x4000_6000;
+
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+
+/// State shared with the interrupt handler
+pub struct State {
+    /// Whether the driver runs, the interrupts are shared with CAN0
+    started: AtomicBool,
+    bus_waker: AtomicWaker,
+    ep_in_wakers: [AtomicWaker; ENDPOINT_COUNT],
+    ep_out_wakers: [AtomicWaker; ENDPOINT_COUNT],
+    /// Set when endpoint 0 received a SETUP packet, until it's read
+    ep0_setup: AtomicBool,
+    /// Bus events seen by the interrupt handler, `IRQ_FLAG_*`
+    irq_flags: AtomicU8,
+    /// Isochronous endpoints that completed a transaction since they were last read or written,
+    /// bit `n` for IN endpoint `n` and bit `8 + n` for OUT endpoint `n`
+    iso_done: AtomicU16,
+    suspend_hook: Mutex<Cell<Option<fn()>>>,
+    wakeup_hook: Mutex<Cell<Option<fn()>>>,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            bus_waker: NEW_AW,
+            ep_in_wakers: [NEW_AW; ENDPOINT_COUNT],
+            ep_out_wakers: [NEW_AW; ENDPOINT_COUNT],
+            ep0_setup: AtomicBool::new(false),
+            irq_flags: AtomicU8::new(0),
+            iso_done: AtomicU16::new(0),
+            suspend_hook: Mutex::new(Cell::new(None)),
+            wakeup_hook: Mutex::new(Cell::new(None)),
+        }
+    }
+}
+
+const IRQ_FLAG_RESET: u8 = 1 << 0;
+const IRQ_FLAG_SUSPEND: u8 = 1 << 1;
+const IRQ_FLAG_RESUME: u8 = 1 << 2;
+
+/// Length of the resume signaling of a remote wakeup
+const REMOTE_WAKEUP_MS: u64 = 5;
+
+/// Handle the interrupts of USBD `T`, called by the handlers it shares with CAN0.
+pub(crate) unsafe fn on_interrupt<T: Instance>() {
+    let state = T::state();
+    if !state.started.load(Ordering::Relaxed) {
+        return;
+    }
+    let r = regs::<T>();
+    let intf = r.read(INTF);
+
+    let mut flags = 0;
+    if intf & INTF_SPSIF != 0 {
+        flags |= IRQ_FLAG_SUSPEND;
+        // The transceiver is only put into low power after the suspend state is entered.
+        r.modify(CTL, CTL_SETSPS, 0);
+        r.modify(CTL, CTL_LOWM, 0);
+        if let Some(hook) = critical_section::with(|cs| state.suspend_hook.borrow(cs).get()) {
+            hook();
+        }
+    }
+    if intf & INTF_WKUPIF != 0 {
+        flags |= IRQ_FLAG_RESUME;
+        leave_suspend::<T>();
+    }
+    if intf & INTF_RSTIF != 0 {
+        flags |= IRQ_FLAG_RESET;
+    }
+    if flags != 0 {
+        state.irq_flags.fetch_or(flags, Ordering::AcqRel);
+        state.bus_waker.wake();
+        // The flags are cleared by writing zero, writing one has no effect.
+        r.write(INTF, !(intf & (INTF_SPSIF | INTF_WKUPIF | INTF_RSTIF)));
+    }
+
+    if intf & INTF_STIF != 0 {
+        // The transaction flags are in the endpoint's register, the interrupt flag follows them.
+        let index = (intf & INTF_EPNUM) as usize;
+        let epcs = r.read(epcs(index));
+        // Isochronous endpoints stay valid, the transactions are counted here instead.
+        let iso = epcs & EPCS_CTL == EPCS_CTL_ISO;
+        if epcs & EPCS_RX_ST != 0 {
+            if index == 0 && epcs & EPCS_SETUP != 0 {
+                state.ep0_setup.store(true, Ordering::Relaxed);
+            }
+            if iso {
+                state
+                    .iso_done
+                    .fetch_or(iso_bit(index, Direction::Out), Ordering::AcqRel);
+            }
+            state.ep_out_wakers[index].wake();
+        }
+        if epcs & EPCS_TX_ST != 0 {
+            if iso {
+                state.iso_done.fetch_or(iso_bit(index, Direction::In), Ordering::AcqRel);
+            }
+            state.ep_in_wakers[index].wake();
+        }
+        // Clear the transaction flags that were seen, and no others.
+        r.write(epcs(index), invariant(epcs) & !(epcs & (EPCS_RX_ST | EPCS_TX_ST)));
+    }
+}
+
+/// Leave the suspend state, after the wakeup hook restored the clocks. Does nothing if the
+/// controller isn't suspended.
+fn leave_suspend<T: Instance>() {
+    let r = regs::<T>();
+    if r.read(CTL) & CTL_SETSPS == 0 {
+        return;
+    }
+    if let Some(hook) = critical_section::with(|cs| T::state().wakeup_hook.borrow(cs).get()) {
+        hook();
+    }
+    r.modify(CTL, 0, CTL_LOWM);
+    r.modify(CTL, 0, CTL_SETSPS);
+}
+
+/// Bit of endpoint `index` in the direction `dir` in [`State::iso_done`]
+fn iso_bit(index: usize, dir: Direction) -> u16 {
+    match dir {
+        Direction::In => 1 << index,
+        Direction::Out => 1 << (8 + index),
+    }
+}
+
+/// Bits of `EPCS` to write back the register read as `epcs` without changing it: the toggle
+/// fields are written as zero, and the transaction flags as one, which don't clear them.
+fn invariant(epcs: u32) -> u32 {
+    (epcs & (EPCS_EPADDR | EPCS_CTL | EPCS_KCTL)) | EPCS_RX_ST | EPCS_TX_ST
+}
+
+/// `EPCS` value that toggles field `mask` from `from` to `to`, e.g. a status of an endpoint.
+fn toggle(mask: u32, from: u32, to: u32) -> u32 {
+    (from ^ to) & mask
+}
+
+fn ep_ctl(ep_type: EndpointType) -> u32 {
+    match ep_type {
+        EndpointType::Bulk => EPCS_CTL_BULK,
+        EndpointType::Control => EPCS_CTL_CONTROL,
+        EndpointType::Isochronous => EPCS_CTL_ISO,
+        EndpointType::Interrupt => EPCS_CTL_INTERRUPT,
+    }
+}
+
+/// Size of an OUT packet buffer for packets up to `len` bytes, and the block bits of its
+/// `COUNT_RX` descriptor.
+fn out_len(len: u16) -> (u16, u16) {
+    match len {
+        0..=62 => ((len + 1) / 2 * 2, ((len + 1) / 2) << 10),
+        63..=480 => ((len + 31) / 32 * 32, (((len + 31) / 32 - 1) << 10) | COUNT_RX_BLKSZ),
+        _ => panic!("invalid OUT packet size {}", len),
+    }
+}
+
+/// A 16-bit word of the packet SRAM, at byte `addr` of the USB side.
+fn packet_word(addr: u16) -> *mut u16 {
+    (PACKET_MEMORY + 2 * addr as usize) as *mut u16
+}
+
+/// Descriptor `n` of endpoint `index` in the table at the start of the packet SRAM: `ADDR_TX`,
+/// `COUNT_TX`, `ADDR_RX` and `COUNT_RX`.
+fn descriptor(index: usize, n: usize) -> *mut u16 {
+    packet_word((8 * index + 2 * n) as u16)
+}
+
+const DESC_ADDR_TX: usize = 0;
+const DESC_COUNT_TX: usize = 1;
+const DESC_ADDR_RX: usize = 2;
+const DESC_COUNT_RX: usize = 3;
+
+/// A packet buffer in the packet SRAM
+struct EndpointBuffer {
+    /// Byte address on the USB side
+    addr: u16,
+    len: u16,
+}
+
+impl EndpointBuffer {
+    fn read(&self, buf: &mut [u8]) {
+        assert!(buf.len() <= self.len as usize);
+        for i in 0..(buf.len() + 1) / 2 {
+            let word = unsafe { packet_word(self.addr + 2 * i as u16).read_volatile() };
+            buf[2 * i] = word as u8;
+            if 2 * i + 1 < buf.len() {
+                buf[2 * i + 1] = (word >> 8) as u8;
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) {
+        assert!(buf.len() <= self.len as usize);
+        for i in 0..(buf.len() + 1) / 2 {
+            let mut word = buf[2 * i] as u16;
+            if 2 * i + 1 < buf.len() {
+                word |= (buf[2 * i + 1] as u16) << 8;
+            }
+            unsafe { packet_word(self.addr + 2 * i as u16).write_volatile(word) };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EndpointData {
+    /// Only valid if a direction is used
+    ep_type: EndpointType,
+    used_in: bool,
+    used_out: bool,
+}
+
+/// USB device driver
+pub struct Driver<'d, T: Instance> {
+    _peri: PhantomData<&'d mut T>,
+    alloc: [EndpointData; ENDPOINT_COUNT],
+    /// First free byte of the packet SRAM
+    ep_mem_free: u16,
+    vbus: Option<ExtiInput<'d, AnyPin>>,
+    pull_up: Option<Output<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> Driver<'d, T> {
+    /// Create the driver, with `dp` and `dm` as D+ and D-, for a bus-powered device. The device
+    /// attaches to the bus once `embassy-usb` starts it.
+    ///
+    /// Panics if the USB clock isn't configured.
+    pub fn new(
+        usbd: impl Peripheral<P = T> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T>> + 'd,
+    ) -> Self {
+        Self::new_inner(usbd, dp, dm, None, None)
+    }
+
+    /// Create the driver for a self-powered device, which is only powered while `vbus` is high.
+    ///
+    /// `pull_up` is driven high to pull D+ up and attach to the bus, and low to detach, see the
+    /// [module documentation](self). Without it, the pull-up has to be switched by VBUS itself.
+    /// Panics like [`Driver::new`].
+    pub fn new_with_vbus(
+        usbd: impl Peripheral<P = T> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T>> + 'd,
+        vbus: ExtiInput<'d, AnyPin>,
+        pull_up: Option<Output<'d, AnyPin>>,
+    ) -> Self {
+        Self::new_inner(usbd, dp, dm, Some(vbus), pull_up)
+    }
+
+    fn new_inner(
+        _usbd: impl Peripheral<P = T> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T>> + 'd,
+        vbus: Option<ExtiInput<'d, AnyPin>>,
+        mut pull_up: Option<Output<'d, AnyPin>>,
+    ) -> Self {
+        into_ref!(dp, dm);
+        unwrap!(crate::cctl::clocks().usb, "USB clock not configured");
+        crate::afio::check_default_layout(&[dp.remaps(), dm.remaps()]);
+
+        T::enable();
+        T::reset();
+        unsafe {
+            dp.set_as_af(AFType::OutputPushPull);
+            dm.set_as_af(AFType::OutputPushPull);
+        }
+
+        if let Some(pull_up) = &mut pull_up {
+            pull_up.set_low();
+        }
+
+        let r = regs::<T>();
+        // Power the transceiver up, keeping the controller in reset, and let it start.
+        r.write(CTL, CTL_SETRST);
+        cortex_m::asm::delay(crate::cctl::clocks().sys.0 / 100_000);
+        r.write(BADDR, 0);
+        r.write(INTF, 0);
+
+        let state = T::state();
+        state.irq_flags.store(0, Ordering::Relaxed);
+        state.ep0_setup.store(false, Ordering::Relaxed);
+        state.started.store(true, Ordering::Relaxed);
+        unsafe {
+            let irq = T::LpInterrupt::steal();
+            irq.unpend();
+            irq.enable();
+            let irq = T::HpInterrupt::steal();
+            irq.unpend();
+            irq.enable();
+        }
+
+        Self {
+            _peri: PhantomData,
+            alloc: [EndpointData {
+                ep_type: EndpointType::Bulk,
+                used_in: false,
+                used_out: false,
+            }; ENDPOINT_COUNT],
+            // The buffer descriptor table has 4 descriptors of 2 bytes for each endpoint.
+            ep_mem_free: ENDPOINT_COUNT as u16 * 8,
+            vbus,
+            pull_up,
+        }
+    }
+
+    /// Call `on_suspend` from the USB interrupt once the bus is suspended and the transceiver is
+    /// in low power, and `on_wakeup` when it resumes, by the host or by
+    /// [`remote_wakeup`](driver::Bus::remote_wakeup), before the controller leaves suspend.
+    /// `None` removes a hook.
+    ///
+    /// See the [module documentation](self) for what they are for.
+    pub fn set_suspend_hooks(&mut self, on_suspend: Option<fn()>, on_wakeup: Option<fn()>) {
+        critical_section::with(|cs| {
+            T::state().suspend_hook.borrow(cs).set(on_suspend);
+            T::state().wakeup_hook.borrow(cs).set(on_wakeup);
+        });
+    }
+
+    fn alloc_ep_mem(&mut self, len: u16) -> Result<u16, EndpointAllocError> {
+        let addr = self.ep_mem_free;
+        if addr + len > PACKET_MEMORY_SIZE as u16 {
+            return Err(EndpointAllocError);
+        }
+        self.ep_mem_free += len;
+        Ok(addr)
+    }
+
+    fn alloc_endpoint<D: Dir>(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Endpoint<'d, T, D>, EndpointAllocError> {
+        trace!(
+            "allocating type={:?} mps={:?} interval={}, dir={:?}",
+            ep_type,
+            max_packet_size,
+            interval,
+            D::dir()
+        );
+
+        // Endpoint 0 is the control endpoint, the others share a type for both directions, except
+        // isochronous endpoints which take the endpoint for a single direction.
+        let iso = ep_type == EndpointType::Isochronous;
+        let index = (0..ENDPOINT_COUNT).find(|&i| {
+            if (i == 0) != (ep_type == EndpointType::Control) {
+                return false;
+            }
+            let ep = &self.alloc[i];
+            let used_dir = match D::dir() {
+                Direction::Out => ep.used_out,
+                Direction::In => ep.used_in,
+            };
+            !(ep.used_out || ep.used_in) || (!iso && ep.ep_type == ep_type && !used_dir)
+        });
+        let index = index.ok_or(EndpointAllocError)?;
+
+        let (len, count_bits) = match D::dir() {
+            Direction::Out => out_len(max_packet_size),
+            // The length of IN packets is written with each packet.
+            Direction::In => ((max_packet_size + 1) / 2 * 2, 0),
+        };
+        let buf = EndpointBuffer {
+            addr: self.alloc_ep_mem(len)?,
+            len,
+        };
+        let double_buf = match (iso, D::dir()) {
+            // Buffer 0 is in the TX descriptors and buffer 1 in the RX descriptors, whatever the
+            // direction.
+            (true, _) => {
+                let buf1 = EndpointBuffer {
+                    addr: self.alloc_ep_mem(len)?,
+                    len,
+                };
+                unsafe {
+                    descriptor(index, DESC_ADDR_TX).write_volatile(buf.addr);
+                    descriptor(index, DESC_COUNT_TX).write_volatile(count_bits);
+                    descriptor(index, DESC_ADDR_RX).write_volatile(buf1.addr);
+                    descriptor(index, DESC_COUNT_RX).write_volatile(count_bits);
+                }
+                self.alloc[index].used_out = true;
+                self.alloc[index].used_in = true;
+                Some(buf1)
+            }
+            (false, Direction::Out) => {
+                unsafe {
+                    descriptor(index, DESC_ADDR_RX).write_volatile(buf.addr);
+                    descriptor(index, DESC_COUNT_RX).write_volatile(count_bits);
+                }
+                self.alloc[index].used_out = true;
+                None
+            }
+            (false, Direction::In) => {
+                unsafe { descriptor(index, DESC_ADDR_TX).write_volatile(buf.addr) };
+                self.alloc[index].used_in = true;
+                None
+            }
+        };
+        self.alloc[index].ep_type = ep_type;
+        trace!("  index={} addr={} len={}", index, buf.addr, buf.len);
+
+        Ok(Endpoint {
+            _phantom: PhantomData,
+            info: EndpointInfo {
+                addr: EndpointAddress::from_parts(index, D::dir()),
+                ep_type,
+                max_packet_size,
+                interval,
+            },
+            buf,
+            double_buf,
+        })
+    }
+}
+
+impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
+    type EndpointOut = Endpoint<'d, T, Out>;
+    type EndpointIn = Endpoint<'d, T, In>;
+    type ControlPipe = ControlPipe<'d, T>;
+    type Bus = Bus<'d, T>;
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval)
+    }
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval)
+    }
+
+    fn start(mut self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let ep_out = unwrap!(self.alloc_endpoint(EndpointType::Control, control_max_packet_size, 0));
+        let ep_in = unwrap!(self.alloc_endpoint(EndpointType::Control, control_max_packet_size, 0));
+
+        let mut ep_types = [EndpointType::Bulk; ENDPOINT_COUNT - 1];
+        for (ep_type, ep) in ep_types.iter_mut().zip(&self.alloc[1..]) {
+            *ep_type = ep.ep_type;
+        }
+
+        (
+            Bus {
+                _phantom: PhantomData,
+                ep_types,
+                powered: false,
+                vbus: self.vbus,
+                pull_up: self.pull_up,
+            },
+            ControlPipe {
+                _phantom: PhantomData,
+                max_packet_size: control_max_packet_size,
+                ep_out,
+                ep_in,
+            },
+        )
+    }
+}
+
+/// USB bus, see [`embassy_usb_driver::Bus`]
+pub struct Bus<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    ep_types: [EndpointType; ENDPOINT_COUNT - 1],
+    /// Whether the last power event was [`Event::PowerDetected`]
+    powered: bool,
+    vbus: Option<ExtiInput<'d, AnyPin>>,
+    pull_up: Option<Output<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
+    async fn poll(&mut self) -> Event {
+        loop {
+            // Without VBUS detection, the bus is powered as far as the driver can tell.
+            let powered = self.vbus.as_ref().map_or(true, |vbus| vbus.is_high());
+            if powered != self.powered {
+                self.powered = powered;
+                return match powered {
+                    true => Event::PowerDetected,
+                    false => Event::PowerRemoved,
+                };
+            }
+
+            match &mut self.vbus {
+                None => return bus_event::<T>(&self.ep_types).await,
+                // The controller is disabled without power, there are no bus events.
+                Some(vbus) if !powered => vbus.wait_for_high().await,
+                Some(vbus) => match select(vbus.wait_for_low(), bus_event::<T>(&self.ep_types)).await {
+                    Either::First(()) => {}
+                    Either::Second(event) => return event,
+                },
+            }
+        }
+    }
+
+    fn set_address(&mut self, addr: u8) {
+        trace!("setting addr: {}", addr);
+        regs::<T>().write(DADDR, DADDR_USBEN | addr as u32);
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        let index = ep_addr.index();
+        // Isochronous transactions have no handshake, so there is no STALL.
+        if self.is_iso(index) {
+            return;
+        }
+        let (mask, offset, not_stalled) = match ep_addr.direction() {
+            Direction::In => (EPCS_TX_STA, TX_STA_OFFSET, STA_NAK),
+            Direction::Out => (EPCS_RX_STA, RX_STA_OFFSET, STA_VALID),
+        };
+        let want = match stalled {
+            true => STA_STALL,
+            false => not_stalled,
+        } << offset;
+        // A transaction can change the status meanwhile, so toggle until it's right.
+        let r = regs::<T>();
+        loop {
+            let epcs = r.read(epcs(index));
+            let sta = epcs & mask;
+            // A disabled endpoint can't be stalled.
+            if sta == STA_DISABLED << offset || sta == want {
+                break;
+            }
+            r.write(epcs(index), invariant(epcs) | toggle(mask, sta, want));
+        }
+        wake(T::state(), ep_addr);
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        let epcs = regs::<T>().read(epcs(ep_addr.index()));
+        match ep_addr.direction() {
+            Direction::In => epcs & EPCS_TX_STA == STA_STALL << TX_STA_OFFSET,
+            Direction::Out => epcs & EPCS_RX_STA == STA_STALL << RX_STA_OFFSET,
+        }
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        trace!("set_enabled {:x} {}", ep_addr, enabled);
+        let index = ep_addr.index();
+        // Isochronous endpoints stay valid, the controller sends the packets as they come.
+        let (mask, offset, sta_enabled) = match ep_addr.direction() {
+            Direction::In if self.is_iso(index) => (EPCS_TX_STA, TX_STA_OFFSET, STA_VALID),
+            Direction::In => (EPCS_TX_STA, TX_STA_OFFSET, STA_NAK),
+            Direction::Out => (EPCS_RX_STA, RX_STA_OFFSET, STA_VALID),
+        };
+        let bit = iso_bit(index, ep_addr.direction());
+        T::state().iso_done.fetch_and(!bit, Ordering::AcqRel);
+        let want = match enabled {
+            true => sta_enabled,
+            false => STA_DISABLED,
+        } << offset;
+        // A transaction can change the status meanwhile, so toggle until it's right.
+        let r = regs::<T>();
+        loop {
+            let epcs = r.read(epcs(index));
+            if epcs & mask == want {
+                break;
+            }
+            r.write(epcs(index), invariant(epcs) | toggle(mask, epcs, want));
+        }
+        wake(T::state(), ep_addr);
+    }
+
+    async fn enable(&mut self) {
+        // Leave the reset, with the interrupts of the bus events and transactions.
+        T::state().irq_flags.store(0, Ordering::Relaxed);
+        let r = regs::<T>();
+        r.write(INTF, 0);
+        r.write(CTL, CTL_RSTIE | CTL_SPSIE | CTL_WKUPIE | CTL_STIE);
+        if let Some(pull_up) = &mut self.pull_up {
+            pull_up.set_high();
+        }
+        trace!("enabled");
+    }
+
+    async fn disable(&mut self) {
+        // Detach first, so the host doesn't see a device that doesn't answer.
+        if let Some(pull_up) = &mut self.pull_up {
+            pull_up.set_low();
+        }
+        regs::<T>().write(CTL, CTL_SETRST);
+        trace!("disabled");
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        critical_section::with(|_| leave_suspend::<T>());
+        // Signal resume for 1 to 15 ms, then the host takes over and resumes the bus.
+        let r = regs::<T>();
+        r.modify(CTL, CTL_RSREQ, 0);
+        #[cfg(feature = "time")]
+        embassy_time::Timer::after(embassy_time::Duration::from_millis(REMOTE_WAKEUP_MS)).await;
+        #[cfg(not(feature = "time"))]
+        cortex_m::asm::delay(crate::cctl::clocks().sys.0 / 1000 * REMOTE_WAKEUP_MS as u32);
+        r.modify(CTL, 0, CTL_RSREQ);
+        trace!("remote wakeup");
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> Bus<'d, T> {
+    fn is_iso(&self, index: usize) -> bool {
+        index != 0 && self.ep_types[index - 1] == EndpointType::Isochronous
+    }
+}
+
+/// Wait for the next reset, suspend or resume event of the bus, and handle a reset by
+/// configuring the endpoints of `ep_types` again.
+async fn bus_event<T: Instance>(ep_types: &[EndpointType; ENDPOINT_COUNT - 1]) -> Event {
+    poll_fn(|cx| {
+        let state = T::state();
+        state.bus_waker.register(cx.waker());
+
+        let flags = state.irq_flags.load(Ordering::Acquire);
+        if flags & IRQ_FLAG_RESUME != 0 {
+            state.irq_flags.fetch_and(!IRQ_FLAG_RESUME, Ordering::AcqRel);
+            return Poll::Ready(Event::Resume);
+        }
+
+        if flags & IRQ_FLAG_RESET != 0 {
+            state.irq_flags.fetch_and(!IRQ_FLAG_RESET, Ordering::AcqRel);
+            let r = regs::<T>();
+            r.write(DADDR, DADDR_USBEN);
+            // The status fields reset to disabled, writing them toggles them.
+            r.write(
+                epcs(0),
+                EPCS_CTL_CONTROL | STA_NAK << RX_STA_OFFSET | STA_NAK << TX_STA_OFFSET,
+            );
+            for (i, &ep_type) in ep_types.iter().enumerate() {
+                r.write(epcs(i + 1), (i + 1) as u32 | ep_ctl(ep_type));
+            }
+            for waker in state.ep_in_wakers.iter().chain(&state.ep_out_wakers) {
+                waker.wake();
+            }
+            return Poll::Ready(Event::Reset);
+        }
+
+        if flags & IRQ_FLAG_SUSPEND != 0 {
+            state.irq_flags.fetch_and(!IRQ_FLAG_SUSPEND, Ordering::AcqRel);
+            return Poll::Ready(Event::Suspend);
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+/// Wake the tasks waiting on endpoint `ep_addr`.
+fn wake(state: &State, ep_addr: EndpointAddress) {
+    match ep_addr.direction() {
+        Direction::In => state.ep_in_wakers[ep_addr.index()].wake(),
+        Direction::Out => state.ep_out_wakers[ep_addr.index()].wake(),
+    }
+}
+
+trait Dir {
+    fn dir() -> Direction;
+}
+
+/// Type-level IN direction, for [`Endpoint`]
+pub enum In {}
+
+impl Dir for In {
+    fn dir() -> Direction {
+        Direction::In
+    }
+}
+
+/// Type-level OUT direction, for [`Endpoint`]
+pub enum Out {}
+
+impl Dir for Out {
+    fn dir() -> Direction {
+        Direction::Out
+    }
+}
+
+/// Endpoint of direction `D`, see [`embassy_usb_driver::Endpoint`]
+pub struct Endpoint<'d, T: Instance, D> {
+    _phantom: PhantomData<(&'d mut T, D)>,
+    info: EndpointInfo,
+    buf: EndpointBuffer,
+    /// Buffer 1 of an isochronous endpoint, which is double-buffered
+    double_buf: Option<EndpointBuffer>,
+}
+
+impl<'d, T: Instance, D> Endpoint<'d, T, D> {
+    /// Copy `buf` into the packet buffer, as the next IN packet.
+    fn write_data(&mut self, buf: &[u8]) {
+        self.buf.write(buf);
+        unsafe { descriptor(self.info.addr.index(), DESC_COUNT_TX).write_volatile(buf.len() as u16) };
+    }
+
+    /// Copy the received OUT packet into `buf`.
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let count = unsafe { descriptor(self.info.addr.index(), DESC_COUNT_RX).read_volatile() };
+        let rx_len = (count & COUNT_RX_CNT) as usize;
+        if rx_len > buf.len() {
+            return Err(EndpointError::BufferOverflow);
+        }
+        self.buf.read(&mut buf[..rx_len]);
+        Ok(rx_len)
+    }
+
+    /// Wait until the status field `mask` of the endpoint is NAK, i.e. a transaction completed,
+    /// or disabled. Returns whether it's disabled.
+    async fn wait_nak(&mut self, mask: u32, offset: u32) -> bool {
+        let index = self.info.addr.index();
+        poll_fn(|cx| {
+            waker::<T>(self.info.addr).register(cx.waker());
+            let sta = regs::<T>().read(epcs(index)) & mask;
+            match sta >> offset {
+                STA_NAK => Poll::Ready(false),
+                STA_DISABLED => Poll::Ready(true),
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait until the endpoint is enabled, i.e. its status field `mask` isn't disabled.
+    async fn wait_enabled_sta(&mut self, mask: u32) {
+        let index = self.info.addr.index();
+        poll_fn(|cx| {
+            waker::<T>(self.info.addr).register(cx.waker());
+            match regs::<T>().read(epcs(index)) & mask {
+                0 => Poll::Pending,
+                _ => Poll::Ready(()),
+            }
+        })
+        .await
+    }
+
+    /// Wait until the isochronous endpoint completed a transaction since the last call, or is
+    /// disabled, i.e. its status field `mask` is. Returns whether it's disabled.
+    async fn wait_iso(&mut self, mask: u32) -> bool {
+        let index = self.info.addr.index();
+        let bit = iso_bit(index, self.info.addr.direction());
+        poll_fn(|cx| {
+            waker::<T>(self.info.addr).register(cx.waker());
+            if regs::<T>().read(epcs(index)) & mask == 0 {
+                return Poll::Ready(true);
+            }
+            match T::state().iso_done.fetch_and(!bit, Ordering::AcqRel) & bit {
+                0 => Poll::Pending,
+                _ => Poll::Ready(false),
+            }
+        })
+        .await
+    }
+
+    /// The buffer of the isochronous endpoint that the controller doesn't use for the next
+    /// transaction, given the data toggle `dtg` of its direction, and its count descriptor.
+    fn iso_buf(&self, dtg: u32) -> (&EndpointBuffer, usize) {
+        match (dtg, &self.double_buf) {
+            (0, Some(buf1)) => (buf1, DESC_COUNT_RX),
+            _ => (&self.buf, DESC_COUNT_TX),
+        }
+    }
+
+    /// Toggle status field `mask` from NAK to VALID, to let the next transaction through.
+    fn set_valid(&mut self, mask: u32, offset: u32) {
+        let index = self.info.addr.index();
+        let valid = toggle(mask, STA_NAK << offset, STA_VALID << offset);
+        let ctl = index as u32 | ep_ctl(self.info.ep_type);
+        regs::<T>().write(epcs(index), ctl | EPCS_RX_ST | EPCS_TX_ST | valid);
+    }
+}
+
+/// The waker of endpoint `ep_addr` of USBD `T`.
+fn waker<T: Instance>(ep_addr: EndpointAddress) -> &'static AtomicWaker {
+    let state = T::state();
+    match ep_addr.direction() {
+        Direction::In => &state.ep_in_wakers[ep_addr.index()],
+        Direction::Out => &state.ep_out_wakers[ep_addr.index()],
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.wait_enabled_sta(EPCS_TX_STA).await
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.wait_enabled_sta(EPCS_RX_STA).await
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        if self.double_buf.is_some() {
+            return self.read_iso(buf).await;
+        }
+        if self.wait_nak(EPCS_RX_STA, RX_STA_OFFSET).await {
+            return Err(EndpointError::Disabled);
+        }
+        let rx_len = self.read_data(buf)?;
+        self.set_valid(EPCS_RX_STA, RX_STA_OFFSET);
+        Ok(rx_len)
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if buf.len() > self.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+        if self.double_buf.is_some() {
+            return self.write_iso(buf).await;
+        }
+        if self.wait_nak(EPCS_TX_STA, TX_STA_OFFSET).await {
+            return Err(EndpointError::Disabled);
+        }
+        self.write_data(buf);
+        self.set_valid(EPCS_TX_STA, TX_STA_OFFSET);
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> Endpoint<'d, T, Out> {
+    /// Read the packet received by the isochronous endpoint in the last frame. The controller
+    /// meanwhile receives the next one into the other buffer.
+    async fn read_iso(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        if self.wait_iso(EPCS_RX_STA).await {
+            return Err(EndpointError::Disabled);
+        }
+        let index = self.info.addr.index();
+        let (packet, count) = self.iso_buf(regs::<T>().read(epcs(index)) & EPCS_RX_DTG);
+        let rx_len = (unsafe { descriptor(index, count).read_volatile() } & COUNT_RX_CNT) as usize;
+        if rx_len > buf.len() {
+            return Err(EndpointError::BufferOverflow);
+        }
+        packet.read(&mut buf[..rx_len]);
+        Ok(rx_len)
+    }
+}
+
+impl<'d, T: Instance> Endpoint<'d, T, In> {
+    /// Queue the packet that the isochronous endpoint sends in the frame after the next one,
+    /// once the controller sent a packet since the last write. The controller keeps sending a
+    /// buffer until it's written again.
+    async fn write_iso(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if self.wait_iso(EPCS_TX_STA).await {
+            return Err(EndpointError::Disabled);
+        }
+        let index = self.info.addr.index();
+        let (packet, count) = self.iso_buf(regs::<T>().read(epcs(index)) & EPCS_TX_DTG);
+        packet.write(buf);
+        unsafe { descriptor(index, count).write_volatile(buf.len() as u16) };
+        Ok(())
+    }
+}
+
+/// Control pipe of endpoint 0, see [`embassy_usb_driver::ControlPipe`]
+pub struct ControlPipe<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    max_packet_size: u16,
+    ep_in: Endpoint<'d, T, In>,
+    ep_out: Endpoint<'d, T, Out>,
+}
+
+impl<'d, T: Instance> ControlPipe<'d, T> {
+    /// Write `EPCS0` as a control endpoint, toggling the status fields by `toggle` and with
+    /// `extra` bits such as `EP_KCTL`.
+    fn write_ep0(&mut self, toggle: u32, extra: u32) {
+        regs::<T>().write(epcs(0), EPCS_CTL_CONTROL | EPCS_RX_ST | EPCS_TX_ST | toggle | extra);
+    }
+
+    /// Wait until the status field `mask` of endpoint 0 is NAK, woken by both directions.
+    async fn wait_ep0_nak(&mut self, mask: u32, offset: u32) {
+        poll_fn(|cx| {
+            let state = T::state();
+            state.ep_in_wakers[0].register(cx.waker());
+            state.ep_out_wakers[0].register(cx.waker());
+            match (regs::<T>().read(epcs(0)) & mask) >> offset {
+                STA_NAK => Poll::Ready(()),
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
+    fn max_packet_size(&self) -> usize {
+        usize::from(self.max_packet_size)
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        loop {
+            poll_fn(|cx| {
+                let state = T::state();
+                state.ep_out_wakers[0].register(cx.waker());
+                match state.ep0_setup.load(Ordering::Relaxed) {
+                    true => Poll::Ready(()),
+                    false => Poll::Pending,
+                }
+            })
+            .await;
+
+            let mut buf = [0; 8];
+            let rx_len = self.ep_out.read_data(&mut buf);
+            if rx_len != Ok(8) {
+                trace!("SETUP read failed: {:?}", rx_len);
+                continue;
+            }
+            T::state().ep0_setup.store(false, Ordering::Relaxed);
+            return buf;
+        }
+    }
+
+    async fn data_out(&mut self, buf: &mut [u8], first: bool, last: bool) -> Result<usize, EndpointError> {
+        // A SETUP packet sets both directions to NAK. The first packet needs RX VALID to come in,
+        // and TX is STALL so that the host gets a STALL if it starts the status stage too early,
+        // until the last packet, after which the status stage waits with NAK for accept or reject.
+        if first || last {
+            let mut rx = 0;
+            let mut tx = 0;
+            if first {
+                rx ^= toggle(EPCS_RX_STA, STA_NAK << RX_STA_OFFSET, STA_VALID << RX_STA_OFFSET);
+                tx ^= toggle(EPCS_TX_STA, STA_NAK << TX_STA_OFFSET, STA_STALL << TX_STA_OFFSET);
+            }
+            if last {
+                tx ^= toggle(EPCS_TX_STA, STA_STALL << TX_STA_OFFSET, STA_NAK << TX_STA_OFFSET);
+            }
+            self.write_ep0(rx | tx, 0);
+        }
+
+        self.wait_ep0_nak(EPCS_RX_STA, RX_STA_OFFSET).await;
+        if T::state().ep0_setup.load(Ordering::Relaxed) {
+            trace!("received another SETUP, aborting data_out.");
+            return Err(EndpointError::Disabled);
+        }
+
+        let rx_len = self.ep_out.read_data(buf)?;
+        // After the last packet, further ones are stalled, otherwise the next one may come in.
+        let next = match last {
+            true => STA_STALL,
+            false => STA_VALID,
+        };
+        self.write_ep0(toggle(EPCS_RX_STA, STA_NAK << RX_STA_OFFSET, next << RX_STA_OFFSET), 0);
+        Ok(rx_len)
+    }
+
+    async fn data_in(&mut self, data: &[u8], first: bool, last: bool) -> Result<(), EndpointError> {
+        if data.len() > self.ep_in.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+
+        // A SETUP packet sets both directions to NAK. RX is STALL until the last packet, then
+        // VALID with `EP_KCTL` so that the hardware accepts the zero-length status stage.
+        let kctl = match last {
+            true => EPCS_KCTL,
+            false => 0,
+        };
+        if first || last {
+            let mut rx = 0;
+            if first {
+                rx ^= toggle(EPCS_RX_STA, STA_NAK << RX_STA_OFFSET, STA_STALL << RX_STA_OFFSET);
+            }
+            if last {
+                rx ^= toggle(EPCS_RX_STA, STA_STALL << RX_STA_OFFSET, STA_VALID << RX_STA_OFFSET);
+            }
+            self.write_ep0(rx, kctl);
+        }
+
+        self.wait_ep0_nak(EPCS_TX_STA, TX_STA_OFFSET).await;
+        if T::state().ep0_setup.load(Ordering::Relaxed) {
+            trace!("received another SETUP, aborting data_in.");
+            return Err(EndpointError::Disabled);
+        }
+
+        self.ep_in.write_data(data);
+        self.write_ep0(
+            toggle(EPCS_TX_STA, STA_NAK << TX_STA_OFFSET, STA_VALID << TX_STA_OFFSET),
+            kctl,
+        );
+        Ok(())
+    }
+
+    async fn accept(&mut self) {
+        // Send the zero-length status packet, and stall further OUT packets.
+        self.ep_in.write_data(&[]);
+        let epcs = regs::<T>().read(epcs(0));
+        let rx = toggle(EPCS_RX_STA, epcs, STA_STALL << RX_STA_OFFSET);
+        let tx = toggle(EPCS_TX_STA, epcs, STA_VALID << TX_STA_OFFSET);
+        self.write_ep0(rx | tx, 0);
+        // `embassy-usb` sets the address right after, which must wait for the status stage.
+        poll_fn(|cx| {
+            T::state().ep_in_wakers[0].register(cx.waker());
+            match (regs::<T>().read(epcs(0)) & EPCS_TX_STA) >> TX_STA_OFFSET {
+                STA_NAK => Poll::Ready(()),
+                _ => Poll::Pending,
+            }
+        })
+        .await;
+    }
+
+    async fn reject(&mut self) {
+        let epcs = regs::<T>().read(epcs(0));
+        let rx = toggle(EPCS_RX_STA, epcs, STA_STALL << RX_STA_OFFSET);
+        let tx = toggle(EPCS_TX_STA, epcs, STA_STALL << TX_STA_OFFSET);
+        self.write_ep0(rx | tx, 0);
+    }
+}
+
+struct Regs(usize);
+
+impl Regs {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Set the `set` bits and clear the `clear` bits of the register at `offset`.
+    fn modify(&self, offset: usize, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set)
+    }
+}
+
+fn regs<T: Instance>() -> Regs {
+    Regs(T::base())
+}
+
+pub(crate) mod sealed {
+    pub trait Instance: crate::cctl::CCTLPeripherial {
+        fn base() -> usize;
+        fn state() -> &'static super::State;
+    }
+}
+
+/// USBD instance
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static {
+    /// The high priority interrupt, for isochronous and double-buffered bulk transactions
+    type HpInterrupt: Interrupt;
+    /// The low priority interrupt, for the other transactions and the bus events
+    type LpInterrupt: Interrupt;
+}
+
+pin_trait!(DpPin, Instance);
+pin_trait!(DmPin, Instance);
+
+macro_rules! impl_usbd {
+    ($inst:ident, $hp:ident, $lp:ident) => {
+        impl crate::usbd::sealed::Instance for peripherals::$inst {
+            fn base() -> usize {
+                crate::pac::$inst::ptr() as usize
+            }
+
+            fn state() -> &'static crate::usbd::State {
+                static STATE: crate::usbd::State = crate::usbd::State::new();
+                &STATE
+            }
+        }
+
+        impl crate::usbd::Instance for peripherals::$inst {
+            type HpInterrupt = crate::interrupt::$hp;
+            type LpInterrupt = crate::interrupt::$lp;
+        }
+    };
+}
+
+/// Offset of the `USBD_EPxCS` register of endpoint `index`
+fn epcs(index: usize) -> usize {
+    4 * index
+}
+
+// Register offsets
+const CTL: usize = 0x40;
+const INTF: usize = 0x44;
+const DADDR: usize = 0x4C;
+const BADDR: usize = 0x50;
+
+// USBD_EPxCS
+const EPCS_EPADDR: u32 = 0b1111;
+const TX_STA_OFFSET: u32 = 4;
+const EPCS_TX_STA: u32 = 0b11 << 4;
+const EPCS_TX_DTG: u32 = 1 << 6;
+const EPCS_TX_ST: u32 = 1 << 7;
+const EPCS_KCTL: u32 = 1 << 8;
+const EPCS_CTL: u32 = 0b11 << 9;
+const EPCS_CTL_BULK: u32 = 0b00 << 9;
+const EPCS_CTL_CONTROL: u32 = 0b01 << 9;
+const EPCS_CTL_ISO: u32 = 0b10 << 9;
+const EPCS_CTL_INTERRUPT: u32 = 0b11 << 9;
+const EPCS_SETUP: u32 = 1 << 11;
+const RX_STA_OFFSET: u32 = 12;
+const EPCS_RX_STA: u32 = 0b11 << 12;
+const EPCS_RX_DTG: u32 = 1 << 14;
+const EPCS_RX_ST: u32 = 1 << 15;
+// `TX_STA`/`RX_STA` values
+const STA_DISABLED: u32 = 0b00;
+const STA_STALL: u32 = 0b01;
+const STA_NAK: u32 = 0b10;
+const STA_VALID: u32 = 0b11;
+
+// USBD_CTL
+const CTL_SETRST: u32 = 1 << 0;
+const CTL_LOWM: u32 = 1 << 2;
+const CTL_SETSPS: u32 = 1 << 3;
+const CTL_RSREQ: u32 = 1 << 4;
+const CTL_WKUPIE: u32 = 1 << 12;
+const CTL_SPSIE: u32 = 1 << 11;
+const CTL_RSTIE: u32 = 1 << 10;
+const CTL_STIE: u32 = 1 << 15;
+
+// USBD_INTF
+const INTF_EPNUM: u32 = 0b1111;
+const INTF_RSTIF: u32 = 1 << 10;
+const INTF_SPSIF: u32 = 1 << 11;
+const INTF_WKUPIF: u32 = 1 << 12;
+const INTF_STIF: u32 = 1 << 15;
+
+// USBD_DADDR
+const DADDR_USBEN: u32 = 1 << 7;
+
+// COUNT_RX descriptor
+const COUNT_RX_CNT: u16 = 0x3FF;
+const COUNT_RX_BLKSZ: u16 = 1 << 15;
@@ -0,0 +1,156 @@
+//! Window watchdog timer (WWDGT)
+//!
+//! The window watchdog resets the chip unless it's fed in time, and also if it's fed too early,
+//! before the window opens. This catches a program that runs too slowly as well as one that
+//! races through its main loop, e.g. because a step is skipped. The reset is reported as
+//! [`ResetReason::WindowWatchdog`](crate::pmu::ResetReason::WindowWatchdog).
+//!
+//! ```no_run
+//! # let p = embassy_gd32::init(Default::default()).unwrap();
+//! use embassy_gd32::wwdgt::WindowWatchdog;
+//!
+//! // Feed between 10 ms and 40 ms after the last feed.
+//! let mut wdgt = WindowWatchdog::new(p.WWDGT, 40_000, 10_000);
+//! wdgt.set_early_wakeup_callback(Some(|| {
+//!     // Save the state to look at after the reset, e.g. in the backup registers.
+//! }));
+//! wdgt.start();
+//! loop {
+//!     // Work...
+//!     wdgt.feed();
+//! }
+//! ```
+//!
+//! One counter tick before the reset, the early wakeup interrupt runs the callback and wakes
+//! [`wait_for_early_wakeup`]. The tick is 4096 × 2^n APB1 cycles, e.g. 68 µs to 546 µs at
+//! 60 MHz, which leaves little time: the callback is the reliable way to react, and the `WWDGT`
+//! interrupt should have a high priority. The counter runs from APB1, so it stops in
+//! deep-sleep.
+//!
+//! Once started, the watchdog can only be stopped by a reset.
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use atomic_polyfill::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use embassy_hal_common::{into_ref, Peripheral, PeripheralRef};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::cctl::sealed::CCTLPeripherial;
+use crate::interrupt::{Interrupt, InterruptExt};
+use crate::{interrupt, pac};
+
+/// Longest timeout in counter ticks, from the largest counter value `0x7F` to `0x3F`
+const MAX_TICKS: u32 = 64;
+
+static EARLY_WAKEUP_WAKER: AtomicWaker = AtomicWaker::new();
+static EARLY_WAKEUP: AtomicBool = AtomicBool::new(false);
+static EARLY_WAKEUP_CALLBACK: Mutex<Cell<Option<fn()>>> = Mutex::new(Cell::new(None));
+
+/// Window watchdog timer
+pub struct WindowWatchdog<'d> {
+    _inner: PeripheralRef<'d, crate::peripherals::WWDGT>,
+    /// Counter value written on each feed
+    counter: u32,
+}
+
+impl<'d> WindowWatchdog<'d> {
+    /// Configure the watchdog to reset the chip `timeout_us` microseconds after the last feed,
+    /// or when it's fed less than `window_us` microseconds after the last feed. A `window_us` of
+    /// zero allows feeding at any time.
+    ///
+    /// The watchdog only runs once [`start`](Self::start)ed. The times are rounded to counter
+    /// ticks. Panics if `timeout_us` is shorter than a tick or longer than 64 ticks of the
+    /// largest prescaler, about 35 ms at 60 MHz, or if the window isn't shorter than the timeout.
+    pub fn new(inner: impl Peripheral<P = crate::peripherals::WWDGT> + 'd, timeout_us: u32, window_us: u32) -> Self {
+        into_ref!(inner);
+
+        crate::peripherals::WWDGT::enable();
+        let pclk = crate::peripherals::WWDGT::frequency().0 as u64;
+        let ticks = |us: u32, psc: u32| (us as u64 * pclk / (4096 << psc) as u64 / 1_000_000) as u32;
+        let ticks_up = |us: u32, psc: u32| {
+            let tick = (4096u64 << psc) * 1_000_000;
+            ((us as u64 * pclk + tick - 1) / tick) as u32
+        };
+        let psc = unwrap!(
+            (0..4).find(|&psc| ticks(timeout_us, psc) <= MAX_TICKS),
+            "watchdog timeout too long"
+        );
+        let timeout = ticks(timeout_us, psc);
+        assert!(timeout > 0, "watchdog timeout too short");
+        // Round the window up, so feeding is never allowed before `window_us`.
+        let window = ticks_up(window_us, psc);
+        assert!(window < timeout, "watchdog window must be shorter than the timeout");
+
+        // The reset happens when the counter goes from 0x40 to 0x3F, and feeding is allowed
+        // once it's at or below the window value.
+        let counter = 0x3F + timeout;
+        let r = regs();
+        unsafe {
+            r.stat.write(|w| w.bits(0));
+            r.cfg
+                .write(|w| w.bits((counter - window) | psc << CFG_PSC_OFFSET | CFG_EWIE));
+
+            let irq = interrupt::WWDGT::steal();
+            irq.unpend();
+            irq.enable();
+        }
+
+        Self { _inner: inner, counter }
+    }
+
+    /// Start the watchdog, which is fed by starting it.
+    pub fn start(&mut self) {
+        unsafe { regs().ctl.write(|w| w.bits(CTL_WDGTEN | self.counter)) };
+    }
+
+    /// Feed the watchdog, which must be done inside the window.
+    pub fn feed(&mut self) {
+        unsafe { regs().ctl.write(|w| w.bits(CTL_WDGTEN | self.counter)) };
+    }
+
+    /// Call `callback` from the early wakeup interrupt, right before the watchdog resets the
+    /// chip, e.g. to save state for after the reset. `None` removes the callback.
+    pub fn set_early_wakeup_callback(&mut self, callback: Option<fn()>) {
+        critical_section::with(|cs| EARLY_WAKEUP_CALLBACK.borrow(cs).set(callback));
+    }
+}
+
+/// Wait for the early wakeup interrupt of the window watchdog, right before it resets the chip.
+///
+/// Returns right away if the interrupt occurred since the last call. The task may not get to
+/// run before the reset, see the [module documentation](self).
+pub async fn wait_for_early_wakeup() {
+    poll_fn(|cx| {
+        EARLY_WAKEUP_WAKER.register(cx.waker());
+        match EARLY_WAKEUP.swap(false, Ordering::AcqRel) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await
+}
+
+#[interrupt]
+unsafe fn WWDGT() {
+    regs().stat.write(|w| w.bits(0));
+
+    if let Some(callback) = critical_section::with(|cs| EARLY_WAKEUP_CALLBACK.borrow(cs).get()) {
+        callback();
+    }
+    EARLY_WAKEUP.store(true, Ordering::Release);
+    EARLY_WAKEUP_WAKER.wake();
+}
+
+fn regs() -> &'static pac::wwdgt::RegisterBlock {
+    unsafe { &*pac::WWDGT::ptr() }
+}
+
+// WWDGT_CTL
+const CTL_WDGTEN: u32 = 1 << 7;
+
+// WWDGT_CFG
+const CFG_PSC_OFFSET: u32 = 7;
+const CFG_EWIE: u32 = 1 << 9;
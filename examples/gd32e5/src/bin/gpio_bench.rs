@@ -0,0 +1,81 @@
+//! Measures the cost of GPIO reconfiguration, e.g. turning around a bit-banged bus.
+//!
+//! Run with `cargo run --release --bin gpio_bench`.
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::DWT;
+use cortex_m_rt::entry;
+use defmt::*;
+use embassy_gd32::gpio::{AnyPin, Flex, OutputType, Pin, PortBus, Pull, Speed};
+use {defmt_rtt as _, panic_probe as _};
+
+const ROUNDS: u32 = 1000;
+
+fn measure(mut f: impl FnMut()) -> u32 {
+    let start = DWT::cycle_count();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    DWT::cycle_count().wrapping_sub(start) / ROUNDS
+}
+
+#[entry]
+fn main() -> ! {
+    let p = unwrap!(embassy_gd32::init(Default::default()));
+    let mut cp = unwrap!(cortex_m::Peripherals::take());
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let baseline = measure(|| cortex_m::asm::nop());
+    info!("loop overhead: {} cycles", baseline);
+
+    let mut pin = Flex::new(p.PA0);
+    let cycles = measure(|| {
+        pin.set_as_output(Speed::VeryHigh);
+        pin.set_as_input(Pull::None);
+    });
+    info!("Flex output -> input round trip: {} cycles", cycles - baseline);
+
+    let cycles = measure(|| {
+        pin.set_as_input_output(Speed::VeryHigh, OutputType::OpenDrain);
+        pin.set_as_input(Pull::Up);
+    });
+    info!("Flex open drain -> pulled-up input round trip: {} cycles", cycles - baseline);
+
+    let cycles = measure(|| {
+        pin.set_high();
+        pin.set_low();
+    });
+    info!("Flex set_high + set_low: {} cycles", cycles - baseline);
+    drop(pin);
+
+    let pins: [AnyPin; 8] = [
+        p.PB8.degrade(),
+        p.PB9.degrade(),
+        p.PB10.degrade(),
+        p.PB11.degrade(),
+        p.PB12.degrade(),
+        p.PB13.degrade(),
+        p.PB14.degrade(),
+        p.PB15.degrade(),
+    ];
+    let mut bus = PortBus::new(pins);
+    let cycles = measure(|| {
+        bus.set_as_output(Speed::VeryHigh);
+        bus.set_as_input(Pull::None);
+    });
+    info!("PortBus (8 pins) output -> input round trip: {} cycles", cycles - baseline);
+
+    bus.set_as_output(Speed::VeryHigh);
+    let mut value = 0u16;
+    let cycles = measure(|| {
+        bus.write(value << 8);
+        value = value.wrapping_add(1);
+    });
+    info!("PortBus (8 pins) write: {} cycles", cycles - baseline);
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}